@@ -0,0 +1,100 @@
+use psc::env::{Captured, Env, Hook};
+use psc::err::RuntimeError;
+use psc::eval::Stmt;
+use psc::lex::LexOptions;
+use psc::load;
+use std::cell::Cell;
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Counts the statements a run executes
+struct Counter(Rc<Cell<u64>>);
+
+impl Hook for Counter {
+    fn before(&mut self, _stmt: &Stmt, _env: &mut Env) -> Result<(), RuntimeError> {
+        self.0.set(self.0.get() + 1);
+        Ok(())
+    }
+}
+
+fn ms(x: Duration) -> String {
+    format!("{:.3}ms", x.as_secs_f64() * 1000.0)
+}
+
+/// `psc bench FILE [--iterations N] [--input FILE]` runs a program over and
+/// over, with its output kept in memory and its input read from the file or
+/// empty, and reports how long the runs took and how many statements they
+/// ran. `RANDOM` is seeded the same every run so they all do the same work
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut file: Option<String> = None;
+    let mut iterations: usize = 10;
+    let mut input: Vec<u8> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => match args.next().and_then(|x| x.parse::<usize>().ok()) {
+                Some(x) if x > 0 => iterations = x,
+                _ => return Err("--iterations expects a positive number".into()),
+            },
+            "--input" => match args.next() {
+                Some(path) => input = fs::read(&path).map_err(|e| format!("{}: {}", path, e))?,
+                None => return Err("--input expects a file".into()),
+            },
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unknown bench argument {}", arg).into()),
+        }
+    }
+
+    let file = match file {
+        Some(x) => x,
+        None => return Err("bench expects a file".into()),
+    };
+
+    let stmts = load::load(Path::new(&file), &options)?;
+    let mut times: Vec<Duration> = Vec::new();
+    let mut counts: Vec<u64> = Vec::new();
+
+    for _ in 0..iterations {
+        let count = Rc::new(Cell::new(0));
+
+        let mut env = Env::new();
+        env.seed(0);
+        env.dialect = options.dialect;
+        env.out = Box::new(Captured::default());
+        env.input = Some(Box::new(Cursor::new(input.clone())));
+        env.strict_input = true;
+        env.hook = Some(Box::new(Counter(count.clone())));
+
+        let start = Instant::now();
+        let res = env.run(&stmts);
+        times.push(start.elapsed());
+        counts.push(count.get());
+
+        if let (Err(e), None) = (res, env.halted) {
+            return Err(format!("{}: the program failed: {}", file, e.msg).into());
+        }
+    }
+
+    let total: Duration = times.iter().sum();
+    let (min, max) = (times.iter().min(), times.iter().max());
+    let (fewest, most) = (counts.iter().min(), counts.iter().max());
+
+    println!("{}, {} iterations", file, iterations);
+    if let (Some(min), Some(max), Some(fewest), Some(most)) = (min, max, fewest, most) {
+        println!("  min         {}", ms(*min));
+        println!("  mean        {}", ms(total / iterations as u32));
+        println!("  max         {}", ms(*max));
+        match fewest == most {
+            true => println!("  statements  {} per run", fewest),
+            false => println!("  statements  {} to {} per run", fewest, most),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+const BASE: u64 = 1_000_000_000;
+
+/// Arbitrary precision integer used by `--bignum` once an `i64` overflows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    neg: bool,
+    /// Base 10^9 digits, least significant first, without trailing zeros
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(neg: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        BigInt {
+            neg: neg && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    /// Decimal digits after an optional `-`, the way they're displayed
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (neg, text) = match text.strip_prefix('-') {
+            Some(x) => (true, x),
+            None => (false, text),
+        };
+        if text.is_empty() || !text.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        // Nine decimal digits to each base 10^9 one, from the end
+        let digits = text.as_bytes().rchunks(9).map(|x| x.iter().fold(0, |acc, c| acc * 10 + (c - b'0') as u32));
+        Some(BigInt::new(neg, digits.collect()))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let mut x: i128 = 0;
+
+        for &d in self.digits.iter().rev() {
+            x = x.checked_mul(BASE as i128)?.checked_add(d as i128)?;
+        }
+
+        i64::try_from(if self.neg { -x } else { x }).ok()
+    }
+
+    /// Bytes taken up by the digits
+    pub fn size(&self) -> usize {
+        self.digits.len() * std::mem::size_of::<u32>()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let x = self.digits.iter().rev().fold(0.0, |acc, &d| acc * BASE as f64 + d as f64);
+        if self.neg {
+            -x
+        } else {
+            x
+        }
+    }
+
+    fn cmp_digits(l: &[u32], r: &[u32]) -> Ordering {
+        l.len().cmp(&r.len()).then_with(|| l.iter().rev().cmp(r.iter().rev()))
+    }
+
+    fn add_digits(l: &[u32], r: &[u32]) -> Vec<u32> {
+        let mut ret = Vec::new();
+        let mut carry = 0;
+
+        for i in 0..l.len().max(r.len()) {
+            let x = *l.get(i).unwrap_or(&0) as u64 + *r.get(i).unwrap_or(&0) as u64 + carry;
+            ret.push((x % BASE) as u32);
+            carry = x / BASE;
+        }
+
+        if carry > 0 {
+            ret.push(carry as u32);
+        }
+
+        ret
+    }
+
+    /// `l - r` where `l >= r`
+    fn sub_digits(l: &[u32], r: &[u32]) -> Vec<u32> {
+        let mut ret = Vec::new();
+        let mut borrow = 0;
+
+        for (i, &d) in l.iter().enumerate() {
+            let mut x = d as i64 - *r.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+
+            if x < 0 {
+                x += BASE as i64;
+                borrow = 1;
+            }
+
+            ret.push(x as u32);
+        }
+
+        ret
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::new(!self.neg, self.digits.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::new(self.neg, BigInt::add_digits(&self.digits, &other.digits));
+        }
+
+        match BigInt::cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.neg, BigInt::sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.neg, BigInt::sub_digits(&self.digits, &other.digits)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut ret = vec![0u64; self.digits.len() + other.digits.len() + 1];
+
+        for (i, &l) in self.digits.iter().enumerate() {
+            let mut carry = 0;
+
+            for (j, &r) in other.digits.iter().enumerate() {
+                let x = ret[i + j] + l as u64 * r as u64 + carry;
+                ret[i + j] = x % BASE;
+                carry = x / BASE;
+            }
+
+            ret[i + other.digits.len()] += carry;
+        }
+
+        BigInt::new(self.neg != other.neg, ret.into_iter().map(|x| x as u32).collect())
+    }
+
+    /// Truncating division by a divisor below 10^9, returning the quotient and
+    /// the remainder (which has the sign of `self`)
+    pub fn div_rem_small(&self, divisor: i64) -> Option<(BigInt, i64)> {
+        let d = divisor.unsigned_abs();
+        if d == 0 || d >= BASE {
+            return None;
+        }
+
+        let mut quot = vec![0; self.digits.len()];
+        let mut rem = 0;
+
+        for (i, &x) in self.digits.iter().enumerate().rev() {
+            let cur = rem * BASE + x as u64;
+            quot[i] = (cur / d) as u32;
+            rem = cur % d;
+        }
+
+        let rem = if self.neg { -(rem as i64) } else { rem as i64 };
+        Some((BigInt::new(self.neg != (divisor < 0), quot), rem))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(x: i64) -> BigInt {
+        let mut mag = x.unsigned_abs();
+        let mut digits = Vec::new();
+
+        while mag > 0 {
+            digits.push((mag % BASE) as u32);
+            mag /= BASE;
+        }
+
+        BigInt::new(x < 0, digits)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => BigInt::cmp_digits(&self.digits, &other.digits),
+            (true, true) => BigInt::cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut it = self.digits.iter().rev();
+
+        match it.next() {
+            Some(first) => {
+                if self.neg {
+                    write!(f, "-")?;
+                }
+                write!(f, "{}", first)?;
+            }
+            None => return write!(f, "0"),
+        }
+
+        for d in it {
+            write!(f, "{:09}", d)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use crate::dialect::Dialect;
+use crate::err::ParseError;
+use crate::eval::*;
+use crate::lex::{LexOptions, Span};
+use crate::pretty;
+use std::cell::OnceCell;
+use std::ops;
+use std::rc::Rc;
+
+/// Where a node that didn't come from source is, line 0 being before the
+/// first line. `source` gives a built program real lines
+const BUILT: Span = Span { line: 0, col: 0, len: 0 };
+
+/// Expressions for tools that write programs rather than parse them, like
+/// `Expr::var("X").lt(Expr::int(10))` or `Expr::var("X") + Expr::int(1)`
+impl Expr {
+    pub fn int(x: i64) -> Expr {
+        Expr::IntLit(x)
+    }
+
+    pub fn real(x: f64) -> Expr {
+        Expr::FloatLit(x)
+    }
+
+    pub fn boolean(x: bool) -> Expr {
+        Expr::BoolLit(x)
+    }
+
+    pub fn string(x: &str) -> Expr {
+        Expr::StrLit(x.to_string())
+    }
+
+    pub fn null() -> Expr {
+        Expr::NullLit
+    }
+
+    pub fn var(name: &str) -> Expr {
+        Expr::Ident(name.to_string())
+    }
+
+    pub fn array(items: Vec<Expr>) -> Expr {
+        Expr::Array(items)
+    }
+
+    pub fn map(entries: Vec<(Expr, Expr)>) -> Expr {
+        Expr::Map(entries)
+    }
+
+    pub fn set(items: Vec<Expr>) -> Expr {
+        Expr::Set(items)
+    }
+
+    /// A function the program defines or a builtin, like `LENGTH(A)`
+    pub fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Call(Call { name: name.to_string(), args, span: BUILT })
+    }
+
+    pub fn new_object(class: &str, args: Vec<Expr>) -> Expr {
+        Expr::New(New { class: class.to_string(), args, span: BUILT })
+    }
+
+    /// `self` and `right` on either side of one of the binary operators,
+    /// for the ones without a method of their own
+    ///
+    /// ```
+    /// use psc::eval::{BinaryOp, Expr};
+    ///
+    /// let shifted = Expr::var("X").binary(BinaryOp::Shl, Expr::int(2));
+    /// assert_eq!(shifted.to_string(), "X << 2");
+    /// ```
+    pub fn binary(self, op: BinaryOp, right: Expr) -> Expr {
+        Expr::BinOp(Box::new(BinOp { left: self, right, op, span: BUILT }))
+    }
+
+    pub fn equals(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Equals, right)
+    }
+
+    pub fn not_equals(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::NotEquals, right)
+    }
+
+    pub fn lt(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::LT, right)
+    }
+
+    pub fn le(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::LE, right)
+    }
+
+    pub fn gt(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::GT, right)
+    }
+
+    pub fn ge(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::GE, right)
+    }
+
+    /// `div`, where `/` always gives a real
+    pub fn floor_div(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::FloorDiv, right)
+    }
+
+    /// `self in right`
+    pub fn is_in(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::In, right)
+    }
+
+    /// `self[index]`
+    pub fn at(self, index: Expr) -> Expr {
+        Expr::Index(Box::new(Index { target: self, index }))
+    }
+
+    pub fn field(self, name: &str) -> Expr {
+        Expr::Field(Box::new(Field { target: self, name: name.to_string() }))
+    }
+
+    pub fn method(self, name: &str, args: Vec<Expr>) -> Expr {
+        Expr::MethodCall(Box::new(MethodCall { target: self, name: name.to_string(), args, span: BUILT }))
+    }
+}
+
+impl ops::Add for Expr {
+    type Output = Expr;
+
+    fn add(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Plus, right)
+    }
+}
+
+impl ops::Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Minus, right)
+    }
+}
+
+impl ops::Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Mul, right)
+    }
+}
+
+impl ops::Div for Expr {
+    type Output = Expr;
+
+    fn div(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Div, right)
+    }
+}
+
+/// `mod`
+impl ops::Rem for Expr {
+    type Output = Expr;
+
+    fn rem(self, right: Expr) -> Expr {
+        self.binary(BinaryOp::Mod, right)
+    }
+}
+
+fn built(kind: StmtKind) -> Stmt {
+    Stmt { kind, span: BUILT }
+}
+
+/// Statements to go with the expressions above
+impl Stmt {
+    /// `target` is a variable, or a place like `Expr::var("A").at(Expr::int(0))`
+    pub fn assign(target: Expr, expr: Expr) -> Stmt {
+        built(StmtKind::Assign(Assign { target, expr, declared: None, literal: OnceCell::new() }))
+    }
+
+    pub fn output(expr: Expr) -> Stmt {
+        built(StmtKind::Output(Output { expr }))
+    }
+
+    /// The type is guessed from what's typed without `kind`
+    pub fn input(name: &str, kind: Option<InputType>) -> Stmt {
+        built(StmtKind::Input(Input { ident: name.to_string(), kind }))
+    }
+
+    pub fn if_then(cond: Expr, body: Vec<Stmt>) -> Stmt {
+        Stmt::if_else(vec![(cond, body)], Vec::new())
+    }
+
+    /// `if`, then an `else if` for each branch after the first, then `else`
+    /// unless `otherwise` is empty
+    pub fn if_else(mut branches: Vec<(Expr, Vec<Stmt>)>, otherwise: Vec<Stmt>) -> Stmt {
+        if !otherwise.is_empty() {
+            branches.push((Expr::BoolLit(true), otherwise));
+        }
+        built(StmtKind::If(If { branches }))
+    }
+
+    pub fn while_loop(cond: Expr, body: Vec<Stmt>) -> Stmt {
+        built(StmtKind::While(While { cond, stmts: body }))
+    }
+
+    /// `loop until cond`, which checks before each run of the body
+    pub fn until_loop(cond: Expr, body: Vec<Stmt>) -> Stmt {
+        built(StmtKind::Until(Until { cond, stmts: body }))
+    }
+
+    /// `repeat ... until cond`, which checks after. Not every dialect has it
+    pub fn repeat(body: Vec<Stmt>, cond: Expr) -> Stmt {
+        built(StmtKind::Repeat(Until { cond, stmts: body }))
+    }
+
+    /// `loop NAME from start to end`, counting up and including `end`
+    pub fn for_loop(name: &str, start: Expr, end: Expr, body: Vec<Stmt>) -> Stmt {
+        built(StmtKind::For(For { name: name.to_string(), start, end, stmts: body }))
+    }
+
+    pub fn for_each(name: &str, iter: Expr, body: Vec<Stmt>) -> Stmt {
+        built(StmtKind::ForEach(ForEach { name: name.to_string(), iter, stmts: body }))
+    }
+
+    /// An expression run for what it does, usually a call
+    pub fn expr(expr: Expr) -> Stmt {
+        built(StmtKind::Expr(expr))
+    }
+
+    /// A procedure, or a function when the body returns a value
+    pub fn function(name: &str, params: &[&str], body: Vec<Stmt>) -> Stmt {
+        built(StmtKind::Function(Rc::new(Function {
+            name: name.to_string(),
+            params: params.iter().map(|x| x.to_string()).collect(),
+            stmts: body,
+            doc: None,
+        })))
+    }
+
+    pub fn ret(expr: Option<Expr>) -> Stmt {
+        built(StmtKind::Return(expr))
+    }
+
+    pub fn halt(code: Option<Expr>) -> Stmt {
+        built(StmtKind::Halt(code))
+    }
+
+    pub fn assert(cond: Expr, msg: Option<Expr>) -> Stmt {
+        built(StmtKind::Assert(Assert { cond, msg }))
+    }
+
+    /// `attempt ... on error NAME ... end attempt`
+    pub fn attempt(body: Vec<Stmt>, name: Option<&str>, handler: Vec<Stmt>) -> Stmt {
+        built(StmtKind::Attempt(Attempt {
+            stmts: body,
+            name: name.map(str::to_string),
+            handler,
+        }))
+    }
+}
+
+/// The source of a built program, and the program parsed back from it so
+/// each statement has the line it's written on, for errors, `--trace` and
+/// trace tables. Fails for a program that can't be written, like one
+/// assigning to a literal
+///
+/// ```
+/// use psc::eval::{Expr, Stmt};
+///
+/// let program = vec![
+///     Stmt::assign(Expr::var("TOTAL"), Expr::int(0)),
+///     Stmt::for_loop("I", Expr::int(1), Expr::int(3), vec![
+///         Stmt::assign(Expr::var("TOTAL"), Expr::var("TOTAL") + Expr::var("I")),
+///     ]),
+///     Stmt::output(Expr::var("TOTAL")),
+/// ];
+/// let (source, stmts) = psc::build::source(&program, Default::default()).unwrap();
+/// assert!(source.contains("loop I from 1 to 3"));
+/// psc::Interpreter::new().run(&stmts).unwrap();
+/// ```
+pub fn source(stmts: &[Stmt], dialect: Dialect) -> Result<(String, Vec<Stmt>), ParseError> {
+    let source = pretty::program(stmts, dialect);
+    let options = LexOptions { dialect, ..Default::default() };
+    let parsed = crate::parse(&source, &options)?;
+    Ok((source, parsed))
+}
@@ -0,0 +1,306 @@
+use crate::bigint::BigInt;
+use crate::env::Env;
+use crate::err::RuntimeError;
+use crate::eval::{as_big, big_int_op, BinaryOp, PscObject};
+#[cfg(feature = "regex")]
+use crate::regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub fn call(name: &str, args: Vec<PscObject>, env: &mut Env) -> Result<PscObject, RuntimeError> {
+    let name = env.dialect.table().builtin(name);
+
+    match (name, args.as_slice()) {
+        ("SET", _) => Ok(PscObject::set_from(args)),
+
+        // Allocates a fresh cell, e.g. for linked list nodes
+        ("NEW", [x]) => Ok(PscObject::RefT(Rc::new(RefCell::new(x.clone())))),
+
+        ("LENGTH", [PscObject::StringT(s)]) => Ok(PscObject::IntT(s.chars().count() as i64)),
+        ("LENGTH", [PscObject::ArrayT(items) | PscObject::SetT(items)]) => Ok(PscObject::IntT(items.len() as i64)),
+        ("LENGTH", [PscObject::MapT(entries)]) => Ok(PscObject::IntT(entries.len() as i64)),
+
+        ("INDEXOF", [PscObject::ArrayT(items), x]) => {
+            let pos = items.iter().position(|y| y == x);
+            Ok(PscObject::IntT(pos.map_or(-1, |i| i as i64)))
+        }
+        ("INDEXOF", [PscObject::StringT(s), PscObject::StringT(x)]) => {
+            // Position in characters rather than bytes
+            let pos = s.find(x.as_str()).map(|i| s[..i].chars().count());
+            Ok(PscObject::IntT(pos.map_or(-1, |i| i as i64)))
+        }
+
+        ("COUNT", [PscObject::ArrayT(items), x]) => {
+            Ok(PscObject::IntT(items.iter().filter(|y| *y == x).count() as i64))
+        }
+        ("COUNT", [PscObject::StringT(s), PscObject::StringT(x)]) if !x.is_empty() => {
+            Ok(PscObject::IntT(s.matches(x.as_str()).count() as i64))
+        }
+        ("COUNT", [PscObject::StringT(_), PscObject::StringT(_)]) => Err(RuntimeError {
+            msg: "COUNT expects a non-empty string to count".into(),
+            span: None,
+            code: Some("E031"),
+        }),
+
+        // `MAX(A)` looks inside a single array, `MAX(X, Y, Z)` compares the
+        // arguments themselves
+        ("MIN" | "MAX", [PscObject::ArrayT(items)]) => extreme(name, items),
+        ("MIN" | "MAX", [_, ..]) => extreme(name, &args),
+
+        ("SUM", [PscObject::ArrayT(items)]) => sum(items, env.bignum),
+        ("AVERAGE", [PscObject::ArrayT(items)]) if !items.is_empty() => match sum(items, env.bignum)? {
+            PscObject::IntT(x) => Ok(PscObject::FloatT(x as f64 / items.len() as f64)),
+            PscObject::FloatT(x) => Ok(PscObject::FloatT(x / items.len() as f64)),
+            PscObject::BigIntT(x) => Ok(PscObject::FloatT(x.to_f64() / items.len() as f64)),
+            _ => unreachable!(),
+        },
+        ("AVERAGE", [PscObject::ArrayT(_)]) => Err(RuntimeError {
+            msg: "AVERAGE of an empty array".into(),
+            span: None,
+            code: Some("E024"),
+        }),
+
+        ("TRIM", [PscObject::StringT(s)]) => Ok(PscObject::StringT(s.trim().to_string())),
+        ("REPLACE", [PscObject::StringT(s), PscObject::StringT(from), PscObject::StringT(to)]) if !from.is_empty() => {
+            Ok(PscObject::StringT(s.replace(from.as_str(), to)))
+        }
+        ("REPLACE", [PscObject::StringT(_), PscObject::StringT(_), PscObject::StringT(_)]) => Err(RuntimeError {
+            msg: "REPLACE expects a non-empty string to replace".into(),
+            span: None,
+            code: Some("E031"),
+        }),
+        // An empty separator splits into single characters
+        ("SPLIT", [PscObject::StringT(s), PscObject::StringT(sep)]) => {
+            let parts: Vec<PscObject> = if sep.is_empty() {
+                s.chars().map(|c| PscObject::StringT(c.to_string())).collect()
+            } else {
+                s.split(sep.as_str()).map(|x| PscObject::StringT(x.to_string())).collect()
+            };
+            Ok(PscObject::ArrayT(parts))
+        }
+        ("JOIN", [PscObject::ArrayT(items), PscObject::StringT(sep)]) => {
+            let parts: Vec<String> = items.iter().map(|x| x.to_string()).collect();
+            Ok(PscObject::StringT(parts.join(sep)))
+        }
+
+        ("CONTAINS", [PscObject::StringT(s), PscObject::StringT(x)]) => Ok(PscObject::BoolT(s.contains(x.as_str()))),
+        ("STARTSWITH", [PscObject::StringT(s), PscObject::StringT(x)]) => {
+            Ok(PscObject::BoolT(s.starts_with(x.as_str())))
+        }
+        ("ENDSWITH", [PscObject::StringT(s), PscObject::StringT(x)]) => Ok(PscObject::BoolT(s.ends_with(x.as_str()))),
+
+        // Patterns match anywhere in the string unless anchored with ^ and $
+        #[cfg(feature = "regex")]
+        ("MATCHES", [PscObject::StringT(s), PscObject::StringT(pattern)]) => {
+            Ok(PscObject::BoolT(Regex::new(pattern)?.is_match(s)))
+        }
+        #[cfg(feature = "regex")]
+        ("MATCHGROUPS", [PscObject::StringT(s), PscObject::StringT(pattern)]) => match Regex::new(pattern)?.captures(s) {
+            Some(groups) => Ok(PscObject::ArrayT(
+                groups.into_iter().map(|x| x.map_or(PscObject::NullT, PscObject::StringT)).collect(),
+            )),
+            None => Ok(PscObject::NullT),
+        },
+        #[cfg(not(feature = "regex"))]
+        ("MATCHES" | "MATCHGROUPS", _) => Err(RuntimeError {
+            msg: format!("{} needs psc to be built with the regex feature", name),
+            span: None,
+            code: Some("E030"),
+        }),
+
+        // A real in [0, 1), or an integer between the bounds inclusive
+        ("RANDOM", []) => Ok(PscObject::FloatT((env.random() >> 11) as f64 / (1u64 << 53) as f64)),
+        ("RANDOM", [PscObject::IntT(lo), PscObject::IntT(hi)]) if lo <= hi => {
+            let span = hi.abs_diff(*lo) as u128 + 1;
+            Ok(PscObject::IntT((*lo as i128 + (env.random() as u128 % span) as i128) as i64))
+        }
+        ("RANDOM", [PscObject::IntT(_), PscObject::IntT(_)]) => Err(RuntimeError {
+            msg: "RANDOM lower bound is above upper bound".into(),
+            span: None,
+            code: Some("E015"),
+        }),
+        ("SETSEED", [PscObject::IntT(seed)]) => {
+            env.seed(*seed as u64);
+            Ok(PscObject::NullT)
+        }
+
+        ("SLEEP" | "WAIT", [PscObject::IntT(ms)]) if *ms >= 0 => {
+            let wake = Instant::now() + Duration::from_millis(*ms as u64);
+
+            // Cut short by the time limit rather than sleeping past it
+            match env.deadline {
+                Some(deadline) if deadline < wake => {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    Err(env.time_out())
+                }
+                _ => {
+                    thread::sleep(wake.saturating_duration_since(Instant::now()));
+                    Ok(PscObject::NullT)
+                }
+            }
+        }
+
+        ("ARGS", []) => Ok(PscObject::ArrayT(
+            env.args.iter().map(|x| PscObject::StringT(x.clone())).collect(),
+        )),
+
+        ("GETENV", [PscObject::StringT(name)]) => match std::env::var(name) {
+            Ok(x) => Ok(PscObject::StringT(x)),
+            Err(_) => Ok(PscObject::NullT),
+        },
+
+        // Milliseconds since 1970, and the date in UTC like `2024-05-31`
+        ("NOW", []) => Ok(PscObject::IntT(env.now())),
+        ("TODAY", []) => Ok(PscObject::StringT(date(env.now()))),
+
+        _ => Err(misuse(name, &args)),
+    }
+}
+
+/// Why a call none of the arms of `call` take is wrong: the wrong number
+/// of arguments, the wrong kinds of them, or a function that isn't there
+fn misuse(name: &str, args: &[PscObject]) -> RuntimeError {
+    // How many arguments each builtin takes, any number where it's empty
+    let (counts, takes): (&[usize], &str) = match name {
+        "NEW" => (&[1], "one value"),
+        "LENGTH" => (&[1], "a string, array, set or map"),
+        "INDEXOF" => (&[2], "an array and an item, or two strings"),
+        "COUNT" => (&[2], "an array and an item, or two strings"),
+        "MIN" | "MAX" => (&[], "an array or at least one value"),
+        "SUM" | "AVERAGE" => (&[1], "an array"),
+        "TRIM" => (&[1], "a string"),
+        "REPLACE" => (&[3], "three strings"),
+        "SPLIT" => (&[2], "two strings"),
+        "JOIN" => (&[2], "an array and a string"),
+        "CONTAINS" | "STARTSWITH" | "ENDSWITH" => (&[2], "two strings"),
+        "MATCHES" | "MATCHGROUPS" => (&[2], "a string and a pattern"),
+        "RANDOM" => (&[0, 2], "no arguments or two integers"),
+        "SETSEED" => (&[1], "an integer"),
+        "SLEEP" | "WAIT" => (&[1], "a non-negative number of milliseconds"),
+        "ARGS" | "NOW" | "TODAY" => (&[0], "no arguments"),
+        "GETENV" => (&[1], "a string"),
+        _ => {
+            return RuntimeError {
+                msg: format!("Unknown function {} with {} argument(s)", name, args.len()),
+                span: None,
+                code: Some("E002"),
+            }
+        }
+    };
+
+    let (msg, code) = if counts.is_empty() || counts.contains(&args.len()) {
+        (format!("{} expects {}", name, takes), "E031")
+    } else {
+        let counts: Vec<String> = counts.iter().map(|x| x.to_string()).collect();
+        (format!("{} expects {} argument(s) but got {}", name, counts.join(" or "), args.len()), "E002")
+    };
+    RuntimeError { msg, span: None, code: Some(code) }
+}
+
+/// The day `ms` milliseconds after 1970 began is in, as `YYYY-MM-DD`
+fn date(ms: i64) -> String {
+    // Howard Hinnant's civil_from_days, counting years from March so the
+    // leap day is last
+    let z = ms.div_euclid(86_400_000) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn compare(l: &PscObject, r: &PscObject) -> Result<Ordering, RuntimeError> {
+    let ord = match (l, r) {
+        (PscObject::IntT(l), PscObject::IntT(r)) => Some(l.cmp(r)),
+        (PscObject::FloatT(l), PscObject::FloatT(r)) => l.partial_cmp(r),
+        (PscObject::IntT(l), PscObject::FloatT(r)) => (*l as f64).partial_cmp(r),
+        (PscObject::FloatT(l), PscObject::IntT(r)) => l.partial_cmp(&(*r as f64)),
+        (PscObject::BigIntT(l), PscObject::BigIntT(r)) => Some(l.cmp(r)),
+        (PscObject::BigIntT(l), PscObject::IntT(r)) => Some(l.cmp(&BigInt::from(*r))),
+        (PscObject::IntT(l), PscObject::BigIntT(r)) => Some(BigInt::from(*l).cmp(r)),
+        (PscObject::BigIntT(l), PscObject::FloatT(r)) => l.to_f64().partial_cmp(r),
+        (PscObject::FloatT(l), PscObject::BigIntT(r)) => l.partial_cmp(&r.to_f64()),
+        (PscObject::StringT(l), PscObject::StringT(r)) => Some(l.cmp(r)),
+        _ => None,
+    };
+
+    match ord {
+        Some(x) => Ok(x),
+        None => Err(RuntimeError {
+            msg: format!("Cannot compare {} with {}", l.type_name(), r.type_name()),
+            span: None,
+            code: Some("E014"),
+        }),
+    }
+}
+
+/// Stays an integer unless one of the items is real, growing into a big
+/// integer with `--bignum`
+fn sum(items: &[PscObject], bignum: bool) -> Result<PscObject, RuntimeError> {
+    let mut ret = PscObject::IntT(0);
+
+    for x in items {
+        ret = match (ret, x) {
+            (PscObject::IntT(l), PscObject::IntT(r)) => match l.checked_add(*r) {
+                Some(x) => PscObject::IntT(x),
+                None if bignum => big_int_op(&BinaryOp::Plus, BigInt::from(l), BigInt::from(*r))?,
+                None => {
+                    return Err(RuntimeError {
+                        msg: "Integer overflow in SUM".into(),
+                        span: None,
+                        code: Some("E018"),
+                    })
+                }
+            },
+            (PscObject::IntT(l), PscObject::FloatT(r)) => PscObject::FloatT(l as f64 + r),
+            (PscObject::FloatT(l), PscObject::IntT(r)) => PscObject::FloatT(l + *r as f64),
+            (PscObject::FloatT(l), PscObject::FloatT(r)) => PscObject::FloatT(l + r),
+            (PscObject::BigIntT(l), PscObject::FloatT(r)) => PscObject::FloatT(l.to_f64() + r),
+            (PscObject::FloatT(l), PscObject::BigIntT(r)) => PscObject::FloatT(l + r.to_f64()),
+            (l, r) => match (as_big(&l), as_big(r)) {
+                (Some(l), Some(r)) => big_int_op(&BinaryOp::Plus, l, r)?,
+                _ => {
+                    return Err(RuntimeError {
+                        msg: format!("Cannot add up {}", r.type_name()),
+                        span: None,
+                        code: Some("E014"),
+                    })
+                }
+            },
+        };
+    }
+
+    Ok(ret)
+}
+
+/// The smallest item for `MIN`, the largest for `MAX`, the first one on ties
+fn extreme(name: &str, items: &[PscObject]) -> Result<PscObject, RuntimeError> {
+    let want = if name == "MIN" { Ordering::Less } else { Ordering::Greater };
+
+    let mut it = items.iter();
+    let mut best = match it.next() {
+        Some(x) => x,
+        None => {
+            return Err(RuntimeError {
+                msg: format!("{} of an empty array", name),
+                span: None,
+                code: Some("E024"),
+            })
+        }
+    };
+
+    for x in it {
+        if compare(x, best)? == want {
+            best = x;
+        }
+    }
+
+    Ok(best.clone())
+}
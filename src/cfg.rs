@@ -0,0 +1,166 @@
+use psc::eval::{Expr, Stmt, StmtKind};
+
+/// The node every control-flow graph starts at
+pub const ENTRY: usize = 0;
+/// The node a graph ends at, from the last statement, `return` or `halt`
+pub const EXIT: usize = 1;
+
+pub enum Node<'a> {
+    Entry,
+    Exit,
+    /// Statements that run one after another without branching
+    Block(Vec<&'a Stmt>),
+    /// A statement that decides where to go next, an `if` condition or the
+    /// check at the top or bottom of a loop, with how it reads
+    Branch(String),
+}
+
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Which way a branch went, empty for falling through
+    pub label: &'static str,
+}
+
+/// How control can move between the statements of one body of code.
+/// Nested procedures and classes aren't part of it, they get graphs of
+/// their own
+pub struct Cfg<'a> {
+    pub nodes: Vec<Node<'a>>,
+    pub edges: Vec<Edge>,
+}
+
+/// Edges still to be joined to whatever node comes next
+type Open = Vec<(usize, &'static str)>;
+
+struct Builder<'a> {
+    cfg: Cfg<'a>,
+    /// The next statement has to start a block of its own, since something
+    /// jumps back to it
+    fresh: bool,
+}
+
+impl<'a> Builder<'a> {
+    fn link(&mut self, from: Open, to: usize) {
+        for (node, label) in from {
+            self.cfg.edges.push(Edge { from: node, to, label });
+        }
+    }
+
+    fn node(&mut self, node: Node<'a>, from: Open) -> usize {
+        self.cfg.nodes.push(node);
+        let ret = self.cfg.nodes.len() - 1;
+        self.link(from, ret);
+        self.fresh = false;
+        ret
+    }
+
+    fn stmts(&mut self, stmts: &'a [Stmt], mut from: Open) -> Open {
+        for stmt in stmts {
+            from = self.stmt(stmt, from);
+        }
+        from
+    }
+
+    /// Adds a statement that doesn't branch, to the block before it where
+    /// nothing else leads in between
+    fn simple(&mut self, stmt: &'a Stmt, from: Open) -> Open {
+        if let ([(node, "")], false) = (from.as_slice(), self.fresh) {
+            if let Node::Block(stmts) = &mut self.cfg.nodes[*node] {
+                stmts.push(stmt);
+                return from;
+            }
+        }
+        let node = self.node(Node::Block(vec![stmt]), from);
+        vec![(node, "")]
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt, from: Open) -> Open {
+        match &stmt.kind {
+            StmtKind::If(x) => {
+                let mut from = from;
+                let mut ret = Vec::new();
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    if let (Expr::BoolLit(true), true) = (cond, i == x.branches.len() - 1) {
+                        ret.extend(self.stmts(stmts, from));
+                        return ret;
+                    }
+                    let node = self.node(Node::Branch(format!("if {}", cond)), from);
+                    ret.extend(self.stmts(stmts, vec![(node, "true")]));
+                    from = vec![(node, "false")];
+                }
+                ret.extend(from);
+                ret
+            }
+            StmtKind::While(x) => self.top_checked(format!("while {}", x.cond), &x.stmts, from, ("true", "false")),
+            StmtKind::Until(x) => self.top_checked(format!("until {}", x.cond), &x.stmts, from, ("false", "true")),
+            StmtKind::For(x) => {
+                let text = format!("loop {} from {} to {}", x.name, x.start, x.end);
+                self.top_checked(text, &x.stmts, from, ("next", "done"))
+            }
+            StmtKind::ForEach(x) => {
+                let text = format!("loop {} over {}", x.name, x.iter);
+                self.top_checked(text, &x.stmts, from, ("next", "done"))
+            }
+            StmtKind::Repeat(x) => {
+                let start = self.cfg.nodes.len();
+                self.fresh = true;
+                let body = self.stmts(&x.stmts, from);
+                let check = self.node(Node::Branch(format!("until {}", x.cond)), body);
+                self.link(vec![(check, "false")], start);
+                vec![(check, "true")]
+            }
+            StmtKind::Attempt(x) => {
+                let text = match &x.name {
+                    Some(name) => format!("attempt, on error {}", name),
+                    None => "attempt".into(),
+                };
+                let node = self.node(Node::Branch(text), from);
+                let mut ret = self.stmts(&x.stmts, vec![(node, "")]);
+                ret.extend(self.stmts(&x.handler, vec![(node, "error")]));
+                ret
+            }
+            StmtKind::Return(_) | StmtKind::Halt(_) => {
+                let from = self.simple(stmt, from);
+                self.link(from, EXIT);
+                Vec::new()
+            }
+            StmtKind::Function(_) | StmtKind::Class(_) | StmtKind::Include(_) => from,
+            _ => self.simple(stmt, from),
+        }
+    }
+
+    /// A loop that checks before each run of the body, going `ways.0` into
+    /// the body and `ways.1` out of the loop
+    fn top_checked(&mut self, text: String, stmts: &'a [Stmt], from: Open, ways: (&'static str, &'static str)) -> Open {
+        let check = self.node(Node::Branch(text), from);
+        let body = self.stmts(stmts, vec![(check, ways.0)]);
+        self.link(body, check);
+        vec![(check, ways.1)]
+    }
+}
+
+impl<'a> Cfg<'a> {
+    pub fn build(stmts: &'a [Stmt]) -> Cfg<'a> {
+        let mut builder = Builder {
+            cfg: Cfg {
+                nodes: vec![Node::Entry, Node::Exit],
+                edges: Vec::new(),
+            },
+            fresh: false,
+        };
+        let end = builder.stmts(stmts, vec![(ENTRY, "")]);
+        builder.link(end, EXIT);
+        builder.cfg
+    }
+
+    /// How `node` reads, one line per statement
+    pub fn text(&self, node: usize) -> String {
+        match &self.nodes[node] {
+            Node::Entry => "entry".into(),
+            Node::Exit => "exit".into(),
+            Node::Block(stmts) => stmts.iter().map(|x| x.to_string()).collect::<Vec<_>>().join("\n"),
+            Node::Branch(text) => text.clone(),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+/// What a flag takes after it
+pub enum Value {
+    None,
+    File,
+    Dir,
+    /// One of a fixed set of words
+    Choice(&'static [&'static str]),
+    /// The code or name of a warning, or `all`
+    Warning,
+    /// Anything, described by the name shown for it
+    Other(&'static str),
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    pub value: Value,
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub flags: &'static [Flag],
+    /// Takes `--naming` and `--dialect`
+    pub lex: bool,
+    /// What goes after the flags, usually files of programs
+    pub args: Value,
+}
+
+const fn flag(name: &'static str, value: Value, help: &'static str) -> Flag {
+    Flag { name, value, help }
+}
+
+/// What `psc completions` writes scripts for
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+pub const LEX_FLAGS: &[Flag] = &[
+    flag("--naming", Value::Choice(&["upper", "camel", "any"]), "How identifiers have to be written"),
+    flag("--dialect", Value::Choice(&["ib", "cambridge", "edexcel", "aqa"]), "Which exam board's pseudocode to accept"),
+];
+
+pub const WARNING_FLAGS: &[Flag] = &[
+    flag("-W", Value::Warning, "Show a warning"),
+    flag("-A", Value::Warning, "Hide a warning"),
+    flag("-D", Value::Warning, "Make a warning an error"),
+];
+
+/// What `psc FILE` and `psc run FILE` take
+pub const RUN_FLAGS: &[Flag] = &[
+    flag("-e", Value::Other("CODE"), "Run CODE instead of a file"),
+    flag("--strict-input", Value::None, "Fail on input of the wrong type instead of asking again"),
+    flag("--bignum", Value::None, "Switch to big integers instead of overflowing"),
+    flag("--jit", Value::None, "Compile hot loops and functions to native code"),
+    flag("--emit-ast", Value::None, "Print the parsed program as JSON, before folding"),
+    flag("--emit-tokens", Value::None, "Print the tokens of the program"),
+    flag("--check", Value::None, "Only parse the files, reporting errors and warnings"),
+    flag("--typecheck", Value::None, "Reject type errors before running"),
+    flag("--no-opt", Value::None, "Don't fold constant expressions"),
+    flag("--explain", Value::Other("CODE"), "Describe an error code"),
+    flag("--deterministic", Value::None, "Seed RANDOM, stop NOW() and TODAY() at 1970 and round floats"),
+    flag("--step", Value::None, "Pause before each statement"),
+    flag("--profile", Value::None, "Report where the time went"),
+    flag("--trace", Value::None, "Print each statement as it runs"),
+    flag("--trace-file", Value::File, "Write the trace to a file"),
+    flag("--seed", Value::Other("N"), "Seed RANDOM"),
+    flag("--max-steps", Value::Other("N"), "Stop after this many statements"),
+    flag("--max-memory", Value::Other("SIZE"), "Stop once the program uses this much memory"),
+    flag("--timeout", Value::Other("TIME"), "Stop after this long"),
+    flag("--record", Value::File, "Save a session to replay"),
+    flag("--replay", Value::File, "Run a recorded session again"),
+    flag("--stdin", Value::File, "Read input from a file"),
+    flag("--out", Value::File, "Write output to a file"),
+    flag("--tee", Value::File, "Write a copy of the output to a file"),
+    flag("--expect", Value::File, "Compare the output with a file"),
+    flag("--help", Value::None, "Show what psc can do"),
+    flag("--version", Value::None, "Show the version of psc"),
+];
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "run",
+        help: "Run a program",
+        flags: RUN_FLAGS,
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "check",
+        help: "Report errors and warnings in programs without running them",
+        flags: &[flag("--typecheck", Value::None, "Report type errors as well")],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "repl",
+        help: "Start an interactive session",
+        flags: &[],
+        lex: true,
+        args: Value::None,
+    },
+    Command {
+        name: "fmt",
+        help: "Format programs in place",
+        flags: &[flag("--check", Value::None, "Only list the files that would change")],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "lint",
+        help: "Print warnings about programs",
+        flags: WARNING_FLAGS,
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "lsp",
+        help: "Start a language server",
+        flags: &[],
+        lex: true,
+        args: Value::None,
+    },
+    Command {
+        name: "highlight",
+        help: "Write a program as highlighted HTML",
+        flags: &[flag("-o", Value::File, "Write to a file"), flag("--ansi", Value::None, "Colour for a terminal instead")],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "dap",
+        help: "Start a debug adapter",
+        flags: &[],
+        lex: true,
+        args: Value::None,
+    },
+    Command {
+        name: "debug",
+        help: "Run a program under the debugger",
+        flags: &[],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "bench",
+        help: "Time a program over many runs",
+        flags: &[flag("--iterations", Value::Other("N"), "How many times to run it"), flag("--input", Value::File, "Input for each run")],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "doc",
+        help: "Write documentation for programs",
+        flags: &[],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "grade",
+        help: "Run submissions against test cases",
+        flags: &[
+            flag("--tests", Value::Dir, "The folder of test cases"),
+            flag("--time-limit", Value::Other("MS"), "Time allowed for each run"),
+            flag("--step-limit", Value::Other("N"), "Statements allowed for each run"),
+            flag("--json", Value::None, "Report everything as JSON"),
+        ],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "graph",
+        help: "Draw the call or control-flow graph of a program",
+        flags: &[
+            flag("--calls", Value::None, "The call graph"),
+            flag("--cfg", Value::None, "The control-flow graph of each procedure"),
+            flag("--mermaid", Value::None, "As a Mermaid flowchart instead of DOT"),
+        ],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "fuzz",
+        help: "Look for programs that crash the interpreter",
+        flags: &[
+            flag("--seed", Value::Other("N"), "Seed of the first program"),
+            flag("--runs", Value::Other("N"), "How many programs to try"),
+            flag("--size", Value::Other("N"), "How big to make them"),
+        ],
+        lex: false,
+        args: Value::None,
+    },
+    Command {
+        name: "test",
+        help: "Run the tests under a folder",
+        flags: &[flag("--fixtures", Value::None, "Compare programs with their .expected output instead")],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "transpile",
+        help: "Write a program in another language",
+        flags: &[
+            flag("--target", Value::Choice(&["js", "rust", "c"]), "The language to write"),
+            flag("-o", Value::File, "Write to a file"),
+            flag("--build", Value::Dir, "Compile the Rust output in a folder"),
+        ],
+        lex: true,
+        args: Value::File,
+    },
+    Command {
+        name: "completions",
+        help: "Print a shell completion script",
+        flags: &[],
+        lex: false,
+        args: Value::Choice(SHELLS),
+    },
+];
+
+impl Command {
+    /// Every flag it takes, its own and the shared ones
+    pub fn all_flags(&self) -> impl Iterator<Item = &'static Flag> {
+        let lex = if self.lex { LEX_FLAGS } else { &[] };
+        // Warnings are shown after a run, so it takes them too
+        let warnings = if matches!(self.name, "run" | "check") { WARNING_FLAGS } else { &[] };
+        self.flags.iter().chain(lex).chain(warnings)
+    }
+
+    fn usage(&self) -> String {
+        let args = match &self.args {
+            Value::File => " FILE...".to_string(),
+            Value::Choice(x) => format!(" {}", x.join("|")),
+            _ => String::new(),
+        };
+        format!("psc {} [OPTIONS]{}", self.name, args)
+    }
+}
+
+impl Flag {
+    /// How it's written with what it takes, like `--seed N`
+    fn usage(&self) -> String {
+        let value = match &self.value {
+            Value::None => return self.name.to_string(),
+            Value::File => "FILE".to_string(),
+            Value::Dir => "DIR".to_string(),
+            Value::Choice(x) => x.join("|"),
+            Value::Warning => "WARNING".to_string(),
+            Value::Other(x) => x.to_string(),
+        };
+        format!("{} {}", self.name, value)
+    }
+}
+
+/// Lines of a name and what it is, with the descriptions lined up
+fn table(rows: &[(String, &str)]) -> String {
+    let width = rows.iter().map(|x| x.0.len()).max().unwrap_or(0);
+    rows.iter().map(|(name, help)| format!("  {:width$}  {}\n", name, help, width = width)).collect()
+}
+
+/// What `--help` shows, for psc itself or one of its commands
+pub fn help(command: Option<&Command>) -> String {
+    let flags = |command: &Command| {
+        let rows: Vec<(String, &str)> = command.all_flags().map(|x| (x.usage(), x.help)).collect();
+        table(&rows)
+    };
+
+    if let Some(command) = command {
+        return format!("{}\n\nUsage: {}\n\nOptions:\n{}", command.help, command.usage(), flags(command));
+    }
+
+    let commands: Vec<(String, &str)> = COMMANDS.iter().map(|x| (x.name.to_string(), x.help)).collect();
+    let run = COMMANDS.iter().find(|x| x.name == "run").expect("run is a command");
+    let mut ret = String::from("Runs pseudocode programs\n\n");
+    ret += "Usage: psc [OPTIONS] FILE [ARGS]...\n";
+    ret += "       psc [OPTIONS] -e CODE [ARGS]...\n";
+    ret += "       psc [OPTIONS] - [ARGS]...   (the program from stdin)\n";
+    ret += "       psc COMMAND [OPTIONS] ...\n\n";
+    ret += &format!("Commands:\n{}\n", table(&commands));
+    ret += &format!("Options:\n{}\n", flags(run));
+    ret += "See psc COMMAND --help for the options of a command\n";
+    ret
+}
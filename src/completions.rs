@@ -0,0 +1,200 @@
+use crate::cli::{Command, Flag, Value, COMMANDS, SHELLS};
+use crate::lint::WARNINGS;
+use std::error::Error;
+
+/// The words a value can be, when it has to be one of them
+fn choices(value: &Value) -> Option<Vec<&'static str>> {
+    match value {
+        Value::Choice(x) => Some(x.to_vec()),
+        Value::Warning => Some(WARNINGS.iter().flat_map(|(code, name)| [*code, *name]).chain(["all"]).collect()),
+        _ => None,
+    }
+}
+
+/// Every flag of every command, with the command's name
+fn flags() -> impl Iterator<Item = (&'static str, &'static Flag)> {
+    COMMANDS.iter().flat_map(|command| command.all_flags().map(move |flag| (command.name, flag)))
+}
+
+fn names(command: &Command) -> String {
+    command.all_flags().map(|x| x.name).collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    let commands: Vec<&str> = COMMANDS.iter().map(|x| x.name).collect();
+    let mut out = String::from("_psc() {\n");
+    out += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\" cmd=run\n";
+    out += &format!("    case \"${{COMP_WORDS[1]}}\" in\n        {}) cmd=\"${{COMP_WORDS[1]}}\" ;;\n    esac\n\n", commands.join("|"));
+
+    out += "    case \"$cmd $prev\" in\n";
+    for (command, flag) in flags() {
+        let reply = match (&flag.value, choices(&flag.value)) {
+            (_, Some(words)) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", words.join(" ")),
+            (Value::File, _) => "COMPREPLY=($(compgen -f -- \"$cur\"))".into(),
+            (Value::Dir, _) => "COMPREPLY=($(compgen -d -- \"$cur\"))".into(),
+            (Value::Other(_), _) => "COMPREPLY=()".into(),
+            _ => continue,
+        };
+        out += &format!("        \"{} {}\") {}; return ;;\n", command, flag.name, reply);
+    }
+    out += "    esac\n\n";
+
+    out += "    local words\n    case \"$cmd\" in\n";
+    for command in COMMANDS {
+        let mut words = names(command);
+        if let Some(args) = choices(&command.args) {
+            words += &format!(" {}", args.join(" "));
+        }
+        out += &format!("        {}) words=\"{}\" ;;\n", command.name, words);
+    }
+    out += "    esac\n";
+    // The first word can be a command, or a file to run
+    out += &format!("    [ \"$COMP_CWORD\" -eq 1 ] && words=\"$words {}\"\n\n", commands.join(" "));
+
+    let files: Vec<&str> = COMMANDS.iter().filter(|x| matches!(x.args, Value::File)).map(|x| x.name).collect();
+    out += "    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n";
+    out += &format!("    case \"$cmd\" in\n        {}) [[ \"$cur\" != -* ]] && COMPREPLY+=($(compgen -f -- \"$cur\")) ;;\n    esac\n", files.join("|"));
+    out += "}\n\ncomplete -o filenames -F _psc psc\n";
+    out
+}
+
+fn zsh() -> String {
+    let commands: Vec<&str> = COMMANDS.iter().map(|x| x.name).collect();
+    let mut out = String::from("#compdef psc\n\n_psc() {\n");
+    out += "    local cmd=run prev=${words[CURRENT-1]}\n";
+    out += &format!("    case ${{words[2]}} in\n        ({}) cmd=${{words[2]}} ;;\n    esac\n\n", commands.join("|"));
+
+    out += "    case \"$cmd $prev\" in\n";
+    for (command, flag) in flags() {
+        let reply = match (&flag.value, choices(&flag.value)) {
+            (_, Some(words)) => format!("compadd -- {}", words.join(" ")),
+            (Value::File, _) => "_files".into(),
+            (Value::Dir, _) => "_files -/".into(),
+            (Value::Other(name), _) => format!("_message {}", name),
+            _ => continue,
+        };
+        out += &format!("        (\"{} {}\") {}; return ;;\n", command, flag.name, reply);
+    }
+    out += "    esac\n\n";
+
+    out += "    local -a flags\n    case $cmd in\n";
+    for command in COMMANDS {
+        let flags: Vec<String> = command.all_flags().map(|x| format!("'{}:{}'", x.name, x.help.replace('\'', "'\\''"))).collect();
+        out += &format!("        ({}) flags=({}) ;;\n", command.name, flags.join(" "));
+    }
+    out += "    esac\n\n";
+
+    out += "    if [[ $PREFIX == -* ]]; then\n        _describe flag flags\n        return\n    fi\n";
+    let described: Vec<String> = COMMANDS.iter().map(|x| format!("'{}:{}'", x.name, x.help)).collect();
+    out += &format!("    if (( CURRENT == 2 )); then\n        local -a commands=({})\n        _describe command commands\n    fi\n", described.join(" "));
+
+    out += "    case $cmd in\n";
+    for command in COMMANDS {
+        match (&command.args, choices(&command.args)) {
+            (_, Some(words)) => out += &format!("        ({}) compadd -- {} ;;\n", command.name, words.join(" ")),
+            (Value::File, _) => out += &format!("        ({}) _files ;;\n", command.name),
+            _ => {}
+        }
+    }
+    out += "    esac\n}\n\n";
+    // Autoloaded from `fpath`, or sourced straight into a shell
+    out += "if [ \"$funcstack[1]\" = \"_psc\" ]; then\n    _psc \"$@\"\nelse\n    compdef _psc psc\nfi\n";
+    out
+}
+
+fn fish() -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut out = String::from("complete -c psc -f\n");
+
+    for command in COMMANDS {
+        out += &format!("complete -c psc -n __fish_use_subcommand -a {} -d {}\n", command.name, quote(command.help));
+    }
+
+    for command in COMMANDS {
+        // Without a command, the flags are for running a program
+        let cond = match command.name {
+            "run" => "'__fish_use_subcommand; or __fish_seen_subcommand_from run'".to_string(),
+            name => format!("'__fish_seen_subcommand_from {}'", name),
+        };
+
+        for flag in command.all_flags() {
+            let name = match flag.name.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => format!("-s {}", &flag.name[1..]),
+            };
+            let value = match (&flag.value, choices(&flag.value)) {
+                (_, Some(words)) => format!(" -x -a {}", quote(&words.join(" "))),
+                (Value::File, _) => " -r -F".into(),
+                (Value::Dir, _) => " -x -a '(__fish_complete_directories)'".into(),
+                (Value::Other(_), _) => " -x".into(),
+                _ => String::new(),
+            };
+            out += &format!("complete -c psc -n {} {}{} -d {}\n", cond, name, value, quote(flag.help));
+        }
+
+        match (&command.args, choices(&command.args)) {
+            (_, Some(words)) => out += &format!("complete -c psc -n {} -a {}\n", cond, quote(&words.join(" "))),
+            (Value::File, _) => out += &format!("complete -c psc -n {} -F\n", cond),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn powershell() -> String {
+    let list = |words: &[&str]| words.iter().map(|x| format!("'{}'", x)).collect::<Vec<_>>().join(", ");
+    let mut out = String::from("Register-ArgumentCompleter -Native -CommandName psc -ScriptBlock {\n");
+    out += "    param($wordToComplete, $commandAst, $cursorPosition)\n\n";
+
+    out += "    $commands = @{\n";
+    for command in COMMANDS {
+        let mut words: Vec<&str> = command.all_flags().map(|x| x.name).collect();
+        words.extend(choices(&command.args).unwrap_or_default());
+        out += &format!("        '{}' = @({})\n", command.name, list(&words));
+    }
+    out += "    }\n";
+
+    out += "    $values = @{\n";
+    for (command, flag) in flags() {
+        let words = match (&flag.value, choices(&flag.value)) {
+            (_, Some(words)) => words,
+            (Value::Other(_), _) => Vec::new(),
+            _ => continue,
+        };
+        out += &format!("        '{} {}' = @({})\n", command, flag.name, list(&words));
+    }
+    out += "    }\n";
+
+    // Left to the shell's own completion of paths
+    let paths: Vec<String> = flags().filter(|(_, x)| matches!(x.value, Value::File | Value::Dir)).map(|(command, x)| format!("{} {}", command, x.name)).collect();
+    let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+    out += &format!("    $paths = @({})\n\n", list(&paths));
+
+    out += "    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n";
+    out += "    $prev = if ($wordToComplete) { $words[-2] } else { $words[-1] }\n";
+    out += "    $cmd = 'run'\n";
+    out += "    if ($words.Count -gt 1 -and $commands.ContainsKey($words[1]) -and $words[1] -ne $wordToComplete) { $cmd = $words[1] }\n\n";
+
+    out += "    if ($paths -contains \"$cmd $prev\") { return }\n";
+    out += "    if ($values.ContainsKey(\"$cmd $prev\")) {\n        $candidates = $values[\"$cmd $prev\"]\n    } else {\n";
+    out += "        $candidates = $commands[$cmd]\n";
+    out += "        if ($words.Count -le 2 -and $cmd -eq 'run') { $candidates += $commands.Keys }\n    }\n";
+    out += "    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | Sort-Object | ForEach-Object {\n";
+    out += "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n";
+    out
+}
+
+/// `psc completions bash|zsh|fish|powershell` prints a script that completes
+/// the commands and flags of psc in that shell, from their definitions in
+/// `cli`
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let script = match args.next().as_deref() {
+        Some("bash") => bash(),
+        Some("zsh") => zsh(),
+        Some("fish") => fish(),
+        Some("powershell") => powershell(),
+        _ => return Err(format!("completions expects one of {}", SHELLS.join(", ")).into()),
+    };
+    print!("{}", script);
+    Ok(())
+}
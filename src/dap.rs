@@ -0,0 +1,550 @@
+use psc::env::{Env, Hook};
+use psc::err::RuntimeError;
+use psc::eval::{Expr, Stmt};
+use psc::json::Json;
+use psc::lex::{self, LexOptions};
+use crate::lsp::{read_message, send};
+use psc::{load, parse};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Sequence number of the next message sent
+static SEQ: AtomicI64 = AtomicI64::new(1);
+
+/// There is only ever the one thread running the program
+const THREAD_ID: i64 = 1;
+
+/// When the program should stop next
+#[derive(Clone, Copy)]
+enum Mode {
+    Continue,
+    /// At the very next statement
+    StepIn,
+    /// At the next statement with at most this many calls running
+    Over(usize),
+    /// Once fewer than this many calls are running
+    Out(usize),
+    Pause,
+}
+
+/// What handling a request asks of the program
+enum Action {
+    None,
+    Resume(Mode),
+    /// Stop the program, `true` when the client is going away as well
+    Stop(bool),
+}
+
+/// Debugger state shared between the request loop and the hook run before
+/// every statement
+struct Session {
+    requests: Receiver<Json>,
+    program: Option<PathBuf>,
+    breakpoints: HashSet<usize>,
+    mode: Mode,
+    disconnected: bool,
+}
+
+fn next_seq() -> Json {
+    Json::Int(SEQ.fetch_add(1, Ordering::Relaxed))
+}
+
+fn respond(request: &Json, success: bool, body: Json) -> io::Result<()> {
+    let mut msg = vec![
+        ("seq", next_seq()),
+        ("type", Json::str("response")),
+        ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+        ("success", Json::Bool(success)),
+        ("command", request.get("command").cloned().unwrap_or(Json::Null)),
+    ];
+
+    match body {
+        Json::String(message) => msg.push(("message", Json::String(message))),
+        body => msg.push(("body", body)),
+    }
+
+    send(Json::object(msg))
+}
+
+fn event(name: &str, body: Json) -> io::Result<()> {
+    send(Json::object(vec![
+        ("seq", next_seq()),
+        ("type", Json::str("event")),
+        ("event", Json::str(name)),
+        ("body", body),
+    ]))
+}
+
+fn output(category: &str, text: &str) -> io::Result<()> {
+    event(
+        "output",
+        Json::object(vec![("category", Json::str(category)), ("output", Json::str(text))]),
+    )
+}
+
+/// Sends what the program writes as `output` events, a line at a time
+struct EventWriter {
+    buf: Vec<u8>,
+}
+
+impl Write for EventWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+
+        if let Some(end) = self.buf.iter().rposition(|&x| x == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            output("stdout", &String::from_utf8_lossy(&line))?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            output("stdout", &String::from_utf8_lossy(&self.buf))?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn args(request: &Json) -> &Json {
+    request.get("arguments").unwrap_or(&Json::Null)
+}
+
+/// The frame an `evaluate` or `scopes` request is about, 0 is the innermost
+fn frame_index(request: &Json, key: &str) -> usize {
+    args(request).get(key).and_then(Json::as_i64).map_or(0, |x| (x - 1).max(0) as usize)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl Session {
+    fn source(&self) -> Json {
+        match &self.program {
+            Some(path) => Json::object(vec![
+                (
+                    "name",
+                    Json::String(path.file_name().map_or(String::new(), |x| x.to_string_lossy().to_string())),
+                ),
+                ("path", Json::String(path.display().to_string())),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn set_breakpoints(&mut self, request: &Json) -> io::Result<()> {
+        let args = args(request);
+        let path = args.get("source").and_then(|x| x.get("path")).and_then(Json::as_str);
+
+        // Only statements of the program itself are checked against breakpoints
+        let ours = match (path, &self.program) {
+            (Some(path), Some(program)) => same_file(Path::new(path), program),
+            _ => false,
+        };
+
+        let lines: Vec<i64> = match args.get("breakpoints").and_then(Json::as_array) {
+            Some(items) => items.iter().filter_map(|x| x.get("line").and_then(Json::as_i64)).collect(),
+            None => Vec::new(),
+        };
+
+        if ours {
+            self.breakpoints = lines.iter().map(|&x| x as usize).collect();
+        }
+
+        let result = lines
+            .iter()
+            .map(|&line| Json::object(vec![("verified", Json::Bool(ours)), ("line", Json::Int(line))]))
+            .collect();
+        respond(request, true, Json::object(vec![("breakpoints", Json::Array(result))]))
+    }
+
+    fn stack_trace(&self, request: &Json, env: &Env) -> io::Result<()> {
+        let frames: Vec<Json> = env
+            .backtrace()
+            .into_iter()
+            .enumerate()
+            .map(|(i, call)| {
+                Json::object(vec![
+                    ("id", Json::Int(i as i64 + 1)),
+                    ("name", Json::String(call.name)),
+                    ("line", Json::Int(call.line as i64)),
+                    ("column", Json::Int(1)),
+                    ("source", self.source()),
+                ])
+            })
+            .collect();
+
+        let total = Json::Int(frames.len() as i64);
+        respond(
+            request,
+            true,
+            Json::object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", total)]),
+        )
+    }
+
+    fn scopes(&self, request: &Json, env: &Env) -> io::Result<()> {
+        let index = frame_index(request, "frameId");
+        let name = if index + 1 == env.depth() { "Globals" } else { "Locals" };
+
+        let scope = Json::object(vec![
+            ("name", Json::str(name)),
+            ("variablesReference", Json::Int(index as i64 + 1)),
+            ("expensive", Json::Bool(false)),
+        ]);
+        respond(request, true, Json::object(vec![("scopes", Json::Array(vec![scope]))]))
+    }
+
+    fn variables(&self, request: &Json, env: &Env) -> io::Result<()> {
+        let index = frame_index(request, "variablesReference");
+        let vars = match env.backtrace().into_iter().nth(index) {
+            Some(call) => call.vars,
+            None => Vec::new(),
+        };
+
+        let vars = vars
+            .into_iter()
+            .map(|(name, val)| {
+                Json::object(vec![
+                    ("name", Json::String(name)),
+                    ("value", Json::String(val.to_string())),
+                    ("type", Json::str(val.type_name())),
+                    ("variablesReference", Json::Int(0)),
+                ])
+            })
+            .collect();
+        respond(request, true, Json::object(vec![("variables", Json::Array(vars))]))
+    }
+
+    /// Expressions are only run in the innermost call, outer ones can just
+    /// look their variables up
+    fn evaluate(&self, request: &Json, env: &mut Env) -> io::Result<()> {
+        let text = args(request).get("expression").and_then(Json::as_str).unwrap_or("").trim();
+        let index = frame_index(request, "frameId");
+
+        let res = if index == 0 {
+            let options = LexOptions {
+                dialect: env.dialect,
+                ..LexOptions::default()
+            };
+            lex::lex(text, &options)
+                .map_err(|e| e.to_string())
+                .and_then(|tokens| parse::parse_expression(tokens).map_err(|e| e.msg))
+                .and_then(|expr| Expr::eval(&expr, env).map_err(|e| e.msg))
+        } else {
+            let vars = env.backtrace().into_iter().nth(index).map_or(Vec::new(), |x| x.vars);
+            match vars.into_iter().find(|(name, _)| name == text) {
+                Some((_, val)) => Ok(val),
+                None => Err(format!("No variable {} in this frame", text)),
+            }
+        };
+
+        match res {
+            Ok(val) => respond(
+                request,
+                true,
+                Json::object(vec![
+                    ("result", Json::String(val.to_string())),
+                    ("type", Json::str(val.type_name())),
+                    ("variablesReference", Json::Int(0)),
+                ]),
+            ),
+            Err(e) => respond(request, false, Json::String(e)),
+        }
+    }
+
+    /// Handles a request, `env` is there while the program is stopped
+    fn handle(&mut self, request: &Json, env: Option<&mut Env>) -> io::Result<Action> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let depth = env.as_ref().map_or(1, |x| x.depth());
+
+        let action = match command {
+            "setBreakpoints" => {
+                self.set_breakpoints(request)?;
+                return Ok(Action::None);
+            }
+            "threads" => {
+                let thread = Json::object(vec![("id", Json::Int(THREAD_ID)), ("name", Json::str("main"))]);
+                respond(request, true, Json::object(vec![("threads", Json::Array(vec![thread]))]))?;
+                return Ok(Action::None);
+            }
+            "stackTrace" | "scopes" | "variables" | "evaluate" => {
+                match env {
+                    Some(env) => match command {
+                        "stackTrace" => self.stack_trace(request, env)?,
+                        "scopes" => self.scopes(request, env)?,
+                        "variables" => self.variables(request, env)?,
+                        _ => self.evaluate(request, env)?,
+                    },
+                    None => respond(request, false, Json::str("The program is not stopped"))?,
+                }
+                return Ok(Action::None);
+            }
+            "pause" => Action::Resume(Mode::Pause),
+            "continue" => Action::Resume(Mode::Continue),
+            "next" => Action::Resume(Mode::Over(depth)),
+            "stepIn" => Action::Resume(Mode::StepIn),
+            "stepOut" => Action::Resume(Mode::Out(depth)),
+            "terminate" => Action::Stop(false),
+            "disconnect" => Action::Stop(true),
+            "setExceptionBreakpoints" | "configurationDone" => Action::None,
+            _ => {
+                respond(request, false, Json::String(format!("Unknown request {}", command)))?;
+                return Ok(Action::None);
+            }
+        };
+
+        let body = match command {
+            "continue" => Json::object(vec![("allThreadsContinued", Json::Bool(true))]),
+            _ => Json::object(vec![]),
+        };
+        respond(request, true, body)?;
+
+        Ok(action)
+    }
+
+    fn should_stop(&self, stmt: &Stmt, env: &Env) -> Option<&'static str> {
+        match self.mode {
+            Mode::Pause => return Some("pause"),
+            Mode::StepIn => return Some("step"),
+            Mode::Over(depth) if env.depth() <= depth => return Some("step"),
+            Mode::Out(depth) if env.depth() < depth => return Some("step"),
+            _ => {}
+        }
+
+        if self.breakpoints.contains(&stmt.span.line) {
+            return Some("breakpoint");
+        }
+        None
+    }
+}
+
+/// Stops the program whenever the session asks it to
+struct Debugger(Rc<RefCell<Session>>);
+
+/// The error unwinding the program once the client stops it
+fn stopped(env: &mut Env) -> RuntimeError {
+    env.halted = Some(0);
+    RuntimeError {
+        msg: "Stopped by the debugger".into(),
+        span: None,
+        code: None,
+    }
+}
+
+impl Hook for Debugger {
+    fn before(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError> {
+        let mut session = self.0.borrow_mut();
+        let io_err = |e: io::Error| RuntimeError { msg: e.to_string(), span: None, code: None };
+
+        // Requests arriving while running, mostly `pause`
+        loop {
+            let request = match session.requests.try_recv() {
+                Ok(x) => x,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    session.disconnected = true;
+                    return Err(stopped(env));
+                }
+            };
+
+            match session.handle(&request, None).map_err(io_err)? {
+                Action::None => {}
+                Action::Resume(mode) => session.mode = mode,
+                Action::Stop(disconnect) => {
+                    session.disconnected = disconnect;
+                    return Err(stopped(env));
+                }
+            }
+        }
+
+        let reason = match session.should_stop(stmt, env) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        env.out.flush().map_err(io_err)?;
+        event(
+            "stopped",
+            Json::object(vec![("reason", Json::str(reason)), ("threadId", Json::Int(THREAD_ID))]),
+        )
+        .map_err(io_err)?;
+
+        loop {
+            let request = match session.requests.recv() {
+                Ok(x) => x,
+                Err(_) => {
+                    session.disconnected = true;
+                    return Err(stopped(env));
+                }
+            };
+
+            match session.handle(&request, Some(env)).map_err(io_err)? {
+                Action::None | Action::Resume(Mode::Pause) => {}
+                Action::Resume(mode) => {
+                    session.mode = mode;
+                    return Ok(());
+                }
+                Action::Stop(disconnect) => {
+                    session.disconnected = disconnect;
+                    return Err(stopped(env));
+                }
+            }
+        }
+    }
+}
+
+/// Sets up the environment for a `launch` request, whose arguments are the
+/// `program` to debug and optionally a file for its `stdin` and its `args`
+fn launch(request: &Json, options: &LexOptions) -> Result<(PathBuf, Vec<Stmt>, Env), String> {
+    let args = args(request);
+
+    let program = match args.get("program").and_then(Json::as_str) {
+        Some(x) => PathBuf::from(x),
+        None => return Err("launch expects a program".into()),
+    };
+    let stmts = load::load(&program, options).map_err(|e| e.to_string())?;
+
+    let mut env = Env::new();
+    env.dialect = options.dialect;
+    env.out = Box::new(EventWriter { buf: Vec::new() });
+
+    // The debugger protocol has stdin, so the program reads a file or nothing
+    env.input = Some(match args.get("stdin").and_then(Json::as_str) {
+        Some(path) => Box::new(BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?)),
+        None => Box::new(io::empty()),
+    });
+
+    if let Some(items) = args.get("args").and_then(Json::as_array) {
+        env.args = items.iter().filter_map(|x| x.as_str().map(String::from)).collect();
+    }
+
+    Ok((program, stmts, env))
+}
+
+/// `psc dap` talks the Debug Adapter Protocol over stdin and stdout,
+/// debugging one program per session
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    while let Some(arg) = args.next() {
+        if !options.parse_flag(&arg, &mut args)? {
+            return Err(format!("Unknown dap argument {}", arg).into());
+        }
+    }
+
+    // Requests are read on their own thread so `pause` gets through while
+    // the program runs
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = io::stdin().lock();
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let session = Rc::new(RefCell::new(Session {
+        requests: rx,
+        program: None,
+        breakpoints: HashSet::new(),
+        mode: Mode::Continue,
+        disconnected: false,
+    }));
+    let mut launched: Option<(Vec<Stmt>, Env)> = None;
+
+    // Configuration, up to `configurationDone`
+    loop {
+        let request = match session.borrow().requests.recv() {
+            Ok(x) => x,
+            Err(_) => return Ok(()),
+        };
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+
+        match command {
+            "initialize" => {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", Json::Bool(true)),
+                    ("supportsTerminateRequest", Json::Bool(true)),
+                ]);
+                respond(&request, true, capabilities)?;
+                event("initialized", Json::object(vec![]))?;
+            }
+            "launch" => match launch(&request, &options) {
+                Ok((program, stmts, env)) => {
+                    session.borrow_mut().program = Some(program);
+                    launched = Some((stmts, env));
+                    respond(&request, true, Json::object(vec![]))?;
+                }
+                Err(e) => respond(&request, false, Json::String(e))?,
+            },
+            _ => {
+                let action = session.borrow_mut().handle(&request, None)?;
+                match action {
+                    Action::Stop(true) => return Ok(()),
+                    Action::Stop(false) => break,
+                    Action::Resume(mode) => session.borrow_mut().mode = mode,
+                    Action::None => {}
+                }
+                if command == "configurationDone" {
+                    break;
+                }
+            }
+        }
+    }
+
+    let code = match launched {
+        Some((stmts, mut env)) => {
+            env.hook = Some(Box::new(Debugger(session.clone())));
+
+            let res = env.run(&stmts);
+            env.out.flush()?;
+            match (res, env.halted) {
+                (_, Some(code)) => code,
+                (Ok(()), None) => 0,
+                (Err(e), None) => {
+                    output("stderr", &format!("{}\n", e))?;
+                    1
+                }
+            }
+        }
+        None => 0,
+    };
+
+    if session.borrow().disconnected {
+        return Ok(());
+    }
+    event("exited", Json::object(vec![("exitCode", Json::Int(code as i64))]))?;
+    event("terminated", Json::object(vec![]))?;
+
+    // Keep answering until the client goes away
+    loop {
+        let request = match session.borrow().requests.recv() {
+            Ok(x) => x,
+            Err(_) => return Ok(()),
+        };
+
+        if let Action::Stop(true) = session.borrow_mut().handle(&request, None)? {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use psc::env::{Env, Hook};
+use psc::err::RuntimeError;
+use psc::eval::{Expr, PscObject, Stmt, StmtKind};
+use psc::lex::{self, LexOptions};
+use psc::visit::{walk_expr, Visitor};
+use psc::{load, parse};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+const HELP: &str = "\
+watch NAME     say whenever NAME changes, `watch` alone lists them
+break LINE     stop before running line LINE, `break` alone lists them
+delete LINE    remove the breakpoint on line LINE
+run            start the program
+continue       carry on until the next breakpoint
+print EXPR     show the value of EXPR where the program stopped
+backtrace      show the calls running, innermost first
+quit           stop debugging
+Commands can be shortened to their first letter, bt is backtrace";
+
+/// What a command asks of the program
+enum Action {
+    None,
+    Run,
+    Continue,
+    Quit,
+}
+
+/// Variables to report changes to, for `watch`
+#[derive(Default)]
+pub struct Watches {
+    /// Each name with the last value it was seen with
+    vars: Vec<(String, Option<String>)>,
+    /// Line of the statement running in each call, outermost first
+    lines: Vec<usize>,
+}
+
+impl Watches {
+    fn value(name: &str, env: &Env) -> Option<String> {
+        env.lookup(name).map(|x| x.borrow().repr())
+    }
+
+    /// Starts watching `name`, `env` gives its value if the program is
+    /// running
+    pub fn add(&mut self, name: &str, env: Option<&Env>) -> String {
+        if self.vars.iter().any(|(x, _)| x == name) {
+            return format!("Already watching {}", name);
+        }
+        let val = env.and_then(|env| Watches::value(name, env));
+        let ret = match &val {
+            Some(x) => format!("Watching {}, which is {}", name, x),
+            None => format!("Watching {}", name),
+        };
+        self.vars.push((name.to_string(), val));
+        ret
+    }
+
+    /// The watched variables with the values they were last seen with
+    pub fn list(&self) -> Vec<String> {
+        self.vars
+            .iter()
+            .map(|(name, val)| format!("{} = {}", name, val.as_deref().unwrap_or("unset")))
+            .collect()
+    }
+
+    /// Reports the watched variables that changed since the last
+    /// statement, then records `stmt` as running. Changes are put down to
+    /// the last statement started in this call, so `X = F(1)` gets the
+    /// blame rather than whatever ran last inside `F`. A variable out of
+    /// scope keeps its old value until it's back
+    pub fn check(&mut self, stmt: &Stmt, env: &Env) -> Vec<String> {
+        let depth = env.depth() - 1;
+        let line = self.lines.get(depth).or(self.lines.last()).copied();
+        self.lines.truncate(depth);
+        self.lines.push(stmt.span.line);
+
+        let mut ret = Vec::new();
+        for (name, old) in &mut self.vars {
+            let new = match Watches::value(name, env) {
+                Some(x) if old.as_ref() != Some(&x) => x,
+                _ => continue,
+            };
+            let at = line.map_or(String::new(), |x| format!(" at line {}", x));
+            ret.push(match old {
+                Some(old) => format!("{} changed from {} to {}{}", name, old, new, at),
+                None => format!("{} set to {}{}", name, new, at),
+            });
+            *old = Some(new);
+        }
+        ret
+    }
+}
+
+/// Debugger state shared between `main` and the hook run before every
+/// statement
+struct Session {
+    /// Lines of the program, for showing where it stopped
+    source: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    watches: Watches,
+    quit: bool,
+}
+
+/// The next command, `None` at the end of input
+fn read_command() -> io::Result<Option<String>> {
+    print!("(psc) ");
+    io::stdout().flush()?;
+
+    let mut buf = String::new();
+    match io::stdin().read_line(&mut buf)? {
+        0 => {
+            println!();
+            Ok(None)
+        }
+        _ => Ok(Some(buf.trim().to_string())),
+    }
+}
+
+/// Runs `text` as an expression in the innermost call
+fn evaluate(text: &str, env: &mut Env) -> Result<String, String> {
+    let options = LexOptions {
+        dialect: env.dialect,
+        ..LexOptions::default()
+    };
+    lex::lex(text, &options)
+        .map_err(|e| e.to_string())
+        .and_then(|tokens| parse::parse_expression(tokens).map_err(|e| e.msg))
+        .and_then(|expr| Expr::eval(&expr, env).map_err(|e| e.msg))
+        .map(|val| val.to_string())
+}
+
+/// Line `line` of `source` without its indentation
+fn source_line(source: &[String], line: usize) -> &str {
+    source.get(line.wrapping_sub(1)).map_or("", |x| x.trim())
+}
+
+/// The lines of `file`, for showing statements
+fn read_source(file: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let source = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+    Ok(source.lines().map(String::from).collect())
+}
+
+impl Session {
+    fn line(&self, line: usize) -> &str {
+        source_line(&self.source, line)
+    }
+
+    fn breakpoint(&mut self, arg: &str, add: bool) {
+        let line = match arg.parse::<usize>() {
+            Ok(x) if x >= 1 && x <= self.source.len() => x,
+            _ => {
+                println!("There's no line {}", arg);
+                return;
+            }
+        };
+
+        if add {
+            self.breakpoints.insert(line);
+            println!("Breakpoint at line {}: {}", line, self.line(line));
+        } else if self.breakpoints.remove(&line) {
+            println!("Removed the breakpoint at line {}", line);
+        } else {
+            println!("There's no breakpoint at line {}", line);
+        }
+    }
+
+    /// Handles a command, `env` is there while the program is stopped
+    fn command(&mut self, text: &str, env: Option<&mut Env>) -> Action {
+        let (name, arg) = match text.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (text, ""),
+        };
+
+        match (name, env) {
+            ("", _) => {}
+            ("b" | "break", _) if arg.is_empty() => {
+                if self.breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for line in &self.breakpoints {
+                    println!("Breakpoint at line {}: {}", line, self.line(*line));
+                }
+            }
+            ("b" | "break", _) => self.breakpoint(arg, true),
+            ("w" | "watch", _) if arg.is_empty() => {
+                if self.watches.vars.is_empty() {
+                    println!("No watches");
+                }
+                self.watches.list().iter().for_each(|x| println!("{}", x));
+            }
+            ("w" | "watch", env) => println!("{}", self.watches.add(arg, env.as_deref())),
+            ("d" | "delete", _) => self.breakpoint(arg, false),
+            ("r" | "run", None) => return Action::Run,
+            ("r" | "run", Some(_)) => println!("The program is already running, carry on with continue"),
+            ("c" | "continue", Some(_)) => return Action::Continue,
+            ("p" | "print", Some(env)) => match evaluate(arg, env) {
+                Ok(x) => println!("{}", x),
+                Err(e) => println!("Error: {}", e),
+            },
+            ("bt" | "backtrace", Some(env)) => {
+                for (i, call) in env.backtrace().iter().enumerate() {
+                    println!("#{} {} at line {}: {}", i, call.name, call.line, self.line(call.line));
+                }
+            }
+            ("c" | "continue" | "p" | "print" | "bt" | "backtrace", None) => {
+                println!("The program isn't running, start it with run")
+            }
+            ("q" | "quit", _) => return Action::Quit,
+            ("h" | "help", _) => println!("{}", HELP),
+            _ => println!("Unknown command {}, type help for the commands", name),
+        }
+
+        Action::None
+    }
+}
+
+/// Stops the program at breakpoints and takes commands until it's told to
+/// carry on
+struct Debugger(Rc<RefCell<Session>>);
+
+impl Hook for Debugger {
+    fn before(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError> {
+        let mut session = self.0.borrow_mut();
+        let changes = session.watches.check(stmt, env);
+        if !changes.is_empty() {
+            env.out.flush().map_err(|e| RuntimeError { msg: e.to_string(), span: None, code: None })?;
+            changes.iter().for_each(|x| println!("{}", x));
+        }
+        if !session.breakpoints.contains(&stmt.span.line) {
+            return Ok(());
+        }
+
+        let io_err = |e: io::Error| RuntimeError { msg: e.to_string(), span: None, code: None };
+        env.out.flush().map_err(io_err)?;
+        println!("Stopped at line {}: {}", stmt.span.line, session.line(stmt.span.line));
+
+        loop {
+            let action = match read_command().map_err(io_err)? {
+                Some(text) => session.command(&text, Some(env)),
+                None => Action::Quit,
+            };
+
+            match action {
+                Action::None | Action::Run => {}
+                Action::Continue => return Ok(()),
+                Action::Quit => {
+                    // Unwinds like a halt, which `attempt` can't catch
+                    session.quit = true;
+                    env.halted = Some(0);
+                    return Err(RuntimeError {
+                        msg: "Stopped by the debugger".into(),
+                        span: None,
+                        code: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Collects the variables read, in order and once each
+struct Reads<'a>(&'a mut Vec<String>);
+
+impl Visitor for Reads<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(name) | Expr::AddressOf(name) = expr {
+            if !self.0.contains(name) {
+                self.0.push(name.clone());
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Adds the variables `expr` reads to `names`, in order and once each
+fn reads(expr: &Expr, names: &mut Vec<String>) {
+    Reads(names).visit_expr(expr);
+}
+
+/// Where an assignment stores to only reads the parts picking the place,
+/// `A[I] = 0` reads `A` and `I` but `X = 0` reads nothing
+fn target_reads(target: &Expr, names: &mut Vec<String>) {
+    match target {
+        Expr::Ident(_) => {}
+        _ => reads(target, names),
+    }
+}
+
+/// The variables a statement reads before anything in its body runs
+fn stmt_reads(stmt: &Stmt) -> Vec<String> {
+    let mut names = Vec::new();
+    match &stmt.kind {
+        StmtKind::Assign(x) => {
+            reads(&x.expr, &mut names);
+            target_reads(&x.target, &mut names);
+        }
+        StmtKind::MultiAssign(x) => {
+            x.exprs.iter().for_each(|x| reads(x, &mut names));
+            x.targets.iter().for_each(|x| target_reads(x, &mut names));
+        }
+        StmtKind::Output(x) => reads(&x.expr, &mut names),
+        StmtKind::If(x) => x.branches.iter().for_each(|(cond, _)| reads(cond, &mut names)),
+        StmtKind::While(x) => reads(&x.cond, &mut names),
+        StmtKind::Until(x) | StmtKind::Repeat(x) => reads(&x.cond, &mut names),
+        StmtKind::For(x) => {
+            reads(&x.start, &mut names);
+            reads(&x.end, &mut names);
+        }
+        StmtKind::ForEach(x) => reads(&x.iter, &mut names),
+        StmtKind::Expr(x) | StmtKind::Return(Some(x)) | StmtKind::Halt(Some(x)) => reads(x, &mut names),
+        StmtKind::Assert(x) => {
+            reads(&x.cond, &mut names);
+            x.msg.iter().for_each(|x| reads(x, &mut names));
+        }
+        _ => {}
+    }
+    names
+}
+
+/// `--step` walks through a program one statement at a time, showing each
+/// with the values of the variables it reads and waiting for Enter, like a
+/// dry run on a whiteboard. Typing `watch NAME` before Enter reports
+/// changes to NAME from then on. It all goes to stderr, apart from the
+/// program
+pub struct Stepper {
+    source: Vec<String>,
+    watches: Watches,
+    /// Once stdin runs out there's nothing to wait for
+    waiting: bool,
+}
+
+impl Stepper {
+    /// For a program with the text `source`
+    pub fn new(source: &str) -> Self {
+        Stepper {
+            source: source.lines().map(String::from).collect(),
+            watches: Watches::default(),
+            waiting: true,
+        }
+    }
+}
+
+impl Hook for Stepper {
+    fn before(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError> {
+        let io_err = |e: io::Error| RuntimeError { msg: e.to_string(), span: None, code: None };
+        env.out.flush().map_err(io_err)?;
+
+        for change in self.watches.check(stmt, env) {
+            eprintln!("{}", change);
+        }
+
+        eprintln!("Line {}: {}", stmt.span.line, source_line(&self.source, stmt.span.line));
+
+        let vars: Vec<String> = stmt_reads(stmt)
+            .into_iter()
+            .filter_map(|name| {
+                let val = env.lookup(&name)?;
+                let val = val.borrow().repr();
+                Some(format!("{} = {}", name, val))
+            })
+            .collect();
+        if !vars.is_empty() {
+            eprintln!("    {}", vars.join(", "));
+        }
+
+        while self.waiting {
+            let mut buf = String::new();
+            self.waiting = io::stdin().read_line(&mut buf).map_err(io_err)? > 0;
+
+            match buf.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => break,
+                ["watch", name] => eprintln!("{}", self.watches.add(name, Some(env))),
+                _ => eprintln!("Press Enter to run the statement, or type watch NAME"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `--trace` logs each statement the program runs with the variables of its
+/// call once it's done, indented by how deep in calls it is. Statements
+/// with bodies are logged as they start instead, so the log reads in order
+pub struct Tracer {
+    source: Vec<String>,
+    out: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(source: &str, out: Box<dyn Write>) -> Self {
+        Tracer {
+            source: source.lines().map(String::from).collect(),
+            out,
+        }
+    }
+
+    fn has_body(stmt: &Stmt) -> bool {
+        matches!(
+            stmt.kind,
+            StmtKind::If(_)
+                | StmtKind::While(_)
+                | StmtKind::Until(_)
+                | StmtKind::Repeat(_)
+                | StmtKind::For(_)
+                | StmtKind::ForEach(_)
+                | StmtKind::Function(_)
+                | StmtKind::Class(_)
+                | StmtKind::Attempt(_)
+                | StmtKind::Halt(_)
+        )
+    }
+
+    fn log(&mut self, stmt: &Stmt, env: &Env, vars: bool) -> Result<(), RuntimeError> {
+        let indent = "  ".repeat(env.depth() - 1);
+        let mut text = format!("{}Line {}: {}", indent, stmt.span.line, source_line(&self.source, stmt.span.line));
+
+        if vars {
+            let vars: Vec<String> = env
+                .current()
+                .vars
+                .iter()
+                .filter(|(_, val)| !matches!(val, PscObject::FunctionT(_)))
+                .map(|(name, val)| format!("{} = {}", name, val.repr()))
+                .collect();
+            if !vars.is_empty() {
+                text += &format!(" -- {}", vars.join(", "));
+            }
+        }
+
+        writeln!(self.out, "{}", text).map_err(|e| RuntimeError { msg: e.to_string(), span: None, code: None })
+    }
+}
+
+impl Hook for Tracer {
+    fn before(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError> {
+        match Tracer::has_body(stmt) {
+            true => self.log(stmt, env, false),
+            false => Ok(()),
+        }
+    }
+
+    fn after(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError> {
+        match Tracer::has_body(stmt) {
+            true => Ok(()),
+            false => self.log(stmt, env, true),
+        }
+    }
+}
+
+/// `psc debug FILE [ARGS]` runs a program under a command line debugger,
+/// taking the commands in `HELP` from stdin. The program's own `input`
+/// reads from stdin too
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut file: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        if !options.parse_flag(&arg, &mut args)? {
+            file = Some(arg);
+            break;
+        }
+    }
+
+    let file = match file {
+        Some(x) => x,
+        None => return Err("debug expects a file".into()),
+    };
+
+    let stmts = load::load(Path::new(&file), &options)?;
+
+    let session = Rc::new(RefCell::new(Session {
+        source: read_source(&file)?,
+        breakpoints: BTreeSet::new(),
+        watches: Watches::default(),
+        quit: false,
+    }));
+
+    println!("Debugging {}, type help for the commands", file);
+
+    // Until `run`, only breakpoints can be set
+    loop {
+        let text = match read_command()? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        match session.borrow_mut().command(&text, None) {
+            Action::Run => break,
+            Action::Quit => return Ok(()),
+            Action::None | Action::Continue => {}
+        }
+    }
+
+    let mut env = Env::new();
+    env.dialect = options.dialect;
+    env.args = args.collect();
+    env.hook = Some(Box::new(Debugger(session.clone())));
+
+    let res = env.run(&stmts);
+    env.out.flush()?;
+
+    if session.borrow().quit {
+        return Ok(());
+    }
+    match (res, env.halted) {
+        (_, Some(code)) => println!("The program halted with code {}", code),
+        (Ok(()), None) => println!("The program finished"),
+        (Err(e), None) => println!("The program stopped with an error: {}", e),
+    }
+
+    Ok(())
+}
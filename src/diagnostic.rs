@@ -0,0 +1,114 @@
+use crate::lex::Span;
+
+/// An error as shown to whoever ran the program, pointing into the line of
+/// source it's about when that's known
+pub struct Diagnostic<'a> {
+    pub msg: &'a str,
+    /// The file the error is in, and its text
+    pub file: Option<(&'a str, &'a str)>,
+    pub span: Option<Span>,
+    /// The calls a runtime error came out of, innermost first, with the line
+    /// each was on
+    pub trace: &'a [(String, usize)],
+    /// The code `psc --explain` describes an error under, when it has one
+    pub code: Option<&'a str>,
+    /// The code of a warning, shown in yellow, when this isn't an error
+    pub warning: Option<&'a str>,
+    /// The warning was made an error with `-D`, so it's shown as one
+    pub denied: bool,
+}
+
+fn paint(sgr: &str, text: &str, color: bool) -> String {
+    match color {
+        true => format!("\x1b[{}m{}\x1b[0m", sgr, text),
+        false => text.to_string(),
+    }
+}
+
+impl Diagnostic<'_> {
+    /// The message under a red `error[CODE]` label, or a yellow one for a
+    /// warning, then the offending line
+    /// with carets under where it went wrong and the calls it came out of,
+    /// like
+    ///
+    /// ```text
+    /// error[E012]: Failed to parse if stmt
+    ///  --> prog.ps:3:10
+    ///   |
+    /// 3 | if X > 1 output X
+    ///   |          ^
+    /// ```
+    ///
+    /// or for an error inside a procedure
+    ///
+    /// ```text
+    /// error[E017]: Division by zero at line 2
+    ///  --> prog.ps:2:5
+    ///   |
+    /// 2 |     output 10 / N
+    ///   |     ^^^^^^^^^^^^^
+    /// stack backtrace:
+    ///    0: SHOW at prog.ps:2
+    ///           output 10 / N
+    ///    1: main at prog.ps:4
+    ///           SHOW(0)
+    /// ```
+    pub fn render(&self, color: bool) -> String {
+        let label = match (self.warning, self.code) {
+            (Some(code), _) if self.denied => paint("1;31", &format!("error[{}]", code), color),
+            (Some(code), _) => paint("1;33", &format!("warning[{}]", code), color),
+            (None, Some(code)) => paint("1;31", &format!("error[{}]", code), color),
+            (None, None) => paint("1;31", "error", color),
+        };
+        let mut ret = format!("{}{}", label, paint("1", &format!(": {}", self.msg), color));
+
+        let ((path, source), span) = match (self.file, self.span) {
+            (Some(file), Some(span)) => (file, span),
+            _ => return ret,
+        };
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        ret += &format!("\n{}{} {}:{}:{}", gutter, paint("1;34", "-->", color), path, span.line, span.col);
+
+        // Past the end of the file when the line is from somewhere else, an
+        // included file, and 0 for a program that was built instead of parsed
+        let text = match span.line.checked_sub(1).and_then(|x| source.lines().nth(x)) {
+            Some(x) => x.trim_end(),
+            None => return ret,
+        };
+
+        // Tabs are shown as four spaces, and the marks moved along to match
+        let width = |c: char| if c == '\t' { 4 } else { 1 };
+        let start: usize = text.chars().take(span.col - 1).map(width).sum();
+        let len: usize = text.chars().skip(span.col - 1).take(span.len).map(width).sum();
+
+        let bar = paint("1;34", "|", color);
+        ret += &format!("\n{} {}", gutter, bar);
+        ret += &format!("\n{} {} {}", paint("1;34", &span.line.to_string(), color), bar, text.replace('\t', "    "));
+        let marks = "^".repeat(len.max(1));
+        let yellow = self.warning.is_some() && !self.denied;
+        let marks = paint(if yellow { "1;33" } else { "1;31" }, &marks, color);
+        ret += &format!("\n{} {} {}{}", gutter, bar, " ".repeat(start), marks);
+
+        // Nothing more to say about an error outside any call
+        if self.trace.len() > 1 {
+            ret += "\nstack backtrace:";
+            let mut i = 0;
+            while i < self.trace.len() {
+                let (name, line) = &self.trace[i];
+                ret += &format!("\n{:>4}: {} at {}:{}", i, paint("1", name, color), path, line);
+                if let Some(text) = source.lines().nth(line.wrapping_sub(1)) {
+                    ret += &format!("\n          {}", text.trim());
+                }
+
+                // Recursion makes the same call over and over
+                let same = self.trace[i + 1..].iter().take_while(|x| *x == &self.trace[i]).count();
+                if same > 0 {
+                    ret += &format!("\n      ... the same call {} more time(s)", same);
+                }
+                i += same + 1;
+            }
+        }
+        ret
+    }
+}
@@ -0,0 +1,279 @@
+use crate::lex::Keyword;
+
+/// The exam board whose pseudocode is being run
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Dialect {
+    #[default]
+    Ib,
+    Cambridge,
+    Edexcel,
+    Aqa,
+}
+
+/// Everything that differs between dialects. The IB keywords are shared by
+/// all of them, each dialect adds its own on top
+pub struct DialectTable {
+    /// Extra keywords, e.g. `for` or `repeat`
+    pub keywords: &'static [(&'static str, Keyword)],
+    /// One word terminators like `ENDIF` or `NEXT I`, closing like `end`
+    /// followed by the keyword. They're only reserved in dialects that have
+    /// them, so `NEXT` can still name a field elsewhere
+    pub terminators: &'static [(&'static str, Keyword)],
+    /// Whether a lone `=` assigns, otherwise it compares and only `<-`/`←`
+    /// assign
+    pub assign_equals: bool,
+    /// Builtin names mapped onto the ones psc provides
+    pub builtins: &'static [(&'static str, &'static str)],
+    /// How the statements are written back out
+    pub forms: &'static Forms,
+}
+
+/// The source the printer writes for each statement, with `{0}`, `{1}`...
+/// standing for its parts in the order given on each field. A `\n` starts
+/// another line at the same indent
+pub struct Forms {
+    /// Whether the other keywords (`RETURN`, `CLASS`, `DIV`...) are written
+    /// in capitals
+    pub upper: bool,
+    /// Target, value
+    pub assign: &'static str,
+    pub equals: &'static str,
+    pub not_equals: &'static str,
+    /// Name, type, where `DECLARE` exists
+    pub declare: Option<&'static str>,
+    /// Name, type, where `TYPE` exists
+    pub type_def: Option<&'static str>,
+    pub output: &'static str,
+    /// Name, and then the type for `input_typed`
+    pub input: &'static str,
+    pub input_typed: &'static str,
+    /// A procedure called as a statement
+    pub call: &'static str,
+    /// Condition
+    pub if_then: &'static str,
+    pub else_if: &'static str,
+    pub end_if: &'static str,
+    /// The first line and the last, the condition in either
+    pub while_loop: [&'static str; 2],
+    pub until_loop: [&'static str; 2],
+    pub repeat: [&'static str; 2],
+    /// Variable, start, end
+    pub for_loop: [&'static str; 2],
+    /// Variable, collection
+    pub for_each: [&'static str; 2],
+    /// Name, parameters
+    pub procedure: [&'static str; 2],
+    pub function: [&'static str; 2],
+    pub end_class: &'static str,
+    pub end_attempt: &'static str,
+}
+
+const IB: DialectTable = DialectTable {
+    keywords: &[],
+    terminators: &[],
+    assign_equals: true,
+    builtins: &[],
+    forms: &Forms {
+        upper: false,
+        assign: "{0} = {1}",
+        equals: "==",
+        not_equals: "!=",
+        declare: None,
+        type_def: None,
+        output: "output {0}",
+        input: "input {0}",
+        input_typed: "input {1} {0}",
+        call: "{0}",
+        if_then: "if {0} then",
+        else_if: "else if {0}",
+        end_if: "end if",
+        while_loop: ["loop while {0}", "end loop"],
+        until_loop: ["loop until {0}", "end loop"],
+        repeat: ["repeat", "until {0}"],
+        for_loop: ["loop {0} from {1} to {2}", "end loop"],
+        for_each: ["loop {0} over {1}", "end loop"],
+        procedure: ["procedure {0}({1})", "end procedure"],
+        function: ["function {0}({1})", "end function"],
+        end_class: "end class",
+        end_attempt: "end attempt",
+    },
+};
+
+/// `DECLARE X : INTEGER`, `DECLARE A : ARRAY[1:10] OF REAL`,
+/// `TYPE INTPTR = ^INTEGER`,
+/// `FOR I ← 1 TO 10 ... NEXT I`, `WHILE C DO ... ENDWHILE`,
+/// `REPEAT ... UNTIL C`, `CALL P()`
+const CAMBRIDGE: DialectTable = DialectTable {
+    keywords: &[
+        ("for", Keyword::For),
+        ("do", Keyword::Do),
+        ("repeat", Keyword::Repeat),
+        ("declare", Keyword::Declare),
+        ("array", Keyword::Array),
+        ("of", Keyword::Of),
+        ("type", Keyword::Type),
+        ("call", Keyword::Call),
+        ("returns", Keyword::Returns),
+    ],
+    terminators: &[
+        ("endif", Keyword::If),
+        ("endwhile", Keyword::Loop),
+        ("endloop", Keyword::Loop),
+        ("endfor", Keyword::Loop),
+        ("next", Keyword::Loop),
+        ("endprocedure", Keyword::Procedure),
+        ("endfunction", Keyword::Function),
+        ("endclass", Keyword::Class),
+        ("endattempt", Keyword::Attempt),
+    ],
+    assign_equals: false,
+    builtins: &[],
+    forms: &Forms {
+        upper: true,
+        assign: "{0} ← {1}",
+        equals: "=",
+        not_equals: "<>",
+        declare: Some("DECLARE {0} : {1}"),
+        type_def: Some("TYPE {0} = {1}"),
+        output: "OUTPUT {0}",
+        input: "INPUT {0}",
+        input_typed: "INPUT {1} {0}",
+        call: "CALL {0}",
+        if_then: "IF {0} THEN",
+        else_if: "ELSE IF {0}",
+        end_if: "ENDIF",
+        while_loop: ["WHILE {0} DO", "ENDWHILE"],
+        until_loop: ["LOOP UNTIL {0}", "ENDLOOP"],
+        repeat: ["REPEAT", "UNTIL {0}"],
+        for_loop: ["FOR {0} ← {1} TO {2}", "NEXT {0}"],
+        for_each: ["LOOP {0} OVER {1}", "ENDLOOP"],
+        procedure: ["PROCEDURE {0}({1})", "ENDPROCEDURE"],
+        function: ["FUNCTION {0}({1})", "ENDFUNCTION"],
+        end_class: "ENDCLASS",
+        end_attempt: "ENDATTEMPT",
+    },
+};
+
+/// `SET X TO 1`, `SEND X TO DISPLAY`, `RECEIVE X FROM (INTEGER) KEYBOARD`,
+/// `FOR I FROM 1 TO 10 DO ... END FOR`, `PROCEDURE P() BEGIN PROCEDURE`
+const EDEXCEL: DialectTable = DialectTable {
+    keywords: &[
+        ("for", Keyword::For),
+        ("do", Keyword::Do),
+        ("repeat", Keyword::Repeat),
+        ("set", Keyword::Set),
+        ("send", Keyword::Send),
+        ("display", Keyword::Display),
+        ("receive", Keyword::Receive),
+        ("keyboard", Keyword::Keyboard),
+        ("begin", Keyword::Begin),
+    ],
+    terminators: &[],
+    assign_equals: false,
+    builtins: &[],
+    forms: &Forms {
+        upper: true,
+        assign: "SET {0} TO {1}",
+        equals: "=",
+        not_equals: "<>",
+        declare: None,
+        type_def: None,
+        output: "SEND {0} TO DISPLAY",
+        input: "RECEIVE {0} FROM KEYBOARD",
+        input_typed: "RECEIVE {0} FROM ({1}) KEYBOARD",
+        call: "{0}",
+        if_then: "IF {0} THEN",
+        else_if: "ELSE IF {0}",
+        end_if: "END IF",
+        while_loop: ["WHILE {0} DO", "END WHILE"],
+        until_loop: ["LOOP UNTIL {0}", "END LOOP"],
+        repeat: ["REPEAT", "UNTIL {0}"],
+        for_loop: ["FOR {0} FROM {1} TO {2} DO", "END FOR"],
+        for_each: ["LOOP {0} OVER {1}", "END LOOP"],
+        procedure: ["PROCEDURE {0}({1})\nBEGIN PROCEDURE", "END PROCEDURE"],
+        function: ["FUNCTION {0}({1})\nBEGIN FUNCTION", "END FUNCTION"],
+        end_class: "END CLASS",
+        end_attempt: "END ATTEMPT",
+    },
+};
+
+/// `FOR I ← 1 TO 10 ... ENDFOR`, `SUBROUTINE S() ... ENDSUBROUTINE`,
+/// `REPEAT ... UNTIL C`, `LEN(S)`
+const AQA: DialectTable = DialectTable {
+    keywords: &[
+        ("for", Keyword::For),
+        ("repeat", Keyword::Repeat),
+        ("subroutine", Keyword::Function),
+    ],
+    terminators: &[
+        ("endif", Keyword::If),
+        ("endwhile", Keyword::Loop),
+        ("endfor", Keyword::Loop),
+        ("endsubroutine", Keyword::Function),
+    ],
+    assign_equals: false,
+    builtins: &[("LEN", "LENGTH"), ("RANDOM_INT", "RANDOM")],
+    forms: &Forms {
+        upper: true,
+        assign: "{0} ← {1}",
+        equals: "=",
+        not_equals: "≠",
+        declare: None,
+        type_def: None,
+        output: "OUTPUT {0}",
+        input: "INPUT {0}",
+        input_typed: "INPUT {1} {0}",
+        call: "{0}",
+        if_then: "IF {0} THEN",
+        else_if: "ELSE IF {0}",
+        end_if: "ENDIF",
+        while_loop: ["WHILE {0}", "ENDWHILE"],
+        until_loop: ["LOOP UNTIL {0}", "END LOOP"],
+        repeat: ["REPEAT", "UNTIL {0}"],
+        for_loop: ["FOR {0} ← {1} TO {2}", "ENDFOR"],
+        for_each: ["LOOP {0} OVER {1}", "END LOOP"],
+        procedure: ["SUBROUTINE {0}({1})", "ENDSUBROUTINE"],
+        function: ["SUBROUTINE {0}({1})", "ENDSUBROUTINE"],
+        end_class: "END CLASS",
+        end_attempt: "END ATTEMPT",
+    },
+};
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name {
+            "ib" => Some(Dialect::Ib),
+            "cambridge" => Some(Dialect::Cambridge),
+            "edexcel" => Some(Dialect::Edexcel),
+            "aqa" => Some(Dialect::Aqa),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> &'static DialectTable {
+        match self {
+            Dialect::Ib => &IB,
+            Dialect::Cambridge => &CAMBRIDGE,
+            Dialect::Edexcel => &EDEXCEL,
+            Dialect::Aqa => &AQA,
+        }
+    }
+}
+
+impl DialectTable {
+    pub fn keyword(&self, word: &str) -> Option<Keyword> {
+        self.keywords.iter().find(|(x, _)| *x == word).map(|(_, k)| k.clone())
+    }
+
+    pub fn terminator(&self, word: &str) -> Option<Keyword> {
+        self.terminators.iter().find(|(x, _)| *x == word).map(|(_, k)| k.clone())
+    }
+
+    /// The psc name of a builtin called `name` in this dialect
+    pub fn builtin<'a>(&self, name: &'a str) -> &'a str {
+        match self.builtins.iter().find(|(x, _)| *x == name) {
+            Some((_, x)) => x,
+            None => name,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use psc::eval::{Function, StmtKind};
+use psc::lex::{self, LexOptions};
+use psc::{load, parse, pretty};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn is_program(path: &Path) -> bool {
+    matches!(path.extension().and_then(|x| x.to_str()), Some("psc" | "ps"))
+}
+
+/// A heading for `func`, its description and how it's called
+fn function(out: &mut String, level: &str, owner: Option<&str>, func: &Function) {
+    let kind = if pretty::returns_value(&func.stmts) { "function" } else { "procedure" };
+    let name = match owner {
+        Some(class) => format!("{}.{}", class, func.name),
+        None => func.name.clone(),
+    };
+
+    *out += &format!("{} `{}({})`\n\n*{}*\n\n", level, name, func.params.join(", "), kind);
+    if let Some(doc) = &func.doc {
+        *out += &format!("{}\n\n", doc);
+    }
+}
+
+/// Markdown for the procedures, functions and classes defined in `prog`,
+/// not counting what it includes
+fn document(title: &str, prog: &str, options: &LexOptions) -> Result<String, Box<dyn Error>> {
+    let stmts = parse::parse(lex::lex(prog, options)?)?;
+    let mut ret = format!("# {}\n\n", title);
+    let mut empty = true;
+
+    for stmt in &stmts {
+        match &stmt.kind {
+            StmtKind::Function(func) => function(&mut ret, "##", None, func),
+            StmtKind::Class(class) => {
+                ret += &format!("## class `{}`\n\n", class.name);
+                if let Some(parent) = &class.parent {
+                    ret += &format!("Inherits `{}`\n\n", parent);
+                }
+                for method in class.methods.iter().filter(|x| x.public) {
+                    function(&mut ret, "###", Some(&class.name), &method.func);
+                }
+            }
+            _ => continue,
+        }
+        empty = false;
+    }
+
+    if empty {
+        ret += "No procedures, functions or classes\n\n";
+    }
+    Ok(ret)
+}
+
+/// `psc doc PATH...` writes Markdown documentation for the files or folders
+/// of programs to stdout, from the `///` comments before each procedure and
+/// function. Private methods are left out
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut paths: Vec<String> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if !options.parse_flag(&arg, &mut args)? {
+            paths.push(arg);
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("doc expects at least one file or folder".into());
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in &paths {
+        load::discover(Path::new(path), is_program, &mut files).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    let mut pages: Vec<String> = Vec::new();
+    for file in &files {
+        let name = file.display().to_string();
+        let prog = fs::read_to_string(file).map_err(|e| format!("{}: {}", name, e))?;
+        pages.push(document(&name, &prog, &options).map_err(|e| format!("{}: {}", name, e))?);
+    }
+
+    print!("{}", pages.concat().trim_end());
+    println!();
+    Ok(())
+}
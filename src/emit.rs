@@ -0,0 +1,203 @@
+use crate::eval::*;
+use crate::json::Json;
+use crate::lex::Span;
+use crate::pretty::op_symbol;
+
+/// `{"type": NAME, ...}`, the type named like the variant in the AST
+fn node(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", Json::str(kind)));
+    Json::object(fields)
+}
+
+/// A node that knows where it is, with `line`, `col` and `len` after the rest
+fn spanned(kind: &str, mut fields: Vec<(&str, Json)>, span: Span) -> Json {
+    fields.push(("line", Json::Int(span.line as i64)));
+    fields.push(("col", Json::Int(span.col as i64)));
+    fields.push(("len", Json::Int(span.len as i64)));
+    node(kind, fields)
+}
+
+fn opt(expr: &Option<Expr>) -> Json {
+    match expr {
+        Some(x) => self::expr(x),
+        None => Json::Null,
+    }
+}
+
+fn exprs(exprs: &[Expr]) -> Json {
+    Json::Array(exprs.iter().map(expr).collect())
+}
+
+fn names(names: &[String]) -> Json {
+    Json::Array(names.iter().map(|x| Json::str(x)).collect())
+}
+
+fn function(func: &Function) -> Json {
+    Json::object(vec![
+        ("name", Json::str(&func.name)),
+        ("params", names(&func.params)),
+        ("doc", func.doc.as_deref().map_or(Json::Null, Json::str)),
+        ("body", stmts(&func.stmts)),
+    ])
+}
+
+pub fn expr(expr: &Expr) -> Json {
+    match expr {
+        Expr::BinOp(x) => spanned(
+            "BinOp",
+            vec![
+                ("op", Json::str(op_symbol(&x.op))),
+                ("left", self::expr(&x.left)),
+                ("right", self::expr(&x.right)),
+            ],
+            x.span,
+        ),
+        Expr::IfElse(x) => node(
+            "IfElse",
+            vec![
+                ("cond", self::expr(&x.cond)),
+                ("then", self::expr(&x.then)),
+                ("otherwise", self::expr(&x.otherwise)),
+            ],
+        ),
+        Expr::Index(x) => node(
+            "Index",
+            vec![("target", self::expr(&x.target)), ("index", self::expr(&x.index))],
+        ),
+        Expr::MethodCall(x) => spanned(
+            "MethodCall",
+            vec![
+                ("target", self::expr(&x.target)),
+                ("name", Json::str(&x.name)),
+                ("args", exprs(&x.args)),
+            ],
+            x.span,
+        ),
+        Expr::Field(x) => node("Field", vec![("target", self::expr(&x.target)), ("name", Json::str(&x.name))]),
+        Expr::Call(x) => spanned("Call", vec![("name", Json::str(&x.name)), ("args", exprs(&x.args))], x.span),
+        Expr::SuperCall(x) => spanned("SuperCall", vec![("name", Json::str(&x.name)), ("args", exprs(&x.args))], x.span),
+        Expr::New(x) => spanned("New", vec![("class", Json::str(&x.class)), ("args", exprs(&x.args))], x.span),
+        Expr::Map(entries) => {
+            let entries = entries
+                .iter()
+                .map(|(k, v)| Json::object(vec![("key", self::expr(k)), ("value", self::expr(v))]))
+                .collect();
+            node("Map", vec![("entries", Json::Array(entries))])
+        }
+        Expr::Set(items) => node("Set", vec![("items", exprs(items))]),
+        Expr::Array(items) => node("Array", vec![("items", exprs(items))]),
+        Expr::AddressOf(name) => node("AddressOf", vec![("name", Json::str(name))]),
+        Expr::Deref(x) => node("Deref", vec![("expr", self::expr(x))]),
+        Expr::BNot(x) => node("BNot", vec![("expr", self::expr(x))]),
+        Expr::IntLit(x) => node("IntLit", vec![("value", Json::Int(*x))]),
+        Expr::FloatLit(x) => node("FloatLit", vec![("value", Json::Float(*x))]),
+        Expr::BoolLit(x) => node("BoolLit", vec![("value", Json::Bool(*x))]),
+        Expr::StrLit(x) => node("StrLit", vec![("value", Json::str(x))]),
+        Expr::NullLit => node("NullLit", vec![]),
+        Expr::Ident(name) => node("Ident", vec![("name", Json::str(name))]),
+    }
+}
+
+fn declared(ty: &Declared) -> Json {
+    let bounds = ty.bounds.iter().map(|&(lower, upper)| Json::Array(vec![Json::Int(lower), Json::Int(upper)]));
+    Json::object(vec![("name", Json::str(&ty.name)), ("bounds", Json::Array(bounds.collect()))])
+}
+
+pub fn stmt(stmt: &Stmt) -> Json {
+    let (kind, fields) = match &stmt.kind {
+        StmtKind::Assign(x) => {
+            let mut fields = vec![("target", expr(&x.target)), ("expr", expr(&x.expr))];
+            if let Some(ty) = &x.declared {
+                fields.push(("declared", declared(ty)));
+            }
+            ("Assign", fields)
+        }
+        StmtKind::MultiAssign(x) => ("MultiAssign", vec![("targets", exprs(&x.targets)), ("exprs", exprs(&x.exprs))]),
+        StmtKind::Input(x) => {
+            let kind = match &x.kind {
+                Some(InputType::Integer) => Json::str("integer"),
+                Some(InputType::Real) => Json::str("real"),
+                Some(InputType::String) => Json::str("string"),
+                Some(InputType::Boolean) => Json::str("boolean"),
+                None => Json::Null,
+            };
+            ("Input", vec![("ident", Json::str(&x.ident)), ("kind", kind)])
+        }
+        StmtKind::Output(x) => ("Output", vec![("expr", expr(&x.expr))]),
+        StmtKind::If(x) => {
+            let branches = x
+                .branches
+                .iter()
+                .map(|(cond, body)| Json::object(vec![("cond", expr(cond)), ("body", stmts(body))]))
+                .collect();
+            ("If", vec![("branches", Json::Array(branches))])
+        }
+        StmtKind::While(x) => ("While", vec![("cond", expr(&x.cond)), ("body", stmts(&x.stmts))]),
+        StmtKind::Until(x) => ("Until", vec![("cond", expr(&x.cond)), ("body", stmts(&x.stmts))]),
+        StmtKind::Repeat(x) => ("Repeat", vec![("cond", expr(&x.cond)), ("body", stmts(&x.stmts))]),
+        StmtKind::For(x) => (
+            "For",
+            vec![
+                ("name", Json::str(&x.name)),
+                ("start", expr(&x.start)),
+                ("end", expr(&x.end)),
+                ("body", stmts(&x.stmts)),
+            ],
+        ),
+        StmtKind::ForEach(x) => (
+            "ForEach",
+            vec![("name", Json::str(&x.name)), ("iter", expr(&x.iter)), ("body", stmts(&x.stmts))],
+        ),
+        StmtKind::Expr(x) => ("Expr", vec![("expr", expr(x))]),
+        StmtKind::Function(x) => ("Function", vec![("function", function(x))]),
+        StmtKind::Class(x) => {
+            let fields = x
+                .fields
+                .iter()
+                .map(|field| {
+                    Json::object(vec![
+                        ("name", Json::str(&field.name)),
+                        ("public", Json::Bool(field.public)),
+                        ("init", opt(&field.init)),
+                    ])
+                })
+                .collect();
+            let methods = x
+                .methods
+                .iter()
+                .map(|method| Json::object(vec![("public", Json::Bool(method.public)), ("function", function(&method.func))]))
+                .collect();
+
+            (
+                "Class",
+                vec![
+                    ("name", Json::str(&x.name)),
+                    ("parent", x.parent.as_deref().map_or(Json::Null, Json::str)),
+                    ("fields", Json::Array(fields)),
+                    ("methods", Json::Array(methods)),
+                ],
+            )
+        }
+        StmtKind::Return(x) => ("Return", vec![("expr", opt(x))]),
+        StmtKind::Global(x) => ("Global", vec![("names", names(x))]),
+        StmtKind::Type(x) => ("Type", vec![("name", Json::str(&x.name)), ("declared", declared(&x.ty))]),
+        StmtKind::Attempt(x) => (
+            "Attempt",
+            vec![
+                ("body", stmts(&x.stmts)),
+                ("name", x.name.as_deref().map_or(Json::Null, Json::str)),
+                ("handler", stmts(&x.handler)),
+            ],
+        ),
+        StmtKind::Assert(x) => ("Assert", vec![("cond", expr(&x.cond)), ("msg", opt(&x.msg))]),
+        StmtKind::Halt(x) => ("Halt", vec![("code", opt(x))]),
+        StmtKind::Include(x) => ("Include", vec![("file", Json::str(x))]),
+    };
+
+    spanned(kind, fields, stmt.span)
+}
+
+/// The program as JSON for `--emit-ast`, an array of statements
+pub fn stmts(stmts: &[Stmt]) -> Json {
+    Json::Array(stmts.iter().map(stmt).collect())
+}
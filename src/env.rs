@@ -0,0 +1,1036 @@
+use crate::dialect::Dialect;
+use crate::err::RuntimeError;
+use crate::eval::{Class, Closure, Flow, Function, Method, Object, PscObject, Stmt, Vars};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Shared so nested functions can keep their enclosing scopes alive
+pub type Scope = Rc<RefCell<Vars>>;
+
+/// What a host embedding psc is given each value that's output
+pub type OnOutput = Box<dyn FnMut(&PscObject)>;
+
+/// A function a host embedding psc gives programs, called with the values
+/// of its arguments
+pub type Native = Rc<dyn Fn(Vec<PscObject>) -> Result<PscObject, RuntimeError>>;
+
+/// Called before every statement runs, which is how debuggers pause and step
+pub trait Hook {
+    fn before(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), RuntimeError>;
+
+    /// Called once `stmt` has finished without an error, including any body
+    fn after(&mut self, _stmt: &Stmt, _env: &mut Env) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+/// Runs hot loops and functions some faster way than walking the tree, like
+/// `jit::Jit` compiling them to native code. `None` leaves them to the
+/// interpreter, which is always right to do
+pub trait Jit {
+    /// Runs a call of the top level function `func` with `args`
+    fn call(&mut self, func: &Rc<Function>, args: &[PscObject], env: &mut Env) -> Option<Result<PscObject, RuntimeError>>;
+
+    /// Runs the rest of the loop `stmt`, starting at the top of an
+    /// iteration. A `for` loop also gives the value its variable takes next
+    /// and the one it ends on
+    fn resume(&mut self, stmt: &Stmt, count: Option<(i64, i64)>, env: &mut Env) -> Option<Result<Flow, RuntimeError>>;
+}
+
+/// Output written to every one of several places, such as the terminal and
+/// a file to grade
+pub struct Tee(pub Vec<Box<dyn Write>>);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for out in &mut self.0 {
+            out.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|x| x.flush())
+    }
+}
+
+/// Output kept in memory, which stays readable through a clone after the
+/// `Env` writing to it is gone
+#[derive(Clone, Default)]
+pub struct Captured(pub Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A snapshot of one call for debuggers, the variables are merged from the
+/// call's scopes and include the globals for the top level
+pub struct CallInfo {
+    pub name: String,
+    pub line: usize,
+    pub vars: Vec<(String, PscObject)>,
+}
+
+/// State for a single procedure/function/method call, or the top level
+struct Frame {
+    /// The function running, or `main` for the top level
+    name: String,
+    /// The statement running while there's a hook or a limit to check, and
+    /// the one an error came out of once it's unwinding
+    line: usize,
+    /// Set once `line` is where the error unwinding through the call came
+    /// out of
+    failed: bool,
+    /// Innermost last; the top level frame starts with none and falls back
+    /// to the globals
+    scopes: Vec<Scope>,
+    /// Names opted into with `global`
+    global_names: HashSet<String>,
+    this: Option<Rc<RefCell<Object>>>,
+    /// The class declaring the running method, which `super` starts from
+    class: Option<Rc<Class>>,
+}
+
+impl Frame {
+    /// A variable from this call's own scopes or object fields
+    fn lookup_local(&self, name: &str) -> Option<Rc<RefCell<PscObject>>> {
+        if let Some(cell) = self.scopes.iter().rev().find_map(|scope| scope.borrow().get(name).cloned()) {
+            return Some(cell);
+        }
+
+        match &self.this {
+            Some(this) => this.borrow().fields.get(name).cloned(),
+            None => None,
+        }
+    }
+
+    fn new(name: &str, scopes: Vec<Scope>, method_of: Option<(Rc<RefCell<Object>>, Rc<Class>)>) -> Frame {
+        let (this, class) = match method_of {
+            Some((this, class)) => (Some(this), Some(class)),
+            None => (None, None),
+        };
+
+        Frame {
+            name: name.to_string(),
+            line: 0,
+            failed: false,
+            scopes,
+            global_names: HashSet::new(),
+            this,
+            class,
+        }
+    }
+}
+
+/// Roughly how many bytes `val` takes up. Cells and objects already in
+/// `seen` aren't counted again, so shared and cyclic values add up once
+fn size_of(val: &PscObject, seen: &mut HashSet<*const ()>) -> usize {
+    let items = |items: &[PscObject], seen: &mut HashSet<*const ()>| items.iter().map(|x| size_of(x, seen)).sum::<usize>();
+
+    mem::size_of::<PscObject>()
+        + match val {
+            PscObject::StringT(x) => x.len(),
+            PscObject::BigIntT(x) => x.size(),
+            PscObject::ArrayT(x) | PscObject::SetT(x) | PscObject::CollectionT(x, _) => items(x, seen),
+            PscObject::MapT(x) => x.iter().map(|(k, v)| size_of(k, seen) + size_of(v, seen)).sum(),
+            PscObject::RefT(x) if seen.insert(Rc::as_ptr(x) as *const ()) => {
+                x.try_borrow().map_or(0, |x| size_of(&x, seen))
+            }
+            PscObject::ObjectT(x) if seen.insert(Rc::as_ptr(x) as *const ()) => {
+                x.try_borrow().map_or(0, |x| vars_size(&x.fields, seen))
+            }
+            _ => 0,
+        }
+}
+
+fn vars_size(vars: &Vars, seen: &mut HashSet<*const ()>) -> usize {
+    let mut ret = 0;
+    for (name, cell) in vars {
+        if seen.insert(Rc::as_ptr(cell) as *const ()) {
+            ret += name.len() + cell.try_borrow().map_or(0, |x| size_of(&x, seen));
+        }
+    }
+    ret
+}
+
+/// A seed that differs between runs. Browsers have no clock for
+/// `wasm32-unknown-unknown`, so there every run starts the same
+pub fn clock_seed() -> u64 {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return 0;
+    }
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_nanos() as u64)
+}
+
+/// How deep calls can go by default. Each one takes a few kilobytes of
+/// the native stack in a release build, so this fits in the usual 8MB
+pub const MAX_DEPTH: usize = 1000;
+
+pub struct Env {
+    globals: Vars,
+    /// Never empty, the first frame is the top level
+    frames: Vec<Frame>,
+    spare_scope: Option<Scope>,
+    /// How many calls can be running at once, which stops runaway recursion
+    /// with an error before it overflows the stack
+    pub max_depth: usize,
+    pub functions: HashMap<String, Rc<Function>>,
+    /// Called like builtins, which they take the place of
+    pub natives: HashMap<String, Native>,
+    pub classes: HashMap<String, Rc<Class>>,
+    /// Fail on badly typed `input` instead of asking again
+    pub strict_input: bool,
+    /// Promote overflowing integers to big integers instead of failing
+    pub bignum: bool,
+    /// Decides which builtin names are understood
+    pub dialect: Dialect,
+    /// Command line arguments after the script, for `ARGS()`
+    pub args: Vec<String>,
+    /// When the time limit runs out, if there is one
+    pub deadline: Option<Instant>,
+    /// Set once the time limit has run out, which like a halt unwinds as an
+    /// error `attempt` can't catch
+    pub timed_out: bool,
+    /// How many steps, statements and passes around loops, can run before
+    /// the program is stopped
+    pub max_steps: Option<u64>,
+    /// Statements run so far, and passes around loops
+    pub steps: u64,
+    /// Set once the step limit is reached, which unwinds like running out
+    /// of time
+    pub out_of_steps: bool,
+    /// Roughly how many bytes the variables can take up between them
+    pub max_memory: Option<usize>,
+    /// The step to next measure every variable at
+    next_measure: u64,
+    /// Set once the memory limit is reached, which unwinds like running out
+    /// of time
+    pub out_of_memory: bool,
+    /// Output the same every run: floats are rounded when output and the
+    /// clock is stopped
+    pub deterministic: bool,
+    /// State of the generator behind `RANDOM`
+    rng: u64,
+    /// Exit code of a `halt`, which unwinds as an error `attempt` can't catch
+    pub halted: Option<i32>,
+    /// The calls the last uncaught error came out of, innermost first, each
+    /// with the line it was running
+    pub trace: Vec<(String, usize)>,
+    /// Where `output` writes to
+    pub out: Box<dyn Write>,
+    /// Called with every value `output` writes, before it's written
+    pub on_output: Option<OnOutput>,
+    /// Where `input` reads from, the process's stdin when none
+    pub input: Option<Box<dyn BufRead>>,
+    /// Where asking again for badly typed input goes
+    pub err: Box<dyn Write>,
+    pub hook: Option<Box<dyn Hook>>,
+    /// Takes over hot loops and functions, unless there's a hook or a limit
+    /// to check each statement against
+    pub jit: Option<Box<dyn Jit>>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env {
+            globals: Vars::new(),
+            frames: vec![Frame::new("main", Vec::new(), None)],
+            max_depth: MAX_DEPTH,
+            spare_scope: None,
+            functions: HashMap::new(),
+            natives: HashMap::new(),
+            classes: HashMap::new(),
+            strict_input: false,
+            bignum: false,
+            dialect: Dialect::default(),
+            args: Vec::new(),
+            deadline: None,
+            timed_out: false,
+            max_steps: None,
+            steps: 0,
+            out_of_steps: false,
+            max_memory: None,
+            next_measure: 0,
+            out_of_memory: false,
+            deterministic: false,
+            rng: clock_seed(),
+            halted: None,
+            trace: Vec::new(),
+            out: Box::new(io::stdout()),
+            on_output: None,
+            input: None,
+            err: Box::new(io::stderr()),
+            hook: None,
+            jit: None,
+        }
+    }
+
+    /// Makes `RANDOM` repeat the same sequence for the same seed
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    /// The next number from a splitmix64 generator
+    pub fn random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut x = self.rng;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    pub fn run(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        self.caught();
+        for stmt in stmts {
+            let res = Stmt::eval(stmt, self);
+            // Only an error out of a call has more than the top level to show
+            if res.is_err() && !self.trace.is_empty() {
+                self.trace.push((self.frame().name.clone(), self.frame().line));
+            }
+            if let Flow::Return(_) = res? {
+                return Err(RuntimeError {
+                    msg: "Return outside of function".into(),
+                    span: None,
+                    code: Some("E020"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every global variable, sorted by name
+    pub fn globals(&self) -> Vec<(String, PscObject)> {
+        let mut ret: Vec<(String, PscObject)> =
+            self.globals.iter().map(|(k, v)| (k.clone(), v.borrow().clone())).collect();
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+        ret
+    }
+
+    /// Reads a line including its ending into `buf`, 0 at the end of input
+    pub fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(buf),
+            None => io::stdin().read_line(buf),
+        }
+    }
+
+    /// Whether anything has to be done around each statement. Most
+    /// programs run with no hook and no limits, and skip all of it
+    fn watched(&self) -> bool {
+        self.hook.is_some() || self.max_steps.is_some() || self.deadline.is_some() || self.max_memory.is_some()
+    }
+
+    /// Runs the hook if there is one, after recording `stmt` as the current
+    /// line of the call. Fails once a limit is reached, and for every
+    /// statement after, so `attempt` can't get past it
+    #[inline]
+    pub fn before(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        if !self.watched() {
+            return Ok(());
+        }
+        self.watch(stmt)
+    }
+
+    /// Counts one pass around the loop `stmt` as a step, so a loop whose
+    /// body runs no statements still runs into the limits
+    #[inline]
+    pub fn tick(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        if !self.watched() {
+            return Ok(());
+        }
+        self.frame_mut().line = stmt.span.line;
+        self.count(stmt)
+    }
+
+    fn watch(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.frame_mut().line = stmt.span.line;
+        self.count(stmt)?;
+
+        match self.hook.take() {
+            Some(mut hook) => {
+                let res = hook.before(stmt, self);
+                self.hook = Some(hook);
+                res
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a step and checks every limit against it
+    fn count(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(max) = self.max_steps.filter(|max| self.steps > *max) {
+            self.out_of_steps = true;
+            return Err(RuntimeError {
+                msg: format!("Step limit of {} exceeded at line {}", max, stmt.span.line),
+                span: None,
+                code: Some("E025"),
+            });
+        }
+        // Reading the clock for every statement would slow down tight loops
+        if self.steps.is_multiple_of(64) && self.deadline.is_some_and(|x| Instant::now() >= x) {
+            return Err(self.time_out());
+        }
+        if self.max_memory.is_some() && self.steps >= self.next_measure {
+            self.measure()?;
+        }
+        Ok(())
+    }
+
+    /// Hands the rest of the loop `stmt` to the jit, if there is one and
+    /// nothing has to watch each statement. See `Jit::resume`
+    #[inline]
+    pub fn resume(&mut self, stmt: &Stmt, count: Option<(i64, i64)>) -> Option<Result<Flow, RuntimeError>> {
+        if self.jit.is_none() || self.watched() || self.bignum {
+            return None;
+        }
+        let mut jit = self.jit.take()?;
+        let res = jit.resume(stmt, count, self);
+        self.jit = Some(jit);
+        res
+    }
+
+    /// Runs the hook's `after` if there is one
+    #[inline]
+    pub fn after(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        match self.hook.take() {
+            Some(mut hook) => {
+                let res = hook.after(stmt, self);
+                self.hook = Some(hook);
+                res
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records `stmt` as where the error coming out of it failed in the
+    /// current call, unless a statement inside it already was, which gives
+    /// the stack backtrace its lines without tracking them as the program
+    /// runs
+    #[cold]
+    pub fn failed(&mut self, stmt: &Stmt) {
+        self.failed_at(stmt.span.line);
+    }
+
+    /// `failed` for a statement known only by its line, like one the jit ran
+    pub fn failed_at(&mut self, line: usize) {
+        let frame = self.frame_mut();
+        if !frame.failed {
+            frame.failed = true;
+            frame.line = line;
+        }
+    }
+
+    /// Forgets the error unwinding through the current call, as `attempt`
+    /// does once it has caught it
+    pub fn caught(&mut self) {
+        self.trace.clear();
+        self.frame_mut().failed = false;
+    }
+
+    /// Whether the program has been stopped by a halt or a limit, which
+    /// `attempt` can't catch
+    pub fn stopped(&self) -> bool {
+        self.halted.is_some() || self.timed_out || self.out_of_steps || self.out_of_memory
+    }
+
+    /// Milliseconds since 1970 began for `NOW()` and `TODAY()`, stopped at
+    /// that moment when running deterministically or there's no clock
+    pub fn now(&self) -> i64 {
+        if self.deterministic || cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            return 0;
+        }
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis() as i64)
+    }
+
+    /// The error for running out of time, marking the program as timed out
+    pub fn time_out(&mut self) -> RuntimeError {
+        self.timed_out = true;
+        RuntimeError {
+            msg: format!("Time limit exceeded at line {}", self.frame().line),
+            span: None,
+            code: Some("E026"),
+        }
+    }
+
+    /// Fails if `val` alone is over the memory limit, checked as it's stored
+    /// so a value that keeps doubling can't get far past it
+    pub fn check_memory(&mut self, val: &PscObject) -> Result<(), RuntimeError> {
+        match self.max_memory {
+            Some(max) if size_of(val, &mut HashSet::new()) > max => Err(self.exceed_memory(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if `count` copies of `item` would be over the memory limit, so
+    /// an array of them can be turned down before it's made
+    pub fn check_items(&mut self, item: &PscObject, count: usize) -> Result<(), RuntimeError> {
+        match self.max_memory {
+            Some(max) if size_of(item, &mut HashSet::new()).saturating_mul(count) > max => Err(self.exceed_memory(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds up every variable against the memory limit. It takes longer the
+    /// more there are, so it's done less often as they grow
+    fn measure(&mut self) -> Result<(), RuntimeError> {
+        let max = match self.max_memory {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut seen = HashSet::new();
+        let mut total = vars_size(&self.globals, &mut seen);
+        for frame in &self.frames {
+            for scope in &frame.scopes {
+                if seen.insert(Rc::as_ptr(scope) as *const ()) {
+                    total += vars_size(&scope.borrow(), &mut seen);
+                }
+            }
+            if let Some(this) = &frame.this {
+                total += size_of(&PscObject::ObjectT(this.clone()), &mut seen);
+            }
+        }
+
+        if total > max {
+            return Err(self.exceed_memory(max));
+        }
+        self.next_measure = self.steps + (total as u64 / 256).max(256);
+        Ok(())
+    }
+
+    /// The error for going over the memory limit, marking the program as
+    /// out of memory
+    fn exceed_memory(&mut self, max: usize) -> RuntimeError {
+        self.out_of_memory = true;
+        RuntimeError {
+            msg: format!("Memory limit of {} bytes exceeded at line {}", max, self.frame().line),
+            span: None,
+            code: Some("E027"),
+        }
+    }
+
+    /// Number of calls running, 1 at the top level
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Every call running, innermost first
+    pub fn backtrace(&self) -> Vec<CallInfo> {
+        (0..self.frames.len()).rev().map(|i| self.call_info(i)).collect()
+    }
+
+    /// The innermost call, the first entry of `backtrace`
+    pub fn current(&self) -> CallInfo {
+        self.call_info(self.frames.len() - 1)
+    }
+
+    fn call_info(&self, i: usize) -> CallInfo {
+        let frame = &self.frames[i];
+        let mut vars: Vars = Vars::new();
+        if i == 0 {
+            vars.extend(self.globals.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        if let Some(this) = &frame.this {
+            vars.extend(this.borrow().fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        for scope in &frame.scopes {
+            vars.extend(scope.borrow().iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
+        let mut vars: Vec<(String, PscObject)> = vars.into_iter().map(|(k, v)| (k, v.borrow().clone())).collect();
+        vars.sort_by(|a, b| a.0.cmp(&b.0));
+
+        CallInfo {
+            name: frame.name.clone(),
+            line: frame.line,
+            vars,
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        match self.frames.last() {
+            Some(x) => x,
+            None => unreachable!(),
+        }
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        match self.frames.last_mut() {
+            Some(x) => x,
+            None => unreachable!(),
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        let scope = self.spare_scope.take().unwrap_or_default();
+        self.frame_mut().scopes.push(scope);
+    }
+
+    /// A scope nothing captured is kept for the next `push_scope`, so a loop
+    /// doesn't allocate one for every time round
+    pub fn pop_scope(&mut self) {
+        if let Some(scope) = self.frame_mut().scopes.pop() {
+            if Rc::strong_count(&scope) == 1 {
+                scope.borrow_mut().clear();
+                self.spare_scope = Some(scope);
+            }
+        }
+    }
+
+    /// Makes `name` refer to the global variable for the rest of the call
+    pub fn declare_global(&mut self, name: &str) {
+        if self.frames.len() > 1 {
+            self.frame_mut().global_names.insert(name.to_string());
+        }
+    }
+
+    /// Creates a variable in the innermost scope, shadowing any outer one
+    pub fn declare(&mut self, name: &str, val: PscObject) {
+        let cell = Rc::new(RefCell::new(val));
+
+        match self.frame().scopes.last() {
+            Some(scope) => scope.borrow_mut().insert(name.to_string(), cell),
+            None => self.globals.insert(name.to_string(), cell),
+        };
+    }
+
+    /// Finds a variable, looking through the block scopes of the current
+    /// call innermost first, then fields of the current object, then globals
+    pub fn lookup(&self, name: &str) -> Option<Rc<RefCell<PscObject>>> {
+        let frame = self.frame();
+
+        if !frame.global_names.contains(name) {
+            if let Some(cell) = frame.lookup_local(name) {
+                return Some(cell);
+            }
+        }
+
+        self.global(name)
+    }
+
+    /// A global variable, even where a local one hides it
+    pub fn global(&self, name: &str) -> Option<Rc<RefCell<PscObject>>> {
+        self.globals.get(name).cloned()
+    }
+
+    /// The existing variable `set_var` would assign to, which isn't always
+    /// the one `lookup` finds: a call reads globals, but only assigns them
+    /// after `global NAME`
+    pub fn place(&self, name: &str) -> Option<Rc<RefCell<PscObject>>> {
+        let top_level = self.frames.len() == 1;
+        let frame = self.frame();
+        let global = frame.global_names.contains(name);
+
+        match frame.lookup_local(name) {
+            Some(cell) if !global => Some(cell),
+            _ if global || top_level => self.global(name),
+            _ => None,
+        }
+    }
+
+    /// Assigns to an existing variable visible from the current call,
+    /// otherwise creates a new one in the innermost scope. Globals are only
+    /// assigned from inside a call after `global NAME`
+    pub fn set_var(&mut self, name: &str, val: PscObject) -> Result<(), RuntimeError> {
+        match self.place(name) {
+            Some(cell) => *borrow_mut(&cell)? = val,
+            None if self.frame().global_names.contains(name) => {
+                self.globals.insert(name.to_string(), Rc::new(RefCell::new(val)));
+            }
+            None => self.declare(name, val),
+        }
+
+        Ok(())
+    }
+
+    pub fn this(&self) -> Option<Rc<RefCell<Object>>> {
+        self.frame().this.clone()
+    }
+
+    pub fn current_class(&self) -> Option<Rc<Class>> {
+        self.frame().class.clone()
+    }
+
+    /// `class` followed by its parent, grandparent and so on
+    pub fn ancestors(&self, class: &Rc<Class>) -> Vec<Rc<Class>> {
+        let mut ret = vec![class.clone()];
+
+        while let Some(parent) = ret.last().and_then(|x| x.parent.as_ref()) {
+            match self.classes.get(parent) {
+                Some(x) if !ret.iter().any(|y| Rc::ptr_eq(x, y)) => ret.push(x.clone()),
+                _ => break,
+            }
+        }
+
+        ret
+    }
+
+    /// Looks a method up through the inheritance chain, returning the class
+    /// that declares it
+    pub fn find_method(&self, class: &Rc<Class>, name: &str) -> Option<(Rc<Class>, Method)> {
+        self.ancestors(class)
+            .into_iter()
+            .find_map(|x| x.method(name).cloned().map(|m| (x.clone(), m)))
+    }
+
+    /// Defines a function in the current call's innermost scope, capturing
+    /// everything visible from it. Top level functions are global instead
+    pub fn define(&mut self, func: &Rc<Function>) {
+        if self.frames.len() == 1 {
+            self.functions.insert(func.name.clone(), func.clone());
+            return;
+        }
+
+        let frame = self.frame();
+        let closure = Closure {
+            func: func.clone(),
+            scopes: frame.scopes.clone(),
+            method_of: frame.this.clone().zip(frame.class.clone()),
+        };
+
+        self.declare(&func.name, PscObject::FunctionT(Rc::new(closure)));
+    }
+
+    /// A nested function (or a variable holding one) visible from the
+    /// current call
+    pub fn closure(&self, name: &str) -> Option<Rc<Closure>> {
+        match self.lookup(name) {
+            Some(cell) => match &*cell.borrow() {
+                PscObject::FunctionT(closure) => Some(closure.clone()),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn call_closure(&mut self, closure: &Closure, args: Vec<PscObject>) -> Result<PscObject, RuntimeError> {
+        self.call_in(&closure.func, args, closure.method_of.clone(), closure.scopes.clone())
+    }
+
+    /// Runs `func` in a fresh frame with its parameters bound to `args`
+    pub fn call(
+        &mut self,
+        func: &Function,
+        args: Vec<PscObject>,
+        method_of: Option<(Rc<RefCell<Object>>, Rc<Class>)>,
+    ) -> Result<PscObject, RuntimeError> {
+        self.call_in(func, args, method_of, Vec::new())
+    }
+
+    /// `call` with the captured `scopes` of a nested function outside the
+    /// parameters
+    fn call_in(
+        &mut self,
+        func: &Function,
+        args: Vec<PscObject>,
+        method_of: Option<(Rc<RefCell<Object>>, Rc<Class>)>,
+        mut scopes: Vec<Scope>,
+    ) -> Result<PscObject, RuntimeError> {
+        if args.len() != func.params.len() {
+            return Err(RuntimeError {
+                msg: format!(
+                    "{} expects {} argument(s) but got {}",
+                    func.name,
+                    func.params.len(),
+                    args.len()
+                ),
+                span: None,
+                code: Some("E002"),
+            });
+        }
+        if self.depth() > self.max_depth {
+            return Err(RuntimeError {
+                msg: format!("Maximum call depth of {} exceeded in {}", self.max_depth, func.name),
+                span: None,
+                code: None,
+            });
+        }
+
+        if self.jit.is_some() && method_of.is_none() && scopes.is_empty() {
+            if let Some(res) = self.jit_call(func, &args) {
+                return res;
+            }
+        }
+
+        let mut locals = Vars::new();
+        for (param, arg) in func.params.iter().zip(args) {
+            locals.insert(param.clone(), Rc::new(RefCell::new(arg)));
+        }
+
+        scopes.push(Rc::new(RefCell::new(locals)));
+        self.frames.push(Frame::new(&func.name, scopes, method_of));
+        let res = Stmt::eval_block(&func.stmts, self);
+        // Each call the error unwinds through adds itself, innermost first
+        if res.is_err() {
+            self.trace.push((self.frame().name.clone(), self.frame().line));
+        }
+        self.frames.pop();
+
+        match res? {
+            Flow::Return(x) => Ok(x),
+            Flow::Next => Ok(PscObject::NullT),
+        }
+    }
+
+    /// Hands a call of `func` to the jit when it's the top level function of
+    /// that name, rather than a nested one or a method, and nothing has to
+    /// watch each statement
+    fn jit_call(&mut self, func: &Function, args: &[PscObject]) -> Option<Result<PscObject, RuntimeError>> {
+        if self.watched() || self.bignum {
+            return None;
+        }
+        let func = self.functions.get(&func.name).filter(|x| std::ptr::eq(&***x, func))?.clone();
+        let mut jit = self.jit.take()?;
+        let res = jit.call(&func, args, self);
+        self.jit = Some(jit);
+        res
+    }
+}
+
+pub fn borrow_mut(cell: &RefCell<PscObject>) -> Result<std::cell::RefMut<'_, PscObject>, RuntimeError> {
+    match cell.try_borrow_mut() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(RuntimeError {
+            msg: "Value is already being modified".into(),
+            span: None,
+            code: Some("E019"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Runs `source` on a stack big enough for `MAX_DEPTH` calls in a debug
+    /// build, returning the error it stopped with
+    fn run_error(source: &str) -> Option<String> {
+        let source = source.to_string();
+        let run = move || {
+            let stmts = crate::parse(&source, &Default::default()).unwrap();
+            let mut env = Env::new();
+            env.out = Box::new(io::sink());
+            env.run(&stmts).err().map(|x| x.msg)
+        };
+        thread::Builder::new().stack_size(256 << 20).spawn(run).unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn recursion_stops_at_max_depth() {
+        let msg = run_error("function F(N)\n  return F(N + 1)\nend function\noutput F(1)");
+        assert_eq!(msg.as_deref(), Some("Maximum call depth of 1000 exceeded in F"));
+    }
+
+    #[test]
+    fn closure_recursion_stops_at_max_depth() {
+        let source = "function OUTER()\n  function INNER(N)\n    return INNER(N + 1)\n  end function\n  return INNER(1)\nend function\noutput OUTER()";
+        assert_eq!(run_error(source).as_deref(), Some("Maximum call depth of 1000 exceeded in INNER"));
+    }
+
+    #[test]
+    fn recursion_within_max_depth_runs() {
+        let msg = run_error("function F(N)\n  if N == 0 then\n    return 0\n  end if\n  return F(N - 1) + 1\nend function\noutput F(900)");
+        assert_eq!(msg, None);
+    }
+
+    /// Runs `source` with a time limit of `ms`, returning the error it
+    /// stopped with
+    fn run_timed(source: &str, ms: u64) -> Option<String> {
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        let mut env = Env::new();
+        env.out = Box::new(io::sink());
+        env.deadline = Some(Instant::now() + std::time::Duration::from_millis(ms));
+        env.run(&stmts).err().map(|x| x.msg)
+    }
+
+    #[test]
+    fn empty_infinite_loop_times_out() {
+        assert_eq!(run_timed("loop while true\nend loop", 50).as_deref(), Some("Time limit exceeded at line 1"));
+        assert_eq!(run_timed("X = 1\nloop while true\nend loop", 50).as_deref(), Some("Time limit exceeded at line 2"));
+        assert_eq!(run_timed("loop until false\nend loop", 50).as_deref(), Some("Time limit exceeded at line 1"));
+    }
+
+    #[test]
+    fn loop_conditions_must_be_bool() {
+        let msg = run_error("X = 1\nloop while X\nend loop");
+        assert_eq!(msg.as_deref(), Some("While expression not bool type"));
+        let msg = run_error("X = 0\nloop until X\nend loop");
+        assert_eq!(msg.as_deref(), Some("Until expression not bool type"));
+    }
+
+    #[test]
+    fn for_loops_never_skip_bounds_they_cannot_use() {
+        let run = |source: &str| {
+            let stmts = crate::parse(source, &Default::default()).unwrap();
+            let mut env = Env::new();
+            env.out = Box::new(io::sink());
+            env.bignum = true;
+            env.run(&stmts).err().map(|x| x.msg)
+        };
+
+        let source = "X = 9223372036854775807 + 1\nloop J from X to X\n  output J\nend loop";
+        assert_eq!(run(source).as_deref(), Some("For loop bound 9223372036854775808 is too big"));
+        let source = "loop J from 1.5 to 3\n  output J\nend loop";
+        assert_eq!(run(source).as_deref(), Some("For loop bounds must be integers, not real"));
+    }
+
+    /// What `source` outputs with `--bignum`, or the error it stops with
+    fn run_bignum(source: &str) -> Result<String, String> {
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        let out = Captured::default();
+        let mut env = Env::new();
+        env.out = Box::new(out.clone());
+        env.bignum = true;
+        env.run(&stmts).map(|_| out.text()).map_err(|x| x.msg)
+    }
+
+    #[test]
+    fn sum_grows_into_big_integers() {
+        let source = "X = 9223372036854775807\noutput SUM([X, X])\noutput SUM([X, 1, 1.5])\nY = X + 1\noutput SUM([Y, Y, 0 - Y])\noutput AVERAGE([Y, Y])";
+        let out = "18446744073709551614\n9223372036854776000\n9223372036854775808\n9223372036854776000\n";
+        assert_eq!(run_bignum(source).as_deref(), Ok(out));
+    }
+
+    #[test]
+    fn min_and_max_compare_big_integers() {
+        let source = "Y = 9223372036854775807 + 1\noutput MAX(Y, 1)\noutput MIN(Y, 1)\noutput MIN([Y, Y * 2])\noutput MAX(0 - Y * 2, 0 - Y)\noutput MAX(Y, 1.5)";
+        let out = "9223372036854775808\n1\n9223372036854775808\n-9223372036854775808\n9223372036854775808\n";
+        assert_eq!(run_bignum(source).as_deref(), Ok(out));
+    }
+
+    #[test]
+    fn builtins_say_what_they_expect() {
+        let msg = run_error("output REPLACE(\"abc\", \"\", \"-\")");
+        assert_eq!(msg.as_deref(), Some("REPLACE expects a non-empty string to replace"));
+        assert_eq!(run_error("output TRIM(5)").as_deref(), Some("TRIM expects a string"));
+        assert_eq!(run_error("output TRIM(\"a\", \"b\")").as_deref(), Some("TRIM expects 1 argument(s) but got 2"));
+        assert_eq!(run_error("output CONTAINS([1, 2], 2)").as_deref(), Some("CONTAINS expects two strings"));
+        let msg = run_error("output RANDOM(10, 1)");
+        assert_eq!(msg.as_deref(), Some("RANDOM lower bound is above upper bound"));
+        assert_eq!(run_error("output RANDOM(1.5, 2)").as_deref(), Some("RANDOM expects no arguments or two integers"));
+        for source in ["SLEEP(0 - 1)", "SLEEP(\"x\")"] {
+            let msg = run_error(source);
+            assert_eq!(msg.as_deref(), Some("SLEEP expects a non-negative number of milliseconds"));
+        }
+        assert_eq!(run_error("output TRIMM(5)").as_deref(), Some("Unknown function TRIMM with 1 argument(s)"));
+    }
+
+    #[test]
+    fn errors_carry_their_code() {
+        let code = |source: &str| {
+            let stmts = crate::parse(source, &Default::default()).unwrap();
+            let mut env = Env::new();
+            env.out = Box::new(io::sink());
+            env.run(&stmts).unwrap_err().code
+        };
+        assert_eq!(code("output X"), Some("E001"));
+        assert_eq!(code("output TRIM(5)"), Some("E031"));
+        assert_eq!(code("output TRIM(\"a\", \"b\")"), Some("E002"));
+        assert_eq!(code("output 1 / 0"), Some("E017"));
+        assert_eq!(code("A = [1]\noutput A[1]"), Some("E015"));
+
+        let e = crate::parse("if true then\n  output 1", &Default::default()).unwrap_err();
+        assert_eq!(e.code, Some("E011"));
+    }
+
+    #[test]
+    fn deterministic_runs_stop_the_clock() {
+        let run = |deterministic: bool| {
+            let stmts = crate::parse("output NOW()\noutput TODAY()", &Default::default()).unwrap();
+            let out = Captured::default();
+            let mut env = Env::new();
+            env.out = Box::new(out.clone());
+            env.deterministic = deterministic;
+            env.run(&stmts).unwrap();
+            out.text()
+        };
+        assert_eq!(run(true), "0\n1970-01-01\n");
+
+        let text = run(false);
+        let (now, today) = text.trim_end().split_once('\n').unwrap();
+        assert!(now.parse::<i64>().unwrap() > 1_700_000_000_000);
+        assert!(today.len() == 10 && today.as_bytes()[4] == b'-' && today.as_bytes()[7] == b'-', "{}", today);
+    }
+
+    #[test]
+    fn declared_arrays_are_made_when_run() {
+        let cambridge = crate::lex::LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let run = |source: &str, max_memory: Option<usize>| {
+            let stmts = crate::parse(source, &cambridge).unwrap();
+            let out = Captured::default();
+            let mut env = Env::new();
+            env.dialect = Dialect::Cambridge;
+            env.out = Box::new(out.clone());
+            env.max_memory = max_memory;
+            env.run(&stmts).map(|_| out.text()).map_err(|x| x.msg)
+        };
+
+        let source = "DECLARE A : ARRAY[5:7, 1:2] OF INTEGER\nOUTPUT LENGTH(A)\nOUTPUT LENGTH(A[2])";
+        assert_eq!(run(source, None).as_deref(), Ok("3\n2\n"));
+        let source = "DECLARE A : ARRAY[1:2000000000] OF INTEGER";
+        assert_eq!(run(source, Some(4096)), Err("Memory limit of 4096 bytes exceeded at line 1".to_string()));
+    }
+
+    #[test]
+    fn empty_infinite_loop_runs_out_of_steps() {
+        let stmts = crate::parse("X = 0\nloop while true\nend loop", &Default::default()).unwrap();
+        let mut env = Env::new();
+        env.max_steps = Some(1000);
+        let msg = env.run(&stmts).err().map(|x| x.msg);
+        assert_eq!(msg.as_deref(), Some("Step limit of 1000 exceeded at line 2"));
+        assert_eq!(env.steps, 1001);
+    }
+
+    #[test]
+    fn attempt_cannot_catch_the_step_limit() {
+        let source = "attempt\n  loop while true\n  end loop\non error E\n  output E\nend attempt\noutput 2";
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        let out = Captured::default();
+        let mut env = Env::new();
+        env.out = Box::new(out.clone());
+        env.max_steps = Some(100);
+        let msg = env.run(&stmts).err().map(|x| x.msg);
+        assert_eq!(msg.as_deref(), Some("Step limit of 100 exceeded at line 2"));
+        assert_eq!(out.text(), "");
+    }
+
+    #[test]
+    fn attempt_cannot_catch_the_memory_limit() {
+        let source = "attempt\n  S = \"x\"\n  loop while true\n    S = S + S\n  end loop\non error E\n  output E\nend attempt\noutput 2";
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        let out = Captured::default();
+        let mut env = Env::new();
+        env.out = Box::new(out.clone());
+        env.max_memory = Some(4096);
+        let msg = env.run(&stmts).err().map(|x| x.msg);
+        assert_eq!(msg.as_deref(), Some("Memory limit of 4096 bytes exceeded at line 4"));
+        assert_eq!(out.text(), "");
+    }
+}
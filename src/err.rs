@@ -1,9 +1,14 @@
+use crate::lex::Span;
 use std::error::Error;
 use std::fmt;
 
 #[derive(Debug)]
 pub struct ParseError {
     pub msg: String,
+    /// Where in the source the error is, when that's known
+    pub span: Option<Span>,
+    /// The code `psc --explain` describes this kind of error under
+    pub code: Option<&'static str>,
 }
 
 impl fmt::Display for ParseError {
@@ -22,6 +27,10 @@ impl Error for ParseError {
 #[derive(Debug)]
 pub struct RuntimeError {
     pub msg: String,
+    /// Where the call or statement that failed is, set as the error leaves it
+    pub span: Option<Span>,
+    /// The code `psc --explain` describes this kind of error under
+    pub code: Option<&'static str>,
 }
 
 impl fmt::Display for RuntimeError {
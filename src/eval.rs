@@ -1,129 +1,1187 @@
+use crate::bigint::BigInt;
+use crate::builtins;
+use crate::env::{borrow_mut, Env, Scope};
 use crate::err::RuntimeError;
-use crate::lex::Punctuation;
+use crate::lex::{Punctuation, Span};
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Every variable lives in its own cell so pointers (`@X`) can alias it
+pub type Vars = HashMap<String, Rc<RefCell<PscObject>>>;
 
 #[derive(Debug, Clone)]
 pub enum PscObject {
     IntT(i64),
+    /// Only produced by `--bignum` when an integer outgrows `i64`
+    BigIntT(BigInt),
     FloatT(f64),
     StringT(String),
     BoolT(bool),
+    ArrayT(Vec<PscObject>),
+    /// Entries are kept in insertion order, keys are unique
+    MapT(Vec<(PscObject, PscObject)>),
+    /// Elements are kept in insertion order without duplicates
+    SetT(Vec<PscObject>),
+    /// IB's `Collection`, the items and the position `getNext` reads from
+    CollectionT(Vec<PscObject>, usize),
+    RefT(Rc<RefCell<PscObject>>),
+    ObjectT(Rc<RefCell<Object>>),
+    FunctionT(Rc<Closure>),
+    NullT,
+}
+
+/// A function defined inside another, along with the scopes it can see
+pub struct Closure {
+    pub func: Rc<Function>,
+    pub scopes: Vec<Scope>,
+    pub method_of: Option<(Rc<RefCell<Object>>, Rc<Class>)>,
+}
+
+// The captured scopes usually contain the closure itself
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Closure({})", self.func.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub class: Rc<Class>,
+    pub fields: Vars,
+}
+
+impl Object {
+    /// Private members may only be touched by methods of the declaring class
+    /// or its subclasses
+    fn check_access(owner: &Rc<Class>, public: bool, name: &str, env: &Env) -> Result<(), RuntimeError> {
+        let inside = match env.this() {
+            Some(this) => {
+                let class = this.borrow().class.clone();
+                env.ancestors(&class).iter().any(|x| Rc::ptr_eq(x, owner))
+            }
+            None => false,
+        };
+
+        if public || inside {
+            Ok(())
+        } else {
+            Err(RuntimeError {
+                msg: format!("{} is private to class {}", name, owner.name),
+                span: None,
+                code: Some("E006"),
+            })
+        }
+    }
+
+    pub fn field(obj: &Rc<RefCell<Object>>, name: &str, env: &Env) -> Result<Rc<RefCell<PscObject>>, RuntimeError> {
+        let obj = obj.borrow();
+
+        let decl = env
+            .ancestors(&obj.class)
+            .into_iter()
+            .find_map(|class| class.fields.iter().find(|x| x.name == name).map(|x| (x.public, class.clone())));
+
+        let (public, owner) = match decl {
+            Some(x) => x,
+            None => {
+                return Err(RuntimeError {
+                    msg: format!("Class {} has no field {}", obj.class.name, name),
+                    span: None,
+                    code: Some("E005"),
+                })
+            }
+        };
+        Object::check_access(&owner, public, name, env)?;
+
+        match obj.fields.get(name) {
+            Some(cell) => Ok(cell.clone()),
+            None => unreachable!(),
+        }
+    }
+
+    pub fn call_method(
+        obj: &Rc<RefCell<Object>>,
+        name: &str,
+        args: Vec<PscObject>,
+        env: &mut Env,
+    ) -> Result<PscObject, RuntimeError> {
+        let class = obj.borrow().class.clone();
+
+        let (owner, method) = match env.find_method(&class, name) {
+            Some(x) => x,
+            None => {
+                return Err(RuntimeError {
+                    msg: format!("Class {} has no method {}", class.name, name),
+                    span: None,
+                    code: Some("E003"),
+                })
+            }
+        };
+        Object::check_access(&owner, method.public, name, env)?;
+
+        env.call(&method.func, args, Some((obj.clone(), owner)))
+    }
+}
+
+impl PartialEq for PscObject {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PscObject::IntT(l), PscObject::IntT(r)) => l == r,
+            (PscObject::BigIntT(l), PscObject::BigIntT(r)) => l == r,
+            (PscObject::FloatT(l), PscObject::FloatT(r)) => l == r,
+            (PscObject::StringT(l), PscObject::StringT(r)) => l == r,
+            (PscObject::BoolT(l), PscObject::BoolT(r)) => l == r,
+            (PscObject::ArrayT(l), PscObject::ArrayT(r)) => l == r,
+            (PscObject::MapT(l), PscObject::MapT(r)) => l == r,
+            (PscObject::SetT(l), PscObject::SetT(r)) => {
+                l.len() == r.len() && l.iter().all(|x| r.contains(x))
+            }
+            (PscObject::CollectionT(l, _), PscObject::CollectionT(r, _)) => l == r,
+            // Pointers are equal when they point at the same cell
+            (PscObject::RefT(l), PscObject::RefT(r)) => Rc::ptr_eq(l, r),
+            (PscObject::ObjectT(l), PscObject::ObjectT(r)) => Rc::ptr_eq(l, r),
+            (PscObject::FunctionT(l), PscObject::FunctionT(r)) => Rc::ptr_eq(l, r),
+            (PscObject::NullT, PscObject::NullT) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<i64> for PscObject {
+    fn from(x: i64) -> Self {
+        PscObject::IntT(x)
+    }
+}
+
+impl From<f64> for PscObject {
+    fn from(x: f64) -> Self {
+        PscObject::FloatT(x)
+    }
+}
+
+impl From<bool> for PscObject {
+    fn from(x: bool) -> Self {
+        PscObject::BoolT(x)
+    }
+}
+
+impl From<&str> for PscObject {
+    fn from(x: &str) -> Self {
+        PscObject::StringT(x.to_string())
+    }
+}
+
+impl From<String> for PscObject {
+    fn from(x: String) -> Self {
+        PscObject::StringT(x)
+    }
+}
+
+impl<T: Into<PscObject>> From<Vec<T>> for PscObject {
+    fn from(x: Vec<T>) -> Self {
+        PscObject::ArrayT(x.into_iter().map(Into::into).collect())
+    }
+}
+
+/// `None` is `null`
+impl<T: Into<PscObject>> From<Option<T>> for PscObject {
+    fn from(x: Option<T>) -> Self {
+        x.map_or(PscObject::NullT, Into::into)
+    }
+}
+
+fn expected(what: &str, got: &PscObject) -> RuntimeError {
+    RuntimeError {
+        msg: format!("Expected {} but got {}", what, got.type_name()),
+        span: None,
+        code: Some("E014"),
+    }
+}
+
+impl TryFrom<PscObject> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(x: PscObject) -> Result<Self, RuntimeError> {
+        match x {
+            PscObject::IntT(x) => Ok(x),
+            x => Err(expected("integer", &x)),
+        }
+    }
+}
+
+/// Integers too, like where a program expects a real
+impl TryFrom<PscObject> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(x: PscObject) -> Result<Self, RuntimeError> {
+        match x {
+            PscObject::FloatT(x) => Ok(x),
+            PscObject::IntT(x) => Ok(x as f64),
+            x => Err(expected("real", &x)),
+        }
+    }
+}
+
+impl TryFrom<PscObject> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(x: PscObject) -> Result<Self, RuntimeError> {
+        match x {
+            PscObject::BoolT(x) => Ok(x),
+            x => Err(expected("boolean", &x)),
+        }
+    }
+}
+
+impl TryFrom<PscObject> for String {
+    type Error = RuntimeError;
+
+    fn try_from(x: PscObject) -> Result<Self, RuntimeError> {
+        match x {
+            PscObject::StringT(x) => Ok(x),
+            x => Err(expected("string", &x)),
+        }
+    }
+}
+
+/// Each item converted in turn, failing on the first that can't be
+impl<T: TryFrom<PscObject, Error = RuntimeError>> TryFrom<PscObject> for Vec<T> {
+    type Error = RuntimeError;
+
+    fn try_from(x: PscObject) -> Result<Self, RuntimeError> {
+        match x {
+            PscObject::ArrayT(items) => items.into_iter().map(T::try_from).collect(),
+            x => Err(expected("array", &x)),
+        }
+    }
+}
+
+impl PscObject {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PscObject::IntT(_) | PscObject::BigIntT(_) => "integer",
+            PscObject::FloatT(_) => "real",
+            PscObject::StringT(_) => "string",
+            PscObject::BoolT(_) => "boolean",
+            PscObject::ArrayT(_) => "array",
+            PscObject::MapT(_) => "map",
+            PscObject::SetT(_) => "set",
+            PscObject::CollectionT(..) => "collection",
+            PscObject::RefT(_) => "pointer",
+            PscObject::ObjectT(_) => "object",
+            PscObject::FunctionT(_) => "function",
+            PscObject::NullT => "null",
+        }
+    }
+
+    /// Formats the value the way it would be written in source, used for
+    /// values nested inside collections
+    pub fn repr(&self) -> String {
+        match self {
+            PscObject::StringT(x) => format!("\"{}\"", x),
+            _ => self.to_string(),
+        }
+    }
+
+    fn array_pos(items: &[PscObject], key: &PscObject) -> Result<usize, RuntimeError> {
+        match key {
+            PscObject::IntT(i) if *i >= 0 && (*i as usize) < items.len() => Ok(*i as usize),
+            PscObject::IntT(i) => Err(RuntimeError {
+                msg: format!("Index {} out of bounds for array of length {}", i, items.len()),
+                span: None,
+                code: Some("E015"),
+            }),
+            _ => Err(RuntimeError {
+                msg: format!("Array index must be an integer, not {}", key.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    fn index(&self, key: &PscObject) -> Result<&PscObject, RuntimeError> {
+        match self {
+            PscObject::ArrayT(items) => Ok(&items[PscObject::array_pos(items, key)?]),
+
+            PscObject::MapT(entries) => match entries.iter().find(|(k, _)| k == key) {
+                Some((_, v)) => Ok(v),
+                None => Err(RuntimeError {
+                    msg: format!("Key not found: {}", key.repr()),
+                    span: None,
+                    code: Some("E016"),
+                }),
+            },
+
+            _ => Err(RuntimeError {
+                msg: format!("Cannot index into {}", self.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    fn index_mut(&mut self, key: &PscObject) -> Result<&mut PscObject, RuntimeError> {
+        match self {
+            PscObject::ArrayT(items) => {
+                let pos = PscObject::array_pos(items, key)?;
+                Ok(&mut items[pos])
+            }
+
+            PscObject::MapT(entries) => match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => Ok(v),
+                None => Err(RuntimeError {
+                    msg: format!("Key not found: {}", key.repr()),
+                    span: None,
+                    code: Some("E016"),
+                }),
+            },
+
+            _ => Err(RuntimeError {
+                msg: format!("Cannot index into {}", self.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    fn set_index(&mut self, key: PscObject, val: PscObject) -> Result<(), RuntimeError> {
+        match self {
+            PscObject::ArrayT(_) => {
+                *self.index_mut(&key)? = val;
+                Ok(())
+            }
+
+            PscObject::MapT(entries) => {
+                match entries.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, v)) => *v = val,
+                    None => entries.push((key, val)),
+                }
+
+                Ok(())
+            }
+
+            _ => Err(RuntimeError {
+                msg: format!("Cannot index into {}", self.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    fn pointee(self) -> Result<Rc<RefCell<PscObject>>, RuntimeError> {
+        match self {
+            PscObject::RefT(cell) => Ok(cell),
+            PscObject::NullT => Err(RuntimeError {
+                msg: "Null pointer dereference".into(),
+                span: None,
+                code: Some("E029"),
+            }),
+            x => Err(RuntimeError {
+                msg: format!("Cannot dereference {}", x.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    fn object(self) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        match self {
+            PscObject::ObjectT(obj) => Ok(obj),
+            x => Err(RuntimeError {
+                msg: format!("Expected object but got {}", x.type_name()),
+                span: None,
+                code: Some("E014"),
+            }),
+        }
+    }
+
+    /// Guesses the type of an untyped `input` line
+    fn from_input(line: &str) -> PscObject {
+        if let Ok(x) = line.parse::<bool>() {
+            PscObject::BoolT(x)
+        } else if let Ok(x) = line.parse::<i64>() {
+            PscObject::IntT(x)
+        } else if let Ok(x) = line.parse::<f64>() {
+            PscObject::FloatT(x)
+        } else {
+            PscObject::StringT(line.to_string())
+        }
+    }
+
+    pub fn set_from(items: Vec<PscObject>) -> PscObject {
+        let mut set: Vec<PscObject> = Vec::new();
+        for item in items {
+            if !set.contains(&item) {
+                set.push(item);
+            }
+        }
+        PscObject::SetT(set)
+    }
+
+    fn call_method(&mut self, name: &str, args: Vec<PscObject>) -> Result<PscObject, RuntimeError> {
+        match (self, name.to_lowercase().as_str(), args.as_slice()) {
+            (PscObject::ArrayT(items), "length", []) => Ok(PscObject::IntT(items.len() as i64)),
+
+            (PscObject::ArrayT(items), "append", [x]) => {
+                items.push(x.clone());
+                Ok(PscObject::NullT)
+            }
+
+            (PscObject::MapT(entries), "containskey", [key]) => {
+                Ok(PscObject::BoolT(entries.iter().any(|(k, _)| k == key)))
+            }
+
+            // Returns whether the element was newly added
+            (PscObject::SetT(items), "add", [x]) => {
+                if items.contains(x) {
+                    return Ok(PscObject::BoolT(false));
+                }
+                items.push(x.clone());
+                Ok(PscObject::BoolT(true))
+            }
+
+            (PscObject::SetT(items), "contains", [x]) => Ok(PscObject::BoolT(items.contains(x))),
+
+            (PscObject::SetT(items), "union", [PscObject::SetT(other)]) => {
+                Ok(PscObject::set_from(items.iter().chain(other).cloned().collect()))
+            }
+
+            (PscObject::SetT(items), "intersection", [PscObject::SetT(other)]) => {
+                Ok(PscObject::SetT(items.iter().filter(|x| other.contains(x)).cloned().collect()))
+            }
+
+            (PscObject::SetT(items), "difference", [PscObject::SetT(other)]) => {
+                Ok(PscObject::SetT(items.iter().filter(|x| !other.contains(x)).cloned().collect()))
+            }
+
+            (PscObject::CollectionT(items, _), "additem", [x]) => {
+                items.push(x.clone());
+                Ok(PscObject::NullT)
+            }
+
+            (PscObject::CollectionT(items, next), "hasnext", []) => Ok(PscObject::BoolT(*next < items.len())),
+
+            (PscObject::CollectionT(items, next), "getnext", []) => match items.get(*next) {
+                Some(x) => {
+                    *next += 1;
+                    Ok(x.clone())
+                }
+                None => Err(RuntimeError {
+                    msg: "getNext called with no items left in the collection".into(),
+                    span: None,
+                    code: Some("E024"),
+                }),
+            },
+
+            (PscObject::CollectionT(_, next), "resetnext", []) => {
+                *next = 0;
+                Ok(PscObject::NullT)
+            }
+
+            (PscObject::CollectionT(items, _), "isempty", []) => Ok(PscObject::BoolT(items.is_empty())),
+
+            (this, _, _) => Err(RuntimeError {
+                msg: format!("Unknown method {} with {} argument(s) on {}", name, args.len(), this.type_name()),
+                span: None,
+                code: Some("E003"),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for PscObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PscObject::IntT(x) => write!(f, "{}", x),
+            PscObject::BigIntT(x) => write!(f, "{}", x),
+            PscObject::FloatT(x) => write!(f, "{}", x),
+            PscObject::StringT(x) => write!(f, "{}", x),
+            PscObject::BoolT(x) => write!(f, "{}", x),
+            PscObject::ArrayT(items) => {
+                write!(f, "[")?;
+
+                for (i, x) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", x.repr())?;
+                }
+
+                write!(f, "]")
+            }
+            PscObject::MapT(entries) => {
+                write!(f, "{{")?;
+
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", k.repr(), v.repr())?;
+                }
+
+                write!(f, "}}")
+            }
+            PscObject::SetT(items) => {
+                write!(f, "{{")?;
+
+                for (i, x) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", x.repr())?;
+                }
+
+                write!(f, "}}")
+            }
+            PscObject::CollectionT(items, _) => {
+                write!(f, "Collection[")?;
+
+                for (i, x) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", x.repr())?;
+                }
+
+                write!(f, "]")
+            }
+            PscObject::RefT(cell) => write!(f, "<pointer {:p}>", Rc::as_ptr(cell)),
+            PscObject::ObjectT(obj) => write!(f, "<{} object>", obj.borrow().class.name),
+            PscObject::FunctionT(closure) => write!(f, "<function {}>", closure.func.name),
+            PscObject::NullT => write!(f, "null"),
+        }
+    }
+}
+
+/// A statement and where it is, its first line up to the last token
+/// of it on that line
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
 #[derive(Debug)]
-pub enum Stmt {
+pub enum StmtKind {
     Assign(Assign),
+    MultiAssign(MultiAssign),
     Input(Input),
     Output(Output),
     If(If),
     While(While),
     Until(Until),
+    /// `repeat ... until C`, checking after each run of the body
+    Repeat(Until),
     For(For),
+    ForEach(ForEach),
+    Expr(Expr),
+    Function(Rc<Function>),
+    Class(Rc<Class>),
+    Return(Option<Expr>),
+    Global(Vec<String>),
+    /// `TYPE NAME = ^INTEGER`, which names a type and does nothing when run
+    Type(TypeDef),
+    Attempt(Attempt),
+    Assert(Assert),
+    /// `halt` or `exit` with an optional exit code
+    Halt(Option<Expr>),
+    /// Resolved by the loader, only allowed at the top level of a file
+    Include(String),
+}
+
+/// Integer `+`, `-` and `*` that report overflow instead of panicking or
+/// wrapping, or switch to big integers with `--bignum`
+fn checked_int(l: i64, r: i64, op: &BinaryOp, bignum: bool) -> Result<PscObject, RuntimeError> {
+    let (res, sym) = match op {
+        BinaryOp::Plus => (l.checked_add(r), "+"),
+        BinaryOp::Minus => (l.checked_sub(r), "-"),
+        BinaryOp::Mul => (l.checked_mul(r), "*"),
+        _ => unreachable!(),
+    };
+
+    match res {
+        Some(x) => Ok(PscObject::IntT(x)),
+        None if bignum => big_int_op(op, BigInt::from(l), BigInt::from(r)),
+        None => Err(RuntimeError {
+            msg: format!("Integer overflow in {} {} {}", l, sym, r),
+            span: None,
+            code: Some("E018"),
+        }),
+    }
+}
+
+/// Big integers go back to plain ones whenever they fit
+fn from_big(x: BigInt) -> PscObject {
+    match x.to_i64() {
+        Some(x) => PscObject::IntT(x),
+        None => PscObject::BigIntT(x),
+    }
+}
+
+/// The start or end of a `for` loop, which has to be an integer. A big one
+/// is taken back to `i64` when it fits
+fn loop_bound(x: PscObject) -> Result<i64, RuntimeError> {
+    match x {
+        PscObject::IntT(x) => Ok(x),
+        PscObject::BigIntT(x) => x.to_i64().ok_or_else(|| RuntimeError {
+            msg: format!("For loop bound {} is too big", x),
+            span: None,
+            code: Some("E015"),
+        }),
+        x => Err(RuntimeError {
+            msg: format!("For loop bounds must be integers, not {}", x.type_name()),
+            span: None,
+            code: Some("E014"),
+        }),
+    }
+}
+
+pub fn as_big(x: &PscObject) -> Option<BigInt> {
+    match x {
+        PscObject::IntT(x) => Some(BigInt::from(*x)),
+        PscObject::BigIntT(x) => Some(x.clone()),
+        _ => None,
+    }
+}
+
+/// Arithmetic and comparisons where either side is a big integer
+pub fn big_int_op(op: &BinaryOp, l: BigInt, r: BigInt) -> Result<PscObject, RuntimeError> {
+    let ret = match op {
+        BinaryOp::Plus => from_big(l.add(&r)),
+        BinaryOp::Minus => from_big(l.sub(&r)),
+        BinaryOp::Mul => from_big(l.mul(&r)),
+        BinaryOp::Div => PscObject::FloatT(l.to_f64() / r.to_f64()),
+
+        BinaryOp::FloorDiv | BinaryOp::Mod => {
+            let (quot, rem) = match r.to_i64().and_then(|d| l.div_rem_small(d)) {
+                Some(x) => x,
+                None => {
+                    return Err(RuntimeError {
+                        msg: format!("Cannot divide big integer {} by {}", l, r),
+                        span: None,
+                        code: Some("E017"),
+                    })
+                }
+            };
+
+            if *op == BinaryOp::Mod {
+                PscObject::IntT(rem)
+            } else if rem != 0 && (rem < 0) != (r < BigInt::from(0)) {
+                from_big(quot.sub(&BigInt::from(1)))
+            } else {
+                from_big(quot)
+            }
+        }
+
+        BinaryOp::GE => PscObject::BoolT(l >= r),
+        BinaryOp::LE => PscObject::BoolT(l <= r),
+        BinaryOp::GT => PscObject::BoolT(l > r),
+        BinaryOp::LT => PscObject::BoolT(l < r),
+        BinaryOp::Equals => PscObject::BoolT(l == r),
+        BinaryOp::NotEquals => PscObject::BoolT(l != r),
+
+        _ => {
+            return Err(RuntimeError {
+                msg: "Operator not supported for big integers".into(),
+                span: None,
+                code: Some("E014"),
+            })
+        }
+    };
+
+    Ok(ret)
+}
+
+/// Runs `f` on the storage an assignable expression (`X`, `M["a"]`, `P^`)
+/// refers to, without copying the value out
+fn with_place(
+    expr: &Expr,
+    env: &mut Env,
+    f: &mut dyn FnMut(&mut PscObject) -> Result<PscObject, RuntimeError>,
+) -> Result<PscObject, RuntimeError> {
+    match expr {
+        Expr::Ident(x) => match env.lookup(x) {
+            Some(cell) => {
+                let mut val = borrow_mut(&cell)?;
+                f(&mut val)
+            }
+            None => Err(RuntimeError {
+                msg: format!("Unknow identifier: {}", x),
+                span: None,
+                code: Some("E001"),
+            }),
+        },
+
+        Expr::Index(index) => {
+            let key = Expr::eval(&index.index, env)?;
+            with_place(&index.target, env, &mut |obj| f(obj.index_mut(&key)?))
+        }
+
+        Expr::Field(field) => {
+            let obj = Expr::eval(&field.target, env)?.object()?;
+            let cell = Object::field(&obj, &field.name, env)?;
+            let mut val = borrow_mut(&cell)?;
+            f(&mut val)
+        }
+
+        Expr::Deref(target) => {
+            let cell = Expr::eval(target, env)?.pointee()?;
+            let mut val = borrow_mut(&cell)?;
+            f(&mut val)
+        }
+
+        _ => {
+            let mut val = Expr::eval(expr, env)?;
+            f(&mut val)
+        }
+    }
+}
+
+/// `val` with its floats rounded to 15 significant digits and `-0` as `0`,
+/// so the last digits of a calculation don't show when it's output
+fn round_floats(val: PscObject) -> PscObject {
+    let all = |items: Vec<PscObject>| items.into_iter().map(round_floats).collect();
+
+    match val {
+        PscObject::FloatT(x) if x.is_finite() => {
+            let x: f64 = format!("{:.14e}", x).parse().unwrap_or(x);
+            PscObject::FloatT(if x == 0.0 { 0.0 } else { x })
+        }
+        PscObject::ArrayT(items) => PscObject::ArrayT(all(items)),
+        PscObject::SetT(items) => PscObject::SetT(all(items)),
+        PscObject::CollectionT(items, next) => PscObject::CollectionT(all(items), next),
+        PscObject::MapT(entries) => PscObject::MapT(entries.into_iter().map(|(k, v)| (round_floats(k), round_floats(v))).collect()),
+        x => x,
+    }
+}
+
+/// The array `DECLARE X : ARRAY[...] OF T` starts `X` with, with `item` in
+/// every place. It's weighed against the memory limit before any of it is
+/// made, so one too big to make fails like any other value
+fn declared_array(ty: &Declared, item: PscObject, env: &mut Env) -> Result<PscObject, RuntimeError> {
+    fn fill(lengths: &[usize], item: &PscObject) -> Result<PscObject, RuntimeError> {
+        let (len, rest) = match lengths {
+            [] => return Ok(item.clone()),
+            [len, rest @ ..] => (*len, rest),
+        };
+        let mut items = Vec::new();
+        if items.try_reserve_exact(len).is_err() {
+            return Err(RuntimeError {
+                msg: format!("Not enough memory for an array of {} items", len),
+                span: None,
+                code: Some("E027"),
+            });
+        }
+        for _ in 0..len {
+            items.push(fill(rest, item)?);
+        }
+        Ok(PscObject::ArrayT(items))
+    }
+
+    let lengths = ty.lengths().unwrap_or_else(|| vec![usize::MAX]);
+    let count = lengths.iter().try_fold(1usize, |n, len| n.checked_mul(*len));
+    env.check_items(&item, count.unwrap_or(usize::MAX))?;
+    fill(&lengths, &item)
+}
+
+/// Stores `res` in the place `target` refers to
+fn assign_to(target: &Expr, res: PscObject, env: &mut Env) -> Result<(), RuntimeError> {
+    env.check_memory(&res)?;
+
+    match target {
+        Expr::Ident(x) => env.set_var(x, res)?,
+
+        Expr::Index(index) => {
+            let key = Expr::eval(&index.index, env)?;
+            let mut res = Some(res);
+
+            with_place(&index.target, env, &mut |obj| {
+                obj.set_index(key.clone(), res.take().unwrap_or(PscObject::NullT))?;
+                Ok(PscObject::NullT)
+            })?;
+        }
+
+        Expr::Field(field) => {
+            let obj = Expr::eval(&field.target, env)?.object()?;
+            let cell = Object::field(&obj, &field.name, env)?;
+            *borrow_mut(&cell)? = res;
+        }
+
+        Expr::Deref(target) => {
+            let cell = Expr::eval(target, env)?.pointee()?;
+            *borrow_mut(&cell)? = res;
+        }
+
+        _ => {
+            return Err(RuntimeError {
+                msg: "Invalid assignment target".into(),
+                span: None,
+                code: Some("E019"),
+            })
+        }
+    }
+
+    Ok(())
+}
+
+/// How control leaves a statement
+#[derive(Debug)]
+pub enum Flow {
+    Next,
+    Return(PscObject),
 }
 
 impl Stmt {
-    pub fn eval(stmt: &Self, vars: &mut HashMap<String, PscObject>) -> Result<(), RuntimeError> {
-        match stmt {
-            Stmt::Assign(assign) => {
-                let res = Expr::eval(&assign.expr, vars)?;
-                vars.insert(assign.ident.to_string(), res);
+    pub fn eval_block(stmts: &[Stmt], env: &mut Env) -> Result<Flow, RuntimeError> {
+        for stmt in stmts {
+            if let Flow::Return(x) = Stmt::eval(stmt, env)? {
+                return Ok(Flow::Return(x));
             }
+        }
 
-            Stmt::Output(output) => {
-                let res = Expr::eval(&output.expr, vars)?;
+        Ok(Flow::Next)
+    }
 
-                match res {
-                    PscObject::IntT(x) => println!("{}", x),
-                    PscObject::FloatT(x) => println!("{}", x),
-                    PscObject::StringT(x) => println!("{}", x),
-                    PscObject::BoolT(x) => println!("{}", x),
-                };
+    /// Runs a loop body in its own scope, with `bind` (the loop variable)
+    /// declared in it
+    fn eval_scoped(stmts: &[Stmt], env: &mut Env, bind: Option<(&str, PscObject)>) -> Result<Flow, RuntimeError> {
+        env.push_scope();
+
+        if let Some((name, val)) = bind {
+            env.declare(name, val);
+        }
+
+        let res = Stmt::eval_block(stmts, env);
+        env.pop_scope();
+        res
+    }
+
+    pub fn eval(stmt: &Self, env: &mut Env) -> Result<Flow, RuntimeError> {
+        // An error points at the innermost statement it came out of
+        let at = |e: RuntimeError| RuntimeError { span: e.span.or(Some(stmt.span)), ..e };
+
+        let res = env.before(stmt).and_then(|_| Stmt::run(stmt, env)).and_then(|x| env.after(stmt).map(|_| x));
+        if res.is_err() {
+            env.failed(stmt);
+        }
+        res.map_err(at)
+    }
+
+    fn run(stmt: &Self, env: &mut Env) -> Result<Flow, RuntimeError> {
+        match &stmt.kind {
+            StmtKind::Assign(assign) => {
+                let mut res = Expr::eval(&assign.expr, env)?;
+                if let Some(ty) = assign.declared.as_ref().filter(|x| !x.bounds.is_empty()) {
+                    res = declared_array(ty, res, env)?;
+                }
+                assign_to(&assign.target, res, env)?;
+            }
+
+            StmtKind::MultiAssign(assign) => {
+                // Everything on the right is evaluated before any target
+                // changes, so `X, Y = Y, X` swaps
+                let mut vals: Vec<PscObject> = Vec::new();
+                for expr in &assign.exprs {
+                    vals.push(Expr::eval(expr, env)?);
+                }
+
+                for (target, val) in assign.targets.iter().zip(vals) {
+                    assign_to(target, val, env)?;
+                }
             }
 
-            Stmt::Input(input) => {
-                let mut buffer = String::new();
-                if let Err(e) = std::io::stdin().read_line(&mut buffer) {
-                    return Err(RuntimeError { msg: e.to_string() });
+            StmtKind::Output(output) => {
+                let mut res = Expr::eval(&output.expr, env)?;
+                if env.deterministic {
+                    res = round_floats(res);
+                }
+                if let Some(on_output) = &mut env.on_output {
+                    on_output(&res);
+                }
+                if let Err(e) = writeln!(env.out, "{}", res) {
+                    return Err(RuntimeError { msg: e.to_string(), span: None, code: None });
                 }
+            }
 
-                let striped_buffer = buffer.trim();
+            StmtKind::Input(input) => {
+                let val = loop {
+                    let mut buffer = String::new();
+                    match env.read_line(&mut buffer) {
+                        Ok(0) => {
+                            return Err(RuntimeError {
+                                msg: format!("Unexpected end of input while reading {}", input.ident),
+                                span: None,
+                                code: Some("E023"),
+                            })
+                        }
+                        Ok(_) => {}
+                        Err(e) => return Err(RuntimeError { msg: e.to_string(), span: None, code: None }),
+                    }
+
+                    let striped_buffer = buffer.trim();
+
+                    let kind = match &input.kind {
+                        Some(kind) => kind,
+                        None => break PscObject::from_input(striped_buffer),
+                    };
+
+                    if let Some(x) = kind.parse(&buffer) {
+                        break x;
+                    }
+
+                    let msg = format!("Expected {} for {}, got \"{}\"", kind.name(), input.ident, striped_buffer);
+                    if env.strict_input {
+                        return Err(RuntimeError { msg, span: None, code: Some("E023") });
+                    }
+                    let _ = writeln!(env.err, "{}, try again", msg);
+                };
 
-                if let Ok(x) = striped_buffer.parse::<bool>() {
-                    vars.insert(input.ident.to_string(), PscObject::BoolT(x));
-                } else if let Ok(x) = striped_buffer.parse::<i64>() {
-                    vars.insert(input.ident.to_string(), PscObject::IntT(x));
-                } else if let Ok(x) = striped_buffer.parse::<f64>() {
-                    vars.insert(input.ident.to_string(), PscObject::FloatT(x));
-                } else {
-                    vars.insert(input.ident.to_string(), PscObject::StringT(buffer));
-                }
+                env.set_var(&input.ident, val)?;
             }
 
-            Stmt::If(if_stmt) => {
+            StmtKind::If(if_stmt) => {
                 for (cond, stmts) in &if_stmt.branches {
-                    if let PscObject::BoolT(b) = Expr::eval(&cond, vars)? {
+                    if let PscObject::BoolT(b) = Expr::eval(cond, env)? {
                         if b {
-                            for stmt in stmts {
-                                Stmt::eval(&stmt, vars)?;
-                            }
-                            break;
+                            return Stmt::eval_block(stmts, env);
                         }
                     } else {
-                        return Err(RuntimeError { msg: "If expression not bool type".into()  });
+                        return Err(RuntimeError {
+                            msg: "If expression not bool type".into(),
+                            span: None,
+                            code: Some("E014"),
+                        });
                     }
                 }
             }
 
-            Stmt::While(while_stmt) => {
+            StmtKind::While(while_stmt) => {
                 loop {
-                    if let PscObject::BoolT(b) = Expr::eval(&while_stmt.cond, vars)? {
-                        if b {
-                            for stmt in &while_stmt.stmts {
-                                Stmt::eval(&stmt, vars)?;
-                            }
-                        } else {
-                            break;
+                    env.tick(stmt)?;
+                    if let Some(res) = env.resume(stmt, None) {
+                        return res;
+                    }
+                    let b = match Expr::eval(&while_stmt.cond, env)? {
+                        PscObject::BoolT(b) => b,
+                        _ => {
+                            return Err(RuntimeError {
+                                msg: "While expression not bool type".into(),
+                                span: None,
+                                code: Some("E014"),
+                            })
+                        }
+                    };
+                    if b {
+                        if let Flow::Return(x) = Stmt::eval_scoped(&while_stmt.stmts, env, None)? {
+                            return Ok(Flow::Return(x));
                         }
+                    } else {
+                        break;
                     }
                 }
             }
 
-            Stmt::Until(until_stmt) => {
+            StmtKind::Until(until_stmt) => {
                 loop {
-                    if let PscObject::BoolT(b) = Expr::eval(&until_stmt.cond, vars)? {
-                        if !b {
-                            for stmt in &until_stmt.stmts {
-                                Stmt::eval(&stmt, vars)?;
-                            }
-                        } else {
-                            break;
+                    env.tick(stmt)?;
+                    if let Some(res) = env.resume(stmt, None) {
+                        return res;
+                    }
+                    let b = match Expr::eval(&until_stmt.cond, env)? {
+                        PscObject::BoolT(b) => b,
+                        _ => {
+                            return Err(RuntimeError {
+                                msg: "Until expression not bool type".into(),
+                                span: None,
+                                code: Some("E014"),
+                            })
                         }
+                    };
+                    if !b {
+                        if let Flow::Return(x) = Stmt::eval_scoped(&until_stmt.stmts, env, None)? {
+                            return Ok(Flow::Return(x));
+                        }
+                    } else {
+                        break;
                     }
                 }
             }
 
-            Stmt::For(for_stmt) => {
-                let start = Expr::eval(&for_stmt.start, vars)?;
-                let end = Expr::eval(&for_stmt.end, vars)?;
-                vars.insert(for_stmt.name.clone(), PscObject::IntT(0));
+            StmtKind::Repeat(repeat) => loop {
+                env.tick(stmt)?;
+                if let Some(res) = env.resume(stmt, None) {
+                    return res;
+                }
+                if let Flow::Return(x) = Stmt::eval_scoped(&repeat.stmts, env, None)? {
+                    return Ok(Flow::Return(x));
+                }
+
+                match Expr::eval(&repeat.cond, env)? {
+                    PscObject::BoolT(true) => break,
+                    PscObject::BoolT(false) => {}
+                    _ => {
+                        return Err(RuntimeError {
+                            msg: "Until expression not bool type".into(),
+                            span: None,
+                            code: Some("E014"),
+                        })
+                    }
+                }
+            },
 
-                if let (PscObject::IntT(s), PscObject::IntT(e)) = (start, end) {
-                    for i in s..=e {
-                        if let Some(x) = vars.get_mut(&for_stmt.name) {
-                            *x = PscObject::IntT(i);
+            StmtKind::For(for_stmt) => {
+                let s = loop_bound(Expr::eval(&for_stmt.start, env)?)?;
+                let e = loop_bound(Expr::eval(&for_stmt.end, env)?)?;
 
-                            for stmt in &for_stmt.stmts {
-                                Stmt::eval(&stmt, vars)?;
-                            } 
-                        } else {
-                            unreachable!();
-                        }
+                for i in s..=e {
+                    env.tick(stmt)?;
+                    // The jit picks up where the interpreter got to
+                    if let Some(res) = env.resume(stmt, Some((i, e))) {
+                        return res;
+                    }
+                    let bind = Some((for_stmt.name.as_str(), PscObject::IntT(i)));
+
+                    if let Flow::Return(x) = Stmt::eval_scoped(&for_stmt.stmts, env, bind)? {
+                        return Ok(Flow::Return(x));
+                    }
+                }
+            }
+
+            StmtKind::ForEach(for_each) => {
+                let items = match Expr::eval(&for_each.iter, env)? {
+                    PscObject::ArrayT(items) => items,
+                    PscObject::StringT(x) => x.chars().map(|c| PscObject::StringT(c.to_string())).collect(),
+                    PscObject::MapT(entries) => entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+                    PscObject::SetT(items) => items,
+                    x => {
+                        return Err(RuntimeError {
+                            msg: format!("Cannot loop over {}", x.type_name()),
+                            span: None,
+                            code: Some("E014"),
+                        })
+                    }
+                };
+
+                for item in items {
+                    env.tick(stmt)?;
+                    let bind = Some((for_each.name.as_str(), item));
+
+                    if let Flow::Return(x) = Stmt::eval_scoped(&for_each.stmts, env, bind)? {
+                        return Ok(Flow::Return(x));
+                    }
+                }
+            }
+
+            StmtKind::Expr(expr) => {
+                Expr::eval(expr, env)?;
+            }
+
+            StmtKind::Function(func) => env.define(func),
+
+            StmtKind::Class(class) => {
+                if let Some(parent) = &class.parent {
+                    if !env.classes.contains_key(parent) {
+                        return Err(RuntimeError {
+                            msg: format!("Class {} inherits unknown class {}", class.name, parent),
+                            span: None,
+                            code: Some("E004"),
+                        });
                     }
                 }
+
+                env.classes.insert(class.name.clone(), class.clone());
+            }
+
+            StmtKind::Attempt(attempt) => match Stmt::eval_block(&attempt.stmts, env) {
+                Ok(flow) => return Ok(flow),
+                Err(e) if env.stopped() => return Err(e),
+                Err(e) => {
+                    env.caught();
+                    let bind = attempt.name.as_deref().map(|name| (name, PscObject::StringT(e.msg)));
+                    return Stmt::eval_scoped(&attempt.handler, env, bind);
+                }
+            },
+
+            StmtKind::Assert(assert) => match Expr::eval(&assert.cond, env)? {
+                PscObject::BoolT(true) => {}
+                PscObject::BoolT(false) => {
+                    let msg = match &assert.msg {
+                        Some(msg) => format!("Assertion failed at line {}: {}", stmt.span.line, Expr::eval(msg, env)?),
+                        None => format!("Assertion failed at line {}", stmt.span.line),
+                    };
+                    return Err(RuntimeError { msg, span: None, code: Some("E028") });
+                }
+                _ => {
+                    return Err(RuntimeError {
+                        msg: format!("Assert expression not bool type at line {}", stmt.span.line),
+                        span: None,
+                        code: Some("E014"),
+                    })
+                }
+            },
+
+            StmtKind::Halt(code) => {
+                let code = match code {
+                    Some(code) => match Expr::eval(code, env)? {
+                        PscObject::IntT(x) => match i32::try_from(x) {
+                            Ok(x) => x,
+                            Err(_) => {
+                                return Err(RuntimeError {
+                                    msg: format!("Exit code {} out of range", x),
+                                    span: None,
+                                    code: Some("E015"),
+                                })
+                            }
+                        },
+                        x => {
+                            return Err(RuntimeError {
+                                msg: format!("Exit code must be an integer, not {}", x.type_name()),
+                                span: None,
+                                code: Some("E014"),
+                            })
+                        }
+                    },
+                    None => 0,
+                };
+
+                env.halted = Some(code);
+                return Err(RuntimeError {
+                    msg: format!("Program halted with exit code {}", code),
+                    span: None,
+                    code: None,
+                });
+            }
+
+            StmtKind::Global(names) => {
+                for name in names {
+                    env.declare_global(name);
+                }
+            }
+
+            StmtKind::Type(_) => {}
+
+            StmtKind::Include(file) => {
+                return Err(RuntimeError {
+                    msg: format!("include \"{}\" is only allowed at the top level of a file", file),
+                    span: None,
+                    code: Some("E022"),
+                });
+            }
+
+            StmtKind::Return(expr) => {
+                let res = match expr {
+                    Some(expr) => Expr::eval(expr, env)?,
+                    None => PscObject::NullT,
+                };
+
+                return Ok(Flow::Return(res));
             }
         }
 
-        Ok(())
+        Ok(Flow::Next)
     }
 }
 
@@ -152,15 +1210,182 @@ pub struct For {
     pub stmts: Vec<Stmt>
 }
 
+#[derive(Debug)]
+pub struct ForEach {
+    pub name: String,
+    pub iter: Expr,
+    pub stmts: Vec<Stmt>
+}
+
+/// A procedure, function or method; procedures simply return null
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub stmts: Vec<Stmt>,
+    /// From the `///` comments before the definition, one line each
+    pub doc: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct FieldDecl {
+    pub name: String,
+    pub public: bool,
+    pub init: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub public: bool,
+    pub func: Rc<Function>,
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub parent: Option<String>,
+    pub fields: Vec<FieldDecl>,
+    pub methods: Vec<Method>,
+}
+
+impl Class {
+    /// Finds a method declared directly in this class, see `Env::find_method`
+    /// for inherited ones
+    pub fn method(&self, name: &str) -> Option<&Method> {
+        self.methods.iter().find(|x| x.func.name == name)
+    }
+}
+
+/// `attempt ... on error E ... end attempt`, `E` holds the error message
+#[derive(Debug)]
+pub struct Attempt {
+    pub stmts: Vec<Stmt>,
+    pub name: Option<String>,
+    pub handler: Vec<Stmt>,
+}
+
+/// `assert COND, "message"`, the message is optional
+#[derive(Debug)]
+pub struct Assert {
+    pub cond: Expr,
+    pub msg: Option<Expr>,
+}
+
 #[derive(Debug)]
 pub struct Assign {
-    pub ident: String,
+    /// One of `Expr::Ident`, `Expr::Index`, `Expr::Field` or `Expr::Deref`
+    pub target: Expr,
     pub expr: Expr,
+    /// The type of a `DECLARE`, whose `expr` is the value it starts with,
+    /// or for an array the value each of its items starts with
+    pub declared: Option<Declared>,
+    /// A declared array written out in full, made the first time `value`
+    /// asks for it
+    pub literal: OnceCell<Expr>,
+}
+
+impl Assign {
+    /// The value assigned, with a declared array written out as a literal
+    /// for the transpilers and type checks that need one. Running the
+    /// program makes the array itself instead, see `Declared::lengths`
+    pub fn value(&self) -> &Expr {
+        fn spell(ty: &Declared, lengths: &[usize]) -> Expr {
+            match lengths {
+                [] => ty.item(),
+                [len, rest @ ..] => Expr::Array((0..*len).map(|_| spell(ty, rest)).collect()),
+            }
+        }
+
+        match &self.declared {
+            Some(ty) if !ty.bounds.is_empty() => {
+                self.literal.get_or_init(|| spell(ty, &ty.lengths().unwrap_or_default()))
+            }
+            _ => &self.expr,
+        }
+    }
+}
+
+/// The type in `DECLARE X : TYPE`, which only decides the value `X` starts
+/// with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declared {
+    /// `INTEGER`, `REAL`, `STRING`, `BOOLEAN`, a pointer like `^INTEGER`
+    /// or the name of any other type
+    pub name: String,
+    /// The lower and upper bound of each dimension of `ARRAY[1:10] OF`,
+    /// none for a single value
+    pub bounds: Vec<(i64, i64)>,
+}
+
+impl Declared {
+    /// The value a variable of this type starts with, or each item of an
+    /// array of it
+    pub fn item(&self) -> Expr {
+        match self.name.as_str() {
+            "INTEGER" => Expr::IntLit(0),
+            "REAL" => Expr::FloatLit(0.0),
+            "STRING" => Expr::StrLit(String::new()),
+            "BOOLEAN" => Expr::BoolLit(false),
+            _ => Expr::NullLit,
+        }
+    }
+
+    /// How many items each dimension has, from its bounds. psc indexes
+    /// every array from 0, so `ARRAY[1:10]` has ten items, 0 to 9. `None`
+    /// when there are more than fit in memory
+    pub fn lengths(&self) -> Option<Vec<usize>> {
+        let len = |(lower, upper): &(i64, i64)| usize::try_from(*upper as i128 - *lower as i128 + 1).ok();
+        self.bounds.iter().map(len).collect()
+    }
+}
+
+/// A name for a type, see `StmtKind::Type`
+#[derive(Debug)]
+pub struct TypeDef {
+    pub name: String,
+    pub ty: Declared,
+}
+
+#[derive(Debug)]
+pub struct MultiAssign {
+    pub targets: Vec<Expr>,
+    pub exprs: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputType {
+    Integer,
+    Real,
+    String,
+    Boolean,
+}
+
+impl InputType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputType::Integer => "an integer",
+            InputType::Real => "a real",
+            InputType::String => "a string",
+            InputType::Boolean => "a boolean",
+        }
+    }
+
+    /// Strings keep surrounding spaces, only the line ending is dropped
+    fn parse(&self, line: &str) -> Option<PscObject> {
+        match self {
+            InputType::Integer => line.trim().parse::<i64>().ok().map(PscObject::IntT),
+            InputType::Real => line.trim().parse::<f64>().ok().map(PscObject::FloatT),
+            InputType::String => Some(PscObject::StringT(line.trim_end_matches(['\r', '\n']).to_string())),
+            InputType::Boolean => line.trim().parse::<bool>().ok().map(PscObject::BoolT),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Input {
     pub ident: String,
+    /// `input integer X`, otherwise the type is guessed from the text
+    pub kind: Option<InputType>,
 }
 
 #[derive(Debug)]
@@ -168,46 +1393,422 @@ pub struct Output {
     pub expr: Expr,
 }
 
+/// The operators that go between two operands
+#[derive(Debug, PartialEq, Clone)]
+pub enum BinaryOp {
+    Plus,
+    Minus,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Equals,
+    NotEquals,
+    GT,
+    LT,
+    GE,
+    LE,
+    In,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    /// The operator a token stands for, or `None` for punctuation that
+    /// can't appear between two operands
+    pub fn from_token(tok: &Punctuation) -> Option<BinaryOp> {
+        match tok {
+            Punctuation::Plus => Some(BinaryOp::Plus),
+            Punctuation::Minus => Some(BinaryOp::Minus),
+            Punctuation::Mul => Some(BinaryOp::Mul),
+            Punctuation::Div => Some(BinaryOp::Div),
+            Punctuation::FloorDiv => Some(BinaryOp::FloorDiv),
+            Punctuation::Mod => Some(BinaryOp::Mod),
+            Punctuation::Equals => Some(BinaryOp::Equals),
+            Punctuation::NotEquals => Some(BinaryOp::NotEquals),
+            Punctuation::GT => Some(BinaryOp::GT),
+            Punctuation::LT => Some(BinaryOp::LT),
+            Punctuation::GE => Some(BinaryOp::GE),
+            Punctuation::LE => Some(BinaryOp::LE),
+            Punctuation::In => Some(BinaryOp::In),
+            Punctuation::BAnd => Some(BinaryOp::BAnd),
+            Punctuation::BOr => Some(BinaryOp::BOr),
+            Punctuation::BXor => Some(BinaryOp::BXor),
+            Punctuation::Shl => Some(BinaryOp::Shl),
+            Punctuation::Shr => Some(BinaryOp::Shr),
+            _ => None,
+        }
+    }
+
+    /// Binding power, the higher the tighter
+    pub fn precedence(&self) -> u32 {
+        match self {
+            BinaryOp::Equals | BinaryOp::NotEquals | BinaryOp::GT | BinaryOp::LT | BinaryOp::GE | BinaryOp::LE | BinaryOp::In => 1,
+            BinaryOp::BOr => 2,
+            BinaryOp::BXor => 3,
+            BinaryOp::BAnd => 4,
+            BinaryOp::Shl | BinaryOp::Shr => 5,
+            BinaryOp::Plus | BinaryOp::Minus => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod => 7,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BinOp {
     pub left: Expr,
     pub right: Expr,
-    pub op: Punctuation,
+    pub op: BinaryOp,
+    /// Where the operator is, for errors
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct IfElse {
+    pub cond: Expr,
+    pub then: Expr,
+    pub otherwise: Expr,
+}
+
+#[derive(Debug)]
+pub struct Index {
+    pub target: Expr,
+    pub index: Expr,
+}
+
+#[derive(Debug)]
+pub struct MethodCall {
+    pub target: Expr,
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub target: Expr,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct SuperCall {
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct New {
+    pub class: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub enum Expr {
     BinOp(Box<BinOp>),
+    IfElse(Box<IfElse>),
+    Index(Box<Index>),
+    MethodCall(Box<MethodCall>),
+    Field(Box<Field>),
+    Call(Call),
+    SuperCall(SuperCall),
+    New(New),
+    Map(Vec<(Expr, Expr)>),
+    Set(Vec<Expr>),
+    Array(Vec<Expr>),
+    AddressOf(String),
+    Deref(Box<Expr>),
+    BNot(Box<Expr>),
     IntLit(i64),
     FloatLit(f64),
     BoolLit(bool),
     StrLit(String),
+    NullLit,
     Ident(String),
 }
 
 impl Expr {
-    fn eval(expr: &Self, vars: &mut HashMap<String, PscObject>) -> Result<PscObject, RuntimeError> {
+    pub fn eval(expr: &Self, env: &mut Env) -> Result<PscObject, RuntimeError> {
+        let span = match expr {
+            Expr::Call(x) => x.span,
+            Expr::MethodCall(x) => x.span,
+            Expr::SuperCall(x) => x.span,
+            Expr::New(x) => x.span,
+            _ => return Expr::run(expr, env),
+        };
+        // Calls are the one kind of expression that knows where it is, which
+        // narrows an error down from the whole statement
+        Expr::run(expr, env).map_err(|e| RuntimeError { span: e.span.or(Some(span)), ..e })
+    }
+
+    fn run(expr: &Self, env: &mut Env) -> Result<PscObject, RuntimeError> {
         match expr {
             Expr::IntLit(x) => Ok(PscObject::IntT(*x)),
             Expr::FloatLit(x) => Ok(PscObject::FloatT(*x)),
             Expr::StrLit(x) => Ok(PscObject::StringT(x.to_string())),
             Expr::BoolLit(x) => Ok(PscObject::BoolT(*x)),
+            Expr::NullLit => Ok(PscObject::NullT),
             Expr::Ident(x) => {
-                if let Some(val) = vars.get(x) {
-                    Ok(val.clone())
+                if let Some(cell) = env.lookup(x) {
+                    let val = cell.borrow().clone();
+                    Ok(val)
                 } else {
                     Err(RuntimeError {
                         msg: format!("Unknow identifier: {}", x),
+                        span: None,
+                        code: Some("E001"),
                     })
                 }
             }
+            Expr::IfElse(if_else) => match Expr::eval(&if_else.cond, env)? {
+                PscObject::BoolT(true) => Expr::eval(&if_else.then, env),
+                PscObject::BoolT(false) => Expr::eval(&if_else.otherwise, env),
+                _ => Err(RuntimeError {
+                    msg: "If expression not bool type".into(),
+                    span: None,
+                    code: Some("E014"),
+                }),
+            },
+            Expr::Map(entries) => {
+                let mut map = PscObject::MapT(Vec::new());
+                for (k, v) in entries {
+                    let k = Expr::eval(k, env)?;
+                    let v = Expr::eval(v, env)?;
+                    map.set_index(k, v)?;
+                }
+                Ok(map)
+            }
+            Expr::Index(index) => {
+                let key = Expr::eval(&index.index, env)?;
+
+                // Look up in place rather than cloning the whole collection
+                with_place(&index.target, env, &mut |obj| obj.index(&key).cloned())
+            }
+            Expr::AddressOf(x) => match env.lookup(x) {
+                Some(cell) => Ok(PscObject::RefT(cell)),
+                None => Err(RuntimeError {
+                    msg: format!("Unknow identifier: {}", x),
+                    span: None,
+                    code: Some("E001"),
+                }),
+            },
+            Expr::Deref(target) => {
+                let cell = Expr::eval(target, env)?.pointee()?;
+                let val = cell.borrow().clone();
+                Ok(val)
+            }
+            Expr::BNot(inner) => match Expr::eval(inner, env)? {
+                PscObject::IntT(x) => Ok(PscObject::IntT(!x)),
+                x => Err(RuntimeError {
+                    msg: format!("bnot expects an integer, not {}", x.type_name()),
+                    span: None,
+                    code: Some("E014"),
+                }),
+            },
+            Expr::Array(items) => {
+                let mut array: Vec<PscObject> = Vec::new();
+                for item in items {
+                    array.push(Expr::eval(item, env)?);
+                }
+                Ok(PscObject::ArrayT(array))
+            }
+            Expr::Set(items) => {
+                let mut set: Vec<PscObject> = Vec::new();
+                for item in items {
+                    set.push(Expr::eval(item, env)?);
+                }
+                Ok(PscObject::set_from(set))
+            }
+            Expr::Call(call) => {
+                let mut args: Vec<PscObject> = Vec::new();
+                for arg in &call.args {
+                    args.push(Expr::eval(arg, env)?);
+                }
+
+                if let Some(closure) = env.closure(&call.name) {
+                    return env.call_closure(&closure, args);
+                }
+
+                if let Some(func) = env.functions.get(&call.name).cloned() {
+                    return env.call(&func, args, None);
+                }
+
+                // Methods can call other methods of the same object unqualified
+                if let Some(this) = env.this() {
+                    let class = this.borrow().class.clone();
+                    if env.find_method(&class, &call.name).is_some() {
+                        return Object::call_method(&this, &call.name, args, env);
+                    }
+                }
+
+                if let Some(native) = env.natives.get(&call.name).cloned() {
+                    return native(args);
+                }
+
+                builtins::call(&call.name, args, env)
+            }
+            Expr::SuperCall(call) => {
+                let (this, parent) = match (env.this(), env.current_class().and_then(|x| x.parent.clone())) {
+                    (Some(this), Some(parent)) => (this, parent),
+                    _ => {
+                        return Err(RuntimeError {
+                            msg: "super used outside of a subclass method".into(),
+                            span: None,
+                            code: Some("E021"),
+                        })
+                    }
+                };
+
+                let mut args: Vec<PscObject> = Vec::new();
+                for arg in &call.args {
+                    args.push(Expr::eval(arg, env)?);
+                }
+
+                let parent = match env.classes.get(&parent) {
+                    Some(x) => x.clone(),
+                    None => unreachable!(),
+                };
+
+                match env.find_method(&parent, &call.name) {
+                    Some((owner, method)) => env.call(&method.func, args, Some((this, owner))),
+                    None => Err(RuntimeError {
+                        msg: format!("Class {} has no method {}", parent.name, call.name),
+                        span: None,
+                        code: Some("E003"),
+                    }),
+                }
+            }
+            Expr::New(new) => {
+                let class = match env.classes.get(&new.class) {
+                    Some(x) => x.clone(),
+                    None if new.class == "Collection" && new.args.is_empty() => {
+                        return Ok(PscObject::CollectionT(Vec::new(), 0));
+                    }
+                    None => {
+                        return Err(RuntimeError {
+                            msg: format!("Unknown class: {}", new.class),
+                            span: None,
+                            code: Some("E004"),
+                        })
+                    }
+                };
+
+                let mut args: Vec<PscObject> = Vec::new();
+                for arg in &new.args {
+                    args.push(Expr::eval(arg, env)?);
+                }
+
+                // Inherited fields first so subclasses can redeclare them
+                let mut fields = Vars::new();
+                for ancestor in env.ancestors(&class).iter().rev() {
+                    for decl in &ancestor.fields {
+                        let val = match &decl.init {
+                            Some(init) => Expr::eval(init, env)?,
+                            None => PscObject::NullT,
+                        };
+                        fields.insert(decl.name.clone(), Rc::new(RefCell::new(val)));
+                    }
+                }
+
+                let obj = Rc::new(RefCell::new(Object {
+                    class: class.clone(),
+                    fields,
+                }));
+
+                let constructor = env
+                    .ancestors(&class)
+                    .into_iter()
+                    .find_map(|x| x.methods.iter().find(|m| m.func.name.eq_ignore_ascii_case("new")).map(|m| (x.clone(), m.clone())));
+
+                match constructor {
+                    Some((owner, method)) => {
+                        env.call(&method.func, args, Some((obj.clone(), owner)))?;
+                    }
+                    None if !args.is_empty() => {
+                        return Err(RuntimeError {
+                            msg: format!("Class {} has no constructor", class.name),
+                            span: None,
+                            code: Some("E021"),
+                        })
+                    }
+                    None => {}
+                }
+
+                Ok(PscObject::ObjectT(obj))
+            }
+            Expr::Field(field) => {
+                let obj = Expr::eval(&field.target, env)?.object()?;
+                let cell = Object::field(&obj, &field.name, env)?;
+                let val = cell.borrow().clone();
+                Ok(val)
+            }
+            Expr::MethodCall(call) => {
+                let mut args: Vec<PscObject> = Vec::new();
+                for arg in &call.args {
+                    args.push(Expr::eval(arg, env)?);
+                }
+
+                // Methods run in place so `S.add(x)` can mutate `S`, objects
+                // are picked out and run through the interpreter afterwards
+                let mut args = Some(args);
+                let mut object = None;
+
+                let res = with_place(&call.target, env, &mut |obj| {
+                    if let PscObject::ObjectT(o) = obj {
+                        object = Some(o.clone());
+                        Ok(PscObject::NullT)
+                    } else {
+                        obj.call_method(&call.name, args.take().unwrap_or_default())
+                    }
+                })?;
+
+                match object {
+                    Some(obj) => Object::call_method(&obj, &call.name, args.unwrap_or_default(), env),
+                    None => Ok(res),
+                }
+            }
             Expr::BinOp(bin_op) => {
-                let left = Expr::eval(&bin_op.left, vars)?;
-                let right = Expr::eval(&bin_op.right, vars)?;
+                let left = Expr::eval(&bin_op.left, env)?;
+                let right = Expr::eval(&bin_op.right, env)?;
+
+                let (left, right) = match (left, right) {
+                    (PscObject::BigIntT(l), PscObject::FloatT(r)) => (PscObject::FloatT(l.to_f64()), PscObject::FloatT(r)),
+                    (PscObject::FloatT(l), PscObject::BigIntT(r)) => (PscObject::FloatT(l), PscObject::FloatT(r.to_f64())),
+                    x => x,
+                };
+
+                if matches!(bin_op.op, BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod)
+                    && (matches!(right, PscObject::IntT(0)) || matches!(right, PscObject::FloatT(x) if x == 0.0))
+                {
+                    return Err(RuntimeError {
+                        msg: format!("Division by zero at line {}", bin_op.span.line),
+                        span: Some(bin_op.span),
+                        code: Some("E017"),
+                    });
+                }
+
+                if bin_op.op != BinaryOp::In
+                    && (matches!(left, PscObject::BigIntT(_)) || matches!(right, PscObject::BigIntT(_)))
+                {
+                    if let (Some(l), Some(r)) = (as_big(&left), as_big(&right)) {
+                        return big_int_op(&bin_op.op, l, r);
+                    }
+                }
 
                 let ret = match bin_op.op {
-                    Punctuation::Plus => match (left, right) {
-                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::IntT(l + r),
+                    BinaryOp::Plus => match (left, right) {
+                        (PscObject::IntT(l), PscObject::IntT(r)) => checked_int(l, r, &bin_op.op, env.bignum)?,
                         (PscObject::FloatT(l), PscObject::FloatT(r)) => PscObject::FloatT(l + r),
                         (PscObject::IntT(l), PscObject::FloatT(r)) => {
                             PscObject::FloatT(l as f64 + r)
@@ -223,12 +1824,14 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    Punctuation::Minus => match (left, right) {
-                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::IntT(l - r),
+                    BinaryOp::Minus => match (left, right) {
+                        (PscObject::IntT(l), PscObject::IntT(r)) => checked_int(l, r, &bin_op.op, env.bignum)?,
                         (PscObject::FloatT(l), PscObject::FloatT(r)) => PscObject::FloatT(l - r),
                         (PscObject::IntT(l), PscObject::FloatT(r)) => {
                             PscObject::FloatT(l as f64 - r)
@@ -240,12 +1843,14 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    Punctuation::Mul => match (left, right) {
-                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::IntT(l * r),
+                    BinaryOp::Mul => match (left, right) {
+                        (PscObject::IntT(l), PscObject::IntT(r)) => checked_int(l, r, &bin_op.op, env.bignum)?,
                         (PscObject::FloatT(l), PscObject::FloatT(r)) => PscObject::FloatT(l * r),
                         (PscObject::IntT(l), PscObject::FloatT(r)) => {
                             PscObject::FloatT(l as f64 * r)
@@ -257,11 +1862,13 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    Punctuation::Div => match (left, right) {
+                    BinaryOp::Div => match (left, right) {
                         (PscObject::IntT(l), PscObject::IntT(r)) => {
                             PscObject::FloatT(l as f64 / r as f64)
                         }
@@ -276,11 +1883,13 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    Punctuation::FloorDiv => match (left, right) {
+                    BinaryOp::FloorDiv => match (left, right) {
                         (PscObject::IntT(l), PscObject::IntT(r)) => {
                             PscObject::FloatT((l as f64 / r as f64).floor())
                         }
@@ -297,12 +1906,14 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    Punctuation::Mod => match (left, right) {
-                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::IntT(l % r),
+                    BinaryOp::Mod => match (left, right) {
+                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::IntT(l.wrapping_rem(r)),
                         (PscObject::FloatT(l), PscObject::FloatT(r)) => {
                             PscObject::FloatT((l % r).floor())
                         }
@@ -316,11 +1927,13 @@ impl Expr {
                         _ => {
                             return Err(RuntimeError {
                                 msg: "Mismatched types".into(),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     }
 
-                    Punctuation::GE | Punctuation::LE | Punctuation::GT | Punctuation::LT => {
+                    BinaryOp::GE | BinaryOp::LE | BinaryOp::GT | BinaryOp::LT => {
                         let (a, b, c) = match (left, right) {
                             (PscObject::IntT(l), PscObject::IntT(r)) => (l > r, l == r, l < r),
                             (PscObject::FloatT(l), PscObject::FloatT(r)) => (l > r, l == r, l < r),
@@ -332,39 +1945,109 @@ impl Expr {
                                 let r = r as f64;
                                 (l > r, l == r, l < r)
                             }
-                            _ => todo!(),
+                            (PscObject::StringT(l), PscObject::StringT(r)) => (l > r, l == r, l < r),
+                            (l, r) => {
+                                return Err(RuntimeError {
+                                    msg: format!("Cannot compare {} with {}", l.type_name(), r.type_name()),
+                                    span: None,
+                                    code: Some("E014"),
+                                })
+                            }
                         };
 
                         match bin_op.op {
-                            Punctuation::GE => PscObject::BoolT(a || b),
-                            Punctuation::LE => PscObject::BoolT(c || b),
-                            Punctuation::GT => PscObject::BoolT(a),
-                            Punctuation::LT => PscObject::BoolT(c),
+                            BinaryOp::GE => PscObject::BoolT(a || b),
+                            BinaryOp::LE => PscObject::BoolT(c || b),
+                            BinaryOp::GT => PscObject::BoolT(a),
+                            BinaryOp::LT => PscObject::BoolT(c),
                             _ => unreachable!(),
                         }
                     }
 
-                    Punctuation::Equals => match (left, right) {
-                        (PscObject::IntT(l), PscObject::IntT(r)) => PscObject::BoolT(l == r),
-                        (PscObject::FloatT(l), PscObject::FloatT(r)) => PscObject::BoolT(l == r),
-                        (PscObject::StringT(l), PscObject::StringT(r)) => PscObject::BoolT(l == r),
-                        (PscObject::BoolT(l), PscObject::BoolT(r)) => PscObject::BoolT(l == r),
+                    BinaryOp::In => match (left, right) {
+                        (PscObject::StringT(l), PscObject::StringT(r)) => PscObject::BoolT(r.contains(&l)),
+                        (l, PscObject::ArrayT(r)) => PscObject::BoolT(r.contains(&l)),
+                        (l, PscObject::SetT(r)) => PscObject::BoolT(r.contains(&l)),
+                        (l, PscObject::MapT(r)) => PscObject::BoolT(r.iter().any(|(k, _)| *k == l)),
 
-                        (PscObject::IntT(l), PscObject::FloatT(r)) => {
-                            PscObject::BoolT(l as f64 == r)
-                        }
-                        (PscObject::FloatT(l), PscObject::IntT(r)) => {
-                            PscObject::BoolT(l == r as f64)
-                        }
-
-                        _ => {
+                        (l, r) => {
                             return Err(RuntimeError {
-                                msg: "Mismatched types".into(),
+                                msg: format!("Cannot look for {} in {}", l.type_name(), r.type_name()),
+                                span: None,
+                                code: Some("E014"),
                             })
                         }
                     },
 
-                    _ => todo!(),
+                    BinaryOp::Equals | BinaryOp::NotEquals => {
+                        let eq = match (left, right) {
+                            (PscObject::IntT(l), PscObject::IntT(r)) => l == r,
+                            (PscObject::FloatT(l), PscObject::FloatT(r)) => l == r,
+                            (PscObject::StringT(l), PscObject::StringT(r)) => l == r,
+                            (PscObject::BoolT(l), PscObject::BoolT(r)) => l == r,
+
+                            (PscObject::IntT(l), PscObject::FloatT(r)) => l as f64 == r,
+                            (PscObject::FloatT(l), PscObject::IntT(r)) => l == r as f64,
+
+                            (l, r) if l.type_name() == r.type_name()
+                                || matches!(l, PscObject::NullT)
+                                || matches!(r, PscObject::NullT) =>
+                            {
+                                l == r
+                            }
+
+                            _ => {
+                                return Err(RuntimeError {
+                                    msg: "Mismatched types".into(),
+                                    span: None,
+                                    code: Some("E014"),
+                                })
+                            }
+                        };
+
+                        PscObject::BoolT(eq == (bin_op.op == BinaryOp::Equals))
+                    }
+
+                    BinaryOp::BAnd | BinaryOp::BOr | BinaryOp::BXor | BinaryOp::Shl | BinaryOp::Shr => {
+                        let (l, r) = match (left, right) {
+                            (PscObject::IntT(l), PscObject::IntT(r)) => (l, r),
+                            (l, r) => {
+                                return Err(RuntimeError {
+                                    msg: format!(
+                                        "Bitwise operators expect integers, not {} and {}",
+                                        l.type_name(),
+                                        r.type_name()
+                                    ),
+                                    span: None,
+                                    code: Some("E014"),
+                                })
+                            }
+                        };
+
+                        match bin_op.op {
+                            BinaryOp::BAnd => PscObject::IntT(l & r),
+                            BinaryOp::BOr => PscObject::IntT(l | r),
+                            BinaryOp::BXor => PscObject::IntT(l ^ r),
+                            _ => {
+                                let shifted = match u32::try_from(r) {
+                                    Ok(r) if bin_op.op == BinaryOp::Shl => l.checked_shl(r),
+                                    Ok(r) => l.checked_shr(r),
+                                    Err(_) => None,
+                                };
+
+                                match shifted {
+                                    Some(x) => PscObject::IntT(x),
+                                    None => {
+                                        return Err(RuntimeError {
+                                            msg: format!("Cannot shift by {} bits", r),
+                                            span: None,
+                                            code: Some("E015"),
+                                        })
+                                    }
+                                }
+                            }
+                        }
+                    }
                 };
 
                 Ok(ret)
@@ -0,0 +1,429 @@
+/// A kind of error, with a code that stays the same between versions so it
+/// can be looked up with `psc --explain`
+struct Code {
+    code: &'static str,
+    title: &'static str,
+    /// What it means, an example and how to fix it
+    text: &'static str,
+}
+
+const CODES: &[Code] = &[
+    Code {
+        code: "E001",
+        title: "Unknown identifier",
+        text: "A variable was read before anything was stored in it. Either it was
+never assigned, it's spelled differently where it was assigned, or it was
+assigned inside a procedure and is being read outside it.
+
+    output TOTAL
+    TOTAL = 0
+
+Assign the variable before reading it, and check the spelling matches:
+
+    TOTAL = 0
+    output TOTAL",
+    },
+    Code {
+        code: "E002",
+        title: "Unknown function",
+        text: "A procedure or function was called that doesn't exist, or was called
+with the wrong number of arguments.
+
+    function SQUARE(N)
+        return N * N
+    end function
+    output SQUARE(2, 3)
+
+Check the name is spelled the same as where it's defined, and pass one
+value for each parameter:
+
+    output SQUARE(2)",
+    },
+    Code {
+        code: "E003",
+        title: "Unknown method",
+        text: "A method was called on a value or object that doesn't have it.
+
+    NAMES = [\"Ann\", \"Bo\"]
+    NAMES.push(\"Cy\")
+
+Check which methods the value has, here arrays use append:
+
+    NAMES.append(\"Cy\")",
+    },
+    Code {
+        code: "E004",
+        title: "Unknown class",
+        text: "An object was made from, or a class inherits, a class that isn't
+defined.
+
+    P = new Pet(\"Rex\")
+
+Define the class before using it, and check the spelling:
+
+    class Pet
+        NAME
+        ...
+    end class",
+    },
+    Code {
+        code: "E005",
+        title: "Unknown field",
+        text: "A field was read or set on an object whose class doesn't declare it.
+
+    P = new Point(1, 2)
+    output P.Z
+
+Declare every field in the class, or use one that exists:
+
+    output P.X",
+    },
+    Code {
+        code: "E006",
+        title: "Private member",
+        text: "A private field or method was used from outside its class. Private
+members can only be used by the methods of the class and its subclasses.
+
+    class Account
+        private BALANCE = 0
+    end class
+    A = new Account()
+    output A.BALANCE
+
+Add a public method that gives the value out, and call that instead:
+
+    function GETBALANCE()
+        return BALANCE
+    end function",
+    },
+    Code {
+        code: "E007",
+        title: "Invalid name",
+        text: "A name doesn't follow the naming rule, which by default is that
+variables, procedures and functions are written in UPPERCASE.
+
+    total = 0
+
+Write the name in capitals, or pick another rule with --naming:
+
+    TOTAL = 0",
+    },
+    Code {
+        code: "E008",
+        title: "Invalid character",
+        text: "The program contains a symbol that isn't part of the language, often
+from copying code from a word processor.
+
+    output 3 $ 4
+
+Remove the character or replace it with an operator that exists:
+
+    output 3 + 4",
+    },
+    Code {
+        code: "E009",
+        title: "Malformed number",
+        text: "A number is written in a way that can't be read, like a float with two
+points or a letter in a decimal number.
+
+    X = 1.2.3
+
+Write one number with at most one decimal point:
+
+    X = 1.23",
+    },
+    Code {
+        code: "E010",
+        title: "Unterminated string",
+        text: "A string was started with a quote but never closed.
+
+    output \"Hello
+
+Close the string with a matching quote on the same line:
+
+    output \"Hello\"",
+    },
+    Code {
+        code: "E011",
+        title: "Unexpected end of file",
+        text: "The program ended in the middle of a statement, usually because a
+block was never closed.
+
+    loop while X < 10
+        X = X + 1
+
+Close every if, loop, procedure and class with its end line:
+
+    loop while X < 10
+        X = X + 1
+    end loop",
+    },
+    Code {
+        code: "E012",
+        title: "Syntax error",
+        text: "A statement isn't written the way the language expects: a keyword,
+bracket or name is missing or in the wrong place.
+
+    if X > 3
+        output X
+    end if
+
+Compare the line with an example of the same statement. Here `if` needs
+`then`:
+
+    if X > 3 then
+        output X
+    end if",
+    },
+    Code {
+        code: "E013",
+        title: "Misplaced comment",
+        text: "Comments are written with /// and describe the procedure or function
+that follows them, so they can't go anywhere else.
+
+    X = 0 /// start at zero
+
+Put the comment on the lines just before a procedure or function:
+
+    /// Adds one to N
+    function NEXT(N)
+        return N + 1
+    end function",
+    },
+    Code {
+        code: "E014",
+        title: "Mismatched types",
+        text: "An operator or statement was given a kind of value it can't work with,
+like adding a number to a string or using a number as a condition.
+
+    X = 5
+    if X then
+        output X
+    end if
+
+Check each variable holds what you think it does, and give conditions
+something that's true or false:
+
+    if X > 0 then
+        output X
+    end if",
+    },
+    Code {
+        code: "E015",
+        title: "Out of range",
+        text: "A number is outside the values allowed, most often an array index past
+the end. Arrays start at index 0, so the last item is at LENGTH(A) - 1.
+
+    A = [10, 20, 30]
+    output A[3]
+
+Keep indexes from 0 to one less than the length:
+
+    output A[2]",
+    },
+    Code {
+        code: "E016",
+        title: "Missing key",
+        text: "A map was read with a key it doesn't have.
+
+    AGES = {\"Ann\": 15}
+    output AGES[\"Bo\"]
+
+Check the key is there first with `in`:
+
+    if \"Bo\" in AGES then
+        output AGES[\"Bo\"]
+    end if",
+    },
+    Code {
+        code: "E017",
+        title: "Division by zero",
+        text: "A number was divided by zero, which has no answer.
+
+    AVERAGE = TOTAL / COUNT
+
+Check the divisor before dividing:
+
+    if COUNT != 0 then
+        AVERAGE = TOTAL / COUNT
+    end if",
+    },
+    Code {
+        code: "E018",
+        title: "Integer overflow",
+        text: "A calculation gave an integer too big to store, past about 9.2 x 10^18.
+
+    X = 1
+    loop I from 1 to 100
+        X = X * 2
+    end loop
+
+Use floats for very large values, or run with --bignum to allow integers
+of any size.",
+    },
+    Code {
+        code: "E019",
+        title: "Invalid assignment",
+        text: "Something was assigned to that can't hold a value, or the number of
+targets and values don't match.
+
+    X, Y = 1, 2, 3
+
+Assign to variables, array items or fields, with one value per target:
+
+    X, Y = 1, 2",
+    },
+    Code {
+        code: "E020",
+        title: "Return outside of function",
+        text: "`return` was used outside of any procedure or function, where there is
+nothing to return to.
+
+    X = 5
+    return X
+
+Use `halt` to end the program, or move the code into a function.",
+    },
+    Code {
+        code: "E021",
+        title: "Super outside of a subclass",
+        text: "`super` was used outside a method of a class that inherits another, or
+an object was made from a class with no constructor to call.
+
+    class Dog inherits Pet
+        function SPEAK()
+            return super.SPEAK()
+        end function
+    end class
+
+Only call `super` from methods of a subclass, and give a class a `new`
+method to make objects of it with arguments.",
+    },
+    Code {
+        code: "E022",
+        title: "Include error",
+        text: "A file named by `include` can't be read, includes itself through other
+files, or the include isn't at the top level.
+
+    include \"helpers.psc\"
+
+Check the file exists next to the program, and that files don't include
+each other in a loop.",
+    },
+    Code {
+        code: "E023",
+        title: "Bad input",
+        text: "The program asked for input that wasn't there, or got input of the
+wrong type.
+
+    input integer AGE
+
+Type a value of the type asked for, or make sure the input file has a
+line for every `input` the program runs.",
+    },
+    Code {
+        code: "E024",
+        title: "Empty collection",
+        text: "A value was asked of a collection with nothing in it, like the smallest
+item of an empty array.
+
+    SCORES = []
+    output MIN(SCORES)
+
+Check the collection has items first:
+
+    if LENGTH(SCORES) > 0 then
+        output MIN(SCORES)
+    end if",
+    },
+    Code {
+        code: "E025",
+        title: "Step limit exceeded",
+        text: "The program ran more statements than --max-steps allows, which usually
+means a loop never ends.
+
+    X = 0
+    loop while X < 10
+        output X
+    end loop
+
+Make sure every loop changes what its condition checks:
+
+    loop while X < 10
+        output X
+        X = X + 1
+    end loop",
+    },
+    Code {
+        code: "E026",
+        title: "Time limit exceeded",
+        text: "The program ran for longer than --timeout allows. Look for loops that
+never end, or work that's repeated more than it needs to be.",
+    },
+    Code {
+        code: "E027",
+        title: "Memory limit exceeded",
+        text: "The program's variables grew past --max-memory, usually from a string
+or array that keeps growing in a loop.
+
+    S = \"a\"
+    loop while true
+        S = S + S
+    end loop
+
+Make sure loops that grow values stop.",
+    },
+    Code {
+        code: "E028",
+        title: "Assertion failed",
+        text: "An `assert` found its condition was false, so the program isn't in the
+state it was expected to be.
+
+    assert COUNT >= 0, \"COUNT went negative\"
+
+Work out how the condition became false, the assert itself is usually
+right.",
+    },
+    Code {
+        code: "E029",
+        title: "Null pointer",
+        text: "A pointer was followed that doesn't point at anything.
+
+Make sure a pointer is set with @NAME before it's read.",
+    },
+    Code {
+        code: "E030",
+        title: "Invalid pattern or data",
+        text: "A regular expression or a piece of JSON given to a builtin can't be
+read, or regular expressions were left out when psc was built.
+
+Check brackets are balanced and special characters are escaped with a
+backslash.",
+    },
+    Code {
+        code: "E031",
+        title: "Invalid argument",
+        text: "A builtin was given a kind of value it doesn't take, or a value it
+can't do anything with, like an empty string to replace.
+
+    output TRIM(5)
+    output REPLACE(\"abc\", \"\", \"-\")
+
+Check what the builtin takes, and give it values of that kind:
+
+    output TRIM(\" 5 \")
+    output REPLACE(\"abc\", \"b\", \"-\")",
+    },
+];
+
+/// The longer description of `code` for `--explain`, or every code and
+/// what it's for without one
+pub fn explain(code: Option<&str>) -> Result<String, String> {
+    match code {
+        Some(code) => match CODES.iter().find(|x| x.code.eq_ignore_ascii_case(code)) {
+            Some(x) => Ok(format!("{}: {}\n\n{}", x.code, x.title, x.text)),
+            None => Err(format!("Unknown error code {}, see psc --explain for the list", code)),
+        },
+        None => Ok(CODES.iter().map(|x| format!("{}  {}", x.code, x.title)).collect::<Vec<_>>().join("\n")),
+    }
+}
@@ -0,0 +1,241 @@
+use psc::lex::{self, LexOptions, Span, Token};
+use psc::{parse, pretty};
+use std::error::Error;
+use std::fs;
+
+/// How far ahead `align` looks for the tokens to line up again
+const LOOKAHEAD: usize = 4;
+
+/// Source text found by token spans
+struct Text {
+    chars: Vec<char>,
+    /// Where each line starts in `chars`
+    lines: Vec<usize>,
+}
+
+impl Text {
+    fn new(text: &str) -> Text {
+        let chars: Vec<char> = text.chars().collect();
+        let mut lines = vec![0];
+        lines.extend(chars.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(i, _)| i + 1));
+        Text { chars, lines }
+    }
+
+    fn start(&self, span: Span) -> usize {
+        self.lines[span.line - 1] + span.col - 1
+    }
+
+    fn end(&self, span: Span) -> usize {
+        self.start(span) + span.len
+    }
+
+    fn get(&self, from: usize, to: usize) -> String {
+        self.chars[from..to].iter().collect()
+    }
+
+    /// What a token was written as, doc comments without trailing spaces
+    fn token(&self, span: Span) -> String {
+        self.get(self.start(span), self.end(span)).trim_end().to_string()
+    }
+
+    /// The line a token ends on, later than it starts for strings with
+    /// line breaks in them
+    fn end_line(&self, span: Span) -> usize {
+        self.lines.partition_point(|x| *x < self.end(span))
+    }
+
+    /// The tabs a line starts with
+    fn indent(&self, line: usize) -> usize {
+        self.chars[self.lines[line - 1]..].iter().take_while(|c| **c == '\t').count()
+    }
+}
+
+/// For each token of `source`, the printed token it lines up with. Tokens
+/// the printer left out (`;`, types, extra brackets) get none, and where
+/// it wrote something else (`procedure` for `function`) they're paired
+/// anyway
+fn align(source: &[Token], printed: &[Token]) -> Vec<Option<usize>> {
+    let same = |i: usize, j: usize| match (source.get(i), printed.get(j)) {
+        (Some(x), Some(y)) => x.tok == y.tok,
+        (None, None) => true,
+        _ => false,
+    };
+    let resumes = |i: usize, j: usize| same(i, j) && same(i + 1, j + 1);
+
+    let mut pairs = Vec::new();
+    let mut j = 0;
+    while pairs.len() < source.len() {
+        let i = pairs.len();
+        if !same(i, j) && j < printed.len() {
+            let skip = (1..=LOOKAHEAD).find_map(|k| match (resumes(i + k, j), resumes(i, j + k)) {
+                (true, _) => Some((k, 0)),
+                (_, true) => Some((0, k)),
+                _ => None,
+            });
+            match skip {
+                Some((k, 0)) => {
+                    pairs.extend(std::iter::repeat_n(None, k));
+                    continue;
+                }
+                Some((_, k)) => j += k,
+                None => {}
+            }
+        }
+        pairs.push((j < printed.len()).then_some(j));
+        j += 1;
+    }
+    pairs
+}
+
+/// The formatted source of `prog`. Lines and indents come from printing
+/// the program back out, but every token is written the way it was in
+/// `prog`, so `0xFF`, `new Collection`, types, `;` and `\` line breaks all
+/// stay as they were
+pub fn format(prog: &str, options: &LexOptions) -> Result<String, Box<dyn Error>> {
+    let source = lex::lex(prog, options)?;
+    let stmts = parse::parse(source.clone())?;
+    let printed = pretty::program(&stmts, options.dialect);
+    let layout = lex::lex(&printed, options)?;
+    let pairs = align(&source, &layout);
+    let (text, printed) = (Text::new(prog), Text::new(&printed));
+
+    let mut out = String::new();
+    let mut indent = 0;
+    // The last token written, and the last one the printer wrote too
+    let mut prev: Option<(&Token, Option<usize>)> = None;
+    let mut paired: Option<usize> = None;
+    for (tok, &pair) in source.iter().zip(&pairs) {
+        let Some((last, last_pair)) = prev else {
+            indent = pair.map_or(0, |j| printed.indent(layout[j].span.line));
+            out.extend(std::iter::repeat_n('\t', indent));
+            out.push_str(&text.token(tok.span));
+            (prev, paired) = (Some((tok, pair)), pair);
+            continue;
+        };
+        // `NEXT I` is two tokens in one span
+        if tok.span == last.span {
+            continue;
+        }
+
+        let between = text.get(text.end(last.span), text.start(tok.span));
+        let broken = match (paired, pair) {
+            (Some(i), Some(j)) if layout[j].span.line > printed.end_line(layout[i].span) => Some((i, j)),
+            _ => None,
+        };
+
+        match (broken, between.contains('\n')) {
+            (Some((i, j)), true) => {
+                let blank = layout[j].span.line > printed.end_line(layout[i].span) + 1
+                    || between.matches('\n').count() > between.matches('\\').count() + 1;
+                out.push_str(if blank { "\n\n" } else { "\n" });
+                indent = printed.indent(layout[j].span.line);
+                out.extend(std::iter::repeat_n('\t', indent));
+            }
+            // A statement carried on over lines, with `\` or inside brackets
+            (_, true) => {
+                out.push_str(if between.contains('\\') { " \\\n" } else { "\n" });
+                out.extend(std::iter::repeat_n('\t', indent + 1));
+            }
+            // Statements sharing a line, usually with `;`
+            (Some(_), false) if between.is_empty() => {}
+            (Some(_), false) => out.push(' '),
+            (None, false) => match (last_pair, pair) {
+                (Some(i), Some(j)) if i + 1 == j => {
+                    out.push_str(&printed.get(printed.end(layout[i].span), printed.start(layout[j].span)));
+                }
+                _ if between.is_empty() => {}
+                _ => out.push(' '),
+            },
+        }
+        out.push_str(&text.token(tok.span));
+        prev = Some((tok, pair));
+        paired = pair.or(paired);
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// `psc fmt [--check] FILE...` rewrites the files in place, or with
+/// `--check` only lists the ones that would change and fails if there are any
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut check = false;
+    let mut options = LexOptions::default();
+    let mut files: Vec<String> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return Err("fmt expects at least one file".into());
+    }
+
+    let mut unformatted = false;
+    for file in &files {
+        let prog = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        let formatted = format(&prog, &options).map_err(|e| format!("{}: {}", file, e))?;
+
+        if formatted == prog {
+            continue;
+        }
+
+        if check {
+            println!("{} needs formatting", file);
+            unformatted = true;
+        } else {
+            fs::write(file, formatted).map_err(|e| format!("{}: {}", file, e))?;
+        }
+    }
+
+    if unformatted {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use psc::dialect::Dialect;
+    use psc::lex::LexOptions;
+
+    fn ib(prog: &str) -> String {
+        format(prog, &LexOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn tokens_keep_their_text() {
+        let prog = "X=0xFF+0b1010+0o17\nY = 1_000_000 * 1.5e6\nC = new Collection\nD = new Collection()\n";
+        let formatted = "X = 0xFF + 0b1010 + 0o17\nY = 1_000_000 * 1.5e6\nC = new Collection\nD = new Collection()\n";
+        assert_eq!(ib(prog), formatted);
+        assert_eq!(ib(formatted), formatted);
+    }
+
+    #[test]
+    fn line_breaks_inside_statements_are_kept() {
+        let prog = "X = 1 + \\\n2\nA = 1;B = 2\nif A == 1 then\noutput [1,\n2]\nend if\n";
+        let formatted = "X = 1 + \\\n\t2\nA = 1;B = 2\nif A == 1 then\n\toutput [1,\n\t\t2]\nend if\n";
+        assert_eq!(ib(prog), formatted);
+        assert_eq!(ib(formatted), formatted);
+    }
+
+    #[test]
+    fn dialect_forms_are_kept() {
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let prog = "DECLARE TOTAL : INTEGER\nFOR I <- 1 TO 3\n\tTOTAL <- TOTAL + I\nNEXT I\n\
+            IF TOTAL = 6 THEN\n\tOUTPUT TOTAL\nENDIF\nREPEAT\n\tTOTAL <- TOTAL - 1\nUNTIL TOTAL < 0\n";
+        assert_eq!(format(prog, &cambridge).unwrap(), prog);
+
+        let edexcel = LexOptions { dialect: Dialect::Edexcel, ..Default::default() };
+        let prog = "SET X TO 1\nFOR I FROM 1 TO 3 DO\n\tSEND I TO DISPLAY\nEND FOR\n";
+        assert_eq!(format(prog, &edexcel).unwrap(), prog);
+    }
+}
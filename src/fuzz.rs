@@ -0,0 +1,651 @@
+use psc::env::{self, Env};
+use psc::lex::{self, LexOptions};
+use psc::parse;
+use std::cell::RefCell;
+use std::error::Error;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+
+/// splitmix64, the same generator as `RANDOM`, so a seed always gives the
+/// same programs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// True `percent` times out of a hundred
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+const OPS: &[&str] = &[
+    "+", "-", "*", "/", "div", "mod", "==", "!=", "<", ">", "<=", ">=", "in", "band", "bor", "bxor", "shl", "shr",
+];
+
+const LITERALS: &[&str] = &[
+    "0", "1", "2", "3", "7", "64", "9223372036854775807", "0.5", "3.0", "100.25", "true", "false", "null", "\"\"",
+    "\"ab\"", "\"a,b\"", "\" x \"",
+];
+
+/// Builtins with how many arguments they take
+const BUILTINS: &[(&str, usize)] = &[
+    ("LENGTH", 1),
+    ("INDEXOF", 2),
+    ("COUNT", 2),
+    ("MIN", 1),
+    ("MAX", 2),
+    ("SUM", 1),
+    ("AVERAGE", 1),
+    ("TRIM", 1),
+    ("REPLACE", 3),
+    ("SPLIT", 2),
+    ("JOIN", 2),
+    ("CONTAINS", 2),
+    ("STARTSWITH", 2),
+    ("ENDSWITH", 2),
+    ("SET", 2),
+    ("RANDOM", 2),
+];
+
+/// Methods with how many arguments they take
+const METHODS: &[(&str, usize)] = &[
+    ("length", 0),
+    ("append", 1),
+    ("containsKey", 1),
+    ("add", 1),
+    ("contains", 1),
+    ("union", 1),
+];
+
+/// Programs are generated as trees rather than text so they can shrink a
+/// piece at a time
+#[derive(Clone)]
+enum E {
+    Lit(&'static str),
+    Var(String),
+    Bin(Box<E>, &'static str, Box<E>),
+    BNot(Box<E>),
+    Call(String, Vec<E>),
+    Method(Box<E>, &'static str, Vec<E>),
+    Index(Box<E>, Box<E>),
+    Array(Vec<E>),
+    Map(Vec<(E, E)>),
+}
+
+#[derive(Clone)]
+enum S {
+    Assign(String, E),
+    SetIndex(String, E, E),
+    Multi(Vec<String>, Vec<E>),
+    Output(E),
+    Expr(E),
+    Assert(E),
+    If(E, Vec<S>, Vec<S>),
+    For(String, &'static str, &'static str, Vec<S>),
+    ForEach(String, E, Vec<S>),
+    Attempt(Vec<S>, Vec<S>),
+    Function(String, Vec<String>, Vec<S>, Option<E>),
+}
+
+fn show_expr(expr: &E) -> String {
+    let list = |items: &[E]| items.iter().map(show_expr).collect::<Vec<_>>().join(", ");
+    match expr {
+        E::Lit(x) => x.to_string(),
+        E::Var(x) => x.clone(),
+        E::Bin(l, op, r) => format!("({} {} {})", show_expr(l), op, show_expr(r)),
+        E::BNot(x) => format!("bnot ({})", show_expr(x)),
+        E::Call(name, args) => format!("{}({})", name, list(args)),
+        E::Method(target, name, args) => match &**target {
+            E::Var(x) => format!("{}.{}({})", x, name, list(args)),
+            x => format!("({}).{}({})", show_expr(x), name, list(args)),
+        },
+        E::Index(target, index) => format!("({})[{}]", show_expr(target), show_expr(index)),
+        E::Array(items) => format!("[{}]", list(items)),
+        E::Map(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", show_expr(k), show_expr(v))).collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+fn show(stmts: &[S], indent: usize, out: &mut String) {
+    let pad = "    ".repeat(indent);
+    for stmt in stmts {
+        match stmt {
+            S::Assign(name, x) => *out += &format!("{}{} = {}\n", pad, name, show_expr(x)),
+            S::SetIndex(name, i, x) => *out += &format!("{}{}[{}] = {}\n", pad, name, show_expr(i), show_expr(x)),
+            S::Multi(names, exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(show_expr).collect();
+                *out += &format!("{}{} = {}\n", pad, names.join(", "), exprs.join(", "));
+            }
+            S::Output(x) => *out += &format!("{}output {}\n", pad, show_expr(x)),
+            S::Expr(x) => *out += &format!("{}{}\n", pad, show_expr(x)),
+            S::Assert(x) => *out += &format!("{}assert {}\n", pad, show_expr(x)),
+            S::If(cond, then, otherwise) => {
+                *out += &format!("{}if {} then\n", pad, show_expr(cond));
+                show(then, indent + 1, out);
+                if !otherwise.is_empty() {
+                    *out += &format!("{}else\n", pad);
+                    show(otherwise, indent + 1, out);
+                }
+                *out += &format!("{}end if\n", pad);
+            }
+            S::For(name, from, to, body) => {
+                *out += &format!("{}loop {} from {} to {}\n", pad, name, from, to);
+                show(body, indent + 1, out);
+                *out += &format!("{}end loop\n", pad);
+            }
+            S::ForEach(name, iter, body) => {
+                *out += &format!("{}loop {} over {}\n", pad, name, show_expr(iter));
+                show(body, indent + 1, out);
+                *out += &format!("{}end loop\n", pad);
+            }
+            S::Attempt(body, handler) => {
+                *out += &format!("{}attempt\n", pad);
+                show(body, indent + 1, out);
+                *out += &format!("{}on error ERR\n", pad);
+                show(handler, indent + 1, out);
+                *out += &format!("{}end attempt\n", pad);
+            }
+            S::Function(name, params, body, ret) => {
+                let kind = if ret.is_some() { "function" } else { "procedure" };
+                *out += &format!("{}{} {}({})\n", pad, kind, name, params.join(", "));
+                show(body, indent + 1, out);
+                if let Some(x) = ret {
+                    *out += &format!("{}    return {}\n", pad, show_expr(x));
+                }
+                *out += &format!("{}end {}\n", pad, kind);
+            }
+        }
+    }
+}
+
+/// Whether `stmts` read back as written. An `if` first thing in an `else`
+/// would be taken as `else if`
+fn well_formed(stmts: &[S]) -> bool {
+    stmts.iter().all(|stmt| match stmt {
+        S::If(_, then, otherwise) => {
+            !matches!(otherwise.first(), Some(S::If(..))) && well_formed(then) && well_formed(otherwise)
+        }
+        S::For(.., body) | S::ForEach(_, _, body) | S::Function(_, _, body, _) => well_formed(body),
+        S::Attempt(body, handler) => well_formed(body) && well_formed(handler),
+        _ => true,
+    })
+}
+
+fn source(stmts: &[S]) -> String {
+    let mut ret = String::new();
+    show(stmts, 0, &mut ret);
+    ret
+}
+
+/// Builds random programs that parse, and run in bounded time: loops are
+/// short and few, and functions only call the ones defined before them so
+/// nothing recurses
+struct Gen {
+    rng: Rng,
+    /// Variables known to be set where the next statement goes
+    vars: Vec<String>,
+    /// Functions defined so far, their arity and whether they return
+    functions: Vec<(String, usize, bool)>,
+    /// Statements left to generate
+    budget: usize,
+    loops: usize,
+}
+
+impl Gen {
+    fn expr(&mut self, depth: usize) -> E {
+        if depth == 0 || self.rng.chance(30) {
+            return match self.vars.is_empty() || self.rng.chance(40) {
+                true => E::Lit(self.rng.pick(LITERALS)),
+                false => E::Var(self.vars[self.rng.below(self.vars.len())].clone()),
+            };
+        }
+
+        let d = depth - 1;
+        match self.rng.below(9) {
+            0..=2 => E::Bin(Box::new(self.expr(d)), self.rng.pick(OPS), Box::new(self.expr(d))),
+            3 => E::BNot(Box::new(self.expr(d))),
+            4 => {
+                let funcs: Vec<(String, usize)> =
+                    self.functions.iter().filter(|x| x.2).map(|x| (x.0.clone(), x.1)).collect();
+                let (name, arity) = match funcs.is_empty() || self.rng.chance(60) {
+                    true => {
+                        let (name, arity) = self.rng.pick(BUILTINS);
+                        (name.to_string(), arity)
+                    }
+                    false => funcs[self.rng.below(funcs.len())].clone(),
+                };
+                E::Call(name, (0..arity).map(|_| self.expr(d)).collect())
+            }
+            5 => {
+                let (name, arity) = self.rng.pick(METHODS);
+                E::Method(Box::new(self.expr(d)), name, (0..arity).map(|_| self.expr(d)).collect())
+            }
+            6 => E::Index(Box::new(self.expr(d)), Box::new(self.expr(d))),
+            7 => E::Array((0..self.rng.below(4)).map(|_| self.expr(d)).collect()),
+            _ => E::Map((0..self.rng.below(3)).map(|_| (self.expr(d), self.expr(d))).collect()),
+        }
+    }
+
+    fn new_var(&mut self) -> String {
+        match self.vars.is_empty() || self.rng.chance(40) {
+            true => self.rng.pick(&["A", "B", "C", "X", "Y", "Z"]).to_string(),
+            false => self.vars[self.rng.below(self.vars.len())].clone(),
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if !self.vars.iter().any(|x| x == name) {
+            self.vars.push(name.to_string());
+        }
+    }
+
+    /// A block whose new variables are forgotten after it, since it might
+    /// not run
+    fn block(&mut self, max: usize) -> Vec<S> {
+        let vars = self.vars.len();
+        let ret = self.stmts(max, false);
+        self.vars.truncate(vars);
+        ret
+    }
+
+    fn stmts(&mut self, max: usize, top: bool) -> Vec<S> {
+        let mut ret = Vec::new();
+        for _ in 0..self.rng.below(max) + 1 {
+            if self.budget == 0 {
+                break;
+            }
+            self.budget -= 1;
+            ret.push(self.stmt(top));
+        }
+        ret
+    }
+
+    fn stmt(&mut self, top: bool) -> S {
+        match self.rng.below(if top { 13 } else { 12 }) {
+            0..=2 => {
+                let name = self.new_var();
+                let expr = self.expr(3);
+                self.define(&name);
+                S::Assign(name, expr)
+            }
+            3 if !self.vars.is_empty() => {
+                let name = self.vars[self.rng.below(self.vars.len())].clone();
+                S::SetIndex(name, self.expr(1), self.expr(2))
+            }
+            4 => {
+                let names = vec![self.new_var(), self.new_var()];
+                let exprs = vec![self.expr(2), self.expr(2)];
+                names.iter().for_each(|x| self.define(x));
+                S::Multi(names, exprs)
+            }
+            5 => {
+                let procs: Vec<(String, usize)> =
+                    self.functions.iter().filter(|x| !x.2).map(|x| (x.0.clone(), x.1)).collect();
+                match procs.is_empty() || self.rng.chance(50) {
+                    true if self.vars.is_empty() => S::Output(self.expr(3)),
+                    true => {
+                        let (name, arity) = self.rng.pick(METHODS);
+                        let target = E::Var(self.vars[self.rng.below(self.vars.len())].clone());
+                        let args = (0..arity).map(|_| self.expr(2)).collect();
+                        S::Expr(E::Method(Box::new(target), name, args))
+                    }
+                    false => {
+                        let (name, arity) = procs[self.rng.below(procs.len())].clone();
+                        S::Expr(E::Call(name, (0..arity).map(|_| self.expr(2)).collect()))
+                    }
+                }
+            }
+            6 if self.rng.chance(20) => S::Assert(self.expr(2)),
+            7 => {
+                let cond = self.expr(2);
+                let then = self.block(3);
+                let mut otherwise = if self.rng.chance(50) { self.block(3) } else { Vec::new() };
+                if matches!(otherwise.first(), Some(S::If(..))) {
+                    otherwise.clear();
+                }
+                S::If(cond, then, otherwise)
+            }
+            8 if self.loops < 2 => {
+                self.loops += 1;
+                let name = self.rng.pick(&["I", "J", "K"]).to_string();
+                let from = self.rng.pick(&["0", "1", "2"]);
+                let to = self.rng.pick(&["0", "2", "3"]);
+                let vars = self.vars.len();
+                self.define(&name);
+                let body = self.block(3);
+                self.vars.truncate(vars);
+                self.loops -= 1;
+                S::For(name, from, to, body)
+            }
+            9 if self.loops < 2 => {
+                self.loops += 1;
+                let iter = self.expr(2);
+                let vars = self.vars.len();
+                self.define("EACH");
+                let body = self.block(3);
+                self.vars.truncate(vars);
+                self.loops -= 1;
+                S::ForEach("EACH".into(), iter, body)
+            }
+            10 => {
+                let body = self.block(3);
+                let vars = self.vars.len();
+                self.define("ERR");
+                let handler = self.block(2);
+                self.vars.truncate(vars);
+                S::Attempt(body, handler)
+            }
+            12 => {
+                let name = format!("F{}", self.functions.len() + 1);
+                let arity = self.rng.below(3);
+                let params: Vec<String> = (1..=arity).map(|i| format!("P{}", i)).collect();
+
+                // Only the parameters are in scope, and no loops so calls
+                // from loops stay cheap
+                let (vars, loops) = (std::mem::replace(&mut self.vars, params.clone()), self.loops);
+                self.loops = 2;
+                let body = self.stmts(3, false);
+                let ret = if self.rng.chance(60) { Some(self.expr(2)) } else { None };
+                self.vars = vars;
+                self.loops = loops;
+
+                self.functions.push((name.clone(), arity, ret.is_some()));
+                S::Function(name, params, body, ret)
+            }
+            _ => S::Output(self.expr(3)),
+        }
+    }
+}
+
+fn generate(seed: u64, size: usize) -> Vec<S> {
+    let mut gen = Gen {
+        rng: Rng(seed),
+        vars: Vec::new(),
+        functions: Vec::new(),
+        budget: size,
+        loops: 0,
+    };
+
+    let mut ret = Vec::new();
+    while gen.budget > 0 {
+        ret.append(&mut gen.stmts(size, true));
+    }
+    ret
+}
+
+thread_local! {
+    /// Where the panic hook leaves the last panic's message
+    static PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// What went wrong running `prog` through lex, parse and eval, if anything.
+/// Errors the program reports itself are fine, only panics count, and
+/// generated programs failing to parse, which they never should
+fn check(prog: &str) -> Option<String> {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let options = LexOptions::default();
+        let stmts = match lex::lex(prog, &options).and_then(parse::parse) {
+            Ok(x) => x,
+            Err(e) => return Some(format!("Didn't parse: {}", e.msg)),
+        };
+
+        let mut env = Env::new();
+        env.seed(0);
+        env.out = Box::new(io::sink());
+        env.input = Some(Box::new(io::empty()));
+        env.strict_input = true;
+        let _ = env.run(&stmts);
+        None
+    }));
+
+    match res {
+        Ok(x) => x,
+        Err(_) => Some(PANIC.with(|x| x.borrow_mut().take()).unwrap_or_else(|| "Panicked".into())),
+    }
+}
+
+/// Versions of a call or array with one of `items` in place of it, or one
+/// of them shrunk, or with `removable` one of them left out
+fn shrink_list(items: &[E], removable: bool, make: &dyn Fn(Vec<E>) -> E) -> Vec<E> {
+    let mut ret: Vec<E> = items.to_vec();
+
+    for (i, item) in items.iter().enumerate() {
+        for x in shrink_expr(item) {
+            let mut items = items.to_vec();
+            items[i] = x;
+            ret.push(make(items));
+        }
+    }
+
+    if removable {
+        for i in 0..items.len() {
+            let mut items = items.to_vec();
+            items.remove(i);
+            ret.push(make(items));
+        }
+    }
+    ret
+}
+
+/// Smaller versions of `expr`: its parts, or a literal
+fn shrink_expr(expr: &E) -> Vec<E> {
+    let mut ret: Vec<E> = Vec::new();
+    let boxed = |x: &E| Box::new(x.clone());
+
+    match expr {
+        E::Lit("0") => return ret,
+        E::Lit(_) | E::Var(_) => {}
+        E::Bin(l, op, r) => {
+            ret.push((**l).clone());
+            ret.push((**r).clone());
+            ret.extend(shrink_expr(l).into_iter().map(|x| E::Bin(Box::new(x), op, r.clone())));
+            ret.extend(shrink_expr(r).into_iter().map(|x| E::Bin(l.clone(), op, Box::new(x))));
+        }
+        E::BNot(x) => {
+            ret.push((**x).clone());
+            ret.extend(shrink_expr(x).into_iter().map(|x| E::BNot(Box::new(x))));
+        }
+        E::Call(name, args) => ret.extend(shrink_list(args, false, &|args| E::Call(name.clone(), args))),
+        E::Array(items) => ret.extend(shrink_list(items, true, &E::Array)),
+        E::Method(target, name, args) => {
+            ret.push((**target).clone());
+            ret.extend(shrink_expr(target).into_iter().map(|x| E::Method(Box::new(x), name, args.clone())));
+            ret.extend(shrink_list(args, false, &|args| E::Method(target.clone(), name, args)));
+        }
+        E::Index(target, index) => {
+            ret.push((**target).clone());
+            ret.extend(shrink_expr(target).into_iter().map(|x| E::Index(Box::new(x), boxed(index))));
+            ret.extend(shrink_expr(index).into_iter().map(|x| E::Index(boxed(target), Box::new(x))));
+        }
+        E::Map(entries) => {
+            ret.extend((0..entries.len()).map(|i| {
+                let mut entries = entries.clone();
+                entries.remove(i);
+                E::Map(entries)
+            }));
+        }
+    }
+
+    ret.push(E::Lit("0"));
+    ret
+}
+
+/// Smaller versions of `stmts`: without a statement, with a block replaced
+/// by its body, or with one statement shrunk
+fn shrink_stmts(stmts: &[S]) -> Vec<Vec<S>> {
+    let mut ret = Vec::new();
+
+    for i in 0..stmts.len() {
+        let mut without = stmts.to_vec();
+        without.remove(i);
+        ret.push(without);
+    }
+
+    for (i, stmt) in stmts.iter().enumerate() {
+        let bodies: Vec<&Vec<S>> = match stmt {
+            S::If(_, a, b) | S::Attempt(a, b) => vec![a, b],
+            S::For(.., body) | S::ForEach(_, _, body) | S::Function(_, _, body, _) => vec![body],
+            _ => vec![],
+        };
+        for body in bodies {
+            let mut spliced = stmts[..i].to_vec();
+            spliced.extend(body.iter().cloned());
+            spliced.extend(stmts[i + 1..].iter().cloned());
+            ret.push(spliced);
+        }
+
+        for x in shrink_stmt(stmt) {
+            let mut changed = stmts.to_vec();
+            changed[i] = x;
+            ret.push(changed);
+        }
+    }
+
+    ret
+}
+
+/// Whether an expression statement is a call, of a method on a variable
+/// or of a procedure
+fn statement(stmt: &S) -> bool {
+    match stmt {
+        S::Expr(E::Call(..)) => true,
+        S::Expr(E::Method(target, ..)) => matches!(**target, E::Var(_)),
+        _ => false,
+    }
+}
+
+fn shrink_stmt(stmt: &S) -> Vec<S> {
+    let exprs = |x: &E, f: &dyn Fn(E) -> S| shrink_expr(x).into_iter().map(f).collect::<Vec<S>>();
+    let bodies = |x: &Vec<S>, f: &dyn Fn(Vec<S>) -> S| shrink_stmts(x).into_iter().map(f).collect::<Vec<S>>();
+
+    match stmt {
+        S::Assign(name, x) => exprs(x, &|x| S::Assign(name.clone(), x)),
+        S::SetIndex(name, i, x) => {
+            let mut ret = exprs(i, &|i| S::SetIndex(name.clone(), i, x.clone()));
+            ret.extend(exprs(x, &|x| S::SetIndex(name.clone(), i.clone(), x)));
+            ret
+        }
+        S::Multi(names, xs) => (0..xs.len())
+            .flat_map(|i| {
+                exprs(&xs[i], &|x| {
+                    let mut xs = xs.clone();
+                    xs[i] = x;
+                    S::Multi(names.clone(), xs)
+                })
+            })
+            .collect(),
+        S::Output(x) => exprs(x, &S::Output),
+        // Only calls can stand alone as statements
+        S::Expr(x) => exprs(x, &S::Expr)
+            .into_iter()
+            .filter(statement)
+            .collect(),
+        S::Assert(x) => exprs(x, &S::Assert),
+        S::If(cond, then, otherwise) => {
+            let mut ret = exprs(cond, &|x| S::If(x, then.clone(), otherwise.clone()));
+            ret.extend(bodies(then, &|x| S::If(cond.clone(), x, otherwise.clone())));
+            ret.extend(bodies(otherwise, &|x| S::If(cond.clone(), then.clone(), x)));
+            ret
+        }
+        S::For(name, from, to, body) => bodies(body, &|x| S::For(name.clone(), from, to, x)),
+        S::ForEach(name, iter, body) => {
+            let mut ret = exprs(iter, &|x| S::ForEach(name.clone(), x, body.clone()));
+            ret.extend(bodies(body, &|x| S::ForEach(name.clone(), iter.clone(), x)));
+            ret
+        }
+        S::Attempt(body, handler) => {
+            let mut ret = bodies(body, &|x| S::Attempt(x, handler.clone()));
+            ret.extend(bodies(handler, &|x| S::Attempt(body.clone(), x)));
+            ret
+        }
+        S::Function(name, params, body, ret_expr) => {
+            let f = |body: Vec<S>, ret_expr: Option<E>| S::Function(name.clone(), params.clone(), body, ret_expr);
+            let mut ret = bodies(body, &|x| f(x, ret_expr.clone()));
+            if let Some(x) = ret_expr {
+                ret.extend(exprs(x, &|x| f(body.clone(), Some(x))));
+            }
+            ret
+        }
+    }
+}
+
+/// Shrinks `prog` for as long as some smaller version fails the same way
+fn shrink(mut prog: Vec<S>, failure: &str) -> Vec<S> {
+    'outer: loop {
+        for candidate in shrink_stmts(&prog) {
+            if well_formed(&candidate) && check(&source(&candidate)).as_deref() == Some(failure) {
+                prog = candidate;
+                continue 'outer;
+            }
+        }
+        return prog;
+    }
+}
+
+/// `psc fuzz [--seed N] [--runs N] [--size N]` runs random programs until
+/// one panics the interpreter, then shrinks it to a small program that
+/// still does. Each program gets its own seed, so a failure can be run
+/// again with `--seed S --runs 1`
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut seed = env::clock_seed();
+    let mut runs: u64 = 1000;
+    let mut size: usize = 20;
+
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| match args.next().and_then(|x| x.parse::<u64>().ok()) {
+            Some(x) => Ok(x),
+            None => Err(format!("{} expects a number", name)),
+        };
+        match arg.as_str() {
+            "--seed" => seed = number("--seed")?,
+            "--runs" => runs = number("--runs")?,
+            "--size" => size = number("--size")?.max(1) as usize,
+            _ => return Err(format!("Unknown fuzz argument {}", arg).into()),
+        }
+    }
+
+    // Panics are expected and reported here, not by the default hook
+    panic::set_hook(Box::new(|info| {
+        let msg = match info.location() {
+            Some(at) => format!("Panicked at {}:{}: {}", at.file(), at.line(), info.payload_as_str().unwrap_or("")),
+            None => "Panicked".into(),
+        };
+        PANIC.with(|x| *x.borrow_mut() = Some(msg));
+    }));
+
+    let mut rng = Rng(seed);
+    for i in 0..runs {
+        let case = if i == 0 { seed } else { rng.next() };
+        let prog = generate(case, size);
+
+        if let Some(failure) = check(&source(&prog)) {
+            println!("Seed {} fails: {}", case, failure);
+            println!("Shrinking...");
+            let prog = shrink(prog, &failure);
+            let _ = panic::take_hook();
+            print!("{}", source(&prog));
+            std::process::exit(1);
+        }
+    }
+
+    let _ = panic::take_hook();
+    println!("{} programs ran without a panic, from seed {}", runs, seed);
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use psc::env::{Captured, Env};
+use psc::eval::Stmt;
+use psc::json::Json;
+use psc::lex::LexOptions;
+use psc::load;
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A test case, what to feed a submission and what it should output
+struct Case {
+    name: String,
+    input: Vec<u8>,
+    expected: String,
+}
+
+enum Verdict {
+    Pass,
+    /// Ran to the end, but output something else
+    Wrong,
+    Error(String),
+    Timeout,
+    /// Ran more steps than allowed, most likely an endless loop
+    Steps,
+}
+
+impl Verdict {
+    fn name(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Wrong => "fail",
+            Verdict::Error(_) => "error",
+            Verdict::Timeout => "timeout",
+            Verdict::Steps => "steps",
+        }
+    }
+}
+
+struct Run {
+    verdict: Verdict,
+    time: Duration,
+    steps: u64,
+}
+
+struct Limits {
+    time: Duration,
+    steps: u64,
+}
+
+fn is_program(path: &Path) -> bool {
+    matches!(path.extension().and_then(|x| x.to_str()), Some("psc" | "ps"))
+}
+
+fn is_case(path: &Path) -> bool {
+    path.extension().and_then(|x| x.to_str()) == Some("expected")
+}
+
+/// Every `NAME.expected` under `dir`, with `NAME.in` as its input if there
+/// is one
+fn cases(dir: &Path) -> Result<Vec<Case>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    load::discover(dir, is_case, &mut files).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let mut ret = Vec::new();
+    for file in files {
+        let read = |path: &Path| fs::read(path).map_err(|e| format!("{}: {}", path.display(), e));
+        let input = file.with_extension("in");
+
+        ret.push(Case {
+            name: file.file_stem().and_then(|x| x.to_str()).unwrap_or("").to_string(),
+            input: if input.is_file() { read(&input)? } else { Vec::new() },
+            expected: String::from_utf8_lossy(&read(&file)?).into_owned(),
+        });
+    }
+    Ok(ret)
+}
+
+/// Output with the spaces at the ends of lines and the blank lines at the
+/// end left off, which students can't see and shouldn't lose marks for
+fn normalize(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    lines.join("\n").trim_end_matches('\n').to_string()
+}
+
+fn run_case(stmts: &[Stmt], options: &LexOptions, case: &Case, limits: &Limits) -> Run {
+    let out = Captured::default();
+
+    let mut env = Env::new();
+    env.seed(0);
+    env.deterministic = true;
+    env.dialect = options.dialect;
+    env.out = Box::new(out.clone());
+    env.input = Some(Box::new(Cursor::new(case.input.clone())));
+    env.strict_input = true;
+    env.max_steps = Some(limits.steps);
+
+    let start = Instant::now();
+    env.deadline = Some(start + limits.time);
+    let res = env.run(stmts);
+    let time = start.elapsed();
+
+    let verdict = match (res, env.halted) {
+        _ if env.steps > limits.steps => Verdict::Steps,
+        _ if env.timed_out => Verdict::Timeout,
+        (_, Some(code)) if code != 0 => Verdict::Error(format!("Halted with code {}", code)),
+        (Err(e), None) => Verdict::Error(e.msg),
+        _ if normalize(&out.text()) == normalize(&case.expected) => Verdict::Pass,
+        _ => Verdict::Wrong,
+    };
+
+    Run { verdict, time, steps: env.steps }
+}
+
+/// A field of a CSV row, quoted if it has to be
+fn csv(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// `psc grade PATH... --tests DIR` runs every program under the paths
+/// against every case in `DIR`, a `NAME.expected` output with an optional
+/// `NAME.in` input, and reports a row of results per submission as CSV, or
+/// everything as JSON with `--json`. Each run is stopped after
+/// `--time-limit MS` or `--step-limit N` statements and loop passes
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut paths: Vec<String> = Vec::new();
+    let mut tests: Option<String> = None;
+    let mut json = false;
+    let mut limits = Limits {
+        time: Duration::from_secs(5),
+        steps: 10_000_000,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tests" => match args.next() {
+                Some(x) => tests = Some(x),
+                None => return Err("--tests expects a folder".into()),
+            },
+            "--time-limit" => match args.next().and_then(|x| x.parse::<u64>().ok()) {
+                Some(x) if x > 0 => limits.time = Duration::from_millis(x),
+                _ => return Err("--time-limit expects a positive number of milliseconds".into()),
+            },
+            "--step-limit" => match args.next().and_then(|x| x.parse::<u64>().ok()) {
+                Some(x) if x > 0 => limits.steps = x,
+                _ => return Err("--step-limit expects a positive number".into()),
+            },
+            "--json" => json = true,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ => paths.push(arg),
+        }
+    }
+
+    let tests = match tests {
+        Some(x) => x,
+        None => return Err("grade expects --tests with a folder of test cases".into()),
+    };
+    if paths.is_empty() {
+        return Err("grade expects at least one submission".into());
+    }
+
+    let cases = cases(Path::new(&tests))?;
+    if cases.is_empty() {
+        return Err(format!("No test cases in {}, each needs a NAME.expected", tests).into());
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in &paths {
+        load::discover(Path::new(path), is_program, &mut files).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    // A submission that doesn't parse fails every case with the same error
+    let results: Vec<(String, Vec<Run>)> = files
+        .iter()
+        .map(|file| {
+            let runs = match load::load(file, &options) {
+                Ok(stmts) => cases.iter().map(|x| run_case(&stmts, &options, x, &limits)).collect(),
+                Err(e) => cases
+                    .iter()
+                    .map(|_| Run {
+                        verdict: Verdict::Error(e.to_string()),
+                        time: Duration::ZERO,
+                        steps: 0,
+                    })
+                    .collect(),
+            };
+            (file.display().to_string(), runs)
+        })
+        .collect();
+
+    let passed = |runs: &[Run]| runs.iter().filter(|x| matches!(x.verdict, Verdict::Pass)).count();
+
+    if json {
+        let report = results.iter().map(|(file, runs)| {
+            let details = cases.iter().zip(runs).map(|(case, run)| {
+                let mut entries = vec![
+                    ("case", Json::str(&case.name)),
+                    ("result", Json::str(run.verdict.name())),
+                    ("time_ms", Json::Float((run.time.as_secs_f64() * 1e6).round() / 1000.0)),
+                    ("steps", Json::Int(run.steps as i64)),
+                ];
+                if let Verdict::Error(msg) = &run.verdict {
+                    entries.push(("error", Json::str(msg)));
+                }
+                Json::object(entries)
+            });
+
+            Json::object(vec![
+                ("submission", Json::str(file)),
+                ("passed", Json::Int(passed(runs) as i64)),
+                ("total", Json::Int(cases.len() as i64)),
+                ("cases", Json::Array(details.collect())),
+            ])
+        });
+        println!("{}", Json::Array(report.collect()));
+        return Ok(());
+    }
+
+    let header: Vec<String> = ["submission", "passed", "total"].iter().map(|x| x.to_string()).chain(cases.iter().map(|x| csv(&x.name))).collect();
+    println!("{}", header.join(","));
+
+    for (file, runs) in &results {
+        let mut row = vec![csv(file), passed(runs).to_string(), cases.len().to_string()];
+        row.extend(runs.iter().map(|x| x.verdict.name().to_string()));
+        println!("{}", row.join(","));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, limits: &Limits) -> Verdict {
+        let options = LexOptions::default();
+        let stmts = psc::parse(source, &options).unwrap();
+        let case = Case { name: "case".into(), input: Vec::new(), expected: String::new() };
+        run_case(&stmts, &options, &case, limits).verdict
+    }
+
+    #[test]
+    fn empty_infinite_loop_is_stopped() {
+        let source = "loop while true\nend loop";
+        let limits = Limits { time: Duration::from_millis(50), steps: u64::MAX };
+        assert!(matches!(run(source, &limits), Verdict::Timeout));
+
+        let limits = Limits { time: Duration::from_secs(60), steps: 1000 };
+        assert!(matches!(run(source, &limits), Verdict::Steps));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::cfg::{Cfg, Node};
+use psc::eval::{Expr, Function, Stmt, StmtKind};
+use psc::lex::LexOptions;
+use psc::load;
+use psc::visit::{walk_expr, Visitor};
+use crate::transpile::walk;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+
+/// The expressions a statement evaluates itself, not those in its body
+fn own_exprs(stmt: &Stmt) -> Vec<&Expr> {
+    match &stmt.kind {
+        StmtKind::Assign(x) => vec![&x.target, &x.expr],
+        StmtKind::MultiAssign(x) => x.targets.iter().chain(&x.exprs).collect(),
+        StmtKind::Output(x) => vec![&x.expr],
+        StmtKind::If(x) => x.branches.iter().map(|(cond, _)| cond).collect(),
+        StmtKind::While(x) => vec![&x.cond],
+        StmtKind::Until(x) | StmtKind::Repeat(x) => vec![&x.cond],
+        StmtKind::For(x) => vec![&x.start, &x.end],
+        StmtKind::ForEach(x) => vec![&x.iter],
+        StmtKind::Expr(x) | StmtKind::Return(Some(x)) | StmtKind::Halt(Some(x)) => vec![x],
+        StmtKind::Assert(x) => x.msg.iter().chain([&x.cond]).collect(),
+        _ => vec![],
+    }
+}
+
+/// Collects the names of the functions and methods called
+struct Calls<'a>(&'a mut BTreeSet<String>);
+
+impl Visitor for Calls<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call(x) => self.0.insert(x.name.clone()),
+            Expr::MethodCall(x) => self.0.insert(format!(".{}", x.name)),
+            Expr::SuperCall(x) => self.0.insert(format!(".{}", x.name)),
+            Expr::New(x) => self.0.insert(format!("{}.new", x.class)),
+            _ => false,
+        };
+        walk_expr(self, expr);
+    }
+}
+
+/// Adds the names of the functions and methods `expr` calls to `out`
+fn expr_calls(expr: &Expr, out: &mut BTreeSet<String>) {
+    Calls(out).visit_expr(expr);
+}
+
+/// A body of code that can be called, `main` for the top level
+struct Unit<'a> {
+    name: String,
+    stmts: &'a [Stmt],
+}
+
+/// `main`, then every procedure, function and method, nested ones included
+fn units(stmts: &[Stmt]) -> Vec<Unit<'_>> {
+    fn add<'a>(func: &'a Function, name: String, ret: &mut Vec<Unit<'a>>) {
+        ret.push(Unit { name, stmts: &func.stmts });
+        nested(&func.stmts, ret);
+    }
+
+    fn nested<'a>(stmts: &'a [Stmt], ret: &mut Vec<Unit<'a>>) {
+        walk(stmts, &mut |stmt| match &stmt.kind {
+            StmtKind::Function(func) => add(func, func.name.clone(), ret),
+            StmtKind::Class(class) => {
+                for method in &class.methods {
+                    add(&method.func, format!("{}.{}", class.name, method.func.name), ret);
+                }
+            }
+            _ => {}
+        });
+    }
+
+    let mut ret = vec![Unit { name: "main".into(), stmts }];
+    nested(stmts, &mut ret);
+    ret
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// A Graphviz graph of which procedures and functions call which. Method
+/// calls can't be told apart by class, so they link to every method of
+/// that name
+fn call_graph(stmts: &[Stmt]) -> String {
+    let units = units(stmts);
+    let names: Vec<&str> = units.iter().map(|x| x.name.as_str()).collect();
+
+    let mut ret = String::from("digraph calls {\n    node [shape=box];\n");
+    for name in &names {
+        ret += &format!("    {};\n", quote(name));
+    }
+
+    for unit in &units {
+        let mut calls = BTreeSet::new();
+        walk(unit.stmts, &mut |stmt| own_exprs(stmt).into_iter().for_each(|x| expr_calls(x, &mut calls)));
+
+        // Inside a class a bare call can be to one of its own methods
+        let class = unit.name.rsplit_once('.').map(|(class, _)| class);
+
+        let mut targets = BTreeSet::new();
+        for call in &calls {
+            for name in &names {
+                let found = match (call.strip_prefix('.'), name.rsplit_once('.')) {
+                    (Some(method), Some((_, x))) => x.eq_ignore_ascii_case(method),
+                    (Some(_), None) => false,
+                    (None, Some((owner, x))) => (Some(owner) == class && x.eq_ignore_ascii_case(call)) || name == call,
+                    (None, None) => name == call,
+                };
+                if found {
+                    targets.insert(*name);
+                }
+            }
+        }
+        for target in targets {
+            ret += &format!("    {} -> {};\n", quote(&unit.name), quote(target));
+        }
+    }
+
+    ret += "}";
+    ret
+}
+
+/// The control-flow graph of every procedure, function and method, each
+/// boxed off in Graphviz DOT or in a Mermaid flowchart
+fn cfg_graph(stmts: &[Stmt], mermaid: bool) -> String {
+    let mut ret = match mermaid {
+        true => String::from("flowchart TD\n"),
+        false => String::from("digraph cfg {\n    node [shape=box];\n"),
+    };
+
+    for (i, unit) in units(stmts).iter().enumerate() {
+        let cfg = Cfg::build(unit.stmts);
+        let id = |node: usize| format!("u{}n{}", i, node);
+
+        match mermaid {
+            true => ret += &format!("    subgraph u{} [{}]\n", i, mermaid_text(&unit.name)),
+            false => ret += &format!("    subgraph cluster_{} {{\n        label={};\n", i, quote(&unit.name)),
+        }
+
+        for (node, kind) in cfg.nodes.iter().enumerate() {
+            let text = cfg.text(node);
+            ret += &match (mermaid, kind) {
+                (true, Node::Branch(..)) => format!("        {}{{{}}}\n", id(node), mermaid_text(&text)),
+                (true, Node::Entry | Node::Exit) => format!("        {}([{}])\n", id(node), mermaid_text(&text)),
+                (true, Node::Block(_)) => format!("        {}[{}]\n", id(node), mermaid_text(&text)),
+                (false, Node::Branch(..)) => format!("        {} [label={}, shape=diamond];\n", id(node), quote(&text)),
+                (false, Node::Entry | Node::Exit) => format!("        {} [label={}, shape=oval];\n", id(node), quote(&text)),
+                (false, Node::Block(_)) => format!("        {} [label={}];\n", id(node), quote(&text)),
+            };
+        }
+
+        for edge in &cfg.edges {
+            ret += &match (mermaid, edge.label) {
+                (true, "") => format!("        {} --> {}\n", id(edge.from), id(edge.to)),
+                (true, label) => format!("        {} -->|{}| {}\n", id(edge.from), label, id(edge.to)),
+                (false, "") => format!("        {} -> {};\n", id(edge.from), id(edge.to)),
+                (false, label) => format!("        {} -> {} [label={}];\n", id(edge.from), id(edge.to), quote(label)),
+            };
+        }
+
+        ret += match mermaid {
+            true => "    end\n",
+            false => "    }\n",
+        };
+    }
+
+    if !mermaid {
+        ret += "}";
+    }
+    ret.trim_end().to_string()
+}
+
+/// Text for a Mermaid node, quoted with the characters it can't hold as
+/// entities
+fn mermaid_text(text: &str) -> String {
+    format!("\"{}\"", text.replace('&', "#amp;").replace('"', "#quot;").replace('\n', "<br>"))
+}
+
+/// `psc graph --calls FILE` writes the program's call graph as Graphviz DOT,
+/// and `psc graph --cfg FILE` the control-flow graph of each procedure,
+/// as a Mermaid flowchart with `--mermaid`
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut calls = false;
+    let mut cfg = false;
+    let mut mermaid = false;
+    let mut file: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--calls" => calls = true,
+            "--cfg" => cfg = true,
+            "--mermaid" => mermaid = true,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unknown graph argument {}", arg).into()),
+        }
+    }
+
+    let file = match file {
+        Some(x) => x,
+        None => return Err("graph expects a file".into()),
+    };
+    if calls == cfg {
+        return Err("graph expects one of --calls or --cfg".into());
+    }
+    if mermaid && !cfg {
+        return Err("--mermaid only works with --cfg".into());
+    }
+
+    let stmts = load::load(Path::new(&file), &options)?;
+    match cfg {
+        true => println!("{}", cfg_graph(&stmts, mermaid)),
+        false => println!("{}", call_graph(&stmts)),
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use psc::env::{Captured, Env};
+use psc::lex::LexOptions;
+use psc::load;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+enum Outcome {
+    Pass,
+    /// An `assert` that didn't hold, or a halt with a non-zero code
+    Fail(String),
+    /// Any other error, including programs that don't parse
+    Error(String),
+}
+
+struct Report {
+    file: PathBuf,
+    outcome: Outcome,
+    /// Shown for cases that don't pass, the output or how it differs
+    detail: String,
+    time: Duration,
+}
+
+/// Whether `path` is named like a test, `*_test.psc` or `*_test.ps`
+fn is_test(path: &Path) -> bool {
+    let name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    name.ends_with("_test.psc") || name.ends_with("_test.ps")
+}
+
+/// Whether `path` is a program with an expected output next to it
+fn is_fixture(path: &Path) -> bool {
+    matches!(path.extension().and_then(|x| x.to_str()), Some("psc" | "ps")) && path.with_extension("expected").is_file()
+}
+
+/// Runs `file` in an interpreter of its own reading `input`, with what it
+/// output
+fn run_program(file: &Path, options: &LexOptions, input: Vec<u8>) -> (Outcome, String) {
+    let out = Captured::default();
+
+    let outcome = match load::load(file, options) {
+        Err(e) => Outcome::Error(e.to_string()),
+        Ok(stmts) => {
+            let mut env = Env::new();
+            env.out = Box::new(out.clone());
+            env.input = Some(Box::new(io::Cursor::new(input)));
+            // The input is all there, so running out of it is an error
+            env.strict_input = true;
+            env.dialect = options.dialect;
+
+            match (env.run(&stmts), env.halted) {
+                (_, Some(0)) | (Ok(()), None) => Outcome::Pass,
+                (_, Some(code)) => Outcome::Fail(format!("Halted with code {}", code)),
+                (Err(e), None) if e.msg.starts_with("Assertion failed") => Outcome::Fail(e.msg),
+                (Err(e), None) => Outcome::Error(e.msg),
+            }
+        }
+    };
+
+    (outcome, out.text())
+}
+
+/// Runs one test with no input
+fn run_test(file: &Path, options: &LexOptions) -> Report {
+    let start = Instant::now();
+    let (outcome, output) = run_program(file, options, Vec::new());
+
+    Report {
+        file: file.to_path_buf(),
+        outcome,
+        detail: output,
+        time: start.elapsed(),
+    }
+}
+
+/// The lines of `expected` and `got` marked `-` and `+` where they differ,
+/// from their longest common subsequence
+pub fn diff(expected: &str, got: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = got.lines().collect();
+
+    // lcs[i][j] is the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ret = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ret += &format!("  {}\n", a[i]);
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ret += &format!("+ {}\n", b[j]);
+            j += 1;
+        } else {
+            ret += &format!("- {}\n", a[i]);
+            i += 1;
+        }
+    }
+    ret
+}
+
+/// How `got` differs from `expected`, starting with the first line that
+/// doesn't match, or `None` if they're the same. `color` highlights that
+/// line with terminal escapes
+pub fn mismatch(expected: &str, got: &str, color: bool) -> Option<String> {
+    if expected == got {
+        return None;
+    }
+
+    let (a, b): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), got.lines().collect());
+    let n = a.len().max(b.len());
+    let show = |x: Option<&&str>| x.map_or("nothing".into(), |x| format!("{:?}", x));
+
+    let (at, first) = match (0..n).find(|&i| a.get(i) != b.get(i)) {
+        Some(i) => (format!("line {}", i + 1), format!("expected {}\n    got      {}", show(a.get(i)), show(b.get(i)))),
+        None => ("the end".into(), "the lines match, but not the line endings".into()),
+    };
+    let first = match color {
+        true => format!("\x1b[1;31m{}\x1b[0m", first),
+        false => first,
+    };
+
+    Some(format!("The output differs from the expected output at {}:\n    {}\n\n{}", at, first, diff(expected, got)))
+}
+
+/// Runs `file` with `file.in` as its input, if there is one, and compares
+/// the output with `file.expected`
+fn run_fixture(file: &Path, options: &LexOptions) -> Report {
+    let start = Instant::now();
+    let read = |ext: &str| fs::read(file.with_extension(ext));
+
+    let (outcome, detail) = match (read("in"), read("expected")) {
+        (_, Err(e)) => (Outcome::Error(format!("{}: {}", file.with_extension("expected").display(), e)), String::new()),
+        (Err(e), _) if e.kind() != io::ErrorKind::NotFound => {
+            (Outcome::Error(format!("{}: {}", file.with_extension("in").display(), e)), String::new())
+        }
+        (input, Ok(expected)) => {
+            let expected = String::from_utf8_lossy(&expected).into_owned();
+            let (outcome, output) = run_program(file, options, input.unwrap_or_default());
+
+            match outcome {
+                Outcome::Pass if output == expected => (Outcome::Pass, String::new()),
+                Outcome::Pass => (Outcome::Fail("The output differs".into()), diff(&expected, &output)),
+                x => (x, diff(&expected, &output)),
+            }
+        }
+    };
+
+    Report {
+        file: file.to_path_buf(),
+        outcome,
+        detail,
+        time: start.elapsed(),
+    }
+}
+
+/// `psc test [PATH...]` runs every `*_test.psc` under the paths, the
+/// current directory by default, and fails unless they all pass. With
+/// `--fixtures` it runs every program that has a `.expected` file next to
+/// it instead, feeding it the `.in` file, and compares what it output
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut paths: Vec<String> = Vec::new();
+    let mut fixtures = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fixtures" => fixtures = true,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(".".into());
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        let want = if fixtures { is_fixture } else { is_test };
+        load::discover(Path::new(path), want, &mut files).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if files.is_empty() && fixtures {
+        return Err("No fixtures found, each needs a name.expected next to name.psc".into());
+    } else if files.is_empty() {
+        return Err("No tests found, test files are named like name_test.psc".into());
+    }
+
+    let run = if fixtures { run_fixture } else { run_test };
+    let reports: Vec<Report> = files.iter().map(|x| run(x, &options)).collect();
+
+    // What the cases that went wrong output, to help find why
+    for report in &reports {
+        if !matches!(report.outcome, Outcome::Pass) && !report.detail.is_empty() {
+            match fixtures {
+                true => println!("--- {} (- expected, + output)", report.file.display()),
+                false => println!("--- output of {}", report.file.display()),
+            }
+            print!("{}", report.detail);
+            if !report.detail.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+
+    let width = reports.iter().map(|x| x.file.display().to_string().len()).max().unwrap_or(0);
+    let (mut passed, mut failed, mut errors) = (0, 0, 0);
+
+    for report in &reports {
+        let (status, msg) = match &report.outcome {
+            Outcome::Pass => {
+                passed += 1;
+                ("PASS", "")
+            }
+            Outcome::Fail(msg) => {
+                failed += 1;
+                ("FAIL", msg.as_str())
+            }
+            Outcome::Error(msg) => {
+                errors += 1;
+                ("ERROR", msg.as_str())
+            }
+        };
+        let line = format!(
+            "{:<5}  {:<width$}  {:>9.3}ms  {}",
+            status,
+            report.file.display(),
+            report.time.as_secs_f64() * 1000.0,
+            msg,
+            width = width
+        );
+        println!("{}", line.trim_end());
+    }
+
+    println!("{} passed, {} failed, {} errors", passed, failed, errors);
+
+    if failed + errors > 0 {
+        io::stdout().flush()?;
+        std::process::exit(1);
+    }
+    Ok(())
+}
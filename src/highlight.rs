@@ -0,0 +1,211 @@
+use psc::lex::{self, LexOptions, LexerToken, Punctuation, Token};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// What a piece of source text is, for colouring
+#[derive(Clone, Copy)]
+enum Class {
+    Keyword,
+    Number,
+    String,
+    /// `true`, `false` and `null`
+    Constant,
+    Name,
+    /// A name right before `(`
+    Call,
+    Operator,
+    /// `///` doc comments
+    Comment,
+    /// Whitespace between tokens
+    Plain,
+}
+
+impl Class {
+    fn css(&self) -> &'static str {
+        match self {
+            Class::Keyword => "kw",
+            Class::Number => "num",
+            Class::String => "str",
+            Class::Constant => "const",
+            Class::Name => "name",
+            Class::Call => "call",
+            Class::Operator => "op",
+            Class::Comment => "doc",
+            Class::Plain => "",
+        }
+    }
+
+    /// SGR parameters for terminals
+    fn ansi(&self) -> &'static str {
+        match self {
+            Class::Keyword => "1;34",
+            Class::Number => "36",
+            Class::String => "32",
+            Class::Constant => "35",
+            Class::Name => "",
+            Class::Call => "33",
+            Class::Operator => "90",
+            Class::Comment => "3;90",
+            Class::Plain => "",
+        }
+    }
+}
+
+const STYLE: &str = "pre.psc { background: #fafafa; color: #1f2328; padding: 1em; tab-size: 4; }
+.psc .kw { color: #0550ae; font-weight: bold; }
+.psc .num { color: #0a3069; }
+.psc .str { color: #116329; }
+.psc .const { color: #8250df; }
+.psc .call { color: #953800; }
+.psc .op { color: #57606a; }
+.psc .doc { color: #6e7781; font-style: italic; }
+";
+
+fn classify(tokens: &[Token], index: usize) -> Class {
+    match &tokens[index].tok {
+        LexerToken::Keyword(_) => Class::Keyword,
+        LexerToken::IntLit(_) | LexerToken::FloatLit(_) => Class::Number,
+        LexerToken::StrLit(_) => Class::String,
+        LexerToken::BoolLit(_) | LexerToken::NullLit => Class::Constant,
+        LexerToken::Identifier(_) => match tokens.get(index + 1).map(|x| &x.tok) {
+            Some(LexerToken::Punctuation(Punctuation::LParen)) => Class::Call,
+            _ => Class::Name,
+        },
+        LexerToken::Punctuation(_) => Class::Operator,
+        LexerToken::Doc(_) => Class::Comment,
+    }
+}
+
+/// Splits the whole of `prog` into classified pieces. A token runs up to
+/// where the next one starts, less any whitespace in between
+fn pieces(prog: &str, options: &LexOptions) -> Result<Vec<(Class, String)>, Box<dyn Error>> {
+    let tokens = lex::lex(prog, options)?;
+    let chars: Vec<char> = prog.chars().collect();
+
+    // Where each line starts, in characters
+    let mut line_starts = vec![0];
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '\n' {
+            line_starts.push(i + 1);
+        }
+    }
+    let offset = |token: &Token| line_starts[token.span.line - 1] + token.span.col - 1;
+
+    let mut ret: Vec<(Class, String)> = Vec::new();
+    let mut pos = 0;
+
+    for i in 0..tokens.len() {
+        let start = offset(&tokens[i]).max(pos);
+        let mut end = tokens[i + 1..].iter().map(offset).find(|&x| x > start).unwrap_or(chars.len());
+        while end > start && chars[end - 1].is_whitespace() {
+            end -= 1;
+        }
+
+        // Words like `ENDIF` become several tokens with nothing left over
+        // for the later ones
+        if end <= start {
+            continue;
+        }
+
+        if start > pos {
+            ret.push((Class::Plain, chars[pos..start].iter().collect()));
+        }
+        ret.push((classify(&tokens, i), chars[start..end].iter().collect()));
+        pos = end;
+    }
+
+    if pos < chars.len() {
+        ret.push((Class::Plain, chars[pos..].iter().collect()));
+    }
+
+    Ok(ret)
+}
+
+fn escape(text: &str) -> String {
+    let mut ret = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// A standalone HTML page showing `prog`
+pub fn html(prog: &str, options: &LexOptions, title: &str) -> Result<String, Box<dyn Error>> {
+    let mut body = String::new();
+    for (class, text) in pieces(prog, options)? {
+        match class.css() {
+            "" => body.push_str(&escape(&text)),
+            css => body.push_str(&format!("<span class=\"{}\">{}</span>", css, escape(&text))),
+        }
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<pre class=\"psc\"><code>{}</code></pre>\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    ))
+}
+
+/// `prog` coloured with terminal escape codes
+pub fn ansi(prog: &str, options: &LexOptions) -> Result<String, Box<dyn Error>> {
+    let mut ret = String::new();
+    for (class, text) in pieces(prog, options)? {
+        match class.ansi() {
+            "" => ret.push_str(&text),
+            sgr => ret.push_str(&format!("\x1b[{}m{}\x1b[0m", sgr, text)),
+        }
+    }
+    Ok(ret)
+}
+
+/// `psc highlight FILE [-o OUT] [--ansi]` writes HTML, to `OUT` or stdout,
+/// or with `--ansi` colours for a terminal
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut file: Option<String> = None;
+    let mut out: Option<String> = None;
+    let mut use_ansi = false;
+    let mut options = LexOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(x) => out = Some(x),
+                None => return Err("-o expects a file name".into()),
+            },
+            "--ansi" => use_ansi = true,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unexpected highlight argument {}", arg).into()),
+        }
+    }
+
+    let file = match file {
+        Some(x) => x,
+        None => return Err("highlight expects a file".into()),
+    };
+
+    let prog = fs::read_to_string(&file).map_err(|e| format!("{}: {}", file, e))?;
+    let title = Path::new(&file).file_name().map_or(file.clone(), |x| x.to_string_lossy().to_string());
+
+    let res = if use_ansi {
+        ansi(&prog, &options)
+    } else {
+        html(&prog, &options, &title)
+    };
+    let res = res.map_err(|e| format!("{}: {}", file, e))?;
+
+    match out {
+        Some(out) => fs::write(&out, res).map_err(|e| format!("{}: {}", out, e))?,
+        None => print!("{}", res),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use crate::env::Env;
+use crate::err::RuntimeError;
+use crate::eval::{PscObject, Stmt};
+use std::io::{BufReader, Read, Write};
+use std::rc::Rc;
+
+/// Runs programs for a program embedding psc. What one run defines is still
+/// there for the next, like lines typed into the repl
+pub struct Interpreter {
+    /// Everything else about running, like limits and the dialect
+    pub env: Env,
+}
+
+impl Interpreter {
+    /// Reading the process's stdin and writing to its stdout
+    pub fn new() -> Interpreter {
+        Interpreter { env: Env::new() }
+    }
+
+    /// Reading `input` statements from `input` and writing what's output to
+    /// `output`, which can be kept in memory with a `Captured`
+    ///
+    /// ```
+    /// use psc::env::Captured;
+    /// use std::io::Cursor;
+    ///
+    /// let out = Captured::default();
+    /// let mut interpreter = psc::Interpreter::with_io(Cursor::new("5\n"), out.clone());
+    /// let stmts = psc::parse("input integer X\noutput X * 2", &Default::default()).unwrap();
+    /// interpreter.run(&stmts).unwrap();
+    /// assert_eq!(out.text(), "10\n");
+    /// ```
+    pub fn with_io(input: impl Read + 'static, output: impl Write + 'static) -> Interpreter {
+        let mut ret = Interpreter::new();
+        ret.env.input = Some(Box::new(BufReader::new(input)));
+        ret.env.out = Box::new(output);
+        ret
+    }
+
+    /// Calls `f` with each value the program outputs, as well as writing
+    /// it as text, so a host can show arrays as tables and so on. Output
+    /// only goes to `f` with `io::sink()` for the text
+    ///
+    /// ```
+    /// use psc::eval::PscObject;
+    /// use std::cell::RefCell;
+    /// use std::io;
+    /// use std::rc::Rc;
+    ///
+    /// let values = Rc::new(RefCell::new(Vec::new()));
+    /// let mut interpreter = psc::Interpreter::with_io(io::empty(), io::sink());
+    /// let seen = values.clone();
+    /// interpreter.on_output(move |x| seen.borrow_mut().push(x.clone()));
+    /// interpreter.run(&psc::parse("output [1, 2]", &Default::default()).unwrap()).unwrap();
+    /// assert!(matches!(&values.borrow()[0], PscObject::ArrayT(x) if x.len() == 2));
+    /// ```
+    pub fn on_output(&mut self, f: impl FnMut(&PscObject) + 'static) {
+        self.env.on_output = Some(Box::new(f));
+    }
+
+    /// Makes `f` a function programs can call as `name`, ahead of any
+    /// builtin of the same name. Procedures the program defines still come
+    /// first
+    ///
+    /// ```
+    /// use psc::env::Captured;
+    /// use psc::eval::PscObject;
+    /// use std::io;
+    ///
+    /// let out = Captured::default();
+    /// let mut interpreter = psc::Interpreter::with_io(io::empty(), out.clone());
+    /// interpreter.register_fn("DOUBLE", |args| {
+    ///     let x: i64 = args.into_iter().next().unwrap_or(PscObject::NullT).try_into()?;
+    ///     Ok((x * 2).into())
+    /// });
+    /// interpreter.run(&psc::parse("output DOUBLE(21)", &Default::default()).unwrap()).unwrap();
+    /// assert_eq!(out.text(), "42\n");
+    /// ```
+    pub fn register_fn(&mut self, name: &str, f: impl Fn(Vec<PscObject>) -> Result<PscObject, RuntimeError> + 'static) {
+        self.env.natives.insert(name.to_string(), Rc::new(f));
+    }
+
+    /// Runs statements from `parse` or `load::load_file`. A `halt` isn't an
+    /// error, only the end of the program, see `halted`
+    pub fn run(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        let res = self.env.run(stmts);
+        // Anything buffered on the way to `output` is the program's too
+        let _ = self.env.out.flush();
+        match res {
+            Err(_) if self.env.halted.is_some() => Ok(()),
+            x => x,
+        }
+    }
+
+    /// The exit code of the `halt` that ended the last run, if one did
+    pub fn halted(&self) -> Option<i32> {
+        self.env.halted
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1198 @@
+use super::{fail, fmod, Ctx, Unit};
+use crate::env::Env;
+use crate::eval::*;
+use crate::lex::Span;
+use crate::visit::{walk_expr, walk_stmt, walk_stmts, Visitor};
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, Block, BlockArg, FuncRef, InstBuilder, MemFlagsData, Signature, UserFuncName, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+use std::mem::{self, offset_of};
+use std::rc::Rc;
+
+/// The type of a value compiled code works with. Every one fits in the 64
+/// bits of a slot, as itself, the bits of the real, or 0 and 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int,
+    Real,
+    Bool,
+}
+
+impl Ty {
+    pub fn of(val: &PscObject) -> Option<Ty> {
+        match val {
+            PscObject::IntT(_) => Some(Ty::Int),
+            PscObject::FloatT(_) => Some(Ty::Real),
+            PscObject::BoolT(_) => Some(Ty::Bool),
+            _ => None,
+        }
+    }
+
+    fn ir(self) -> types::Type {
+        match self {
+            Ty::Int => types::I64,
+            Ty::Real => types::F64,
+            Ty::Bool => types::I8,
+        }
+    }
+}
+
+/// What a variable holds. An array is a pointer to its slots and a length
+/// that never changes, since compiled code can't append to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Scalar(Ty),
+    Array(Ty),
+}
+
+impl Kind {
+    /// `None` for anything else, and arrays of more than one type
+    pub fn of(val: &PscObject) -> Option<Kind> {
+        match val {
+            PscObject::ArrayT(items) => {
+                // An empty array can't be read from or stored into anyway
+                let ty = items.first().map_or(Some(Ty::Int), Ty::of)?;
+                items.iter().all(|x| Ty::of(x) == Some(ty)).then_some(Kind::Array(ty))
+            }
+            x => Ty::of(x).map(Kind::Scalar),
+        }
+    }
+}
+
+/// A variable of the interpreter's that a unit reads, in the order of the
+/// slots it's passed in
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    /// Written back once the unit is done
+    pub assigned: bool,
+}
+
+/// How compiled code failed, which is turned into the interpreter's error
+pub enum Fault {
+    /// With the operator, and the operands passed to `fail`
+    Overflow(&'static str),
+    /// With the line of the operator
+    DivZero(usize),
+    Bounds,
+    Shift,
+    /// With the function that would have been called
+    Depth(String),
+    Assert,
+    /// A call failed, which only adds to the trace
+    Unwind,
+}
+
+/// A place compiled code can fail, passed to `fail` by its index
+pub struct Site {
+    pub fault: Fault,
+    /// The statement running
+    pub line: usize,
+    pub span: Span,
+    /// The function it's in, `None` for a unit
+    pub func: Option<String>,
+}
+
+/// How a function returns, `Value(None)` until its first `return` is
+/// compiled
+#[derive(Clone, Copy, PartialEq)]
+enum Ret {
+    Null,
+    Value(Option<Ty>),
+}
+
+type Key = (*const Function, Vec<Ty>);
+
+/// A function compiled for the types of its arguments. Each takes the
+/// context and its arguments, and returns the bits of its value
+#[derive(Clone, Copy)]
+struct Func {
+    id: FuncId,
+    ret: Ret,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Var {
+    kind: Kind,
+    /// The value, or an array's pointer
+    val: Variable,
+    /// An array's length
+    len: Option<Variable>,
+}
+
+/// The names a loop reads and assigns, from the variables around it or its
+/// own
+#[derive(Default)]
+struct Names {
+    read: Vec<String>,
+    assigned: HashSet<String>,
+}
+
+impl Visitor for Names {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        let targets = match &stmt.kind {
+            StmtKind::Assign(x) => std::slice::from_ref(&x.target),
+            StmtKind::MultiAssign(x) => &x.targets[..],
+            _ => &[],
+        };
+        for target in targets {
+            let name = match target {
+                Expr::Ident(x) => x,
+                Expr::Index(x) => match &x.target {
+                    Expr::Ident(x) => x,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            self.assigned.insert(name.clone());
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(x) = expr {
+            if !self.read.contains(x) {
+                self.read.push(x.clone());
+            }
+        }
+        walk_expr(self, expr)
+    }
+}
+
+/// Whether any `return` in `stmts` gives a value
+fn returns_value(stmts: &[Stmt]) -> bool {
+    struct Returns(bool);
+
+    impl Visitor for Returns {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            self.0 |= matches!(stmt.kind, StmtKind::Return(Some(_)));
+            walk_stmt(self, stmt)
+        }
+    }
+
+    let mut returns = Returns(false);
+    walk_stmts(&mut returns, stmts);
+    returns.0
+}
+
+/// Whether `stmts` can't finish without a `return`, so a function made of
+/// them never gives null
+fn always_returns(stmts: &[Stmt]) -> bool {
+    match stmts.last().map(|x| &x.kind) {
+        Some(StmtKind::Return(_)) => true,
+        Some(StmtKind::If(x)) => {
+            matches!(x.branches.last(), Some((Expr::BoolLit(true), _)))
+                && x.branches.iter().all(|(_, stmts)| always_returns(stmts))
+        }
+        _ => false,
+    }
+}
+
+/// Turns loops and the functions they call into native code
+pub struct Compiler {
+    module: JITModule,
+    pub sites: Vec<Site>,
+    funcs: HashMap<Key, Func>,
+    /// Functions that can't be compiled for those argument types
+    unsupported: HashSet<Key>,
+    /// Compiled for the unit being compiled, defined once all of it is
+    pending: Vec<(FuncId, Context)>,
+    /// Added to `funcs` for the unit being compiled
+    added: Vec<Key>,
+    fail: FuncId,
+    fmod: FuncId,
+    /// For naming functions apart
+    next: u32,
+}
+
+impl Compiler {
+    pub fn new() -> Result<Compiler, String> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+        flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+        flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+        let isa = cranelift_native::builder()?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("psc_fail", fail as *const u8);
+        builder.symbol("psc_fmod", fmod as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let mut sig = module.make_signature();
+        sig.params.extend([AbiParam::new(types::I64); 4]);
+        let fail = module.declare_function("psc_fail", Linkage::Import, &sig).map_err(|e| e.to_string())?;
+        let mut sig = module.make_signature();
+        sig.params.extend([AbiParam::new(types::F64); 2]);
+        sig.returns.push(AbiParam::new(types::F64));
+        let fmod = module.declare_function("psc_fmod", Linkage::Import, &sig).map_err(|e| e.to_string())?;
+
+        Ok(Compiler {
+            module,
+            sites: Vec::new(),
+            funcs: HashMap::new(),
+            unsupported: HashSet::new(),
+            pending: Vec::new(),
+            added: Vec::new(),
+            fail,
+            fmod,
+            next: 0,
+        })
+    }
+
+    /// A unit for the rest of the loop `stmt`, with the variables it reads
+    /// as they are in `env`. `None` if anything in it can't be compiled
+    pub fn compile_loop(&mut self, stmt: &Stmt, env: &Env) -> Option<Unit> {
+        let mut names = Names::default();
+        names.visit_stmt(stmt);
+
+        let mut entries = Vec::new();
+        let mut absent = Vec::new();
+        for name in names.read {
+            let cell = match env.lookup(&name) {
+                Some(x) => x,
+                None => {
+                    absent.push(name);
+                    continue;
+                }
+            };
+            let assigned = names.assigned.contains(&name);
+            if assigned && !env.place(&name).is_some_and(|x| Rc::ptr_eq(&x, &cell)) {
+                return None;
+            }
+            let kind = Kind::of(&*cell.try_borrow().ok()?)?;
+            entries.push(Entry { name, kind, assigned });
+        }
+
+        let count = matches!(stmt.kind, StmtKind::For(_));
+        let (id, ctx, ret) = self.unit(env, |lower, slots| {
+            let mut k = 0;
+            let mut vars = Vec::new();
+            for entry in &entries {
+                let var = lower.load_entry(slots, &mut k, entry.kind);
+                lower.scopes[0].insert(entry.name.clone(), var);
+                vars.push(var);
+            }
+            let count = match count {
+                true => {
+                    let next = lower.b.ins().load(types::I64, MemFlagsData::trusted(), slots, k * 8);
+                    let end = lower.b.ins().load(types::I64, MemFlagsData::trusted(), slots, k * 8 + 8);
+                    Some((next, end))
+                }
+                false => None,
+            };
+
+            let exit = lower.exit?;
+            lower.lower_loop(stmt, count)?;
+            let done = lower.b.ins().iconst(types::I64, 0);
+            lower.b.ins().jump(exit, &[BlockArg::Value(done)]);
+
+            // Everything assigned goes back to the interpreter's variables,
+            // however the unit finished
+            lower.b.switch_to_block(exit);
+            let mut k = 0;
+            for (entry, var) in entries.iter().zip(vars) {
+                match var.kind {
+                    Kind::Scalar(ty) if entry.assigned => {
+                        let val = lower.b.use_var(var.val);
+                        let bits = lower.bits(val, ty);
+                        lower.b.ins().store(MemFlagsData::trusted(), bits, slots, k * 8);
+                    }
+                    _ => {}
+                }
+                k += if matches!(var.kind, Kind::Array(_)) { 2 } else { 1 };
+            }
+            let status = lower.b.block_params(exit)[0];
+            lower.b.ins().return_(&[status]);
+            Some(())
+        })?;
+
+        let code = self.finish(id, ctx)?;
+        Some(Unit { code, entries, absent, ret: ret.flatten() })
+    }
+
+    /// A unit calling `func` with arguments of the types `tys`
+    pub fn compile_call(&mut self, func: &Rc<Function>, tys: &[Ty], env: &Env) -> Option<Unit> {
+        let mut ret = None;
+        let (id, ctx, _) = self.unit(env, |lower, slots| {
+            let callee = lower.c.function(func, tys, env)?;
+            ret = match callee.ret {
+                Ret::Null => None,
+                Ret::Value(ty) => Some(ty?),
+            };
+            let mut args = vec![lower.ctx];
+            for (k, ty) in tys.iter().enumerate() {
+                let bits = lower.b.ins().load(types::I64, MemFlagsData::trusted(), slots, k as i32 * 8);
+                args.push(lower.of_bits(bits, *ty));
+            }
+
+            let callee_ref = lower.func_ref(callee.id);
+            let call = lower.b.ins().call(callee_ref, &args);
+            let ret = lower.b.inst_results(call)[0];
+            lower.b.ins().store(MemFlagsData::trusted(), ret, lower.ctx, offset_of!(Ctx, ret) as i32);
+            let failed = lower.b.ins().load(types::I64, MemFlagsData::trusted(), lower.ctx, offset_of!(Ctx, failed) as i32);
+            lower.b.ins().return_(&[failed]);
+            Some(())
+        })?;
+
+        let code = self.finish(id, ctx)?;
+        let entries = func
+            .params
+            .iter()
+            .zip(tys)
+            .map(|(name, ty)| Entry { name: name.clone(), kind: Kind::Scalar(*ty), assigned: false })
+            .collect();
+        Some(Unit { code, entries, absent: Vec::new(), ret })
+    }
+
+    /// Builds a unit, which takes the context and its slots and returns 0
+    /// when it's done, 1 when it failed and 2 when it returned. `body`
+    /// fills it in from the first block, and gives the type of anything it
+    /// returned
+    #[allow(clippy::type_complexity)]
+    fn unit(
+        &mut self,
+        env: &Env,
+        body: impl FnOnce(&mut Lower, Value) -> Option<()>,
+    ) -> Option<(FuncId, Context, Option<Option<Ty>>)> {
+        self.pending.clear();
+        self.added.clear();
+
+        let mut sig = self.module.make_signature();
+        sig.params.extend([AbiParam::new(types::I64); 2]);
+        sig.returns.push(AbiParam::new(types::I64));
+        let id = self.declare(&sig)?;
+
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let mut fctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+        let start = b.create_block();
+        b.append_block_params_for_function_params(start);
+        b.switch_to_block(start);
+        let params = b.block_params(start).to_vec();
+        let exit = b.create_block();
+        b.append_block_param(exit, types::I64);
+
+        let config = self.module.target_config();
+        let mut lower = Lower {
+            c: self,
+            b,
+            env,
+            ctx: params[0],
+            scopes: vec![HashMap::new()],
+            func: None,
+            exit: Some(exit),
+            ret: None,
+            at: Span { line: 0, col: 0, len: 0 },
+            made: HashMap::new(),
+            refs: HashMap::new(),
+        };
+        let res = body(&mut lower, params[1]);
+        let ret = lower.ret;
+        let mut b = lower.b;
+        // What was built so far is dropped half finished
+        if res.is_none() {
+            for key in self.added.drain(..) {
+                self.funcs.remove(&key);
+            }
+            self.pending.clear();
+            return None;
+        }
+        b.seal_all_blocks();
+        b.finalize(config);
+        Some((id, ctx, ret))
+    }
+
+    /// Defines the unit and everything compiled for it, giving its code
+    fn finish(&mut self, id: FuncId, mut ctx: Context) -> Option<extern "C" fn(*mut Ctx, *mut i64) -> i64> {
+        let mut ok = true;
+        for (func, mut ctx) in mem::take(&mut self.pending) {
+            ok &= self.module.define_function(func, &mut ctx).is_ok();
+        }
+        ok &= self.module.define_function(id, &mut ctx).is_ok();
+        if !ok || self.module.finalize_definitions().is_err() {
+            for key in self.added.drain(..) {
+                self.funcs.remove(&key);
+            }
+            return None;
+        }
+        self.added.clear();
+
+        let code = self.module.get_finalized_function(id);
+        // Built with exactly this signature by `unit`
+        Some(unsafe { mem::transmute::<*const u8, extern "C" fn(*mut Ctx, *mut i64) -> i64>(code) })
+    }
+
+    fn declare(&mut self, sig: &Signature) -> Option<FuncId> {
+        self.next += 1;
+        self.module.declare_function(&format!("psc_{}", self.next), Linkage::Local, sig).ok()
+    }
+
+    /// `func` compiled for arguments of the types `tys`, or being compiled
+    /// further up
+    fn function(&mut self, func: &Rc<Function>, tys: &[Ty], env: &Env) -> Option<Func> {
+        let key = (Rc::as_ptr(func), tys.to_vec());
+        if let Some(x) = self.funcs.get(&key) {
+            return Some(*x);
+        }
+        if self.unsupported.contains(&key) || func.params.len() != tys.len() {
+            return None;
+        }
+
+        // A function that gives a value has to every time, or it would give
+        // null, which compiled code has no type for
+        let ret = match returns_value(&func.stmts) {
+            true if !always_returns(&func.stmts) => return None,
+            true => Ret::Value(None),
+            false => Ret::Null,
+        };
+
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(types::I64));
+        sig.params.extend(tys.iter().map(|x| AbiParam::new(x.ir())));
+        sig.returns.push(AbiParam::new(types::I64));
+        let id = self.declare(&sig)?;
+        self.funcs.insert(key.clone(), Func { id, ret });
+        self.added.push(key.clone());
+
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let mut fctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+        let start = b.create_block();
+        b.append_block_params_for_function_params(start);
+        b.switch_to_block(start);
+        let params = b.block_params(start).to_vec();
+
+        let config = self.module.target_config();
+        let mut lower = Lower {
+            c: self,
+            b,
+            env,
+            ctx: params[0],
+            scopes: vec![HashMap::new()],
+            func: Some((func.name.clone(), key.clone())),
+            exit: None,
+            ret: None,
+            at: Span { line: 0, col: 0, len: 0 },
+            made: HashMap::new(),
+            refs: HashMap::new(),
+        };
+        for ((name, ty), param) in func.params.iter().zip(tys).zip(&params[1..]) {
+            let val = lower.b.declare_var(ty.ir());
+            lower.b.def_var(val, *param);
+            lower.scopes[0].insert(name.clone(), Var { kind: Kind::Scalar(*ty), val, len: None });
+        }
+        let res = lower.stmts(&func.stmts);
+        if res.is_some() {
+            // Only a procedure gets to the end
+            let null = lower.b.ins().iconst(types::I64, 0);
+            lower.b.ins().return_(&[null]);
+        }
+        let mut b = lower.b;
+        if res.is_none() {
+            self.funcs.remove(&key);
+            self.unsupported.insert(key);
+            return None;
+        }
+        b.seal_all_blocks();
+        b.finalize(config);
+        self.pending.push((id, ctx));
+        self.funcs.get(&(Rc::as_ptr(func), tys.to_vec())).copied()
+    }
+}
+
+/// Compiles the statements of one function or unit
+struct Lower<'a> {
+    c: &'a mut Compiler,
+    b: FunctionBuilder<'a>,
+    env: &'a Env,
+    ctx: Value,
+    /// Innermost last, like the interpreter's
+    scopes: Vec<HashMap<String, Var>>,
+    /// Variables the earlier branches of an `if` first assigned, for the
+    /// branch being compiled to assign too
+    made: HashMap<String, Var>,
+    /// The function being compiled and its key, `None` for a unit
+    func: Option<(String, Key)>,
+    /// Where a unit goes to finish, given its status
+    exit: Option<Block>,
+    /// What a unit's `return` gives, once it has one
+    ret: Option<Option<Ty>>,
+    /// The statement being compiled
+    at: Span,
+    refs: HashMap<FuncId, FuncRef>,
+}
+
+impl Lower<'_> {
+    fn func_ref(&mut self, id: FuncId) -> FuncRef {
+        if let Some(x) = self.refs.get(&id) {
+            return *x;
+        }
+        let ret = self.c.module.declare_func_in_func(id, self.b.func);
+        self.refs.insert(id, ret);
+        ret
+    }
+
+    fn lookup(&self, name: &str) -> Option<Var> {
+        self.scopes.iter().rev().find_map(|x| x.get(name).copied())
+    }
+
+    /// Reads a variable of the kind `kind` from slot `k` on, moving `k` past it
+    fn load_entry(&mut self, slots: Value, k: &mut i32, kind: Kind) -> Var {
+        let load = |b: &mut FunctionBuilder, k: i32| b.ins().load(types::I64, MemFlagsData::trusted(), slots, k * 8);
+        let ret = match kind {
+            Kind::Scalar(ty) => {
+                let bits = load(&mut self.b, *k);
+                let val = self.b.declare_var(ty.ir());
+                let x = self.of_bits(bits, ty);
+                self.b.def_var(val, x);
+                Var { kind, val, len: None }
+            }
+            Kind::Array(_) => {
+                let val = self.b.declare_var(types::I64);
+                let len = self.b.declare_var(types::I64);
+                let x = load(&mut self.b, *k);
+                self.b.def_var(val, x);
+                let x = load(&mut self.b, *k + 1);
+                self.b.def_var(len, x);
+                Var { kind, val, len: Some(len) }
+            }
+        };
+        *k += if matches!(kind, Kind::Array(_)) { 2 } else { 1 };
+        ret
+    }
+
+    fn bits(&mut self, val: Value, ty: Ty) -> Value {
+        match ty {
+            Ty::Int => val,
+            Ty::Real => self.b.ins().bitcast(types::I64, MemFlagsData::new(), val),
+            Ty::Bool => self.b.ins().uextend(types::I64, val),
+        }
+    }
+
+    fn of_bits(&mut self, bits: Value, ty: Ty) -> Value {
+        match ty {
+            Ty::Int => bits,
+            Ty::Real => self.b.ins().bitcast(types::F64, MemFlagsData::new(), bits),
+            Ty::Bool => self.b.ins().ireduce(types::I8, bits),
+        }
+    }
+
+    fn real(&mut self, val: Value, ty: Ty) -> Value {
+        match ty {
+            Ty::Int => self.b.ins().fcvt_from_sint(types::F64, val),
+            _ => val,
+        }
+    }
+
+    /// Where the code after a `return` or a failure goes, which nothing
+    /// jumps to
+    fn dead(&mut self) {
+        let block = self.b.create_block();
+        self.b.switch_to_block(block);
+    }
+
+    /// Counts an iteration of a loop
+    fn step(&mut self) {
+        let steps = self.b.ins().load(types::I64, MemFlagsData::trusted(), self.ctx, offset_of!(Ctx, steps) as i32);
+        let steps = self.b.ins().iadd_imm_s(steps, 1);
+        self.b.ins().store(MemFlagsData::trusted(), steps, self.ctx, offset_of!(Ctx, steps) as i32);
+    }
+
+    /// Leaves the function or unit as having failed
+    fn unwind(&mut self) {
+        match self.exit {
+            Some(exit) => {
+                let failed = self.b.ins().iconst(types::I64, 1);
+                self.b.ins().jump(exit, &[BlockArg::Value(failed)]);
+            }
+            None => {
+                let null = self.b.ins().iconst(types::I64, 0);
+                self.b.ins().return_(&[null]);
+            }
+        }
+    }
+
+    /// Fails with `fault` when `bad` is set, passing `a` and `b` on to
+    /// describe it
+    fn check(&mut self, bad: Value, fault: Fault, span: Span, a: Value, b: Value) {
+        self.c.sites.push(Site {
+            fault,
+            line: self.at.line,
+            span,
+            func: self.func.as_ref().map(|x| x.0.clone()),
+        });
+        let site = self.c.sites.len() as i64 - 1;
+
+        let failed = self.b.create_block();
+        let ok = self.b.create_block();
+        self.b.ins().brif(bad, failed, &[], ok, &[]);
+        self.b.switch_to_block(failed);
+        let site = self.b.ins().iconst(types::I64, site);
+        let fail = self.func_ref(self.c.fail);
+        self.b.ins().call(fail, &[self.ctx, site, a, b]);
+        self.unwind();
+        self.b.switch_to_block(ok);
+    }
+
+    /// `check` with nothing to describe the fault
+    fn check_bare(&mut self, bad: Value, fault: Fault, span: Span) {
+        let zero = self.b.ins().iconst(types::I64, 0);
+        self.check(bad, fault, span, zero, zero);
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Option<()> {
+        stmts.iter().try_for_each(|x| self.stmt(x))
+    }
+
+    /// `stmts` in a scope of their own
+    fn scoped(&mut self, stmts: &[Stmt]) -> Option<()> {
+        self.scopes.push(HashMap::new());
+        let res = self.stmts(stmts);
+        self.scopes.pop();
+        res
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Option<()> {
+        let outer = mem::replace(&mut self.at, stmt.span);
+        let res = self.lower_stmt(stmt);
+        self.at = outer;
+        res
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt) -> Option<()> {
+        match &stmt.kind {
+            // Arrays aren't made in compiled code
+            StmtKind::Assign(x) if x.declared.as_ref().is_some_and(|x| !x.bounds.is_empty()) => None,
+            StmtKind::Assign(x) => {
+                let val = self.expr(&x.expr)?;
+                self.assign(&x.target, val)
+            }
+
+            // Every value before any target, as the interpreter does
+            StmtKind::MultiAssign(x) => {
+                let vals = x.exprs.iter().map(|x| self.expr(x)).collect::<Option<Vec<_>>>()?;
+                x.targets.iter().zip(vals).try_for_each(|(target, val)| self.assign(target, val))
+            }
+
+            // A branch runs in the scope the `if` is in, so a variable every
+            // branch that finishes assigns is there after it, as the same
+            // variable in each
+            StmtKind::If(x) => {
+                let end = self.b.create_block();
+                let outer = mem::take(&mut self.made);
+                let known = self.scopes.last()?.keys().cloned().collect::<HashSet<_>>();
+                let mut made: Option<HashMap<String, Var>> = None;
+                for (cond, stmts) in &x.branches {
+                    let cond = self.cond(cond)?;
+                    let then = self.b.create_block();
+                    let next = self.b.create_block();
+                    self.b.ins().brif(cond, then, &[], next, &[]);
+                    self.b.switch_to_block(then);
+                    self.made = made.clone().unwrap_or_default();
+                    self.stmts(stmts)?;
+
+                    let scope = self.scopes.last_mut()?;
+                    let new = scope.iter().filter(|(k, _)| !known.contains(*k)).map(|(k, v)| (k.clone(), *v)).collect::<HashMap<_, _>>();
+                    scope.retain(|k, _| known.contains(k));
+                    if !always_returns(stmts) {
+                        made = Some(match made {
+                            Some(made) => made.into_iter().filter(|(k, v)| new.get(k) == Some(v)).collect(),
+                            None => new,
+                        });
+                    }
+                    self.b.ins().jump(end, &[]);
+                    self.b.switch_to_block(next);
+                }
+                self.made = outer;
+                self.b.ins().jump(end, &[]);
+                self.b.switch_to_block(end);
+
+                // Without an `else` nothing might have been assigned
+                if matches!(x.branches.last(), Some((Expr::BoolLit(true), _))) {
+                    self.scopes.last_mut()?.extend(made.unwrap_or_default());
+                }
+                Some(())
+            }
+
+            StmtKind::While(_) | StmtKind::Until(_) | StmtKind::Repeat(_) | StmtKind::For(_) => self.lower_loop(stmt, None),
+
+            StmtKind::Expr(Expr::Call(x)) => self.call(x).map(|_| ()),
+            StmtKind::Expr(x) => self.expr(x).map(|_| ()),
+
+            StmtKind::Return(x) => {
+                let val = match x {
+                    Some(x) => Some(self.expr(x)?),
+                    None => None,
+                };
+                self.ret(val)?;
+                self.dead();
+                Some(())
+            }
+
+            StmtKind::Assert(x) if x.msg.is_none() => {
+                let cond = self.cond(&x.cond)?;
+                let bad = self.b.ins().icmp_imm_s(IntCC::Equal, cond, 0);
+                self.check_bare(bad, Fault::Assert, stmt.span);
+                Some(())
+            }
+
+            _ => None,
+        }
+    }
+
+    /// A loop, or a unit's loop from `count` on, which for a `for` loop is
+    /// the value its variable takes next and the one it ends on
+    fn lower_loop(&mut self, stmt: &Stmt, count: Option<(Value, Value)>) -> Option<()> {
+        let outer = mem::replace(&mut self.at, stmt.span);
+        let body = self.b.create_block();
+        let end = self.b.create_block();
+
+        match &stmt.kind {
+            StmtKind::While(While { cond, stmts }) | StmtKind::Until(Until { cond, stmts }) => {
+                let head = self.b.create_block();
+                let while_loop = matches!(stmt.kind, StmtKind::While(_));
+                self.b.ins().jump(head, &[]);
+                self.b.switch_to_block(head);
+                let cond = self.cond(cond)?;
+                match while_loop {
+                    true => self.b.ins().brif(cond, body, &[], end, &[]),
+                    false => self.b.ins().brif(cond, end, &[], body, &[]),
+                };
+                self.b.switch_to_block(body);
+                self.step();
+                self.scoped(stmts)?;
+                self.b.ins().jump(head, &[]);
+            }
+
+            // The condition is checked once the body's scope has gone
+            StmtKind::Repeat(x) => {
+                self.b.ins().jump(body, &[]);
+                self.b.switch_to_block(body);
+                self.step();
+                self.scoped(&x.stmts)?;
+                let cond = self.cond(&x.cond)?;
+                self.b.ins().brif(cond, end, &[], body, &[]);
+            }
+
+            StmtKind::For(x) => {
+                let (start, last) = match count {
+                    Some(x) => x,
+                    None => {
+                        let start = self.int(&x.start)?;
+                        let last = self.int(&x.end)?;
+                        (start, last)
+                    }
+                };
+                let head = self.b.create_block();
+                let i = self.b.declare_var(types::I64);
+                self.b.def_var(i, start);
+                self.b.ins().jump(head, &[]);
+
+                self.b.switch_to_block(head);
+                let val = self.b.use_var(i);
+                let done = self.b.ins().icmp(IntCC::SignedGreaterThan, val, last);
+                self.b.ins().brif(done, end, &[], body, &[]);
+
+                self.b.switch_to_block(body);
+                self.step();
+                let bound = self.b.declare_var(types::I64);
+                self.b.def_var(bound, val);
+                let mut scope = HashMap::new();
+                scope.insert(x.name.clone(), Var { kind: Kind::Scalar(Ty::Int), val: bound, len: None });
+                self.scopes.push(scope);
+                let res = self.stmts(&x.stmts);
+                self.scopes.pop();
+                res?;
+
+                // Stopping at the end before adding one, so ending on the
+                // largest integer doesn't overflow
+                let val = self.b.use_var(i);
+                let last_time = self.b.ins().icmp(IntCC::Equal, val, last);
+                let next = self.b.create_block();
+                self.b.ins().brif(last_time, end, &[], next, &[]);
+                self.b.switch_to_block(next);
+                let val = self.b.ins().iadd_imm_s(val, 1);
+                self.b.def_var(i, val);
+                self.b.ins().jump(head, &[]);
+            }
+
+            _ => return None,
+        }
+
+        self.b.switch_to_block(end);
+        self.at = outer;
+        Some(())
+    }
+
+    fn assign(&mut self, target: &Expr, (val, ty): (Value, Ty)) -> Option<()> {
+        match target {
+            Expr::Ident(name) => match self.lookup(name) {
+                Some(var) if var.kind == Kind::Scalar(ty) => self.b.def_var(var.val, val),
+                Some(_) => return None,
+                None => {
+                    let var = match self.made.get(name) {
+                        Some(var) if var.kind == Kind::Scalar(ty) => var.val,
+                        _ => self.b.declare_var(ty.ir()),
+                    };
+                    self.b.def_var(var, val);
+                    let scope = self.scopes.last_mut()?;
+                    scope.insert(name.clone(), Var { kind: Kind::Scalar(ty), val: var, len: None });
+                }
+            },
+
+            Expr::Index(x) => {
+                let addr = self.element(x, ty)?;
+                let bits = self.bits(val, ty);
+                self.b.ins().store(MemFlagsData::trusted(), bits, addr, 0);
+            }
+
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// The address of an element of an array of `ty`, failing when the
+    /// index is out of bounds
+    fn element(&mut self, index: &Index, ty: Ty) -> Option<Value> {
+        let key = self.int(&index.index)?;
+        let var = match &index.target {
+            Expr::Ident(x) => self.lookup(x)?,
+            _ => return None,
+        };
+        if var.kind != Kind::Array(ty) {
+            return None;
+        }
+
+        let len = self.b.use_var(var.len?);
+        // Negative indexes are past the end as unsigned numbers
+        let bad = self.b.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, key, len);
+        self.check(bad, Fault::Bounds, self.at, key, len);
+        let ptr = self.b.use_var(var.val);
+        let offset = self.b.ins().ishl_imm_s(key, 3);
+        Some(self.b.ins().iadd(ptr, offset))
+    }
+
+    fn ret(&mut self, val: Option<(Value, Ty)>) -> Option<()> {
+        let ty = val.map(|x| x.1);
+        match &self.func {
+            Some((_, key)) => {
+                let func = self.c.funcs.get_mut(key)?;
+                match (func.ret, ty) {
+                    (Ret::Null, None) => {}
+                    (Ret::Value(None), Some(ty)) => func.ret = Ret::Value(Some(ty)),
+                    (Ret::Value(Some(x)), Some(ty)) if x == ty => {}
+                    _ => return None,
+                }
+                let bits = match val {
+                    Some((val, ty)) => self.bits(val, ty),
+                    None => self.b.ins().iconst(types::I64, 0),
+                };
+                self.b.ins().return_(&[bits]);
+            }
+            None => {
+                match self.ret {
+                    None => self.ret = Some(ty),
+                    Some(x) if x == ty => {}
+                    Some(_) => return None,
+                }
+                if let Some((val, ty)) = val {
+                    let bits = self.bits(val, ty);
+                    self.b.ins().store(MemFlagsData::trusted(), bits, self.ctx, offset_of!(Ctx, ret) as i32);
+                }
+                let returned = self.b.ins().iconst(types::I64, 2);
+                self.b.ins().jump(self.exit?, &[BlockArg::Value(returned)]);
+            }
+        }
+        Some(())
+    }
+
+    fn cond(&mut self, expr: &Expr) -> Option<Value> {
+        match self.expr(expr)? {
+            (val, Ty::Bool) => Some(val),
+            _ => None,
+        }
+    }
+
+    fn int(&mut self, expr: &Expr) -> Option<Value> {
+        match self.expr(expr)? {
+            (val, Ty::Int) => Some(val),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Option<(Value, Ty)> {
+        match expr {
+            Expr::IntLit(x) => Some((self.b.ins().iconst(types::I64, *x), Ty::Int)),
+            Expr::FloatLit(x) => Some((self.b.ins().f64const(*x), Ty::Real)),
+            Expr::BoolLit(x) => Some((self.b.ins().iconst(types::I8, *x as i64), Ty::Bool)),
+
+            Expr::Ident(name) => match self.lookup(name)? {
+                Var { kind: Kind::Scalar(ty), val, .. } => Some((self.b.use_var(val), ty)),
+                _ => None,
+            },
+
+            Expr::BinOp(x) => self.bin_op(x),
+
+            Expr::BNot(x) => match self.expr(x)? {
+                (val, Ty::Int) => Some((self.b.ins().bnot(val), Ty::Int)),
+                _ => None,
+            },
+
+            // Only the side that's picked runs
+            Expr::IfElse(x) => {
+                let cond = self.cond(&x.cond)?;
+                let then = self.b.create_block();
+                let otherwise = self.b.create_block();
+                let end = self.b.create_block();
+                self.b.ins().brif(cond, then, &[], otherwise, &[]);
+
+                self.b.switch_to_block(then);
+                let (a, ty) = self.expr(&x.then)?;
+                self.b.append_block_param(end, ty.ir());
+                self.b.ins().jump(end, &[BlockArg::Value(a)]);
+                self.b.switch_to_block(otherwise);
+                let b = match self.expr(&x.otherwise)? {
+                    (b, other) if other == ty => b,
+                    _ => return None,
+                };
+                self.b.ins().jump(end, &[BlockArg::Value(b)]);
+
+                self.b.switch_to_block(end);
+                Some((self.b.block_params(end)[0], ty))
+            }
+
+            Expr::Index(x) => {
+                let ty = match &x.target {
+                    Expr::Ident(name) => match self.lookup(name)?.kind {
+                        Kind::Array(ty) => ty,
+                        Kind::Scalar(_) => return None,
+                    },
+                    _ => return None,
+                };
+                let addr = self.element(x, ty)?;
+                let bits = self.b.ins().load(types::I64, MemFlagsData::trusted(), addr, 0);
+                Some((self.of_bits(bits, ty), ty))
+            }
+
+            Expr::Call(x) if self.length(&x.name) => match &x.args[..] {
+                [arg] => self.len(arg),
+                _ => None,
+            },
+            Expr::MethodCall(x) if x.name.eq_ignore_ascii_case("length") && x.args.is_empty() => self.len(&x.target),
+
+            Expr::Call(x) => match self.call(x)? {
+                (Some(val), Some(ty)) => Some((val, ty)),
+                _ => None,
+            },
+
+            _ => None,
+        }
+    }
+
+    /// The length of the array `target` names
+    fn len(&mut self, target: &Expr) -> Option<(Value, Ty)> {
+        let var = match target {
+            Expr::Ident(x) => self.lookup(x)?,
+            _ => return None,
+        };
+        Some((self.b.use_var(var.len?), Ty::Int))
+    }
+
+    /// Whether a call of `name` is the builtin `LENGTH`, with nothing the
+    /// program or host defines in its place
+    fn length(&self, name: &str) -> bool {
+        let env = self.env;
+        let method = match (&self.func, env.this()) {
+            (None, Some(this)) => env.find_method(&this.borrow().class, name).is_some(),
+            _ => false,
+        };
+        self.callee(name).is_none()
+            && !method
+            && !env.natives.contains_key(name)
+            && env.dialect.table().builtin(name) == "LENGTH"
+    }
+
+    /// The top level function a call of `name` runs, when it's nothing
+    /// nested. A function runs with none of its caller's variables, only
+    /// the globals
+    fn callee(&self, name: &str) -> Option<Rc<Function>> {
+        let cell = match self.func {
+            Some(_) => self.env.global(name),
+            None => self.env.lookup(name),
+        };
+        if cell.is_some_and(|x| matches!(&*x.borrow(), PscObject::FunctionT(_))) {
+            return None;
+        }
+        self.env.functions.get(name).cloned()
+    }
+
+    /// Calls a top level function, giving its value and its type, which is
+    /// `None` for a procedure or one whose type isn't known yet
+    fn call(&mut self, call: &Call) -> Option<(Option<Value>, Option<Ty>)> {
+        let func = self.callee(&call.name)?;
+        let mut args = vec![self.ctx];
+        let mut tys = Vec::new();
+        for arg in &call.args {
+            let (val, ty) = self.expr(arg)?;
+            args.push(val);
+            tys.push(ty);
+        }
+        let callee = self.c.function(&func, &tys, self.env)?;
+
+        // As deep as the interpreter allows, counting the calls it's in
+        let depth = self.b.ins().load(types::I64, MemFlagsData::trusted(), self.ctx, offset_of!(Ctx, depth) as i32);
+        let max = self.b.ins().load(types::I64, MemFlagsData::trusted(), self.ctx, offset_of!(Ctx, max_depth) as i32);
+        let bad = self.b.ins().icmp(IntCC::SignedGreaterThan, depth, max);
+        self.check_bare(bad, Fault::Depth(func.name.clone()), call.span);
+        let deeper = self.b.ins().iadd_imm_s(depth, 1);
+        self.b.ins().store(MemFlagsData::trusted(), deeper, self.ctx, offset_of!(Ctx, depth) as i32);
+
+        let callee_ref = self.func_ref(callee.id);
+        let inst = self.b.ins().call(callee_ref, &args);
+        let bits = self.b.inst_results(inst)[0];
+        self.b.ins().store(MemFlagsData::trusted(), depth, self.ctx, offset_of!(Ctx, depth) as i32);
+        let failed = self.b.ins().load(types::I64, MemFlagsData::trusted(), self.ctx, offset_of!(Ctx, failed) as i32);
+        self.check_bare(failed, Fault::Unwind, self.at);
+
+        match callee.ret {
+            Ret::Value(Some(ty)) => Some((Some(self.of_bits(bits, ty)), Some(ty))),
+            _ => Some((None, None)),
+        }
+    }
+
+    fn bin_op(&mut self, op: &BinOp) -> Option<(Value, Ty)> {
+        let (l, lt) = self.expr(&op.left)?;
+        let (r, rt) = self.expr(&op.right)?;
+        let ints = lt == Ty::Int && rt == Ty::Int;
+        let numbers = lt != Ty::Bool && rt != Ty::Bool;
+
+        if matches!(op.op, BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod) && numbers {
+            let zero = match rt {
+                Ty::Int => self.b.ins().icmp_imm_s(IntCC::Equal, r, 0),
+                _ => {
+                    let zero = self.b.ins().f64const(0.0);
+                    self.b.ins().fcmp(FloatCC::Equal, r, zero)
+                }
+            };
+            self.check_bare(zero, Fault::DivZero(op.span.line), op.span);
+        }
+
+        let ret = match op.op {
+            BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Mul if ints => {
+                let (res, overflow, sym) = match op.op {
+                    BinaryOp::Plus => {
+                        let (res, overflow) = self.b.ins().sadd_overflow(l, r);
+                        (res, overflow, "+")
+                    }
+                    BinaryOp::Minus => {
+                        let (res, overflow) = self.b.ins().ssub_overflow(l, r);
+                        (res, overflow, "-")
+                    }
+                    _ => {
+                        let (res, overflow) = self.b.ins().smul_overflow(l, r);
+                        (res, overflow, "*")
+                    }
+                };
+                self.check(overflow, Fault::Overflow(sym), self.at, l, r);
+                (res, Ty::Int)
+            }
+            BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Mul | BinaryOp::Div | BinaryOp::FloorDiv
+                if numbers =>
+            {
+                let l = self.real(l, lt);
+                let r = self.real(r, rt);
+                let res = match op.op {
+                    BinaryOp::Plus => self.b.ins().fadd(l, r),
+                    BinaryOp::Minus => self.b.ins().fsub(l, r),
+                    BinaryOp::Mul => self.b.ins().fmul(l, r),
+                    BinaryOp::Div => self.b.ins().fdiv(l, r),
+                    _ => {
+                        let res = self.b.ins().fdiv(l, r);
+                        self.b.ins().floor(res)
+                    }
+                };
+                (res, Ty::Real)
+            }
+
+            // The remainder of the smallest integer by -1 is 0, which
+            // dividing by 1 gives without overflowing
+            BinaryOp::Mod if ints => {
+                let minus_one = self.b.ins().icmp_imm_s(IntCC::Equal, r, -1);
+                let one = self.b.ins().iconst(types::I64, 1);
+                let r = self.b.ins().select(minus_one, one, r);
+                (self.b.ins().srem(l, r), Ty::Int)
+            }
+            BinaryOp::Mod if numbers => {
+                let l = self.real(l, lt);
+                let r = self.real(r, rt);
+                let fmod = self.func_ref(self.c.fmod);
+                let inst = self.b.ins().call(fmod, &[l, r]);
+                let res = self.b.inst_results(inst)[0];
+                (self.b.ins().floor(res), Ty::Real)
+            }
+
+            BinaryOp::GE | BinaryOp::LE | BinaryOp::GT | BinaryOp::LT if ints => {
+                let cc = match op.op {
+                    BinaryOp::GE => IntCC::SignedGreaterThanOrEqual,
+                    BinaryOp::LE => IntCC::SignedLessThanOrEqual,
+                    BinaryOp::GT => IntCC::SignedGreaterThan,
+                    _ => IntCC::SignedLessThan,
+                };
+                (self.b.ins().icmp(cc, l, r), Ty::Bool)
+            }
+            BinaryOp::GE | BinaryOp::LE | BinaryOp::GT | BinaryOp::LT if numbers => {
+                let cc = match op.op {
+                    BinaryOp::GE => FloatCC::GreaterThanOrEqual,
+                    BinaryOp::LE => FloatCC::LessThanOrEqual,
+                    BinaryOp::GT => FloatCC::GreaterThan,
+                    _ => FloatCC::LessThan,
+                };
+                let l = self.real(l, lt);
+                let r = self.real(r, rt);
+                (self.b.ins().fcmp(cc, l, r), Ty::Bool)
+            }
+
+            BinaryOp::Equals | BinaryOp::NotEquals => {
+                let eq = match (lt, rt) {
+                    (Ty::Int, Ty::Int) | (Ty::Bool, Ty::Bool) => self.b.ins().icmp(IntCC::Equal, l, r),
+                    _ if numbers => {
+                        let l = self.real(l, lt);
+                        let r = self.real(r, rt);
+                        self.b.ins().fcmp(FloatCC::Equal, l, r)
+                    }
+                    _ => return None,
+                };
+                match op.op {
+                    BinaryOp::Equals => (eq, Ty::Bool),
+                    _ => (self.b.ins().bxor_imm_s(eq, 1), Ty::Bool),
+                }
+            }
+
+            BinaryOp::BAnd if ints => (self.b.ins().band(l, r), Ty::Int),
+            BinaryOp::BOr if ints => (self.b.ins().bor(l, r), Ty::Int),
+            BinaryOp::BXor if ints => (self.b.ins().bxor(l, r), Ty::Int),
+            BinaryOp::Shl | BinaryOp::Shr if ints => {
+                let bad = self.b.ins().icmp_imm_s(IntCC::UnsignedGreaterThanOrEqual, r, 64);
+                self.check(bad, Fault::Shift, self.at, l, r);
+                match op.op {
+                    BinaryOp::Shl => (self.b.ins().ishl(l, r), Ty::Int),
+                    _ => (self.b.ins().sshr(l, r), Ty::Int),
+                }
+            }
+
+            _ => return None,
+        };
+        Some(ret)
+    }
+}
@@ -0,0 +1,398 @@
+//! Compiling hot loops and the functions they call to native code with
+//! Cranelift, for `--jit`. Only integers, reals, booleans and arrays of one
+//! of them are compiled. A loop or call with anything else, like strings or
+//! output, is left to the interpreter, as is a loop whose variables are of
+//! other types the next time it's run
+
+mod compile;
+
+use crate::env::{self, borrow_mut, Env};
+use crate::err::RuntimeError;
+use crate::eval::{Flow, Function, PscObject, Stmt};
+use compile::{Compiler, Entry, Fault, Kind, Site, Ty};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How many times round a loop, or calls of a function, before it's worth
+/// compiling
+const HOT: u32 = 50;
+
+/// How many array items a loop can copy in and out for each iteration it
+/// runs before running it isn't worth that, like an inner loop over a big
+/// array that's only run for a few iterations at a time
+const COPIES: u64 = 16;
+
+/// How many versions of a loop are compiled, each for different types of
+/// the variables it reads
+const VERSIONS: usize = 4;
+
+/// What compiled code is given, the fields it reads and writes first
+#[repr(C)]
+pub struct Ctx {
+    /// Calls running, counting the interpreter's
+    depth: i64,
+    max_depth: i64,
+    /// Set once it has failed, and is returning to the interpreter
+    failed: i64,
+    /// The value of a call, or of a `return` in a loop, as bits
+    ret: i64,
+    /// Iterations of loops run
+    steps: i64,
+    sites: *const [Site],
+    error: Option<RuntimeError>,
+    /// The calls the error came out of, innermost first
+    trace: Vec<(String, usize)>,
+    /// The line of the loop's statement the error came out of
+    line: usize,
+}
+
+/// Called by compiled code as it fails at `site`, and again by each call
+/// the failure returns through. `a` and `b` are what the fault is about,
+/// like the operands that overflowed
+extern "C" fn fail(ctx: *mut Ctx, site: i64, a: i64, b: i64) {
+    // Compiled code always passes its own context and one of its sites
+    let ctx = unsafe { &mut *ctx };
+    let site = unsafe { &(*ctx.sites)[site as usize] };
+
+    let (msg, code) = match &site.fault {
+        Fault::Overflow(sym) => (format!("Integer overflow in {} {} {}", a, sym, b), Some("E018")),
+        Fault::DivZero(line) => (format!("Division by zero at line {}", line), Some("E017")),
+        Fault::Bounds => (format!("Index {} out of bounds for array of length {}", a, b), Some("E015")),
+        Fault::Shift => (format!("Cannot shift by {} bits", b), Some("E015")),
+        Fault::Depth(name) => (format!("Maximum call depth of {} exceeded in {}", ctx.max_depth, name), None),
+        Fault::Assert => (format!("Assertion failed at line {}", site.line), Some("E028")),
+        Fault::Unwind => (String::new(), None),
+    };
+    if !matches!(site.fault, Fault::Unwind) {
+        ctx.error = Some(RuntimeError { msg, span: Some(site.span), code });
+    }
+    ctx.failed = 1;
+
+    match &site.func {
+        Some(name) => ctx.trace.push((name.clone(), site.line)),
+        None => ctx.line = site.line,
+    }
+}
+
+/// The `%` of reals, which Cranelift has no instruction for
+extern "C" fn fmod(a: f64, b: f64) -> f64 {
+    a % b
+}
+
+/// A compiled loop or call, which takes the context and the slots of the
+/// variables it reads, and returns 0 once it's done, 1 if it failed and 2
+/// if it returned
+pub struct Unit {
+    code: extern "C" fn(*mut Ctx, *mut i64) -> i64,
+    /// In the order of their slots, an array taking two for where its
+    /// items are and how many there are
+    entries: Vec<Entry>,
+    /// Names it made variables of its own, so it's only right while the
+    /// interpreter has no variables of those names
+    absent: Vec<String>,
+    /// The type of what it returns, `None` for null
+    ret: Option<Ty>,
+}
+
+/// The slots of a unit's variables, read from the interpreter's
+struct Bound {
+    slots: Vec<i64>,
+    cells: Vec<Rc<std::cell::RefCell<PscObject>>>,
+    /// The items of each array, which the slots point into
+    arrays: Vec<Vec<i64>>,
+}
+
+fn to_bits(val: &PscObject) -> i64 {
+    match val {
+        PscObject::IntT(x) => *x,
+        PscObject::FloatT(x) => x.to_bits() as i64,
+        PscObject::BoolT(x) => *x as i64,
+        _ => 0,
+    }
+}
+
+fn from_bits(bits: i64, ty: Ty) -> PscObject {
+    match ty {
+        Ty::Int => PscObject::IntT(bits),
+        Ty::Real => PscObject::FloatT(f64::from_bits(bits as u64)),
+        Ty::Bool => PscObject::BoolT(bits != 0),
+    }
+}
+
+impl Unit {
+    /// The slots for running it from the variables in `env`, if they're
+    /// still the ones and the types it was compiled for
+    fn bind(&self, env: &Env) -> Option<Bound> {
+        if self.absent.iter().any(|x| env.lookup(x).is_some()) {
+            return None;
+        }
+
+        let mut cells = Vec::new();
+        let mut arrays = Vec::new();
+        for entry in &self.entries {
+            let cell = env.lookup(&entry.name)?;
+            if entry.assigned && !env.place(&entry.name).is_some_and(|x| Rc::ptr_eq(&x, &cell)) {
+                return None;
+            }
+            let val = cell.try_borrow().ok()?;
+            if Kind::of(&val)? != entry.kind {
+                return None;
+            }
+            if let PscObject::ArrayT(items) = &*val {
+                arrays.push(items.iter().map(to_bits).collect());
+            }
+            drop(val);
+            cells.push(cell);
+        }
+
+        // Filled in once the arrays have stopped moving
+        let mut slots = Vec::new();
+        let mut items = arrays.iter_mut();
+        for cell in &cells {
+            match &*cell.borrow() {
+                PscObject::ArrayT(_) => {
+                    let array: &mut Vec<i64> = items.next()?;
+                    slots.push(array.as_mut_ptr() as i64);
+                    slots.push(array.len() as i64);
+                }
+                x => slots.push(to_bits(x)),
+            }
+        }
+        Some(Bound { slots, cells, arrays })
+    }
+
+    /// Runs it with `bound`, and writes what it assigned back to the
+    /// interpreter's variables however it finished
+    fn run(&self, mut bound: Bound, count: Option<(i64, i64)>, depth: usize, env: &Env, sites: &[Site]) -> (i64, Ctx) {
+        if let Some((next, end)) = count {
+            bound.slots.extend([next, end]);
+        }
+        let mut ctx = Ctx {
+            depth: depth as i64,
+            max_depth: env.max_depth as i64,
+            failed: 0,
+            ret: 0,
+            steps: 0,
+            sites,
+            error: None,
+            trace: Vec::new(),
+            line: 0,
+        };
+        let status = (self.code)(&mut ctx, bound.slots.as_mut_ptr());
+
+        let mut k = 0;
+        let mut arrays = bound.arrays.into_iter();
+        for (entry, cell) in self.entries.iter().zip(&bound.cells) {
+            match entry.kind {
+                Kind::Scalar(ty) => {
+                    if entry.assigned {
+                        if let Ok(mut x) = borrow_mut(cell) {
+                            *x = from_bits(bound.slots[k], ty);
+                        }
+                    }
+                    k += 1;
+                }
+                Kind::Array(ty) => {
+                    let array = arrays.next().unwrap_or_default();
+                    if let (true, Ok(mut x)) = (entry.assigned, borrow_mut(cell)) {
+                        if let PscObject::ArrayT(items) = &mut *x {
+                            for (item, bits) in items.iter_mut().zip(array) {
+                                *item = from_bits(bits, ty);
+                            }
+                        }
+                    }
+                    k += 2;
+                }
+            }
+        }
+        (status, ctx)
+    }
+}
+
+/// What's known about a loop or function so far
+#[derive(Default)]
+struct Hot {
+    count: u32,
+    units: Vec<Unit>,
+    /// Set once it couldn't be compiled, or wasn't worth running, so it
+    /// isn't tried again
+    gave_up: bool,
+    /// Iterations its units have run, and the array items they copied
+    steps: u64,
+    copied: u64,
+}
+
+/// Compiles loops and functions once they've run `HOT` times, for an
+/// `Env` to run instead of walking them. They're known by their address,
+/// so one is only for the statements of one program, which have to
+/// outlive it
+pub struct Jit {
+    compiler: Compiler,
+    loops: HashMap<*const Stmt, Hot>,
+    calls: HashMap<*const Function, HashMap<Vec<Ty>, Hot>>,
+}
+
+impl Jit {
+    /// Fails on a machine Cranelift can't generate code for
+    pub fn new() -> Result<Jit, String> {
+        Ok(Jit {
+            compiler: Compiler::new()?,
+            loops: HashMap::new(),
+            calls: HashMap::new(),
+        })
+    }
+}
+
+impl env::Jit for Jit {
+    fn call(&mut self, func: &Rc<Function>, args: &[PscObject], env: &mut Env) -> Option<Result<PscObject, RuntimeError>> {
+        let tys = args.iter().map(Ty::of).collect::<Option<Vec<_>>>()?;
+        let hot = self.calls.entry(Rc::as_ptr(func)).or_default().entry(tys.clone()).or_default();
+        hot.count += 1;
+        if hot.count < HOT || hot.gave_up {
+            return None;
+        }
+        if hot.units.is_empty() {
+            match self.compiler.compile_call(func, &tys, env) {
+                Some(x) => hot.units.push(x),
+                None => {
+                    hot.gave_up = true;
+                    return None;
+                }
+            }
+        }
+
+        let unit = &hot.units[0];
+        let bound = Bound {
+            slots: args.iter().map(to_bits).collect(),
+            cells: Vec::new(),
+            arrays: Vec::new(),
+        };
+        // Run as the call the interpreter was about to make
+        let (status, ctx) = unit.run(bound, None, env.depth() + 1, env, &self.compiler.sites);
+        if status == 1 {
+            env.trace.extend(ctx.trace);
+            return Some(Err(ctx.error?));
+        }
+        Some(Ok(match unit.ret {
+            Some(ty) => from_bits(ctx.ret, ty),
+            None => PscObject::NullT,
+        }))
+    }
+
+    fn resume(&mut self, stmt: &Stmt, count: Option<(i64, i64)>, env: &mut Env) -> Option<Result<Flow, RuntimeError>> {
+        let hot = self.loops.entry(stmt).or_default();
+        hot.count += 1;
+        if hot.count < HOT || hot.gave_up {
+            return None;
+        }
+
+        let found = hot.units.iter().enumerate().find_map(|(i, x)| x.bind(env).map(|x| (i, x)));
+        let (i, bound) = match found {
+            Some(x) => x,
+            None if hot.units.len() >= VERSIONS => return None,
+            None => {
+                let unit = self.compiler.compile_loop(stmt, env);
+                let bound = unit.as_ref().and_then(|x| x.bind(env));
+                match (unit, bound) {
+                    (Some(unit), Some(bound)) => {
+                        hot.units.push(unit);
+                        (hot.units.len() - 1, bound)
+                    }
+                    _ => {
+                        hot.gave_up = true;
+                        return None;
+                    }
+                }
+            }
+        };
+
+        let unit = &hot.units[i];
+        let copied = bound.arrays.iter().map(|x| x.len() as u64).sum::<u64>();
+        let (status, ctx) = unit.run(bound, count, env.depth(), env, &self.compiler.sites);
+        hot.steps += ctx.steps as u64;
+        hot.copied += copied;
+        // Left to the interpreter from the next time on, allowing for a
+        // loop that's run once over a big array without many iterations
+        if hot.copied > COPIES * hot.steps + (1 << 20) {
+            hot.gave_up = true;
+        }
+        match status {
+            1 => {
+                env.trace.extend(ctx.trace);
+                env.failed_at(ctx.line);
+                Some(Err(ctx.error?))
+            }
+            2 => Some(Ok(Flow::Return(match unit.ret {
+                Some(ty) => from_bits(ctx.ret, ty),
+                None => PscObject::NullT,
+            }))),
+            _ => Some(Ok(Flow::Next)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Captured;
+    use std::io;
+    use std::thread;
+
+    /// What `source` outputs, and the error it stops with, run with or
+    /// without the jit
+    /// on a stack big enough for `MAX_DEPTH` calls in a debug build
+    fn run(source: &str, jit: bool) -> (String, Option<String>) {
+        let source = source.to_string();
+        let run = move || {
+            let stmts = crate::parse(&source, &Default::default()).unwrap();
+            let out = Captured::default();
+            let mut env = Env::new();
+            env.out = Box::new(out.clone());
+            env.err = Box::new(io::sink());
+            if jit {
+                env.jit = Some(Box::new(Jit::new().unwrap()));
+            }
+            let res = env.run(&stmts);
+            let err = res.err().map(|x| format!("{} {:?} {:?}", x.msg, x.span, env.trace));
+            (out.text(), err)
+        };
+        thread::Builder::new().stack_size(256 << 20).spawn(run).unwrap().join().unwrap()
+    }
+
+    fn same(source: &str) {
+        assert_eq!(run(source, true), run(source, false), "{}", source);
+    }
+
+    #[test]
+    fn loops_give_what_the_interpreter_does() {
+        same("T = 0\nloop I from 1 to 1000\n  T = T + I * I mod 7\nend loop\noutput T");
+        same("X = 1.5\nN = 0\nloop while N < 200\n  X = X * 1.01 + N / 3\n  N = N + 1\nend loop\noutput X\noutput N");
+        same("N = 0\nloop until N > 500\n  N = N + 3\nend loop\noutput N");
+        same("C = 0\nloop I from 0 to 300\n  F = I mod 3 == 0\n  if F != (I > 150) then\n    C = C + 1\n  end if\nend loop\noutput C");
+        same("T = 0\nloop I from 1 to 200\n  if I mod 3 == 0 then\n    R = I\n  else\n    R = 1\n  end if\n  loop while R > 0\n    T = T + R\n    R = R - 2\n  end loop\nend loop\noutput T");
+        same("C = 0\nloop I from 1 to 100\n  loop J from 1 to I\n    if J mod 2 == 0 then\n      C = C + 1\n    else\n      C = C - (J div 3)\n    end if\n  end loop\nend loop\noutput C");
+    }
+
+    #[test]
+    fn arrays_are_sorted_in_place() {
+        same(
+            "A = []\nloop I from 0 to 199\n  A.append((I * 7919) mod 200)\nend loop\n\
+             loop I from 0 to 198\n  loop J from 0 to 198 - I\n    if A[J] > A[J + 1] then\n      A[J], A[J + 1] = A[J + 1], A[J]\n    end if\n  end loop\nend loop\n\
+             output A[0]\noutput A[199]\noutput LENGTH(A)",
+        );
+    }
+
+    #[test]
+    fn functions_are_compiled_for_their_argument_types() {
+        same("function FIB(N)\n  if N < 2 then\n    return N\n  end if\n  return FIB(N - 1) + FIB(N - 2)\nend function\noutput FIB(20)");
+        same("function HALF(X)\n  return X / 2\nend function\nT = 0\nloop I from 1 to 100\n  T = T + HALF(I)\nend loop\noutput T");
+    }
+
+    #[test]
+    fn errors_are_the_interpreter_s() {
+        same("X = 1\nloop I from 1 to 100\n  X = X * 1000\nend loop");
+        same("A = [1, 2, 3]\nT = 0\nloop I from 0 to 100\n  T = T + A[I mod 4]\nend loop");
+        same("T = 0\nloop I from 0 to 100\n  T = T + 100 mod (60 - I)\nend loop");
+        same("function F(N)\n  return F(N + 1)\nend function\nloop I from 1 to 100\n  X = F(I)\nend loop");
+    }
+}
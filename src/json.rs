@@ -0,0 +1,287 @@
+use crate::err::ParseError;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value, objects keep their keys in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Numbers without a fraction or exponent that fit, kept exact
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from `(key, value)` pairs
+    pub fn object(entries: Vec<(&str, Json)>) -> Json {
+        Json::Object(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn str(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    /// The value under `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Int(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, ParseError> {
+        let mut it = text.chars().peekable();
+        let ret = parse_value(&mut it)?;
+
+        skip_space(&mut it);
+        match it.next() {
+            None => Ok(ret),
+            Some(c) => Err(error(&format!("unexpected '{}' after the value", c))),
+        }
+    }
+}
+
+fn error(msg: &str) -> ParseError {
+    ParseError {
+        msg: format!("Invalid JSON: {}", msg),
+        span: None,
+        code: Some("E030"),
+    }
+}
+
+fn skip_space(it: &mut Peekable<Chars>) {
+    while let Some(' ' | '\t' | '\n' | '\r') = it.peek() {
+        it.next();
+    }
+}
+
+fn expect_word(it: &mut Peekable<Chars>, word: &str, val: Json) -> Result<Json, ParseError> {
+    for c in word.chars() {
+        if it.next() != Some(c) {
+            return Err(error(&format!("expected {}", word)));
+        }
+    }
+
+    Ok(val)
+}
+
+fn parse_string(it: &mut Peekable<Chars>) -> Result<String, ParseError> {
+    let mut ret = String::new();
+    it.next();
+
+    loop {
+        match it.next() {
+            Some('"') => return Ok(ret),
+            Some('\\') => {
+                let c = match it.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let mut code = parse_hex(it)?;
+
+                        // Characters outside the BMP come as a surrogate pair
+                        if (0xd800..0xdc00).contains(&code) {
+                            if it.next() != Some('\\') || it.next() != Some('u') {
+                                return Err(error("unpaired surrogate"));
+                            }
+                            let low = parse_hex(it)?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+
+                        match char::from_u32(code) {
+                            Some(c) => c,
+                            None => return Err(error("invalid \\u escape")),
+                        }
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => c,
+                    _ => return Err(error("invalid escape")),
+                };
+                ret.push(c);
+            }
+            Some(c) => ret.push(c),
+            None => return Err(error("unterminated string")),
+        }
+    }
+}
+
+fn parse_hex(it: &mut Peekable<Chars>) -> Result<u32, ParseError> {
+    let mut code = 0;
+    for _ in 0..4 {
+        match it.next().and_then(|c| c.to_digit(16)) {
+            Some(d) => code = code * 16 + d,
+            None => return Err(error("invalid \\u escape")),
+        }
+    }
+
+    Ok(code)
+}
+
+fn parse_number(it: &mut Peekable<Chars>) -> Result<Json, ParseError> {
+    let mut buf = String::new();
+    while let Some(&c) = it.peek() {
+        if !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+            break;
+        }
+        buf.push(c);
+        it.next();
+    }
+
+    if let Ok(x) = buf.parse::<i64>() {
+        return Ok(Json::Int(x));
+    }
+
+    match buf.parse::<f64>() {
+        Ok(x) => Ok(Json::Float(x)),
+        Err(_) => Err(error(&format!("invalid number {}", buf))),
+    }
+}
+
+fn parse_value(it: &mut Peekable<Chars>) -> Result<Json, ParseError> {
+    skip_space(it);
+
+    match it.peek() {
+        Some('n') => expect_word(it, "null", Json::Null),
+        Some('t') => expect_word(it, "true", Json::Bool(true)),
+        Some('f') => expect_word(it, "false", Json::Bool(false)),
+        Some('"') => Ok(Json::String(parse_string(it)?)),
+        Some(c) if c.is_ascii_digit() || *c == '-' => parse_number(it),
+
+        Some('[') => {
+            it.next();
+            let mut items = Vec::new();
+
+            skip_space(it);
+            if it.peek() == Some(&']') {
+                it.next();
+                return Ok(Json::Array(items));
+            }
+
+            loop {
+                items.push(parse_value(it)?);
+
+                skip_space(it);
+                match it.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err(error("expected ',' or ']' in array")),
+                }
+            }
+        }
+
+        Some('{') => {
+            it.next();
+            let mut entries = Vec::new();
+
+            skip_space(it);
+            if it.peek() == Some(&'}') {
+                it.next();
+                return Ok(Json::Object(entries));
+            }
+
+            loop {
+                skip_space(it);
+                if it.peek() != Some(&'"') {
+                    return Err(error("expected a key in object"));
+                }
+                let key = parse_string(it)?;
+
+                skip_space(it);
+                if it.next() != Some(':') {
+                    return Err(error("expected ':' in object"));
+                }
+                entries.push((key, parse_value(it)?));
+
+                skip_space(it);
+                match it.next() {
+                    Some(',') => {}
+                    Some('}') => return Ok(Json::Object(entries)),
+                    _ => return Err(error("expected ',' or '}' in object")),
+                }
+            }
+        }
+
+        Some(c) => Err(error(&format!("unexpected '{}'", c))),
+        None => Err(error("unexpected end of input")),
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Compact JSON text
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(x) => write!(f, "{}", x),
+            Json::Int(x) => write!(f, "{}", x),
+            // JSON has no infinities or NaN
+            Json::Float(x) if !x.is_finite() => write!(f, "null"),
+            Json::Float(x) => write!(f, "{:?}", x),
+            Json::String(x) => write_string(f, x),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, x) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
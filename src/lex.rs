@@ -1,6 +1,10 @@
+use crate::dialect::Dialect;
 use crate::err::ParseError;
+use crate::xid::{is_xid_continue, is_xid_start};
+use std::iter::Peekable;
+use std::str::Chars;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum LexerToken {
     Keyword(Keyword),
     Punctuation(Punctuation),
@@ -8,7 +12,94 @@ pub enum LexerToken {
     FloatLit(f64),
     BoolLit(bool),
     StrLit(String),
+    NullLit,
     Identifier(String),
+    /// One line of a `///` comment, without the slashes and the space after
+    Doc(String),
+}
+
+/// Which variable names are accepted
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Naming {
+    /// `TOTAL`, `MAX_SCORE2`, as in the IB guide
+    #[default]
+    Upper,
+    /// `total`, `maxScore`, `num1`
+    Camel,
+    /// Any mix of letters, digits and underscores
+    Any,
+}
+
+impl Naming {
+    pub fn from_name(name: &str) -> Option<Naming> {
+        match name {
+            "upper" => Some(Naming::Upper),
+            "camel" => Some(Naming::Camel),
+            "any" => Some(Naming::Any),
+            _ => None,
+        }
+    }
+
+    fn allows(&self, ident: &str) -> bool {
+        match self {
+            // Letters without case, like in CJK scripts, are fine too
+            Naming::Upper => ident.chars().all(|c| !c.is_lowercase()),
+            Naming::Camel => {
+                ident.starts_with(|c: char| c.is_lowercase()) && ident.chars().all(|c| c != '_' && is_xid_continue(c))
+            }
+            Naming::Any => true,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Naming::Upper => "UPPERCASE",
+            Naming::Camel => "camelCase",
+            Naming::Any => "any",
+        }
+    }
+}
+
+/// Settings that change what source is accepted
+#[derive(Debug, Clone, Default)]
+pub struct LexOptions {
+    pub naming: Naming,
+    pub dialect: Dialect,
+}
+
+impl LexOptions {
+    /// Takes `--naming` or `--dialect` along with its value from `args`,
+    /// `false` for any other argument
+    pub fn parse_flag(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, String> {
+        match arg {
+            "--naming" => match args.next().as_deref().and_then(Naming::from_name) {
+                Some(x) => self.naming = x,
+                None => return Err("--naming expects upper, camel or any".into()),
+            },
+            "--dialect" => match args.next().as_deref().and_then(Dialect::from_name) {
+                Some(x) => self.dialect = x,
+                None => return Err("--dialect expects ib, cambridge, edexcel or aqa".into()),
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Where a token is in the source, starting from line and column 1
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    /// How many characters it covers
+    pub len: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub tok: LexerToken,
+    pub span: Span,
 }
 
 impl LexerToken {
@@ -25,19 +116,49 @@ impl LexerToken {
             "end" => Some(LexerToken::Keyword(Keyword::End)),
             "input" => Some(LexerToken::Keyword(Keyword::Input)),
             "output" => Some(LexerToken::Keyword(Keyword::Output)),
+            "over" => Some(LexerToken::Keyword(Keyword::Over)),
+            "procedure" => Some(LexerToken::Keyword(Keyword::Procedure)),
+            "function" => Some(LexerToken::Keyword(Keyword::Function)),
+            "return" => Some(LexerToken::Keyword(Keyword::Return)),
+            "class" => Some(LexerToken::Keyword(Keyword::Class)),
+            "public" => Some(LexerToken::Keyword(Keyword::Public)),
+            "private" => Some(LexerToken::Keyword(Keyword::Private)),
+            "new" => Some(LexerToken::Keyword(Keyword::New)),
+            "inherits" => Some(LexerToken::Keyword(Keyword::Inherits)),
+            "super" => Some(LexerToken::Keyword(Keyword::Super)),
+            "include" => Some(LexerToken::Keyword(Keyword::Include)),
+            "global" => Some(LexerToken::Keyword(Keyword::Global)),
+            "integer" => Some(LexerToken::Keyword(Keyword::Integer)),
+            "real" => Some(LexerToken::Keyword(Keyword::Real)),
+            "string" => Some(LexerToken::Keyword(Keyword::String)),
+            "boolean" => Some(LexerToken::Keyword(Keyword::Boolean)),
+            "attempt" => Some(LexerToken::Keyword(Keyword::Attempt)),
+            "on" => Some(LexerToken::Keyword(Keyword::On)),
+            "error" => Some(LexerToken::Keyword(Keyword::Error)),
+            "assert" => Some(LexerToken::Keyword(Keyword::Assert)),
+            "halt" => Some(LexerToken::Keyword(Keyword::Halt)),
+            "exit" => Some(LexerToken::Keyword(Keyword::Halt)),
 
             "mod" => Some(LexerToken::Punctuation(Punctuation::Mod)),
             "div" => Some(LexerToken::Punctuation(Punctuation::FloorDiv)),
+            "in" => Some(LexerToken::Punctuation(Punctuation::In)),
+            "band" => Some(LexerToken::Punctuation(Punctuation::BAnd)),
+            "bor" => Some(LexerToken::Punctuation(Punctuation::BOr)),
+            "bxor" => Some(LexerToken::Punctuation(Punctuation::BXor)),
+            "bnot" => Some(LexerToken::Punctuation(Punctuation::BNot)),
+            "shl" => Some(LexerToken::Punctuation(Punctuation::Shl)),
+            "shr" => Some(LexerToken::Punctuation(Punctuation::Shr)),
 
             "true" => Some(LexerToken::BoolLit(true)),
             "false" => Some(LexerToken::BoolLit(false)),
+            "null" => Some(LexerToken::NullLit),
 
             _ => None,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Keyword {
     Loop,
     While,
@@ -50,6 +171,42 @@ pub enum Keyword {
     End,
     Input,
     Output,
+    Over,
+    Procedure,
+    Function,
+    Return,
+    Class,
+    Public,
+    Private,
+    New,
+    Inherits,
+    Super,
+    Include,
+    Global,
+    Integer,
+    Real,
+    String,
+    Boolean,
+    Attempt,
+    On,
+    Error,
+    Assert,
+    Halt,
+    For,
+    Do,
+    Repeat,
+    Declare,
+    Call,
+    Returns,
+    Set,
+    Send,
+    Display,
+    Receive,
+    Keyboard,
+    Begin,
+    Array,
+    Of,
+    Type,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -62,131 +219,348 @@ pub enum Punctuation {
     Mod,
     Assign,
     Equals,
+    NotEquals,
     GT,
     LT,
     GE,
     LE,
+    In,
+    BAnd,
+    BOr,
+    BXor,
+    BNot,
+    Shl,
+    Shr,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    Semicolon,
+    Dot,
+    At,
+    Caret,
+}
+
+/// The source characters, keeping track of the current line and column
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    span: Span,
+    /// Characters read so far
+    read: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
 }
 
-impl Punctuation {
-    pub fn precedence(&self) -> u32 {
-        match *self {
-            Punctuation::Assign => 0,
-            Punctuation::Equals => 1,
-            Punctuation::GT => 1,
-            Punctuation::LT => 1,
-            Punctuation::GE => 1,
-            Punctuation::LE => 1,
-            Punctuation::Plus => 2,
-            Punctuation::Minus => 2,
-            Punctuation::Mul => 3,
-            Punctuation::Div => 3,
-            Punctuation::FloorDiv => 3,
-            Punctuation::Mod => 3,
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.read += 1;
+
+        if c == '\n' {
+            self.span.line += 1;
+            self.span.col = 1;
+        } else {
+            self.span.col += 1;
         }
+
+        Some(c)
     }
 }
 
-pub fn lex(prog: &str) -> Result<Vec<LexerToken>, ParseError> {
-    let mut ret: Vec<LexerToken> = vec![];
-    let mut it = prog.chars().peekable();
+/// Whether the identifier following `prev` is a method, procedure or class
+/// name rather than a variable
+fn names_anything(prev: &[LexerToken]) -> bool {
+    match prev {
+        [.., LexerToken::Keyword(Keyword::End | Keyword::Begin), _] => false,
+        [.., last] => matches!(
+            last,
+            LexerToken::Punctuation(Punctuation::Dot)
+                | LexerToken::Keyword(Keyword::Class)
+                | LexerToken::Keyword(Keyword::New)
+                | LexerToken::Keyword(Keyword::Inherits)
+                | LexerToken::Keyword(Keyword::Procedure)
+                | LexerToken::Keyword(Keyword::Function)
+        ),
+        [] => false,
+    }
+}
+
+/// `0x`, `0b` and `0o` prefixed integers in those bases
+fn lex_radix(it: &mut Cursor, radix: u32) -> Result<LexerToken, ParseError> {
+    let mut buf = String::new();
 
-    while let Some(c) = it.peek() {
+    while let Some(&c) = it.peek() {
         match c {
-            ' ' | '\n' | '\t' => {
-                it.next();
+            '_' => {}
+            c if c.is_ascii_alphanumeric() => buf.push(c),
+            _ => break,
+        }
+
+        it.next();
+    }
+
+    match i64::from_str_radix(&buf, radix) {
+        Ok(x) => Ok(LexerToken::IntLit(x)),
+        Err(_) => Err(ParseError {
+            msg: format!("Malformed base {} literal: {}", radix, buf),
+            span: None,
+            code: Some("E009"),
+        }),
+    }
+}
+
+/// Underscores between digits (`1_000_000`) are only for readability
+fn lex_number(it: &mut Cursor) -> Result<LexerToken, ParseError> {
+    let mut buf = String::new();
+    let mut is_float = false;
+
+    if it.peek() == Some(&'0') {
+        it.next();
+
+        let radix = match it.peek() {
+            Some('x' | 'X') => Some(16),
+            Some('b' | 'B') => Some(2),
+            Some('o' | 'O') => Some(8),
+            _ => None,
+        };
+
+        if let Some(radix) = radix {
+            it.next();
+            return lex_radix(it, radix);
+        }
+
+        buf.push('0');
+    }
+
+    while let Some(&c) = it.peek() {
+        match c {
+            c if c.is_ascii_digit() => buf.push(c),
+            '_' => {}
+            '.' if !is_float => {
+                buf.push('.');
+                is_float = true;
             }
 
-            c if c.is_digit(10) => {
-                let mut buf = String::new();
-                let mut is_float = false;
+            '.' if is_float => {
+                return Err(ParseError {
+                    msg: "Malformed float literal".into(),
+                    span: None,
+                    code: Some("E009"),
+                })
+            }
 
-                while let Some(c) = it.next() {
-                    match c {
-                        c if c.is_digit(10) => buf.push(c),
-                        '.' if !is_float => {
-                            buf.push('.');
-                            is_float = true;
-                        }
+            _ => break,
+        }
 
-                        '.' if is_float => {
-                            return Err(ParseError {
-                                msg: "Malformed float literal".into(),
-                            })
-                        }
+        it.next();
+    }
 
-                        _ => break,
-                    }
-                }
+    // `1.5e6`, `3E-2`, always a float like in most languages
+    if let Some('e' | 'E') = it.peek() {
+        it.next();
+        buf.push('e');
+        is_float = true;
 
-                if is_float {
-                    let x: f64 = match buf.parse() {
-                        Ok(x) => x,
-                        Err(_) => {
-                            return Err(ParseError {
-                                msg: "Failed to parse float literal".into(),
-                            })
-                        }
-                    };
+        if let Some(&c @ ('+' | '-')) = it.peek() {
+            it.next();
+            buf.push(c);
+        }
 
-                    ret.push(LexerToken::FloatLit(x));
-                } else {
-                    let x: i64 = match buf.parse() {
-                        Ok(x) => x,
-                        Err(_) => {
-                            return Err(ParseError {
-                                msg: "Failed to parse int literal".into(),
-                            })
-                        }
-                    };
+        let mut digits = false;
+        while let Some(&c) = it.peek() {
+            match c {
+                c if c.is_ascii_digit() => buf.push(c),
+                '_' => {}
+                _ => break,
+            }
 
-                    ret.push(LexerToken::IntLit(x));
-                }
+            digits = true;
+            it.next();
+        }
+
+        if !digits {
+            return Err(ParseError {
+                msg: "Malformed exponent in float literal".into(),
+                span: None,
+                code: Some("E009"),
+            });
+        }
+    }
+
+    if is_float {
+        match buf.parse() {
+            Ok(x) => Ok(LexerToken::FloatLit(x)),
+            Err(_) => Err(ParseError {
+                msg: "Failed to parse float literal".into(),
+                span: None,
+                code: Some("E009"),
+            }),
+        }
+    } else {
+        match buf.parse() {
+            Ok(x) => Ok(LexerToken::IntLit(x)),
+            Err(_) => Err(ParseError {
+                msg: "Failed to parse int literal".into(),
+                span: None,
+                code: Some("E009"),
+            }),
+        }
+    }
+}
+
+pub fn lex(prog: &str, options: &LexOptions) -> Result<Vec<Token>, ParseError> {
+    let mut ret: Vec<LexerToken> = vec![];
+    let mut spans: Vec<Span> = vec![];
+    let mut it = Cursor {
+        chars: prog.chars().peekable(),
+        span: Span { line: 1, col: 1, len: 1 },
+        read: 0,
+    };
+
+    while let Some(&c) = it.peek() {
+        let start = it.span;
+        let read = it.read;
+
+        match c {
+            ' ' | '\n' | '\t' => {
+                it.next();
+            }
+
+            c if c.is_ascii_digit() => {
+                let tok = lex_number(&mut it).map_err(|e| ParseError { span: Some(start), ..e })?;
+                ret.push(tok)
             }
 
             '\"' => {
                 let mut buf = String::new();
                 it.next();
 
-                while let Some(c) = it.next() {
+                let mut closed = false;
+
+                for c in it.by_ref() {
                     match c {
-                        '\"' => break,
+                        '\"' => {
+                            closed = true;
+                            break;
+                        }
                         _ => buf.push(c),
                     }
                 }
 
-                if let None = it.peek() {
+                if !closed {
                     return Err(ParseError {
                         msg: "Failed to parse string literal".into(),
+                        span: Some(start),
+                        code: Some("E010"),
                     });
                 }
 
                 ret.push(LexerToken::StrLit(buf));
             }
 
-            c if c.is_ascii_alphabetic() => {
+            // Unicode identifiers as UAX #31 has them (`ÉLÈVE`, `Σ`), which
+            // aren't normalized, so an accent typed as a combining mark
+            // makes a different name than the accented letter
+            c if is_xid_start(c) => {
                 let mut buf = String::new();
 
-                while let Some(c) = it.next() {
-                    match c {
-                        c if c.is_ascii_alphabetic() => buf.push(c),
-                        '_' => buf.push('_'),
-                        _ => break,
+                while let Some(&c) = it.peek() {
+                    if !is_xid_continue(c) {
+                        break;
                     }
+                    buf.push(c);
+                    it.next();
                 }
 
-                if let Some(tok) = LexerToken::from_identifier(&buf.to_lowercase()) {
+                // Method, procedure and class names follow their own naming
+                // (e.g. `M.containsKey`, `class Animal`, `bump()`), not the
+                // variable naming rules
+                let table = options.dialect.table();
+                let word = buf.to_lowercase();
+                let terminates = table.terminator(&word);
+
+                if names_anything(&ret) {
+                    ret.push(LexerToken::Identifier(buf));
+                } else if let Some(keyword) = terminates {
+                    ret.push(LexerToken::Keyword(Keyword::End));
+                    ret.push(LexerToken::Keyword(keyword));
+
+                    // The loop variable after `NEXT` is only a reminder
+                    if buf.eq_ignore_ascii_case("next") {
+                        while let Some(' ' | '\t') = it.peek() {
+                            it.next();
+                        }
+                        while it.peek().is_some_and(|c| is_xid_continue(*c)) {
+                            it.next();
+                        }
+                    }
+                } else if let Some(tok) = LexerToken::from_identifier(&word) {
                     ret.push(tok);
+                } else if let Some(keyword) = table.keyword(&word) {
+                    ret.push(LexerToken::Keyword(keyword));
+                } else if it.peek() == Some(&'(') {
+                    ret.push(LexerToken::Identifier(buf));
+                } else if options.naming.allows(&buf) {
+                    ret.push(LexerToken::Identifier(buf))
                 } else {
-                    for c in buf.chars() {
-                        if !c.is_uppercase() && c != '_' {
-                            return Err(ParseError {
-                                msg: format!("Invalid identifier: {}", &buf),
-                            });
-                        }
+                    return Err(ParseError {
+                        msg: format!("Invalid identifier: {} (expected {} names)", &buf, options.naming.describe()),
+                        span: Some(start),
+                        code: Some("E007"),
+                    });
+                }
+            }
+
+            // `///` documents the procedure or function that follows, the
+            // parser checks there is one
+            '/' if it.chars.clone().nth(1) == Some('/') => {
+                it.next();
+                it.next();
+                if it.next() != Some('/') {
+                    return Err(ParseError {
+                        msg: "Comments start with ///, and document a procedure or function".into(),
+                        span: Some(start),
+                        code: Some("E013"),
+                    });
+                }
+
+                let mut text = String::new();
+                while let Some(&c) = it.peek() {
+                    if c == '\n' {
+                        break;
                     }
+                    text.push(c);
+                    it.next();
+                }
+                let text = text.strip_prefix(' ').unwrap_or(&text).trim_end();
+                ret.push(LexerToken::Doc(text.to_string()));
+            }
+
+            // A trailing `\` joins the next line onto this one. Newlines
+            // inside brackets need nothing special, they are never tokens
+            '\\' => {
+                it.next();
 
-                    ret.push(LexerToken::Identifier(buf.clone()))
+                while let Some(' ' | '\t' | '\r') = it.peek() {
+                    it.next();
+                }
+
+                if it.next() != Some('\n') {
+                    return Err(ParseError {
+                        msg: "Expected the end of the line after '\\'".into(),
+                        span: Some(start),
+                        code: Some("E012"),
+                    });
                 }
             }
 
@@ -196,6 +570,8 @@ pub fn lex(prog: &str) -> Result<Vec<LexerToken>, ParseError> {
                     None => {
                         return Err(ParseError {
                             msg: "Unexpected EOF".into(),
+                            span: Some(start),
+                            code: Some("E011"),
                         })
                     }
                 };
@@ -214,18 +590,60 @@ pub fn lex(prog: &str) -> Result<Vec<LexerToken>, ParseError> {
                         it.next();
                         LexerToken::Punctuation(Punctuation::LE)
                     }
+                    '<' if it.peek() == Some(&'<') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::Shl)
+                    }
+                    '>' if it.peek() == Some(&'>') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::Shr)
+                    }
                     '=' if it.peek() == Some(&'=') => {
                         it.next();
                         LexerToken::Punctuation(Punctuation::Equals)
                     }
+                    '<' if it.peek() == Some(&'>') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::NotEquals)
+                    }
+                    '!' if it.peek() == Some(&'=') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::NotEquals)
+                    }
+
+                    // `X <- 5` and `X := 5` as used by other exam boards
+                    '<' if it.peek() == Some(&'-') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::Assign)
+                    }
+                    ':' if it.peek() == Some(&'=') => {
+                        it.next();
+                        LexerToken::Punctuation(Punctuation::Assign)
+                    }
 
                     '<' => LexerToken::Punctuation(Punctuation::LT),
                     '>' => LexerToken::Punctuation(Punctuation::GT),
-                    '=' => LexerToken::Punctuation(Punctuation::Assign),
+                    '=' if options.dialect.table().assign_equals => LexerToken::Punctuation(Punctuation::Assign),
+                    '=' => LexerToken::Punctuation(Punctuation::Equals),
+
+                    '(' => LexerToken::Punctuation(Punctuation::LParen),
+                    ')' => LexerToken::Punctuation(Punctuation::RParen),
+                    '[' => LexerToken::Punctuation(Punctuation::LBracket),
+                    ']' => LexerToken::Punctuation(Punctuation::RBracket),
+                    '{' => LexerToken::Punctuation(Punctuation::LBrace),
+                    '}' => LexerToken::Punctuation(Punctuation::RBrace),
+                    ',' => LexerToken::Punctuation(Punctuation::Comma),
+                    ':' => LexerToken::Punctuation(Punctuation::Colon),
+                    ';' => LexerToken::Punctuation(Punctuation::Semicolon),
+                    '.' => LexerToken::Punctuation(Punctuation::Dot),
+                    '@' => LexerToken::Punctuation(Punctuation::At),
+                    '^' => LexerToken::Punctuation(Punctuation::Caret),
 
                     _ => {
                         return Err(ParseError {
                             msg: format!("Invalid punctuation: {}", c),
+                            span: Some(start),
+                            code: Some("E008"),
                         });
                     }
                 };
@@ -233,12 +651,26 @@ pub fn lex(prog: &str) -> Result<Vec<LexerToken>, ParseError> {
                 ret.push(tok);
             }
 
+            '←' => {
+                it.next();
+                ret.push(LexerToken::Punctuation(Punctuation::Assign));
+            }
+
+            '≠' => {
+                it.next();
+                ret.push(LexerToken::Punctuation(Punctuation::NotEquals));
+            }
+
             _ => {
                 let msg = format!("Unknow char: '{}'", c);
-                return Err(ParseError { msg });
+                return Err(ParseError { msg, span: Some(start), code: Some("E008") });
             }
         }
+
+        while spans.len() < ret.len() {
+            spans.push(Span { len: it.read - read, ..start });
+        }
     }
 
-    Ok(ret)
+    Ok(ret.into_iter().zip(spans).map(|(tok, span)| Token { tok, span }).collect())
 }
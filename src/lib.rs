@@ -0,0 +1,54 @@
+//! The lexer, parser and interpreter behind the `psc` command, for running
+//! pseudocode programs from other Rust programs
+//!
+//! ```
+//! let stmts = psc::parse("X = 6 * 7\noutput X", &Default::default()).unwrap();
+//! let mut interpreter = psc::Interpreter::new();
+//! interpreter.run(&stmts).unwrap();
+//! ```
+
+pub mod bigint;
+pub mod build;
+pub mod builtins;
+pub mod dialect;
+pub mod diagnostic;
+pub mod emit;
+pub mod env;
+pub mod err;
+pub mod eval;
+pub mod explain;
+mod interpreter;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod json;
+pub mod lex;
+pub mod load;
+pub mod opt;
+pub mod parse;
+pub mod pretty;
+#[cfg(feature = "regex")]
+pub mod regex;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod typecheck;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod xid;
+
+pub use interpreter::Interpreter;
+
+use err::ParseError;
+use eval::Stmt;
+use lex::{LexOptions, Token};
+
+/// Splits the text of a program into its tokens
+pub fn lex(source: &str, options: &LexOptions) -> Result<Vec<Token>, ParseError> {
+    lex::lex(source, options)
+}
+
+/// The statements of a program. An `include` is left for `load::load_file`
+/// to read, which parses a program in a file along with what it includes
+pub fn parse(source: &str, options: &LexOptions) -> Result<Vec<Stmt>, ParseError> {
+    lex::lex(source, options).and_then(parse::parse)
+}
@@ -0,0 +1,930 @@
+use psc::env::Env;
+use psc::eval::*;
+use psc::lex::{self, Keyword, LexOptions, LexerToken, Span, Token};
+use psc::parse;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+
+/// Every kind of warning, by code and by the name `-W` and `-A` also take
+pub const WARNINGS: &[(&str, &str)] = &[
+    ("W001", "unused-variable"),
+    ("W002", "read-before-assign"),
+    ("W003", "constant-condition"),
+    ("W004", "shadowed-loop-variable"),
+    ("W005", "unread-assignment"),
+    ("W006", "float-equality"),
+    ("W007", "inexact-real"),
+    ("W008", "unreachable-code"),
+    ("W009", "maybe-unassigned"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    /// Shown as an error, and the program isn't run
+    Deny,
+}
+
+/// Which warnings are shown, decided by `-W WARNING`, `-A WARNING` and
+/// `-D WARNING` flags where the last one to name a warning wins. `all`
+/// names every warning, and they're all shown as warnings to begin with
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    /// Takes `-W`, `-A` or `-D` and its warning off `args`, whether `arg`
+    /// was one
+    pub fn parse_flag(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, String> {
+        let show = match arg {
+            "-W" => Level::Warn,
+            "-A" => Level::Allow,
+            "-D" => Level::Deny,
+            _ => return Ok(false),
+        };
+
+        let known = |x: &str| x == "all" || WARNINGS.iter().any(|(code, name)| code.eq_ignore_ascii_case(x) || *name == x);
+        match args.next() {
+            Some(x) if known(&x) => self.rules.push((x, show)),
+            _ => return Err(format!("{} expects a warning code like W001, its name or all", arg)),
+        }
+        Ok(true)
+    }
+
+    pub fn level(&self, code: &str) -> Level {
+        let name = WARNINGS.iter().find(|x| x.0 == code).map_or("", |x| x.1);
+        let rule = self.rules.iter().rev().find(|(x, _)| x == "all" || x.eq_ignore_ascii_case(code) || x == name);
+        rule.map_or(Level::Warn, |(_, level)| *level)
+    }
+
+    pub fn shows(&self, code: &str) -> bool {
+        self.level(code) != Level::Allow
+    }
+}
+
+/// Something suspicious that doesn't stop the program from running
+pub struct Warning {
+    /// Stable identifier, e.g. `W001`
+    pub code: &'static str,
+    pub span: Span,
+    pub msg: String,
+}
+
+struct Var {
+    /// Where it was first assigned
+    span: Span,
+    read: bool,
+    /// The last assignment to it, while nothing can have read it since
+    store: Option<Span>,
+    /// Parameters and loop variables are fine to leave unused
+    report: bool,
+}
+
+/// The variables made in one scope, a loop body or the body of a call
+#[derive(Default)]
+struct Block {
+    vars: HashMap<String, Var>,
+}
+
+/// Mirrors the frames of `Env`, one per call with the top level first
+#[derive(Default)]
+struct Frame<'a> {
+    blocks: Vec<Block>,
+    /// Names opted into with `global`
+    globals: HashSet<String>,
+    /// Fields of the object when checking a method
+    fields: HashSet<String>,
+    /// Nested definitions, checked after the enclosing body so every
+    /// variable they can see is known by then
+    pending: Vec<Pending<'a>>,
+    /// Variables of the loops around the current statement
+    counters: Vec<String>,
+    /// Names already reported as read before assignment
+    reported: HashSet<String>,
+    /// Variables only assigned on some of the ways to the current statement
+    unsure: HashSet<String>,
+    /// Names assigned by the branch being checked
+    written: HashSet<String>,
+}
+
+struct Pending<'a> {
+    func: &'a Function,
+    /// Token the definition starts at
+    pos: usize,
+    fields: HashSet<String>,
+}
+
+/// Where a name refers to
+enum Place {
+    Var(usize, usize),
+    Field,
+    Unknown,
+}
+
+struct Linter<'a> {
+    tokens: &'a [Token],
+    /// Next token to look positions up from, statements are visited in the
+    /// order they appear so names are found by searching forward
+    pos: usize,
+    frames: Vec<Frame<'a>>,
+    classes: HashMap<&'a str, &'a Class>,
+    /// Procedures, classes and variables some procedure makes global
+    known: HashSet<String>,
+    /// Reads of unknown names aren't reported when an include could define
+    /// them
+    includes: bool,
+    warnings: Vec<Warning>,
+}
+
+/// Whether an expression is sure to be a real
+fn real(expr: &Expr) -> bool {
+    match expr {
+        Expr::FloatLit(_) => true,
+        Expr::BinOp(x) => match x.op {
+            BinaryOp::Div => true,
+            BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Mul => real(&x.left) || real(&x.right),
+            _ => false,
+        },
+        Expr::IfElse(x) => real(&x.then) && real(&x.otherwise),
+        _ => false,
+    }
+}
+
+/// Whether an expression always has the same value
+fn constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::StrLit(_) | Expr::NullLit => true,
+        Expr::BinOp(x) => constant(&x.left) && constant(&x.right),
+        Expr::IfElse(x) => constant(&x.cond) && constant(&x.then) && constant(&x.otherwise),
+        Expr::BNot(x) => constant(x),
+        _ => false,
+    }
+}
+
+/// What a constant expression comes out as, or nothing for one that fails
+fn value(expr: &Expr) -> Option<PscObject> {
+    match constant(expr) {
+        true => Expr::eval(expr, &mut Env::new()).ok(),
+        false => None,
+    }
+}
+
+/// Whether nothing after a statement in the same block can run
+fn terminates(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Return(_) | StmtKind::Halt(_) => true,
+        // Only with an `else`, otherwise it can go past every branch
+        StmtKind::If(x) => {
+            matches!(x.branches.last(), Some((Expr::BoolLit(true), _)))
+                && x.branches.iter().all(|(_, stmts)| stmts.iter().any(terminates))
+        }
+        _ => false,
+    }
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, code: &'static str, span: Span, msg: String) {
+        self.warnings.push(Warning { code, span, msg });
+    }
+
+    fn frame(&mut self) -> &mut Frame<'a> {
+        match self.frames.last_mut() {
+            Some(x) => x,
+            None => unreachable!(),
+        }
+    }
+
+    fn here(&self) -> Span {
+        match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some(x) => x.span,
+            None => Span { line: 1, col: 1, len: 0 },
+        }
+    }
+
+    /// Position of the next token matching `pred`, moving past it
+    fn find(&mut self, pred: impl Fn(&LexerToken) -> bool) -> Span {
+        match self.tokens[self.pos..].iter().position(|x| pred(&x.tok)) {
+            Some(i) => {
+                self.pos += i + 1;
+                self.tokens[self.pos - 1].span
+            }
+            None => self.here(),
+        }
+    }
+
+    fn ident(&mut self, name: &str) -> Span {
+        self.find(|x| matches!(x, LexerToken::Identifier(y) if y == name))
+    }
+
+    fn keyword(&mut self, keyword: Keyword) -> Span {
+        self.find(|x| *x == LexerToken::Keyword(keyword.clone()))
+    }
+
+    /// Moves past the next definition opened by one of `kinds` (`end X`
+    /// closes it), returning the token it starts at
+    fn skip_definition(&mut self, kinds: &[Keyword]) -> usize {
+        let tokens = self.tokens;
+        let after_end = |i: usize| i > 0 && tokens[i - 1].tok == LexerToken::Keyword(Keyword::End);
+        let is_kind = |i: usize| matches!(&tokens[i].tok, LexerToken::Keyword(k) if kinds.contains(k));
+
+        let start = (self.pos..tokens.len()).find(|&i| is_kind(i) && !after_end(i)).unwrap_or(tokens.len());
+
+        let mut depth = 0;
+        let mut i = start;
+        while i < tokens.len() {
+            if is_kind(i) {
+                if !after_end(i) {
+                    depth += 1;
+                } else {
+                    depth -= 1;
+                    if depth == 0 {
+                        i += 1;
+                        break;
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        self.pos = i;
+        start
+    }
+
+    /// What `name` refers to from the current call. Writes only reach the
+    /// globals from the top level or after `global NAME`
+    fn resolve(&self, name: &str, write: bool) -> Place {
+        let current = self.frames.len() - 1;
+
+        if self.frames[current].globals.contains(name) {
+            return match self.frames[0].blocks[0].vars.contains_key(name) {
+                true => Place::Var(0, 0),
+                false => Place::Unknown,
+            };
+        }
+
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            if i == 0 && write && current > 0 {
+                break;
+            }
+
+            if let Some(j) = frame.blocks.iter().rposition(|x| x.vars.contains_key(name)) {
+                return Place::Var(i, j);
+            }
+
+            if frame.fields.contains(name) {
+                return Place::Field;
+            }
+        }
+
+        Place::Unknown
+    }
+
+    fn read(&mut self, name: &str, span: Span) {
+        match self.resolve(name, false) {
+            Place::Var(i, j) => {
+                if let Some(var) = self.frames[i].blocks[j].vars.get_mut(name) {
+                    var.read = true;
+                    var.store = None;
+                }
+
+                let current = i == self.frames.len() - 1;
+                let frame = self.frame();
+                if current && frame.unsure.contains(name) && frame.reported.insert(name.to_string()) {
+                    let msg = format!("{} may be read before it is assigned, not every way here assigns it", name);
+                    self.warn("W009", span, msg);
+                }
+            }
+            Place::Field => {}
+            Place::Unknown => {
+                if !self.known.contains(name) && !self.includes && self.frame().reported.insert(name.to_string()) {
+                    self.warn("W002", span, format!("{} is read before it is assigned", name));
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, name: &str, span: Span) {
+        self.frame().unsure.remove(name);
+        self.frame().written.insert(name.to_string());
+
+        if let Place::Var(i, j) = self.resolve(name, true) {
+            let prev = match self.frames[i].blocks[j].vars.get_mut(name) {
+                Some(var) => var.store.replace(span),
+                None => None,
+            };
+            if let Some(prev) = prev {
+                let msg = format!("The value given to {} here is never read, it's replaced at line {}", name, span.line);
+                self.warn("W005", prev, msg);
+            }
+        }
+
+        if let Place::Unknown = self.resolve(name, true) {
+            // Globals set from a call may be read by code that was already
+            // checked, so they aren't reported
+            let global = self.frame().globals.contains(name);
+            let var = Var {
+                span,
+                read: false,
+                report: !global,
+                store: if global { None } else { Some(span) },
+            };
+
+            let block = match global {
+                true => &mut self.frames[0].blocks[0],
+                false => match self.frame().blocks.last_mut() {
+                    Some(x) => x,
+                    None => unreachable!(),
+                },
+            };
+            block.vars.insert(name.to_string(), var);
+        }
+    }
+
+    /// Every variable of the current call, wherever it was made
+    fn names(&mut self) -> HashSet<String> {
+        self.frame().blocks.iter().flat_map(|x| x.vars.keys().cloned()).collect()
+    }
+
+    /// Checks one of the ways through a statement starting from `unsure`,
+    /// returning what it assigns, or nothing when it never comes out the
+    /// other end
+    fn way(&mut self, unsure: &HashSet<String>, stmts: &'a [Stmt], visit: impl FnOnce(&mut Self)) -> Option<HashSet<String>> {
+        self.frame().unsure = unsure.clone();
+        let outer = std::mem::take(&mut self.frame().written);
+        visit(self);
+        let written = std::mem::replace(&mut self.frame().written, outer);
+        self.frame().written.extend(written.iter().cloned());
+        (!stmts.iter().any(terminates)).then_some(written)
+    }
+
+    /// A loop body that might not run at all, so nothing it assigns counts
+    /// once the loop is done
+    fn skippable(&mut self, visit: impl FnOnce(&mut Self)) {
+        let unsure = self.frame().unsure.clone();
+        let written = self.frame().written.clone();
+        visit(self);
+        self.frame().unsure = unsure;
+        self.frame().written = written;
+    }
+
+    /// Where the ways through a statement meet again, a variable is only
+    /// known to be assigned if each of them assigns it, or it was before
+    fn join(&mut self, names: &HashSet<String>, unsure: HashSet<String>, ways: &[Option<HashSet<String>>]) {
+        let through: Vec<&HashSet<String>> = ways.iter().flatten().collect();
+        let mut after = unsure.clone();
+        for name in through.iter().flat_map(|x| x.iter()) {
+            if through.iter().all(|x| x.contains(name)) {
+                after.remove(name);
+            } else if !names.contains(name) || unsure.contains(name) {
+                after.insert(name.clone());
+            }
+        }
+        self.frame().unsure = after;
+    }
+
+    /// Where control flow branches or joins, or something is called that
+    /// could read any variable, an assignment can't be said to go unread
+    fn forget_stores(&mut self) {
+        for frame in &mut self.frames {
+            for block in &mut frame.blocks {
+                block.vars.values_mut().for_each(|x| x.store = None);
+            }
+        }
+    }
+
+    fn forget_store(&mut self, name: &str) {
+        if let Place::Var(i, j) = self.resolve(name, true) {
+            if let Some(var) = self.frames[i].blocks[j].vars.get_mut(name) {
+                var.store = None;
+            }
+        }
+    }
+
+    fn report_unused(&mut self, block: Block) {
+        for (name, var) in block.vars {
+            if var.report && !var.read {
+                self.warn("W001", var.span, format!("{} is assigned but never read", name));
+            }
+        }
+    }
+
+    /// A loop body, with `bind` as its loop variable
+    fn scoped(&mut self, stmts: &'a [Stmt], bind: Option<(&str, Span)>) {
+        let mut block = Block::default();
+        if let Some((name, span)) = bind {
+            let var = Var {
+                span,
+                read: false,
+                report: false,
+                store: None,
+            };
+            block.vars.insert(name.to_string(), var);
+        }
+
+        self.frame().blocks.push(block);
+        self.stmts(stmts);
+
+        if let Some(block) = self.frame().blocks.pop() {
+            self.report_unused(block);
+        }
+    }
+
+    fn loop_var(&mut self, name: &str, span: Span) {
+        if self.frame().counters.iter().any(|x| x == name) {
+            self.warn("W004", span, format!("Loop variable {} shadows the one of an enclosing loop", name));
+        }
+    }
+
+    /// Returns what the condition always is, if it's constant. `dead` is the
+    /// value that means the body never runs
+    fn condition(&mut self, cond: &'a Expr, span: Span, what: &str, dead: Option<bool>) -> Option<bool> {
+        let always = match value(cond) {
+            Some(PscObject::BoolT(x)) => Some(x),
+            _ => None,
+        };
+        match always {
+            Some(x) if Some(x) == dead => {
+                let msg = format!("Unreachable code, the condition of this {} is always {}", what, x);
+                self.warn("W008", span, msg);
+            }
+            _ if constant(cond) => self.warn("W003", span, format!("Constant condition, this {} always goes the same way", what)),
+            _ => {}
+        }
+        self.expr(cond);
+        always
+    }
+
+    /// The target of an assignment, returning the variable it creates
+    fn target(&mut self, target: &'a Expr) -> Option<(&'a str, Span)> {
+        match target {
+            Expr::Ident(name) => Some((name, self.ident(name))),
+            x => {
+                self.expr(x);
+                None
+            }
+        }
+    }
+
+    /// Fields declared by `class` and the classes it inherits from
+    fn fields_of(&self, class: &Class) -> HashSet<String> {
+        let mut ret = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut next = Some(class);
+
+        while let Some(class) = next {
+            if !seen.insert(class.name.as_str()) {
+                break;
+            }
+            ret.extend(class.fields.iter().map(|x| x.name.clone()));
+            next = class.parent.as_deref().and_then(|x| self.classes.get(x).copied());
+        }
+
+        ret
+    }
+
+    fn stmts(&mut self, stmts: &'a [Stmt]) {
+        // Definitions are made before anything runs, wherever they are
+        let definition = |x: &Stmt| matches!(x.kind, StmtKind::Function(_) | StmtKind::Class(_));
+        if let Some(i) = stmts.iter().position(terminates) {
+            if let Some(next) = stmts[i + 1..].iter().find(|x| !definition(x)) {
+                let after = match stmts[i].kind {
+                    StmtKind::Return(_) => "a return",
+                    StmtKind::Halt(_) => "a halt",
+                    _ => "an if that returns or halts on every branch",
+                };
+                self.warn("W008", next.span, format!("Unreachable code, it comes after {}", after));
+            }
+        }
+
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        let branches = !matches!(
+            stmt.kind,
+            StmtKind::Assign(_) | StmtKind::MultiAssign(_) | StmtKind::Input(_) | StmtKind::Output(_) | StmtKind::Expr(_)
+        );
+        if branches {
+            self.forget_stores();
+        }
+        self.visit(stmt);
+        if branches {
+            self.forget_stores();
+        }
+    }
+
+    fn visit(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(x) => {
+                let target = self.target(&x.target);
+                self.expr(&x.expr);
+                if let Some((name, span)) = target {
+                    self.write(name, span);
+                    if x.declared.is_some() {
+                        // A DECLARE only gives the value the type starts
+                        // with, replacing it is the point
+                        self.forget_store(name);
+                    }
+                }
+            }
+            StmtKind::MultiAssign(x) => {
+                let targets: Vec<Option<(&str, Span)>> = x.targets.iter().map(|x| self.target(x)).collect();
+                for expr in &x.exprs {
+                    self.expr(expr);
+                }
+                for (name, span) in targets.into_iter().flatten() {
+                    self.write(name, span);
+                }
+            }
+            StmtKind::Input(x) => {
+                let span = self.ident(&x.ident);
+                self.write(&x.ident, span);
+            }
+            StmtKind::Output(x) => self.expr(&x.expr),
+            StmtKind::If(x) => {
+                let names = self.names();
+                let unsure = self.frame().unsure.clone();
+                let mut ways = Vec::new();
+
+                // Set once a branch is always taken, so none after it can be
+                let mut taken = false;
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    // Only reached when every branch before wasn't taken
+                    self.frame().unsure = unsure.clone();
+                    self.forget_stores();
+                    let span = self.keyword(if i == 0 { Keyword::If } else { Keyword::Else });
+
+                    if taken {
+                        self.warn("W008", span, "Unreachable code, an earlier branch is always taken".into());
+                    }
+
+                    // A plain `else` is stored as `else if true`
+                    if i == 0 || i < x.branches.len() - 1 || !matches!(cond, Expr::BoolLit(true)) {
+                        let dead = if taken { None } else { Some(false) };
+                        taken |= self.condition(cond, span, "if", dead) == Some(true);
+                    }
+                    ways.push(self.way(&unsure, stmts, |x| x.stmts(stmts)));
+                }
+
+                // Without an `else` it can go past every branch
+                if !matches!(x.branches.last(), Some((Expr::BoolLit(true), _))) {
+                    ways.push(Some(HashSet::new()));
+                }
+                self.join(&names, unsure, &ways);
+            }
+            StmtKind::While(x) => {
+                let span = self.keyword(Keyword::While);
+                self.condition(&x.cond, span, "loop", Some(false));
+                self.skippable(|this| this.scoped(&x.stmts, None));
+            }
+            StmtKind::Until(x) => {
+                let span = self.keyword(Keyword::Until);
+                self.condition(&x.cond, span, "loop", Some(true));
+                self.skippable(|this| this.scoped(&x.stmts, None));
+            }
+            // The condition is checked outside the body's scope
+            StmtKind::Repeat(x) => {
+                self.scoped(&x.stmts, None);
+                let span = self.keyword(Keyword::Until);
+                self.condition(&x.cond, span, "loop", None);
+            }
+            StmtKind::For(x) => {
+                let span = self.ident(&x.name);
+                self.expr(&x.start);
+                self.expr(&x.end);
+                self.loop_var(&x.name, span);
+
+                if let (Some(PscObject::IntT(start)), Some(PscObject::IntT(end))) = (value(&x.start), value(&x.end)) {
+                    if start > end {
+                        let msg = format!("Unreachable code, this loop counts up from {} so never reaches {}", start, end);
+                        self.warn("W008", span, msg);
+                    }
+                }
+
+                self.frame().counters.push(x.name.clone());
+                self.skippable(|this| this.scoped(&x.stmts, Some((&x.name, span))));
+                self.frame().counters.pop();
+            }
+            StmtKind::ForEach(x) => {
+                let span = self.ident(&x.name);
+                self.expr(&x.iter);
+                self.loop_var(&x.name, span);
+
+                self.frame().counters.push(x.name.clone());
+                self.skippable(|this| this.scoped(&x.stmts, Some((&x.name, span))));
+                self.frame().counters.pop();
+            }
+            StmtKind::Expr(x) => self.expr(x),
+            StmtKind::Function(func) => {
+                let pos = self.skip_definition(&[Keyword::Procedure, Keyword::Function]);
+                self.frame().pending.push(Pending {
+                    func,
+                    pos,
+                    fields: HashSet::new(),
+                });
+            }
+            StmtKind::Class(class) => {
+                let start = self.skip_definition(&[Keyword::Class]);
+                let end = self.pos;
+
+                self.pos = start;
+                for field in &class.fields {
+                    self.ident(&field.name);
+                    if let Some(init) = &field.init {
+                        self.expr(init);
+                    }
+                }
+
+                self.pos = start;
+                let fields = self.fields_of(class);
+                for method in &class.methods {
+                    let pos = self.skip_definition(&[Keyword::Procedure, Keyword::Function]);
+                    self.frame().pending.push(Pending {
+                        func: &method.func,
+                        pos,
+                        fields: fields.clone(),
+                    });
+                }
+
+                self.pos = end;
+            }
+            StmtKind::Return(x) | StmtKind::Halt(x) => {
+                if let Some(x) = x {
+                    self.expr(x);
+                }
+            }
+            StmtKind::Global(names) => {
+                for name in names {
+                    self.ident(name);
+                    self.frame().globals.insert(name.clone());
+                }
+            }
+            // Any statement can fail over to the handler, which can read
+            // what was assigned before it
+            StmtKind::Attempt(x) => {
+                let names = self.names();
+                let unsure = self.frame().unsure.clone();
+
+                let body = self.way(&unsure, &x.stmts, |this| {
+                    for stmt in &x.stmts {
+                        this.stmt(stmt);
+                        this.forget_stores();
+                    }
+                });
+
+                // The body can fail before it assigns anything
+                let mut failed = unsure.clone();
+                failed.extend(self.names().difference(&names).cloned());
+
+                let span = self.keyword(Keyword::Error);
+                let bind = x.name.as_deref().map(|name| (name, span));
+                let handler = self.way(&failed, &x.handler, |this| this.scoped(&x.handler, bind));
+                self.join(&names, unsure, &[body, handler]);
+            }
+            StmtKind::Assert(x) => {
+                self.expr(&x.cond);
+                if let Some(msg) = &x.msg {
+                    self.expr(msg);
+                }
+            }
+            StmtKind::Type(_) | StmtKind::Include(_) => {}
+        }
+    }
+
+    fn exprs(&mut self, exprs: &'a [Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Ident(name) | Expr::AddressOf(name) => {
+                let span = self.ident(name);
+                self.read(name, span);
+            }
+            Expr::BinOp(x) => {
+                self.expr(&x.left);
+                self.expr(&x.right);
+                self.bin_op(x);
+            }
+            Expr::IfElse(x) => {
+                self.keyword(Keyword::If);
+                self.expr(&x.cond);
+                self.expr(&x.then);
+                self.keyword(Keyword::Else);
+                self.expr(&x.otherwise);
+            }
+            Expr::Index(x) => {
+                self.expr(&x.target);
+                self.expr(&x.index);
+            }
+            Expr::MethodCall(x) => {
+                self.expr(&x.target);
+                self.exprs(&x.args);
+                self.forget_stores();
+            }
+            Expr::Field(x) => self.expr(&x.target),
+            // Calling a variable holding a nested function reads it
+            Expr::Call(x) => {
+                if let Place::Var(i, j) = self.resolve(&x.name, false) {
+                    if let Some(var) = self.frames[i].blocks[j].vars.get_mut(&x.name) {
+                        var.read = true;
+                    }
+                }
+                self.exprs(&x.args);
+                self.forget_stores();
+            }
+            Expr::SuperCall(SuperCall { args, .. }) | Expr::New(New { args, .. }) => {
+                self.exprs(args);
+                self.forget_stores();
+            }
+            Expr::Map(entries) => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+            }
+            Expr::Set(items) | Expr::Array(items) => self.exprs(items),
+            Expr::Deref(x) | Expr::BNot(x) => self.expr(x),
+            Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::StrLit(_) | Expr::NullLit => {}
+        }
+    }
+
+    /// Reals compared exactly, or integers too big to be one mixed with them
+    fn bin_op(&mut self, x: &BinOp) {
+        if matches!(x.op, BinaryOp::Equals | BinaryOp::NotEquals) && (real(&x.left) || real(&x.right)) {
+            let msg = "Reals compared exactly, rounding can make this go the wrong way, compare ABS(A - B) with a small tolerance";
+            self.warn("W006", x.span, msg.into());
+        }
+
+        use BinaryOp::*;
+        let mixes = matches!(x.op, Plus | Minus | Mul | Div | Equals | NotEquals | GT | LT | GE | LE);
+        for (int, other) in [(&x.left, &x.right), (&x.right, &x.left)] {
+            if let (Expr::IntLit(n), true, true) = (int, real(other), mixes) {
+                if n.unsigned_abs() > 1 << 53 {
+                    let msg = format!("{} can't be held exactly as a real, and is rounded to {:.1}", n, *n as f64);
+                    self.warn("W007", x.span, msg);
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, pending: Pending<'a>) {
+        self.pos = pending.pos;
+        self.forget_stores();
+
+        let mut block = Block::default();
+        for param in &pending.func.params {
+            let span = self.ident(param);
+            let var = Var {
+                span,
+                read: false,
+                report: false,
+                store: None,
+            };
+            block.vars.insert(param.clone(), var);
+        }
+
+        self.frames.push(Frame {
+            blocks: vec![block],
+            fields: pending.fields,
+            ..Frame::default()
+        });
+        self.stmts(&pending.func.stmts);
+        self.finish_frame();
+    }
+
+    fn finish_frame(&mut self) {
+        let pending = std::mem::take(&mut self.frame().pending);
+        for x in pending {
+            self.function(x);
+        }
+
+        if let Some(frame) = self.frames.pop() {
+            for block in frame.blocks {
+                self.report_unused(block);
+            }
+        }
+    }
+
+    /// Names that can be used without being assigned first
+    fn collect(&mut self, stmts: &'a [Stmt]) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Function(x) => {
+                    self.known.insert(x.name.clone());
+                    self.collect(&x.stmts);
+                }
+                StmtKind::Class(x) => {
+                    self.known.insert(x.name.clone());
+                    self.classes.insert(&x.name, x);
+                    for method in &x.methods {
+                        self.collect(&method.func.stmts);
+                    }
+                }
+                StmtKind::Global(names) => self.known.extend(names.iter().cloned()),
+                StmtKind::Include(_) => self.includes = true,
+                StmtKind::If(x) => {
+                    for (_, stmts) in &x.branches {
+                        self.collect(stmts);
+                    }
+                }
+                StmtKind::While(While { stmts, .. })
+                | StmtKind::Until(Until { stmts, .. })
+                | StmtKind::Repeat(Until { stmts, .. })
+                | StmtKind::For(For { stmts, .. })
+                | StmtKind::ForEach(ForEach { stmts, .. }) => self.collect(stmts),
+                StmtKind::Attempt(x) => {
+                    self.collect(&x.stmts);
+                    self.collect(&x.handler);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Warnings about `prog`, in source order
+pub fn lint(prog: &str, options: &LexOptions) -> Result<Vec<Warning>, Box<dyn Error>> {
+    let tokens = lex::lex(prog, options)?;
+    let stmts = parse::parse(tokens.clone())?;
+
+    let mut linter = Linter {
+        tokens: &tokens,
+        pos: 0,
+        frames: vec![Frame {
+            blocks: vec![Block::default()],
+            ..Frame::default()
+        }],
+        classes: HashMap::new(),
+        known: HashSet::new(),
+        includes: false,
+        warnings: Vec::new(),
+    };
+
+    linter.collect(&stmts);
+    linter.stmts(&stmts);
+    linter.finish_frame();
+
+    let mut warnings = linter.warnings;
+    warnings.sort_by_key(|x| (x.span.line, x.span.col));
+    Ok(warnings)
+}
+
+/// `psc lint FILE...` prints the warnings for each file, the ones `-W` and
+/// `-A` leave shown, and fails if `-D` made any of them errors
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut options = LexOptions::default();
+    let mut filter = Filter::default();
+    let mut files: Vec<String> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if !options.parse_flag(&arg, &mut args)? && !filter.parse_flag(&arg, &mut args)? {
+            files.push(arg);
+        }
+    }
+
+    if files.is_empty() {
+        return Err("lint expects at least one file".into());
+    }
+
+    let mut denied = 0;
+    for file in &files {
+        let prog = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+
+        for x in lint(&prog, &options).map_err(|e| format!("{}: {}", file, e))?.iter().filter(|x| filter.shows(x.code)) {
+            let kind = match filter.level(x.code) {
+                Level::Deny => "error",
+                _ => "warning",
+            };
+            denied += (kind == "error") as usize;
+            println!("{}:{}:{}: {}[{}]: {}", file, x.span.line, x.span.col, kind, x.code, x.msg);
+        }
+    }
+
+    if denied > 0 {
+        return Err(format!("{} warning(s) denied with -D", denied).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use psc::dialect::Dialect;
+    use psc::lex::LexOptions;
+
+    #[test]
+    fn declare_is_not_an_unread_assignment() {
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let source = "DECLARE T : INTEGER\nT <- 5\nOUTPUT T\nX <- 1\nX <- 2\nOUTPUT X";
+        let warnings = super::lint(source, &cambridge).unwrap();
+        let codes: Vec<_> = warnings.iter().map(|x| (x.code, x.span.line)).collect();
+        assert_eq!(codes, [("W005", 4)]);
+    }
+}
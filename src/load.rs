@@ -0,0 +1,135 @@
+use crate::err::ParseError;
+use crate::eval::{Stmt, StmtKind};
+use crate::lex::{self, LexOptions};
+use crate::parse;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a program couldn't be loaded, every syntax error in one of its files
+/// or why a file couldn't be read
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    /// The text of the file, when it could be read
+    pub source: Option<String>,
+    pub errors: Vec<ParseError>,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, e) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match e.span {
+                Some(span) => write!(f, "{}:{}:{}: {}", self.path.display(), span.line, span.col, e.msg)?,
+                None => write!(f, "{}: {}", self.path.display(), e.msg)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for LoadError {}
+
+/// Reads, lexes and parses `path`, splicing in the statements of any
+/// top-level `include "file"`, resolved relative to the including file
+pub fn load(path: &Path, options: &LexOptions) -> Result<Vec<Stmt>, LoadError> {
+    let mut stack: Vec<PathBuf> = Vec::new();
+    load_file(path, options, &mut stack)
+}
+
+/// `load` for a program that's already been read, from `-e` or stdin.
+/// `path` is what it's called in errors, and includes are resolved
+/// relative to it
+pub fn load_source(path: &Path, prog: String, options: &LexOptions) -> Result<Vec<Stmt>, LoadError> {
+    let mut stack: Vec<PathBuf> = Vec::new();
+    parse_file(path, prog, options, &mut stack)
+}
+
+fn load_file(path: &Path, options: &LexOptions, stack: &mut Vec<PathBuf>) -> Result<Vec<Stmt>, LoadError> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let fail = |path: &Path, source: Option<String>, errors: Vec<ParseError>| LoadError {
+        path: path.to_path_buf(),
+        source,
+        errors,
+    };
+
+    if stack.contains(&canonical) {
+        let mut cycle: Vec<String> = stack.iter().map(|x| x.display().to_string()).collect();
+        cycle.push(canonical.display().to_string());
+
+        let msg = format!("Include cycle: {}", cycle.join(" -> "));
+        return Err(fail(path, None, vec![ParseError { msg, span: None, code: Some("E022") }]));
+    }
+
+    let prog = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => {
+            let (path, msg, code) = match stack.last() {
+                Some(parent) => (parent.as_path(), format!("cannot include {}: {}", path.display(), e), Some("E022")),
+                None => (path, e.to_string(), None),
+            };
+            return Err(fail(path, None, vec![ParseError { msg, span: None, code }]));
+        }
+    };
+    parse_file(path, prog, options, stack)
+}
+
+fn parse_file(path: &Path, prog: String, options: &LexOptions, stack: &mut Vec<PathBuf>) -> Result<Vec<Stmt>, LoadError> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    // Every syntax error in the file, but a token that doesn't lex stops it
+    let stmts = match lex::lex(&prog, options) {
+        Ok(tokens) => parse::parse_all(tokens),
+        Err(e) => Err(vec![e]),
+    };
+    let stmts = stmts.map_err(|errors| LoadError {
+        path: path.to_path_buf(),
+        source: Some(prog),
+        errors,
+    })?;
+
+    stack.push(canonical);
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut ret: Vec<Stmt> = Vec::new();
+
+    for stmt in stmts {
+        match stmt.kind {
+            StmtKind::Include(file) => ret.append(&mut load_file(&dir.join(file), options, stack)?),
+            _ => ret.push(stmt),
+        }
+    }
+
+    stack.pop();
+
+    Ok(ret)
+}
+
+/// Adds the files under `path` that `want` picks to `found`, skipping
+/// hidden directories and `target`. A file given directly is always taken
+pub fn discover(path: &Path, want: fn(&Path) -> bool, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        found.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|x| x.map(|x| x.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for entry in entries {
+        let name = entry.file_name().and_then(|x| x.to_str()).unwrap_or("");
+        if entry.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                discover(&entry, want, found)?;
+            }
+        } else if want(&entry) {
+            found.push(entry);
+        }
+    }
+    Ok(())
+}
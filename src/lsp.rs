@@ -0,0 +1,435 @@
+use psc::err::ParseError;
+use psc::json::Json;
+use psc::lex::{self, Keyword, LexOptions, LexerToken, Punctuation, Span, Token};
+use crate::lint;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+/// Keywords offered by completion in every dialect
+const KEYWORDS: &[&str] = &[
+    "loop", "while", "from", "to", "until", "if", "then", "else", "end", "input", "output", "over", "procedure",
+    "function", "return", "class", "public", "private", "new", "inherits", "super", "include", "global", "integer",
+    "real", "string", "boolean", "attempt", "on", "error", "assert", "halt", "mod", "div", "in", "true", "false",
+    "null",
+];
+
+// Completion item kinds from the specification
+const KIND_FUNCTION: i64 = 3;
+const KIND_VARIABLE: i64 = 6;
+const KIND_CLASS: i64 = 7;
+const KIND_KEYWORD: i64 = 14;
+
+/// Language server over stdio, with the open documents by URI
+struct Server {
+    docs: HashMap<String, String>,
+    options: LexOptions,
+}
+
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut len: Option<usize> = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let len = match len {
+        Some(x) => x,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length")),
+    };
+
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+
+    match Json::parse(&String::from_utf8_lossy(&body)) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.msg)),
+    }
+}
+
+pub fn send(msg: Json) -> io::Result<()> {
+    let body = msg.to_string();
+    let mut out = io::stdout().lock();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+/// LSP positions count from 0
+fn position(line: usize, col: usize) -> Json {
+    Json::object(vec![
+        ("line", Json::Int(line.saturating_sub(1) as i64)),
+        ("character", Json::Int(col.saturating_sub(1) as i64)),
+    ])
+}
+
+fn range(span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(span.line, span.col)),
+        ("end", position(span.line, span.col + span.len)),
+    ])
+}
+
+/// The token under a 0 based position
+fn token_at(tokens: &[Token], line: usize, col: usize) -> Option<usize> {
+    let (line, col) = (line + 1, col + 1);
+
+    let index = tokens.iter().rposition(|x| (x.span.line, x.span.col) <= (line, col))?;
+    let span = tokens[index].span;
+    if span.line == line && col < span.col + span.len {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+fn is_ident(tok: &LexerToken, name: &str) -> bool {
+    matches!(tok, LexerToken::Identifier(x) if x == name)
+}
+
+/// Matches `procedure NAME(A, B: INTEGER` up to a parameter at `index`
+fn is_param(tokens: &[Token], index: usize) -> bool {
+    let mut i = index;
+    while i > 0
+        && matches!(
+            tokens[i - 1].tok,
+            LexerToken::Identifier(_)
+                | LexerToken::Punctuation(Punctuation::Comma | Punctuation::Colon)
+                | LexerToken::Keyword(Keyword::Integer | Keyword::Real | Keyword::String | Keyword::Boolean)
+        )
+    {
+        i -= 1;
+    }
+
+    i >= 3
+        && tokens[i - 1].tok == LexerToken::Punctuation(Punctuation::LParen)
+        && matches!(tokens[i - 3].tok, LexerToken::Keyword(Keyword::Procedure | Keyword::Function))
+}
+
+/// The token where `name` first gets a value: an assignment, `input`, a
+/// loop, a parameter or a definition
+/// The `///` comments before the procedure or function whose name is at
+/// `def`, past its `public` or `private`
+fn doc_before(tokens: &[Token], def: usize) -> Option<String> {
+    let mut i = def.checked_sub(1)?;
+    if !matches!(tokens[i].tok, LexerToken::Keyword(Keyword::Procedure | Keyword::Function)) {
+        return None;
+    }
+    if i > 0 && matches!(tokens[i - 1].tok, LexerToken::Keyword(Keyword::Public | Keyword::Private)) {
+        i -= 1;
+    }
+
+    let mut lines: Vec<&str> = Vec::new();
+    while i > 0 {
+        match &tokens[i - 1].tok {
+            LexerToken::Doc(x) => lines.push(x),
+            _ => break,
+        }
+        i -= 1;
+    }
+    lines.reverse();
+
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+fn first_definition(tokens: &[Token], name: &str) -> Option<usize> {
+    (0..tokens.len()).find(|&i| {
+        if !is_ident(&tokens[i].tok, name) {
+            return false;
+        }
+
+        let next = tokens.get(i + 1).map(|x| &x.tok);
+        let prev = if i > 0 { Some(&tokens[i - 1].tok) } else { None };
+
+        next == Some(&LexerToken::Punctuation(Punctuation::Assign))
+            || matches!(
+                prev,
+                Some(LexerToken::Keyword(
+                    Keyword::Input
+                        | Keyword::Loop
+                        | Keyword::For
+                        | Keyword::Procedure
+                        | Keyword::Function
+                        | Keyword::Class
+                        | Keyword::Declare
+                        | Keyword::Set
+                        | Keyword::Receive
+                        | Keyword::Error
+                ))
+            )
+            // `input integer X`
+            || (i >= 2 && tokens[i - 2].tok == LexerToken::Keyword(Keyword::Input))
+            || is_param(tokens, i)
+    })
+}
+
+/// What a literal or the first value given to a variable is
+fn describe(tok: &LexerToken) -> Option<String> {
+    match tok {
+        LexerToken::IntLit(x) => Some(format!("integer `{}`", x)),
+        LexerToken::FloatLit(x) => Some(format!("real `{:?}`", x)),
+        LexerToken::StrLit(x) => Some(format!("string of length {}", x.chars().count())),
+        LexerToken::BoolLit(x) => Some(format!("boolean `{}`", x)),
+        LexerToken::NullLit => Some("null".into()),
+        LexerToken::Punctuation(Punctuation::LBracket) => Some("array".into()),
+        LexerToken::Punctuation(Punctuation::LBrace) => Some("map or set".into()),
+        LexerToken::Keyword(Keyword::New) => Some("object".into()),
+        _ => None,
+    }
+}
+
+impl Server {
+    fn publish(&self, uri: &str) -> io::Result<()> {
+        let text = self.docs.get(uri).map_or("", |x| x.as_str());
+
+        let diagnostics: Vec<Json> = match lint::lint(text, &self.options) {
+            Ok(warnings) => warnings
+                .iter()
+                .map(|x| {
+                    Json::object(vec![
+                        ("range", range(x.span)),
+                        ("severity", Json::Int(2)),
+                        ("code", Json::str(x.code)),
+                        ("source", Json::str("psc")),
+                        ("message", Json::String(x.msg.clone())),
+                    ])
+                })
+                .collect(),
+
+            Err(e) => {
+                let parse = e.downcast_ref::<ParseError>();
+                let mut fields = vec![
+                    ("range", range(parse.and_then(|x| x.span).unwrap_or(Span { line: 1, col: 1, len: 0 }))),
+                    ("severity", Json::Int(1)),
+                    ("source", Json::str("psc")),
+                    ("message", Json::String(e.to_string())),
+                ];
+                if let Some(code) = parse.and_then(|x| x.code) {
+                    fields.push(("code", Json::str(code)));
+                }
+                vec![Json::object(fields)]
+            }
+        };
+
+        send(Json::object(vec![
+            ("jsonrpc", Json::str("2.0")),
+            ("method", Json::str("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object(vec![("uri", Json::str(uri)), ("diagnostics", Json::Array(diagnostics))]),
+            ),
+        ]))
+    }
+
+    /// The document, its tokens and the token under the cursor of a
+    /// `textDocument/...` request
+    fn locate<'a>(&'a self, params: &'a Json) -> Option<(&'a str, Vec<Token>, usize)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let pos = params.get("position")?;
+        let line = pos.get("line")?.as_i64()? as usize;
+        let col = pos.get("character")?.as_i64()? as usize;
+
+        let text = self.docs.get(uri)?;
+        let tokens = lex::lex(text, &self.options).ok()?;
+        let index = token_at(&tokens, line, col)?;
+
+        Some((uri, tokens, index))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (_, tokens, index) = self.locate(params)?;
+
+        let value = match &tokens[index].tok {
+            LexerToken::Identifier(name) => {
+                let def = first_definition(&tokens, name)?;
+                let line = tokens[def].span.line;
+
+                match tokens.get(def + 1).zip(tokens.get(def + 2)) {
+                    Some((assign, value)) if assign.tok == LexerToken::Punctuation(Punctuation::Assign) => {
+                        match describe(&value.tok) {
+                            Some(kind) => format!("`{}`: {}, first assigned on line {}", name, kind, line),
+                            None => format!("`{}`, first assigned on line {}", name, line),
+                        }
+                    }
+                    _ => match doc_before(&tokens, def) {
+                        Some(doc) => format!("`{}`, defined on line {}\n\n{}", name, line, doc),
+                        None => format!("`{}`, defined on line {}", name, line),
+                    },
+                }
+            }
+            tok => describe(tok)?,
+        };
+
+        Some(Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![("kind", Json::str("markdown")), ("value", Json::String(value))]),
+            ),
+            ("range", range(tokens[index].span)),
+        ]))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (uri, tokens, index) = self.locate(params)?;
+
+        let name = match &tokens[index].tok {
+            LexerToken::Identifier(x) => x,
+            _ => return None,
+        };
+        let def = first_definition(&tokens, name)?;
+
+        Some(Json::object(vec![("uri", Json::str(uri)), ("range", range(tokens[def].span))]))
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items: Vec<Json> = Vec::new();
+        let mut item = |label: &str, kind: i64| {
+            items.push(Json::object(vec![("label", Json::str(label)), ("kind", Json::Int(kind))]));
+        };
+
+        let dialect = self.options.dialect.table().keywords.iter().map(|(x, _)| *x);
+        for keyword in KEYWORDS.iter().copied().chain(dialect) {
+            item(keyword, KIND_KEYWORD);
+        }
+
+        let uri = params.get("textDocument").and_then(|x| x.get("uri")).and_then(Json::as_str);
+        let text = uri.and_then(|x| self.docs.get(x)).map_or("", |x| x.as_str());
+        let tokens = lex::lex(text, &self.options).unwrap_or_default();
+
+        let mut seen: Vec<&str> = Vec::new();
+        for (i, tok) in tokens.iter().enumerate() {
+            let name = match &tok.tok {
+                LexerToken::Identifier(x) if !seen.contains(&x.as_str()) => x.as_str(),
+                _ => continue,
+            };
+            seen.push(name);
+
+            let kind = match i.checked_sub(1).map(|j| &tokens[j].tok) {
+                Some(LexerToken::Keyword(Keyword::Procedure | Keyword::Function)) => KIND_FUNCTION,
+                Some(LexerToken::Keyword(Keyword::Class)) => KIND_CLASS,
+                _ => KIND_VARIABLE,
+            };
+            item(name, kind);
+        }
+
+        Json::Array(items)
+    }
+
+    /// Handles one message, `false` once the client asks to exit
+    fn handle(&mut self, msg: &Json) -> io::Result<bool> {
+        let method = msg.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = msg.get("params").unwrap_or(&Json::Null);
+
+        let text_doc = params.get("textDocument");
+        let uri = text_doc.and_then(|x| x.get("uri")).and_then(Json::as_str).map(String::from);
+
+        let result = match method {
+            "initialize" => Json::object(vec![
+                (
+                    "capabilities",
+                    Json::object(vec![
+                        // Full document sync
+                        ("textDocumentSync", Json::Int(1)),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("completionProvider", Json::object(vec![])),
+                    ]),
+                ),
+                ("serverInfo", Json::object(vec![("name", Json::str("psc"))])),
+            ]),
+            "shutdown" => Json::Null,
+            "exit" => return Ok(false),
+
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => text_doc.and_then(|x| x.get("text")),
+                    _ => params
+                        .get("contentChanges")
+                        .and_then(Json::as_array)
+                        .and_then(|x| x.last())
+                        .and_then(|x| x.get("text")),
+                };
+
+                if let (Some(uri), Some(text)) = (uri, text.and_then(Json::as_str)) {
+                    self.docs.insert(uri.clone(), text.to_string());
+                    self.publish(&uri)?;
+                }
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = uri {
+                    self.docs.remove(&uri);
+                    self.publish(&uri)?;
+                }
+                return Ok(true);
+            }
+
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            "textDocument/completion" => self.completion(params),
+
+            _ => {
+                // Notifications without a handler are ignored
+                if let Some(id) = msg.get("id") {
+                    send(Json::object(vec![
+                        ("jsonrpc", Json::str("2.0")),
+                        ("id", id.clone()),
+                        (
+                            "error",
+                            Json::object(vec![
+                                ("code", Json::Int(-32601)),
+                                ("message", Json::String(format!("Unknown method {}", method))),
+                            ]),
+                        ),
+                    ]))?;
+                }
+                return Ok(true);
+            }
+        };
+
+        if let Some(id) = msg.get("id") {
+            send(Json::object(vec![("jsonrpc", Json::str("2.0")), ("id", id.clone()), ("result", result)]))?;
+        }
+
+        Ok(true)
+    }
+}
+
+/// `psc lsp` talks the Language Server Protocol over stdin and stdout
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut server = Server {
+        docs: HashMap::new(),
+        options: LexOptions::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        if !server.options.parse_flag(&arg, &mut args)? {
+            return Err(format!("Unknown lsp argument {}", arg).into());
+        }
+    }
+
+    let mut input = io::stdin().lock();
+    while let Some(msg) = read_message(&mut input)? {
+        if !server.handle(&msg)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
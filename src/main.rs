@@ -1,23 +1,656 @@
-mod lex;
-mod err;
-mod parse;
-mod eval;
+mod bench;
+mod cfg;
+mod cli;
+mod completions;
+mod doc;
+mod dap;
+mod debug;
+mod format;
+mod grade;
+mod graph;
+mod fuzz;
+mod harness;
+mod highlight;
+mod lint;
+mod lsp;
+mod profile;
+mod repl;
+mod session;
+mod transpile;
 
-use std::{env, error, fs};
-use std::collections::HashMap;
-use crate::eval::{Stmt, PscObject};
+use std::error;
+use std::fs::File;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, IsTerminal, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use psc::{emit, env, err, eval, explain, lex, load, opt, typecheck};
+use psc::diagnostic::Diagnostic;
+use psc::env::Env;
+use psc::lex::LexOptions;
 
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let mut args = env::args();
-    let prog = fs::read_to_string(args.nth(1).unwrap())?;
-    let tokens = lex::lex(prog.as_str())?;
-    let stmts = parse::parse(tokens)?;
+/// The jit for `--jit`, which only exists when psc is built with it
+#[cfg(feature = "jit")]
+fn make_jit() -> Result<Box<dyn env::Jit>, String> {
+    Ok(Box::new(psc::jit::Jit::new()?))
+}
+
+#[cfg(not(feature = "jit"))]
+fn make_jit() -> Result<Box<dyn env::Jit>, String> {
+    Err("--jit needs psc built with the jit feature".into())
+}
+
+/// A number of bytes, optionally followed by K, M or G
+fn parse_size(text: &str) -> Option<usize> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let unit: usize = match text[split..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    text[..split].parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// A time like `2s`, `500ms` or `1m`, in seconds without a unit
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let secs: f64 = match &text[split..] {
+        "" | "s" => 1.0,
+        "ms" => 0.001,
+        "m" => 60.0,
+        _ => return None,
+    };
+    let num = text[..split].parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(num * secs).ok()
+}
+
+/// How an error is shown, with its code if it's a known kind, the line it's
+/// on in `program`, its name and text, if it knows it and the calls it came
+/// out of in `trace`
+fn report(e: &(dyn error::Error + 'static), program: Option<(&str, &str)>, trace: &[(String, usize)]) -> String {
+    let color = std::io::stderr().is_terminal();
+
+    // Every syntax error in the file, each with the line it's on
+    if let Some(e) = e.downcast_ref::<load::LoadError>() {
+        let path = e.path.display().to_string();
+        let file = e.source.as_deref().map(|x| (path.as_str(), x));
+        let errors = e.errors.iter().map(|x| {
+            // Without the text it couldn't be read, so the path goes first
+            let msg = match file {
+                Some(_) => x.msg.clone(),
+                None => format!("{}: {}", path, x.msg),
+            };
+            let diagnostic = Diagnostic {
+                msg: &msg,
+                file,
+                span: x.span,
+                trace: &[],
+                code: x.code,
+                warning: None,
+                denied: false,
+            };
+            diagnostic.render(color)
+        });
+        return errors.collect::<Vec<_>>().join("\n\n");
+    }
+
+    let (msg, span, code) = match (e.downcast_ref::<err::ParseError>(), e.downcast_ref::<err::RuntimeError>()) {
+        (Some(e), _) => (e.msg.clone(), e.span, e.code),
+        (_, Some(e)) => (e.msg.clone(), e.span, e.code),
+        _ => (e.to_string(), None, None),
+    };
+    let diagnostic = Diagnostic {
+        msg: &msg,
+        file: program,
+        span,
+        trace,
+        code,
+        warning: None,
+        denied: false,
+    };
+    diagnostic.render(color)
+}
+
+/// The warnings about `file` that `filter` lets through, each shown like an
+/// error is, and whether any of them were made errors with `-D`
+fn warnings(file: &str, source: &str, options: &LexOptions, filter: &lint::Filter) -> (Vec<String>, bool) {
+    let color = std::io::stderr().is_terminal();
+    let found = lint::lint(source, options).unwrap_or_default();
+    let denied = found.iter().any(|x| filter.level(x.code) == lint::Level::Deny);
+    let shown = found.iter().filter(|x| filter.shows(x.code)).map(|x| {
+        let diagnostic = Diagnostic {
+            msg: &x.msg,
+            file: Some((file, source)),
+            span: Some(x.span),
+            trace: &[],
+            code: None,
+            warning: Some(x.code),
+            denied: filter.level(x.code) == lint::Level::Deny,
+        };
+        diagnostic.render(color)
+    });
+    (shown.collect(), denied)
+}
+
+/// The stack programs run on, which leaves room to reach `env::MAX_DEPTH`
+/// even in a debug build where each call takes far more of it
+const STACK_SIZE: usize = 256 << 20;
+
+fn main() {
+    let main = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+        if let Err(e) = run() {
+            eprintln!("{}", report(&*e, None, &[]));
+            std::process::exit(1);
+        }
+    });
+    // A panic has already been reported by the thread
+    if !main.is_ok_and(|x| x.join().is_ok()) {
+        std::process::exit(101);
+    }
+}
+
+/// Every type error `--typecheck` finds in the program, as one error for
+/// `report` to show
+fn typecheck(file: &str, source: &str, stmts: &[eval::Stmt], options: &LexOptions) -> Result<(), load::LoadError> {
+    let errors = typecheck::check(stmts, options.dialect);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(load::LoadError {
+        path: file.into(),
+        source: Some(source.to_string()),
+        errors,
+    })
+}
+
+fn run() -> Result<(), Box<dyn error::Error>> {
+    let mut file: Option<String> = None;
+    let mut strict_input = false;
+    let mut bignum = false;
+    let mut jit = false;
+    let mut emit_ast = false;
+    let mut emit_tokens = false;
+    let mut check = false;
+    let mut type_check = false;
+    let mut optimize = true;
+    let mut deterministic = false;
+    let mut step = false;
+    let mut profile = false;
+    // `--trace` or `--trace-file FILE`
+    let mut trace: Option<Option<String>> = None;
+
+    let mut seed: Option<u64> = None;
+    let mut max_steps: Option<u64> = None;
+    let mut max_memory: Option<usize> = None;
+    let mut timeout: Option<Duration> = None;
+    let mut record: Option<String> = None;
+    let mut replay: Option<String> = None;
+    let mut stdin: Option<String> = None;
+    // `--out` replaces the terminal, `--tee` writes a copy as well
+    let mut out: Option<String> = None;
+    let mut tee: Option<String> = None;
+    let mut expect: Option<String> = None;
+    let mut options = LexOptions::default();
+    let mut filter = lint::Filter::default();
+
+    // `psc COMMAND --help` is answered here, from the same definitions the
+    // completions are made from
+    let mut words = std::env::args().skip(1);
+    if let (Some(name), Some("--help" | "-h")) = (words.next(), words.next().as_deref()) {
+        if let Some(command) = cli::COMMANDS.iter().find(|x| x.name == name) {
+            print!("{}", cli::help(Some(command)));
+            return Ok(());
+        }
+    }
+
+    let mut args = std::env::args().skip(1).peekable();
+    let mut run = false;
+    let mut repl = false;
+    // The program itself with `-e`, instead of a file
+    let mut inline: Option<String> = None;
+    // The first of the program's arguments, when there's no file before them
+    let mut first_arg: Option<String> = None;
+
+    match args.peek().map(String::as_str) {
+        Some("fmt") => {
+            args.next();
+            return format::main(args);
+        }
+        Some("lint") => {
+            args.next();
+            return lint::main(args);
+        }
+        Some("lsp") => {
+            args.next();
+            return lsp::main(args);
+        }
+        Some("highlight") => {
+            args.next();
+            return highlight::main(args);
+        }
+        Some("dap") => {
+            args.next();
+            return dap::main(args);
+        }
+        Some("debug") => {
+            args.next();
+            return debug::main(args);
+        }
+        Some("bench") => {
+            args.next();
+            return bench::main(args);
+        }
+        Some("doc") => {
+            args.next();
+            return doc::main(args);
+        }
+        Some("grade") => {
+            args.next();
+            return grade::main(args);
+        }
+        Some("graph") => {
+            args.next();
+            return graph::main(args);
+        }
+        Some("fuzz") => {
+            args.next();
+            return fuzz::main(args);
+        }
+        Some("test") => {
+            args.next();
+            return harness::main(args);
+        }
+        // The same as without it, except options can follow the file and
+        // the program's own arguments go after `--`
+        Some("run") => {
+            args.next();
+            run = true;
+        }
+        Some("check") => {
+            args.next();
+            check = true;
+        }
+        Some("repl") => {
+            args.next();
+            repl = true;
+        }
+        Some("transpile") => {
+            args.next();
+            return transpile::main(args);
+        }
+        Some("completions") => {
+            args.next();
+            return completions::main(args);
+        }
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict-input" => strict_input = true,
+            "--bignum" => bignum = true,
+            "--jit" => jit = true,
+            "--emit-ast" => emit_ast = true,
+            "--emit-tokens" => emit_tokens = true,
+            "--check" => check = true,
+            "-e" => match args.next() {
+                Some(x) => inline = Some(x),
+                None => return Err("-e expects a program, like -e \"output 1 + 1\"".into()),
+            },
+            "--help" | "-h" => {
+                print!("{}", cli::help(None));
+                return Ok(());
+            }
+            "--version" | "-V" => {
+                println!("psc {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            "--typecheck" => type_check = true,
+            "--no-opt" => optimize = false,
+            "--explain" => {
+                let code = args.next_if(|x| !x.starts_with('-'));
+                println!("{}", explain::explain(code.as_deref())?);
+                return Ok(());
+            }
+            "--deterministic" => deterministic = true,
+            "--step" => step = true,
+            "--profile" => profile = true,
+            "--trace" => trace = Some(None),
+            "--trace-file" => match args.next() {
+                Some(x) => trace = Some(Some(x)),
+                None => return Err("--trace-file expects a file".into()),
+            },
+            "--seed" => match args.next().and_then(|x| x.parse::<i64>().ok()) {
+                Some(x) => seed = Some(x as u64),
+                None => return Err("--seed expects an integer".into()),
+            },
+            "--max-steps" => match args.next().and_then(|x| x.parse::<u64>().ok()) {
+                Some(x) => max_steps = Some(x),
+                None => return Err("--max-steps expects a number".into()),
+            },
+            "--max-memory" => match args.next().as_deref().and_then(parse_size) {
+                Some(x) => max_memory = Some(x),
+                None => return Err("--max-memory expects a size like 4096, 64K or 16M".into()),
+            },
+            "--timeout" => match args.next().as_deref().and_then(parse_duration) {
+                Some(x) => timeout = Some(x),
+                None => return Err("--timeout expects a time like 2s, 500ms or 1m".into()),
+            },
+            "--record" => match args.next() {
+                Some(x) => record = Some(x),
+                None => return Err("--record expects a file".into()),
+            },
+            "--replay" => match args.next() {
+                Some(x) => replay = Some(x),
+                None => return Err("--replay expects a file".into()),
+            },
+            "--stdin" => match args.next() {
+                Some(x) => stdin = Some(x),
+                None => return Err("--stdin expects a file".into()),
+            },
+            "--out" => match args.next() {
+                Some(x) => out = Some(x),
+                None => return Err("--out expects a file".into()),
+            },
+            "--tee" => match args.next() {
+                Some(x) => tee = Some(x),
+                None => return Err("--tee expects a file".into()),
+            },
+            "--expect" => match args.next() {
+                Some(x) => expect = Some(x),
+                None => return Err("--expect expects a file".into()),
+            },
+            "--" if run => break,
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if filter.parse_flag(&arg, &mut args)? => {}
+            _ if inline.is_some() && !run => {
+                first_arg = Some(arg);
+                break;
+            }
+            _ if run && file.is_none() => file = Some(arg),
+            _ if run => return Err(format!("Unknown run argument {}, the program's arguments go after --", arg).into()),
+            _ => {
+                file = Some(arg);
+                break;
+            }
+        }
+    }
+
+    // Everything after the script is for the script itself
+    let mut script_args: Vec<String> = first_arg.into_iter().chain(args).collect();
+
+    if inline.is_some() && file.is_some() {
+        return Err("-e is the program to run, so it can't have a file as well".into());
+    }
+    if inline.is_some() && (record.is_some() || replay.is_some()) {
+        return Err("--record and --replay need a program in a file, not one given with -e".into());
+    }
+
+    // `-` for a file, or no file with one piped in, reads the program from
+    // stdin, so the program's own input has to come from `--stdin`
+    let piped = file.is_none() && inline.is_none() && replay.is_none() && !repl && !std::io::stdin().is_terminal();
+    let from_stdin = file.as_deref() == Some("-") || piped;
+    if from_stdin && (record.is_some() || replay.is_some()) {
+        return Err("--record and --replay need a program in a file, not one read from stdin".into());
+    }
+    if from_stdin && !repl {
+        file = None;
+        inline = Some(std::io::read_to_string(std::io::stdin())?);
+    }
+
+    if record.is_some() && replay.is_some() {
+        return Err("Only one of --record and --replay can be used at once".into());
+    }
+    if stdin.is_some() && replay.is_some() {
+        return Err("--stdin can't be used with --replay, which has its own input".into());
+    }
+    // A replay runs the recorded program with its arguments and seed, unless
+    // they're given again
+    let replay = match replay {
+        Some(path) => Some(session::Session::load(&path)?),
+        None => None,
+    };
+    if let Some(session) = &replay {
+        file = file.or_else(|| Some(session.program.clone()));
+        seed = seed.or(Some(session.seed));
+        if script_args.is_empty() {
+            script_args = session.args.clone();
+        }
+    } else if record.is_some() && seed.is_none() && !deterministic {
+        // Picked here rather than by `Env` so it can be saved
+        seed = Some(env::clock_seed());
+    }
+
+    let make_env = || {
+        let mut env = Env::new();
+        env.strict_input = strict_input;
+        env.bignum = bignum;
+        env.args = script_args.clone();
+        env.dialect = options.dialect;
+        env.max_steps = max_steps;
+        env.max_memory = max_memory;
+        env.deterministic = deterministic;
+        // The seed can still be picked, but never comes from the clock
+        match (seed, deterministic) {
+            (Some(seed), _) => env.seed(seed),
+            (None, true) => env.seed(0),
+            (None, false) => {}
+        }
+        env
+    };
+
+    if repl && (file.is_some() || inline.is_some()) {
+        return Err("repl doesn't take a program, run one with psc run FILE".into());
+    }
+
+    // Without a script there's nothing to run but an interactive session
+    let file = match (file, &inline) {
+        (_, Some(_)) if from_stdin => "<stdin>".to_string(),
+        (_, Some(_)) => "<inline>".to_string(),
+        (Some(x), None) => x,
+        (None, None) => return Ok(repl::run(&options, &make_env)?),
+    };
+
+    // The text of a program, or the error loading it would give
+    let read = |file: &str| match &inline {
+        Some(code) => Ok(code.clone()),
+        None => std::fs::read_to_string(file).map_err(|e| load::LoadError {
+            path: file.into(),
+            source: None,
+            errors: vec![err::ParseError { msg: e.to_string(), span: None, code: None }],
+        }),
+    };
+
+    // Only lexed, so it works on programs that don't parse
+    if emit_tokens {
+        let prog = read(&file)?;
+        let tokens = lex::lex(&prog, &options).map_err(|e| load::LoadError {
+            path: file.clone().into(),
+            source: Some(prog.clone()),
+            errors: vec![e],
+        })?;
+        for token in tokens {
+            println!("{}:{}\t{:?}", token.span.line, token.span.col, token.tok);
+        }
+        return Ok(());
+    }
+
+    // Only parsed, along with anything else named after the first file, and
+    // quiet unless one of them doesn't parse or has warnings
+    if check {
+        let mut failed = false;
+        let others = if inline.is_some() { &[][..] } else { &script_args[..] };
+        for file in std::iter::once(&file).chain(others) {
+            let checked = read(file).and_then(|source| {
+                let stmts = load::load_source(Path::new(file), source.clone(), &options)?;
+                if type_check {
+                    typecheck(file, &source, &stmts, &options)?;
+                }
+                Ok(source)
+            });
+            match checked {
+                Ok(source) => {
+                    let (found, denied) = warnings(file, &source, &options, &filter);
+                    found.iter().for_each(|x| eprintln!("{}\n", x));
+                    failed |= denied;
+                }
+                Err(e) => {
+                    eprintln!("{}", report(&e, None, &[]));
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let source = read(&file)?;
+    let mut stmts = load::load_source(Path::new(&file), source.clone(), &options)?;
+    // Before anything runs, so a mistake on a branch that's never taken
+    // still stops it
+    if type_check {
+        typecheck(&file, &source, &stmts, &options)?;
+    }
+    // The program as it was written, before any of it is folded
+    if emit_ast {
+        println!("{}", emit::stmts(&stmts));
+        return Ok(());
+    }
+    if optimize {
+        opt::fold(&mut stmts);
+    }
+
+    // Found before running, so one made an error with `-D` stops it
+    let (found, denied) = warnings(&file, &source, &options, &filter);
+    if denied {
+        found.iter().for_each(|x| eprintln!("{}\n", x));
+        std::process::exit(1);
+    }
+
+    let mut env = make_env();
+    if jit {
+        env.jit = Some(make_jit()?);
+    }
+    // Each of these watches the program through the one hook
+    if [step, trace.is_some(), profile].iter().filter(|x| **x).count() > 1 {
+        return Err("Only one of --step, --trace and --profile can be used at once".into());
+    }
+
+    let mut hotspots = None;
+    if step {
+        env.hook = Some(Box::new(debug::Stepper::new(&source)));
+    } else if let Some(path) = trace {
+        let out: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(&path).map_err(|e| format!("{}: {}", path, e))?)),
+            None => Box::new(std::io::stderr()),
+        };
+        env.hook = Some(Box::new(debug::Tracer::new(&source, out)));
+    } else if profile {
+        let profile = profile::Profile::new(&source);
+        env.hook = Some(Box::new(profile::Profiler(profile.clone())));
+        hotspots = Some(profile);
+    }
+
+    let expected = match &expect {
+        Some(path) => Some(std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => None,
+    };
+    let captured = env::Captured::default();
 
-    let mut vars: HashMap<String, PscObject> = HashMap::new();
+    if out.is_some() || tee.is_some() || expected.is_some() {
+        let mut sinks: Vec<Box<dyn Write>> = Vec::new();
+        if out.is_none() {
+            sinks.push(Box::new(std::io::stdout()));
+        }
+        for path in out.iter().chain(&tee) {
+            sinks.push(Box::new(BufWriter::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?)));
+        }
+        if expected.is_some() {
+            sinks.push(Box::new(captured.clone()));
+        }
+        env.out = Box::new(env::Tee(sinks));
+    }
+
+    if let Some(path) = &stdin {
+        let input = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        env.input = Some(Box::new(BufReader::new(input)));
+        // Nobody is there to type a line again when it's the wrong type
+        env.strict_input = true;
+    }
 
-    for stmt in stmts {
-        Stmt::eval(&stmt, &mut vars)?;
+    let recorded = Rc::new(RefCell::new(Vec::new()));
+    if let Some(session) = &replay {
+        env.input = Some(Box::new(Cursor::new(session.input.concat().into_bytes())));
+    } else if record.is_some() {
+        let inner: Box<dyn BufRead> = match env.input.take() {
+            Some(x) => x,
+            None => Box::new(BufReader::new(std::io::stdin())),
+        };
+        env.input = Some(Box::new(session::Recorder { inner, read: recorded.clone() }));
     }
 
+    // Started here, so the limit doesn't count the time spent parsing
+    env.deadline = timeout.map(|x| Instant::now() + x);
+    let res = env.run(&stmts);
+
+    if let Some(path) = record {
+        let session = session::Session {
+            program: file.clone(),
+            seed: seed.unwrap_or(0),
+            args: script_args.clone(),
+            input: session::lines(&recorded.borrow()),
+        };
+        session.save(&path)?;
+    }
+    // Compared however the program ended, since halting or failing early
+    // can be why the output is wrong
+    let mut differs = false;
+    if let Some(expected) = &expected {
+        env.out.flush()?;
+        if let Some(report) = harness::mismatch(expected, &captured.text(), std::io::stderr().is_terminal()) {
+            eprintln!("{}", report.trim_end());
+            differs = true;
+        }
+    }
+
+    if let Some(profile) = hotspots {
+        env.out.flush()?;
+        eprintln!("{}", profile.borrow_mut().table());
+    }
+
+    // After the output, so they don't get mixed in with it
+    if !found.is_empty() {
+        env.out.flush()?;
+        found.iter().for_each(|x| eprintln!("{}\n", x));
+    }
+
+    if let Err(e) = res {
+        match env.halted {
+            Some(code) => {
+                // Exiting skips destructors, which would flush a trace file
+                drop(env.hook.take());
+                env.out.flush()?;
+                std::process::exit(if differs && code == 0 { 1 } else { code });
+            }
+            // A timeout is told apart from other errors by its exit code,
+            // as `timeout` does
+            None => {
+                drop(env.hook.take());
+                env.out.flush()?;
+                eprintln!("{}", report(&e, Some((&file, &source)), &env.trace));
+                std::process::exit(if env.timed_out { 124 } else { 1 });
+            }
+        }
+    }
+
+    env.out.flush()?;
+    if differs {
+        std::process::exit(1);
+    }
     Ok(())
 }
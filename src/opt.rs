@@ -0,0 +1,109 @@
+use crate::env::Env;
+use crate::eval::*;
+use crate::visit::{walk_expr_mut, walk_stmts_mut, VisitorMut};
+use std::mem;
+
+fn is_constant(expr: &Expr) -> bool {
+    matches!(expr, Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::StrLit(_) | Expr::NullLit)
+}
+
+/// The literal for a value, if it can be written as one
+fn literal(value: PscObject) -> Option<Expr> {
+    match value {
+        PscObject::IntT(x) => Some(Expr::IntLit(x)),
+        PscObject::FloatT(x) => Some(Expr::FloatLit(x)),
+        PscObject::BoolT(x) => Some(Expr::BoolLit(x)),
+        PscObject::StringT(x) => Some(Expr::StrLit(x)),
+        PscObject::NullT => Some(Expr::NullLit),
+        _ => None,
+    }
+}
+
+struct Folder {
+    env: Env,
+}
+
+impl VisitorMut for Folder {
+    /// Works out an expression made only of constants the same way it would
+    /// be when the program runs, so the answer is always the same. Anything
+    /// that fails, like dividing by zero, is left for the program to fail on
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+
+        match expr {
+            Expr::BinOp(x) if is_constant(&x.left) && is_constant(&x.right) => {}
+            Expr::BNot(x) if is_constant(x) => {}
+            // Only the side that's picked would ever run
+            Expr::IfElse(x) => {
+                if let Expr::BoolLit(cond) = x.cond {
+                    *expr = mem::replace(if cond { &mut x.then } else { &mut x.otherwise }, Expr::NullLit);
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        if let Some(folded) = Expr::eval(expr, &mut self.env).ok().and_then(literal) {
+            *expr = folded;
+        }
+    }
+}
+
+/// Replaces the parts of the program that come out the same every time it
+/// runs with what they come out as, like `2 * 3 + 1` with `7`. Turned off
+/// with `--no-opt`
+pub fn fold(stmts: &mut [Stmt]) {
+    let mut folder = Folder { env: Env::new() };
+    walk_stmts_mut(&mut folder, stmts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Captured;
+    use crate::lex::Span;
+
+    /// What running `source` outputs, and the error it stops with
+    fn run(source: &str, optimize: bool) -> (String, Option<(String, Option<Span>)>) {
+        let mut stmts = crate::parse(source, &Default::default()).unwrap();
+        if optimize {
+            fold(&mut stmts);
+        }
+        let out = Captured::default();
+        let mut env = Env::new();
+        env.out = Box::new(out.clone());
+        let err = env.run(&stmts).err().map(|e| (e.msg, e.span));
+        (out.text(), err)
+    }
+
+    /// Runs `source` with and without folding, which must make no difference
+    fn same(source: &str) -> (String, Option<(String, Option<Span>)>) {
+        let folded = run(source, true);
+        assert_eq!(folded, run(source, false), "{}", source);
+        folded
+    }
+
+    #[test]
+    fn folding_keeps_the_output() {
+        let source = "output 2 * 3 + 1\noutput \"a\" + \"b\"\noutput 7 div 2\noutput 7 mod 3\noutput 1.5 * 2 - 0.1\noutput 2 < 3 == true\noutput (if 1 > 2 then \"yes\" else \"no\")\noutput 1 << 4";
+        let (out, err) = same(source);
+        assert_eq!(out, "7\nab\n3\n1\n2.9\ntrue\nno\n16\n");
+        assert!(err.is_none());
+    }
+
+    #[test]
+    fn folding_keeps_the_errors() {
+        for source in [
+            "output 1\nX = 9223372036854775807 + 1\noutput X",
+            "output 1\nX = 0 - 9223372036854775807 - 2 * 1",
+            "output 1\noutput 5 / 0",
+            "output 1\noutput 5 div 0",
+            "output 1\noutput 5 mod (2 - 2)",
+            "output 1\noutput 1 + \"a\"",
+        ] {
+            let (out, err) = same(source);
+            assert_eq!(out, "1\n", "{}", source);
+            assert_eq!(err.and_then(|(_, span)| span).map(|x| x.line), Some(2), "{}", source);
+        }
+    }
+}
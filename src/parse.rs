@@ -1,70 +1,335 @@
 use crate::err::ParseError;
 use crate::eval::*;
-use crate::lex::{Keyword, LexerToken, Punctuation};
+use crate::lex::{Keyword, LexerToken, Punctuation, Span, Token};
+use std::cell::OnceCell;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::slice::Iter;
 
-type TokenStream<'a> = std::iter::Peekable<std::slice::Iter<'a, LexerToken>>;
+/// The tokens left to parse, remembering where each one came from
+struct TokenStream<'a> {
+    it: Peekable<Iter<'a, LexerToken>>,
+    spans: &'a [Span],
+    pos: usize,
+    /// The last token peeked at or taken
+    seen: usize,
+    /// Set once anything looks past the last token
+    eof: bool,
+}
+
+impl<'a> TokenStream<'a> {
+    fn peek(&mut self) -> Option<&&'a LexerToken> {
+        let tok = self.it.peek();
+        self.seen = self.pos;
+        self.eof |= tok.is_none();
+        tok
+    }
+
+    fn next(&mut self) -> Option<&'a LexerToken> {
+        let tok = self.it.next();
+        self.seen = self.pos;
+        match tok {
+            Some(_) => self.pos += 1,
+            None => self.eof = true,
+        }
+        tok
+    }
+
+    /// Line of the next token, or of the last one at EOF
+    fn line(&self) -> usize {
+        match self.spans.get(self.pos).or(self.spans.last()) {
+            Some(span) => span.line,
+            None => 1,
+        }
+    }
+
+    /// Where the tokens from `start` up to the last one taken are, as far
+    /// as the end of the first line
+    fn covered(&self, start: usize) -> Span {
+        let first = match self.spans.get(start) {
+            Some(&x) => x,
+            None => return Span { line: self.line(), col: 1, len: 0 },
+        };
+        let spans = self.spans[start..self.pos].iter();
+        let last = spans.take_while(|x| x.line == first.line).last().unwrap_or(&first);
+        Span { len: last.col + last.len - first.col, ..first }
+    }
+
+    /// Points `e` at the last token looked at, where parsing went wrong,
+    /// unless it already knows where it is
+    fn locate(&self, e: ParseError) -> ParseError {
+        let at = self.spans.get(self.seen).or(self.spans.last()).copied();
+        ParseError { span: e.span.or(at), ..e }
+    }
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Stmt>, ParseError> {
+    parse_tokens(tokens).map_err(|(e, _)| e)
+}
+
+/// Like `parse`, but `Ok(None)` when the tokens run out partway through a
+/// statement, e.g. an `if` still waiting for its `end if`
+pub fn parse_partial(tokens: Vec<Token>) -> Result<Option<Vec<Stmt>>, ParseError> {
+    match parse_tokens(tokens) {
+        Ok(x) => Ok(Some(x)),
+        Err((_, true)) => Ok(None),
+        Err((e, false)) => Err(e),
+    }
+}
+
+/// A single expression making up all of `tokens`, as typed into a debugger
+pub fn parse_expression(tokens: Vec<Token>) -> Result<Expr, ParseError> {
+    let (tokens, spans): (Vec<LexerToken>, Vec<Span>) = tokens.into_iter().map(|x| (x.tok, x.span)).unzip();
+
+    let mut it = TokenStream {
+        it: tokens.iter().peekable(),
+        spans: &spans,
+        pos: 0,
+        seen: 0,
+        eof: false,
+    };
+
+    let ret = parse_expr(&mut it).map_err(|e| it.locate(e))?;
+    match it.peek() {
+        None => Ok(ret),
+        Some(_) => Err(ParseError {
+            msg: "Unexpected tokens after the expression".into(),
+            span: None,
+            code: Some("E012"),
+        }),
+    }
+}
+
+/// Like `parse`, but carries on past statements that don't parse to find
+/// every error in the file
+pub fn parse_all(tokens: Vec<Token>) -> Result<Vec<Stmt>, Vec<ParseError>> {
+    let (tokens, spans): (Vec<LexerToken>, Vec<Span>) = tokens.into_iter().map(|x| (x.tok, x.span)).unzip();
+
+    let mut ret: Vec<Stmt> = vec![];
+    let mut errors: Vec<ParseError> = vec![];
+    let mut it = TokenStream {
+        it: tokens.iter().peekable(),
+        spans: &spans,
+        pos: 0,
+        seen: 0,
+        eof: false,
+    };
+
+    while it.peek().is_some() {
+        match parse_stmt(&mut it) {
+            Ok(stmt) => ret.push(stmt),
+            Err(e) => {
+                let line = it.line();
+                errors.push(it.locate(e));
+                recover(&mut it, line);
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(ret),
+        false => Err(errors),
+    }
+}
+
+/// Whether a statement can begin with `tok`
+fn starts_stmt(tok: &LexerToken) -> bool {
+    use Keyword::*;
+
+    match tok {
+        LexerToken::Identifier(_) | LexerToken::Doc(_) => true,
+        LexerToken::Keyword(x) => matches!(
+            x,
+            Loop | While | If | Input | Output | Procedure | Function | Return | Class | Include | Global | Attempt
+                | Assert | Halt | For | Repeat | Declare | Type | Call | Set | Send | Receive
+        ),
+        _ => false,
+    }
+}
+
+/// Skips the rest of a statement that didn't parse, up to a token on a
+/// later line than `line` that can start the next one. The `end` lines of
+/// blocks it was inside are skipped too, rather than being taken for
+/// statements of their own
+fn recover(tokens: &mut TokenStream, line: usize) {
+    while let Some(&tok) = tokens.peek() {
+        if tokens.line() > line && starts_stmt(tok) {
+            break;
+        }
+        let closer = match tokens.next() {
+            Some(LexerToken::Keyword(Keyword::End)) => true,
+            Some(LexerToken::Keyword(Keyword::Else)) => false,
+            _ => continue,
+        };
+        // `end loop` and the like, or the `if` of `else if`
+        match tokens.peek() {
+            Some(LexerToken::Keyword(Keyword::If)) => {
+                tokens.next();
+            }
+            Some(LexerToken::Keyword(_)) if closer => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// On failure also says whether the parser had run out of tokens
+fn parse_tokens(tokens: Vec<Token>) -> Result<Vec<Stmt>, (ParseError, bool)> {
+    let (tokens, spans): (Vec<LexerToken>, Vec<Span>) = tokens.into_iter().map(|x| (x.tok, x.span)).unzip();
 
-pub fn parse(tokens: Vec<LexerToken>) -> Result<Vec<Stmt>, ParseError> {
     let mut ret: Vec<Stmt> = vec![];
-    let mut it = tokens.iter().peekable();
+    let mut it = TokenStream {
+        it: tokens.iter().peekable(),
+        spans: &spans,
+        pos: 0,
+        seen: 0,
+        eof: false,
+    };
+
+    while it.peek().is_some() {
+        it.eof = false;
 
-    while let Some(_) = it.peek() {
-        ret.push(parse_stmt(&mut it)?);
+        match parse_stmt(&mut it) {
+            Ok(stmt) => ret.push(stmt),
+            Err(e) => return Err((it.locate(e), it.eof)),
+        }
     }
 
     Ok(ret)
 }
 
+/// A statement along with any `;` separators after it, so `X = 1; Y = 2`
+/// can share a line
 fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
+    // The statement starts at the definition a doc comment belongs to
+    let doc = parse_doc(tokens);
+    let start = tokens.pos;
+
+    let kind = match doc {
+        Some(doc) => match tokens.peek() {
+            Some(&LexerToken::Keyword(Keyword::Procedure)) | Some(&LexerToken::Keyword(Keyword::Function)) => {
+                StmtKind::Function(Rc::new(parse_function(tokens, Some(doc))?))
+            }
+            _ => return Err(ParseError { msg: DOC_MISPLACED.into(), span: None, code: Some("E013") }),
+        },
+        None => parse_single_stmt(tokens)?,
+    };
+    let stmt = Stmt { kind, span: tokens.covered(start) };
+
+    while tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Semicolon)) {
+        tokens.next();
+    }
+
+    Ok(stmt)
+}
+
+fn parse_single_stmt(tokens: &mut TokenStream) -> Result<StmtKind, ParseError> {
     match tokens.peek() {
-        Some(&LexerToken::Identifier(ident)) => {
-            tokens.next();
+        Some(&LexerToken::Identifier(_)) => {
+            let target = parse_atom(tokens)?;
+
+            if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Comma)) {
+                let mut targets = vec![target];
+                while tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Comma)) {
+                    tokens.next();
+                    targets.push(parse_atom(tokens)?);
+                }
 
-            if tokens.next() == Some(&LexerToken::Punctuation(Punctuation::Assign)) {
-                let left = parse_atom(tokens)?;
+                if targets.iter().any(|x| !is_place(x)) {
+                    return Err(ParseError {
+                        msg: "Invalid assignment target".into(),
+                        span: None,
+                        code: Some("E019"),
+                    });
+                }
 
-                return Ok(Stmt::Assign(Assign {
-                    ident: ident.to_string(),
-                    expr: parse_bin_op(tokens, left, 0)?,
-                }));
+                expect(tokens, Punctuation::Assign, "Expected '=' after assignment targets")?;
+
+                let mut exprs = vec![parse_expr(tokens)?];
+                while tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Comma)) {
+                    tokens.next();
+                    exprs.push(parse_expr(tokens)?);
+                }
+
+                if exprs.len() != targets.len() {
+                    return Err(ParseError {
+                        msg: format!("Cannot assign {} value(s) to {} target(s)", exprs.len(), targets.len()),
+                        span: None,
+                        code: Some("E019"),
+                    });
+                }
+
+                return Ok(StmtKind::MultiAssign(MultiAssign { targets, exprs }));
+            }
+
+            if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Assign)) {
+                tokens.next();
+
+                if !is_place(&target) {
+                    return Err(ParseError {
+                        msg: "Invalid assignment target".into(),
+                        span: None,
+                        code: Some("E019"),
+                    });
+                }
+
+                Ok(StmtKind::Assign(Assign {
+                    target,
+                    expr: parse_expr(tokens)?,
+                    declared: None,
+                    literal: OnceCell::new(),
+                }))
+            } else if let Expr::MethodCall(_) | Expr::Call(_) = target {
+                Ok(StmtKind::Expr(target))
+            } else {
+                Err(ParseError {
+                    msg: "Failed to parse assign stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                })
             }
         }
 
         Some(&LexerToken::Keyword(Keyword::Input)) => {
             tokens.next();
 
+            let kind = parse_input_type(tokens);
+
             if let Some(&LexerToken::Identifier(ident)) = tokens.peek() {
                 tokens.next();
 
-                return Ok(Stmt::Input(Input {
+                Ok(StmtKind::Input(Input {
                     ident: ident.to_string(),
-                }));
+                    kind,
+                }))
             } else {
-                return Err(ParseError {
+                Err(ParseError {
                     msg: "Failed to parse input stmt".into(),
-                });
+                    span: None,
+                    code: Some("E012"),
+                })
             }
         }
 
         Some(&LexerToken::Keyword(Keyword::Output)) => {
             tokens.next();
 
-            let left = parse_atom(tokens)?;
-
-            return Ok(Stmt::Output(Output {
-                expr: parse_bin_op(tokens, left, 0)?,
-            }));
+            Ok(StmtKind::Output(Output {
+                expr: parse_expr(tokens)?,
+            }))
         }
 
         Some(&LexerToken::Keyword(Keyword::If)) => {
             tokens.next();
 
-            let left = parse_atom(tokens)?;
-            let mut branches: Vec<(Expr, Vec<Stmt>)> = vec![(parse_bin_op(tokens, left, 0)?, Vec::new())];
+            let mut branches: Vec<(Expr, Vec<Stmt>)> = vec![(parse_expr(tokens)?, Vec::new())];
 
             if tokens.next() != Some(&LexerToken::Keyword(Keyword::Then)) {
                 return Err(ParseError {
                     msg: "Failed to parse if stmt".into(),
+                    span: None,
+                    code: Some("E012"),
                 });
             }
 
@@ -76,9 +341,7 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
                         let cond = {
                             if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::If)) {
                                 tokens.next();
-
-                                let left = parse_atom(tokens)?;
-                                parse_bin_op(tokens, left, 0)?
+                                parse_expr(tokens)?
                             } else {
                                 Expr::BoolLit(true)
                             }
@@ -89,13 +352,15 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
                     Some(&LexerToken::Keyword(Keyword::End)) => {
                         tokens.next();
 
-                        if tokens.next() != Some(&&LexerToken::Keyword(Keyword::If)) {
+                        if tokens.next() != Some(&LexerToken::Keyword(Keyword::If)) {
                             return Err(ParseError {
                                 msg: "Failed to parse if stmt".into(),
+                                span: None,
+                                code: Some("E012"),
                             });
                         }
 
-                        return Ok(Stmt::If(If {
+                        return Ok(StmtKind::If(If {
                             branches
                         }));
                     }
@@ -113,8 +378,7 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
 
             match tokens.next() {
                 Some(LexerToken::Keyword(Keyword::While)) => {
-                    let left = parse_atom(tokens)?;
-                    let cond = parse_bin_op(tokens, left, 0)?;
+                    let cond = parse_expr(tokens)?;
                     let mut stmts: Vec<Stmt> = Vec::new();
 
                     while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
@@ -122,21 +386,22 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
                     }
                     tokens.next();
 
-                    if tokens.next() != Some(&&LexerToken::Keyword(Keyword::Loop)) {
+                    if tokens.next() != Some(&LexerToken::Keyword(Keyword::Loop)) {
                         return Err(ParseError {
                             msg: "Failed to parse while stmt".into(),
+                            span: None,
+                            code: Some("E012"),
                         });
                     }
 
-                    return Ok(Stmt::While(While {
+                    Ok(StmtKind::While(While {
                         cond,
                         stmts,
-                    }));
+                    }))
                 }
 
                 Some(LexerToken::Keyword(Keyword::Until)) => {
-                    let left = parse_atom(tokens)?;
-                    let cond = parse_bin_op(tokens, left, 0)?;
+                    let cond = parse_expr(tokens)?;
                     let mut stmts: Vec<Stmt> = Vec::new();
 
                     while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
@@ -144,40 +409,68 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
                     }
                     tokens.next();
 
-                    if tokens.next() != Some(&&LexerToken::Keyword(Keyword::Loop)) {
+                    if tokens.next() != Some(&LexerToken::Keyword(Keyword::Loop)) {
                         return Err(ParseError {
                             msg: "Failed to parse while stmt".into(),
+                            span: None,
+                            code: Some("E012"),
                         });
                     }
 
-                    return Ok(Stmt::Until(Until {
+                    Ok(StmtKind::Until(Until {
                         cond,
                         stmts,
-                    }));
+                    }))
+                }
+
+                Some(LexerToken::Identifier(name))
+                    if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Over)) =>
+                {
+                    tokens.next();
+
+                    let iter = parse_expr(tokens)?;
+
+                    let mut stmts: Vec<Stmt> = Vec::new();
+                    while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
+                        stmts.push(parse_stmt(tokens)?);
+                    }
+                    tokens.next();
+
+                    if tokens.next() != Some(&LexerToken::Keyword(Keyword::Loop)) {
+                        return Err(ParseError {
+                            msg: "Failed to parse over stmt".into(),
+                            span: None,
+                            code: Some("E012"),
+                        });
+                    }
+
+                    Ok(StmtKind::ForEach(ForEach {
+                        name: name.clone(),
+                        iter,
+                        stmts,
+                    }))
                 }
 
                 Some(LexerToken::Identifier(name)) => {
                     if tokens.next() != Some(&LexerToken::Keyword(Keyword::From)) {
                         return Err(ParseError {
                             msg: "Failed to parse 'from' in for stmt".into(),
+                            span: None,
+                            code: Some("E012"),
                         });
                     }
 
-                    let start = {
-                        let left = parse_atom(tokens)?;
-                        parse_bin_op(tokens, left, 0)?
-                    };
+                    let start = parse_expr(tokens)?;
 
                     if tokens.next() != Some(&LexerToken::Keyword(Keyword::To)) {
                         return Err(ParseError {
                             msg: "Failed to parse 'to' in for stmt".into(),
+                            span: None,
+                            code: Some("E012"),
                         });
                     }
 
-                    let end = {
-                        let left = parse_atom(tokens)?;
-                        parse_bin_op(tokens, left, 0)?
-                    };
+                    let end = parse_expr(tokens)?;
 
                     let mut stmts: Vec<Stmt> = Vec::new();
                     while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
@@ -185,91 +478,1201 @@ fn parse_stmt(tokens: &mut TokenStream) -> Result<Stmt, ParseError> {
                     }
                     tokens.next();
 
-                    if tokens.next() != Some(&&LexerToken::Keyword(Keyword::Loop)) {
+                    if tokens.next() != Some(&LexerToken::Keyword(Keyword::Loop)) {
                         return Err(ParseError {
                             msg: "Failed to parse stmt".into(),
+                            span: None,
+                            code: Some("E012"),
                         });
                     }
 
-                    return Ok(Stmt::For(For {
+                    Ok(StmtKind::For(For {
                         name: name.clone(),
                         start,
                         end,
                         stmts,
-                    }));
+                    }))
                 }
-                _ => todo!()
+                _ => Err(ParseError {
+                    msg: "Failed to parse loop stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                }),
             }
         }
 
-        _ => {
-            return Err(ParseError {
-                msg: "Failed to parse stmt".into(),
-            });
+        // The statements below only lex in dialects other than IB
+
+        // `FOR I ← 1 TO 10` and `FOR I FROM 1 TO 10 DO`
+        Some(&LexerToken::Keyword(Keyword::For)) => {
+            tokens.next();
+
+            let name = match tokens.next() {
+                Some(LexerToken::Identifier(name)) => name.clone(),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected loop variable after 'for'".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            };
+
+            match tokens.next() {
+                Some(LexerToken::Punctuation(Punctuation::Assign)) | Some(LexerToken::Keyword(Keyword::From)) => {}
+                _ => {
+                    return Err(ParseError {
+                        msg: "Failed to parse start of for stmt".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            }
+
+            let start = parse_expr(tokens)?;
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::To)) {
+                return Err(ParseError {
+                    msg: "Failed to parse 'to' in for stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            let end = parse_expr(tokens)?;
+
+            if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Do)) {
+                tokens.next();
+            }
+
+            let stmts = parse_block(tokens, &[Keyword::Loop, Keyword::For], "Failed to parse end of for stmt")?;
+
+            Ok(StmtKind::For(For { name, start, end, stmts }))
         }
-    };
 
-    unreachable!();
-}
+        // `WHILE C DO`
+        Some(&LexerToken::Keyword(Keyword::While)) => {
+            tokens.next();
 
-fn parse_atom(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
-    match tokens.peek() {
-        Some(&LexerToken::IntLit(x)) => {
+            let cond = parse_expr(tokens)?;
+
+            if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Do)) {
+                tokens.next();
+            }
+
+            let stmts = parse_block(tokens, &[Keyword::Loop, Keyword::While], "Failed to parse end of while stmt")?;
+
+            Ok(StmtKind::While(While { cond, stmts }))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Repeat)) => {
+            tokens.next();
+
+            let mut stmts: Vec<Stmt> = Vec::new();
+            while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::Until)) {
+                if tokens.peek().is_none() {
+                    return Err(ParseError {
+                        msg: "Expected 'until' after repeat".into(),
+                        span: None,
+                        code: Some("E012"),
+                    });
+                }
+                stmts.push(parse_stmt(tokens)?);
+            }
             tokens.next();
-            return Ok(Expr::IntLit(*x));
+
+            Ok(StmtKind::Repeat(Until {
+                cond: parse_expr(tokens)?,
+                stmts,
+            }))
         }
 
-        Some(&LexerToken::FloatLit(x)) => {
+        Some(&LexerToken::Keyword(Keyword::Declare)) => {
             tokens.next();
-            return Ok(Expr::FloatLit(*x));
+
+            let name = match tokens.next() {
+                Some(LexerToken::Identifier(name)) => name.clone(),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected variable name after 'declare'".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            };
+            expect(tokens, Punctuation::Colon, "Expected ':' and a type after the declared name")?;
+            let ty = parse_type(tokens)?;
+
+            Ok(StmtKind::Assign(Assign {
+                target: Expr::Ident(name),
+                expr: ty.item(),
+                declared: Some(ty),
+                literal: OnceCell::new(),
+            }))
         }
 
-        Some(&LexerToken::BoolLit(x)) => {
+        // `TYPE NAME = TYPE`
+        Some(&LexerToken::Keyword(Keyword::Type)) => {
             tokens.next();
-            return Ok(Expr::BoolLit(*x));
+
+            let name = match tokens.next() {
+                Some(LexerToken::Identifier(name)) => name.clone(),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected the name of a type after 'type'".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            };
+            expect(tokens, Punctuation::Equals, "Expected '=' and a type after the type's name")?;
+
+            Ok(StmtKind::Type(TypeDef { name, ty: parse_type(tokens)? }))
         }
 
-        Some(&LexerToken::StrLit(x)) => {
+        Some(&LexerToken::Keyword(Keyword::Call)) => {
             tokens.next();
-            return Ok(Expr::StrLit(x.to_string()));
+
+            match parse_atom(tokens)? {
+                call @ (Expr::Call(_) | Expr::MethodCall(_)) => Ok(StmtKind::Expr(call)),
+                _ => Err(ParseError {
+                    msg: "Expected a procedure call after 'call'".into(),
+                    span: None,
+                    code: Some("E012"),
+                }),
+            }
         }
 
-        Some(&LexerToken::Identifier(ident)) => {
+        // `SET X TO EXPR`
+        Some(&LexerToken::Keyword(Keyword::Set)) => {
             tokens.next();
-            return Ok(Expr::Ident(ident.to_string()));
+
+            let target = parse_atom(tokens)?;
+            if !is_place(&target) {
+                return Err(ParseError {
+                    msg: "Invalid assignment target".into(),
+                    span: None,
+                    code: Some("E019"),
+                });
+            }
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::To)) {
+                return Err(ParseError {
+                    msg: "Expected 'to' in set stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            Ok(StmtKind::Assign(Assign {
+                target,
+                expr: parse_expr(tokens)?,
+                declared: None,
+                literal: OnceCell::new(),
+            }))
         }
 
-        _ => {
-            return Err(ParseError {
-                msg: "Failed to parse atom".into(),
-            });
+        // `SEND EXPR TO DISPLAY`
+        Some(&LexerToken::Keyword(Keyword::Send)) => {
+            tokens.next();
+
+            let expr = parse_expr(tokens)?;
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::To))
+                || tokens.next() != Some(&LexerToken::Keyword(Keyword::Display))
+            {
+                return Err(ParseError {
+                    msg: "Expected 'to display' in send stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            Ok(StmtKind::Output(Output { expr }))
         }
-    }
-}
 
-fn parse_bin_op(tokens: &mut TokenStream, left: Expr, precedence: u32) -> Result<Expr, ParseError> {
-    match tokens.peek() {
-        Some(&LexerToken::Punctuation(op)) if op != &Punctuation::Assign => {
-            let new_precedence = op.precedence();
+        // `RECEIVE X FROM (TYPE) KEYBOARD`
+        Some(&LexerToken::Keyword(Keyword::Receive)) => {
+            tokens.next();
+
+            let ident = match tokens.next() {
+                Some(LexerToken::Identifier(ident)) => ident.clone(),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected variable name after 'receive'".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            };
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::From)) {
+                return Err(ParseError {
+                    msg: "Expected 'from' in receive stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
 
-            if new_precedence >= precedence {
+            let mut kind = None;
+            if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::LParen)) {
                 tokens.next();
-                let next_atom = parse_atom(tokens)?;
+                kind = parse_input_type(tokens);
+                expect(tokens, Punctuation::RParen, "Expected ')' after input type")?;
+            }
 
-                let ret = Expr::BinOp(Box::new(BinOp {
-                    left,
-                    right: parse_bin_op(tokens, next_atom, new_precedence)?,
-                    op: op.clone(),
-                }));
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::Keyboard)) {
+                return Err(ParseError {
+                    msg: "Expected 'keyboard' in receive stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            Ok(StmtKind::Input(Input { ident, kind }))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Procedure)) | Some(&LexerToken::Keyword(Keyword::Function)) => {
+            Ok(StmtKind::Function(Rc::new(parse_function(tokens, None)?)))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Class)) => {
+            tokens.next();
+            Ok(StmtKind::Class(Rc::new(parse_class(tokens)?)))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Super)) => Ok(StmtKind::Expr(parse_atom(tokens)?)),
+
+        Some(&LexerToken::Keyword(Keyword::Attempt)) => {
+            tokens.next();
+
+            let mut stmts: Vec<Stmt> = Vec::new();
+            while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::On)) {
+                stmts.push(parse_stmt(tokens)?);
+            }
+            tokens.next();
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::Error)) {
+                return Err(ParseError {
+                    msg: "Expected 'on error' in attempt stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            let name = match tokens.peek() {
+                Some(&LexerToken::Identifier(name)) => {
+                    tokens.next();
+                    Some(name.clone())
+                }
+                _ => None,
+            };
+
+            let mut handler: Vec<Stmt> = Vec::new();
+            while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
+                handler.push(parse_stmt(tokens)?);
+            }
+            tokens.next();
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::Attempt)) {
+                return Err(ParseError {
+                    msg: "Failed to parse attempt stmt".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+
+            Ok(StmtKind::Attempt(Attempt { stmts, name, handler }))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Assert)) => {
+            tokens.next();
+
+            let cond = parse_expr(tokens)?;
+            let msg = match tokens.peek() {
+                Some(&LexerToken::Punctuation(Punctuation::Comma)) => {
+                    tokens.next();
+                    Some(parse_expr(tokens)?)
+                }
+                _ => None,
+            };
+
+            Ok(StmtKind::Assert(Assert { cond, msg }))
+        }
 
-                return Ok(parse_bin_op(tokens, ret, precedence)?);
+        // The exit code has to be on the same line, `halt` alone exits with 0
+        Some(&LexerToken::Keyword(Keyword::Halt)) => {
+            let line = tokens.line();
+            tokens.next();
+
+            let ends = matches!(tokens.peek(), None | Some(&LexerToken::Punctuation(Punctuation::Semicolon)));
+            if !ends && tokens.line() == line {
+                Ok(StmtKind::Halt(Some(parse_expr(tokens)?)))
             } else {
-                return Ok(left);
+                Ok(StmtKind::Halt(None))
             }
         }
 
-        _ => {
-            return Ok(left);
+        Some(&LexerToken::Keyword(Keyword::Global)) => {
+            tokens.next();
+
+            let mut names: Vec<String> = Vec::new();
+            loop {
+                match tokens.next() {
+                    Some(LexerToken::Identifier(name)) => names.push(name.clone()),
+                    _ => {
+                        return Err(ParseError {
+                            msg: "Expected variable name after 'global'".into(),
+                            span: None,
+                            code: Some("E012"),
+                        })
+                    }
+                }
+
+                if tokens.peek() != Some(&&LexerToken::Punctuation(Punctuation::Comma)) {
+                    return Ok(StmtKind::Global(names));
+                }
+                tokens.next();
+            }
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Include)) => {
+            tokens.next();
+
+            match tokens.next() {
+                Some(LexerToken::StrLit(file)) => Ok(StmtKind::Include(file.clone())),
+                _ => Err(ParseError {
+                    msg: "Expected file name after 'include'".into(),
+                    span: None,
+                    code: Some("E012"),
+                }),
+            }
         }
+
+        Some(&LexerToken::Keyword(Keyword::Return)) => {
+            tokens.next();
+
+            match tokens.peek() {
+                Some(&LexerToken::Keyword(Keyword::End)) | Some(&LexerToken::Punctuation(Punctuation::Semicolon)) | None => {
+                    Ok(StmtKind::Return(None))
+                }
+                _ => Ok(StmtKind::Return(Some(parse_expr(tokens)?))),
+            }
+        }
+
+        None => Err(ParseError {
+            msg: "Unexpected end of file, a block is missing its end line".into(),
+            span: None,
+            code: Some("E011"),
+        }),
+        _ => Err(ParseError {
+            msg: "Failed to parse stmt".into(),
+            span: None,
+            code: Some("E012"),
+        }),
+    }
+}
+
+/// Skips an optional `: TYPE` or `RETURNS TYPE` annotation, types aren't
+/// checked
+fn skip_type(tokens: &mut TokenStream) -> Result<(), ParseError> {
+    if let Some(&LexerToken::Punctuation(Punctuation::Colon)) | Some(&LexerToken::Keyword(Keyword::Returns)) = tokens.peek() {
+        tokens.next();
+        parse_type(tokens)?;
+    }
+    Ok(())
+}
+
+/// A type like `INTEGER`, `^INTEGER`, `ARRAY[1:10] OF REAL` or
+/// `ARRAY[1:3, 1:3] OF CHAR`, or an array of any length with no bounds
+fn parse_type(tokens: &mut TokenStream) -> Result<Declared, ParseError> {
+    if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Caret)) {
+        tokens.next();
+        return match parse_type(tokens)? {
+            Declared { name, bounds } if bounds.is_empty() => Ok(Declared { name: format!("^{}", name), bounds }),
+            _ => Err(ParseError {
+                msg: "Expected the type a pointer points to after '^'".into(),
+                span: None,
+                code: Some("E012"),
+            }),
+        };
+    }
+
+    let mut bounds = Vec::new();
+    if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Array)) {
+        tokens.next();
+
+        if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::LBracket)) {
+            tokens.next();
+            loop {
+                let lower = parse_bound(tokens)?;
+                expect(tokens, Punctuation::Colon, "Expected ':' between the bounds of an array")?;
+                let upper = parse_bound(tokens)?;
+                if upper < lower {
+                    return Err(ParseError {
+                        msg: format!("The array bounds {}:{} are the wrong way round", lower, upper),
+                        span: None,
+                        code: Some("E015"),
+                    });
+                }
+                bounds.push((lower, upper));
+
+                match tokens.next() {
+                    Some(LexerToken::Punctuation(Punctuation::Comma)) => {}
+                    Some(LexerToken::Punctuation(Punctuation::RBracket)) => break,
+                    _ => {
+                        return Err(ParseError {
+                            msg: "Failed to parse array bounds".into(),
+                            span: None,
+                            code: Some("E012"),
+                        })
+                    }
+                }
+            }
+        }
+
+        if tokens.next() != Some(&LexerToken::Keyword(Keyword::Of)) {
+            return Err(ParseError {
+                msg: "Expected 'of' and the type of the items after 'array'".into(),
+                span: None,
+                code: Some("E012"),
+            });
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(LexerToken::Keyword(Keyword::Integer)) => "INTEGER".to_string(),
+        Some(LexerToken::Keyword(Keyword::Real)) => "REAL".to_string(),
+        Some(LexerToken::Keyword(Keyword::String)) => "STRING".to_string(),
+        Some(LexerToken::Keyword(Keyword::Boolean)) => "BOOLEAN".to_string(),
+        Some(LexerToken::Identifier(name)) => name.clone(),
+        _ => {
+            return Err(ParseError {
+                msg: "Expected a type".into(),
+                span: None,
+                code: Some("E012"),
+            })
+        }
+    };
+    Ok(Declared { name, bounds })
+}
+
+/// An integer, maybe negative, as the bound of an array
+fn parse_bound(tokens: &mut TokenStream) -> Result<i64, ParseError> {
+    let negative = tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Minus));
+    if negative {
+        tokens.next();
+    }
+    match tokens.next() {
+        Some(LexerToken::IntLit(x)) if negative => Ok(-x),
+        Some(LexerToken::IntLit(x)) => Ok(*x),
+        _ => Err(ParseError {
+            msg: "Expected an integer as the bound of an array".into(),
+            span: None,
+            code: Some("E012"),
+        }),
+    }
+}
+
+/// Statements up to `end X`, where `X` is one of `closers`
+fn parse_block(tokens: &mut TokenStream, closers: &[Keyword], msg: &str) -> Result<Vec<Stmt>, ParseError> {
+    let mut stmts: Vec<Stmt> = Vec::new();
+    while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
+        if tokens.peek().is_none() {
+            return Err(ParseError { msg: msg.into(), span: None, code: Some("E011") });
+        }
+        stmts.push(parse_stmt(tokens)?);
+    }
+    tokens.next();
+
+    match tokens.next() {
+        Some(LexerToken::Keyword(x)) if closers.contains(x) => Ok(stmts),
+        _ => Err(ParseError { msg: msg.into(), span: None, code: Some("E011") }),
+    }
+}
+
+fn parse_input_type(tokens: &mut TokenStream) -> Option<InputType> {
+    let kind = match tokens.peek() {
+        Some(&LexerToken::Keyword(Keyword::Integer)) => Some(InputType::Integer),
+        Some(&LexerToken::Keyword(Keyword::Real)) => Some(InputType::Real),
+        Some(&LexerToken::Keyword(Keyword::String)) => Some(InputType::String),
+        Some(&LexerToken::Keyword(Keyword::Boolean)) => Some(InputType::Boolean),
+        _ => None,
+    };
+    if kind.is_some() {
+        tokens.next();
+    }
+
+    kind
+}
+
+const DOC_MISPLACED: &str = "Expected a procedure or function after a /// comment";
+
+/// The lines of the `///` comments next, if there are any
+fn parse_doc(tokens: &mut TokenStream) -> Option<String> {
+    let mut lines: Vec<String> = Vec::new();
+    while let Some(&LexerToken::Doc(line)) = tokens.peek() {
+        lines.push(line.clone());
+        tokens.next();
+    }
+
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+fn parse_function(tokens: &mut TokenStream, doc: Option<String>) -> Result<Function, ParseError> {
+    let kind = match tokens.next() {
+        Some(LexerToken::Keyword(kind)) => kind.clone(),
+        _ => unreachable!(),
+    };
+
+    let name = match tokens.next() {
+        Some(LexerToken::Identifier(name)) => name.clone(),
+        _ => {
+            return Err(ParseError {
+                msg: "Expected procedure name".into(),
+                span: None,
+                code: Some("E012"),
+            })
+        }
+    };
+
+    expect(tokens, Punctuation::LParen, "Expected '(' after procedure name")?;
+
+    let mut params: Vec<String> = Vec::new();
+    if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::RParen)) {
+        tokens.next();
+    } else {
+        loop {
+            match tokens.next() {
+                Some(LexerToken::Identifier(param)) => params.push(param.clone()),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected parameter name".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            }
+            skip_type(tokens)?;
+
+            match tokens.next() {
+                Some(LexerToken::Punctuation(Punctuation::Comma)) => {}
+                Some(LexerToken::Punctuation(Punctuation::RParen)) => break,
+                _ => {
+                    return Err(ParseError {
+                        msg: "Failed to parse parameter list".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            }
+        }
+    }
+    skip_type(tokens)?;
+
+    // Edexcel's `BEGIN PROCEDURE`
+    if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Begin)) {
+        tokens.next();
+        tokens.next();
+    }
+
+    let mut stmts: Vec<Stmt> = Vec::new();
+    while tokens.peek() != Some(&&LexerToken::Keyword(Keyword::End)) {
+        stmts.push(parse_stmt(tokens)?);
+    }
+    tokens.next();
+
+    if tokens.next() != Some(&LexerToken::Keyword(kind)) {
+        return Err(ParseError {
+            msg: format!("Failed to parse end of {}", name),
+            span: None,
+            code: Some("E011"),
+        });
+    }
+
+    Ok(Function { name, params, stmts, doc })
+}
+
+fn parse_class(tokens: &mut TokenStream) -> Result<Class, ParseError> {
+    let name = match tokens.next() {
+        Some(LexerToken::Identifier(name)) => name.clone(),
+        _ => {
+            return Err(ParseError {
+                msg: "Expected class name".into(),
+                span: None,
+                code: Some("E012"),
+            })
+        }
+    };
+
+    let parent = if tokens.peek() == Some(&&LexerToken::Keyword(Keyword::Inherits)) {
+        tokens.next();
+
+        match tokens.next() {
+            Some(LexerToken::Identifier(parent)) => Some(parent.clone()),
+            _ => {
+                return Err(ParseError {
+                    msg: "Expected class name after 'inherits'".into(),
+                    span: None,
+                    code: Some("E012"),
+                })
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut fields: Vec<FieldDecl> = Vec::new();
+    let mut methods: Vec<Method> = Vec::new();
+
+    loop {
+        let doc = parse_doc(tokens);
+        let public = match tokens.peek() {
+            Some(&LexerToken::Keyword(Keyword::Public)) => {
+                tokens.next();
+                true
+            }
+            Some(&LexerToken::Keyword(Keyword::Private)) => {
+                tokens.next();
+                false
+            }
+            _ => true,
+        };
+
+        match tokens.peek() {
+            Some(&LexerToken::Keyword(Keyword::Procedure)) | Some(&LexerToken::Keyword(Keyword::Function)) => {
+                methods.push(Method {
+                    public,
+                    func: Rc::new(parse_function(tokens, doc)?),
+                });
+            }
+            _ if doc.is_some() => return Err(ParseError { msg: DOC_MISPLACED.into(), span: None, code: Some("E013") }),
+
+            Some(&LexerToken::Identifier(field)) => {
+                tokens.next();
+                skip_type(tokens)?;
+
+                let init = if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::Assign)) {
+                    tokens.next();
+                    Some(parse_expr(tokens)?)
+                } else {
+                    None
+                };
+
+                fields.push(FieldDecl {
+                    name: field.clone(),
+                    public,
+                    init,
+                });
+            }
+
+            Some(&LexerToken::Keyword(Keyword::End)) => {
+                tokens.next();
+
+                if tokens.next() != Some(&LexerToken::Keyword(Keyword::Class)) {
+                    return Err(ParseError {
+                        msg: "Failed to parse class".into(),
+                        span: None,
+                        code: Some("E012"),
+                    });
+                }
+
+                return Ok(Class {
+                    name,
+                    parent,
+                    fields,
+                    methods,
+                });
+            }
+
+            _ => {
+                return Err(ParseError {
+                    msg: format!("Failed to parse body of class {}", name),
+                    span: None,
+                    code: Some("E012"),
+                })
+            }
+        }
+    }
+}
+
+/// Whether an expression can appear on the left of `=`
+fn is_place(expr: &Expr) -> bool {
+    matches!(expr, Expr::Ident(_) | Expr::Index(_) | Expr::Field(_) | Expr::Deref(_))
+}
+
+fn parse_expr(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    let left = parse_atom(tokens)?;
+    parse_bin_op(tokens, left, 0)
+}
+
+fn expect(tokens: &mut TokenStream, punct: Punctuation, msg: &str) -> Result<(), ParseError> {
+    if tokens.next() == Some(&LexerToken::Punctuation(punct)) {
+        Ok(())
+    } else {
+        Err(ParseError { msg: msg.into(), span: None, code: Some("E012") })
+    }
+}
+
+/// Parses a comma separated list of expressions up to and including `close`
+fn parse_expr_list(tokens: &mut TokenStream, close: Punctuation) -> Result<Vec<Expr>, ParseError> {
+    let mut ret: Vec<Expr> = Vec::new();
+
+    if tokens.peek() == Some(&&LexerToken::Punctuation(close.clone())) {
+        tokens.next();
+        return Ok(ret);
+    }
+
+    loop {
+        ret.push(parse_expr(tokens)?);
+
+        match tokens.next() {
+            Some(LexerToken::Punctuation(Punctuation::Comma)) => {}
+            Some(LexerToken::Punctuation(p)) if *p == close => return Ok(ret),
+            _ => {
+                return Err(ParseError {
+                    msg: "Failed to parse expression list".into(),
+                    span: None,
+                    code: Some("E012"),
+                })
+            }
+        }
+    }
+}
+
+fn parse_atom(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    let atom = parse_primary(tokens)?;
+    parse_postfix(tokens, atom)
+}
+
+fn parse_postfix(tokens: &mut TokenStream, mut expr: Expr) -> Result<Expr, ParseError> {
+    loop {
+        match tokens.peek() {
+            Some(&LexerToken::Punctuation(Punctuation::LBracket)) => {
+                tokens.next();
+                let index = parse_expr(tokens)?;
+                expect(tokens, Punctuation::RBracket, "Expected ']' after index")?;
+
+                expr = Expr::Index(Box::new(Index {
+                    target: expr,
+                    index,
+                }));
+            }
+
+            Some(&LexerToken::Punctuation(Punctuation::Dot)) => {
+                tokens.next();
+                let start = tokens.pos;
+
+                let name = match tokens.next() {
+                    Some(LexerToken::Identifier(name)) => name.clone(),
+                    _ => {
+                        return Err(ParseError {
+                            msg: "Expected method name after '.'".into(),
+                            span: None,
+                            code: Some("E012"),
+                        })
+                    }
+                };
+
+                if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::LParen)) {
+                    tokens.next();
+                    let args = parse_expr_list(tokens, Punctuation::RParen)?;
+
+                    expr = Expr::MethodCall(Box::new(MethodCall {
+                        target: expr,
+                        name,
+                        args,
+                        span: tokens.covered(start),
+                    }));
+                } else {
+                    expr = Expr::Field(Box::new(Field { target: expr, name }));
+                }
+            }
+
+            Some(&LexerToken::Punctuation(Punctuation::Caret)) => {
+                tokens.next();
+                expr = Expr::Deref(Box::new(expr));
+            }
+
+            _ => return Ok(expr),
+        }
+    }
+}
+
+fn parse_primary(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    let start = tokens.pos;
+
+    match tokens.peek() {
+        Some(&LexerToken::IntLit(x)) => {
+            tokens.next();
+            Ok(Expr::IntLit(*x))
+        }
+
+        Some(&LexerToken::FloatLit(x)) => {
+            tokens.next();
+            Ok(Expr::FloatLit(*x))
+        }
+
+        Some(&LexerToken::BoolLit(x)) => {
+            tokens.next();
+            Ok(Expr::BoolLit(*x))
+        }
+
+        Some(&LexerToken::StrLit(x)) => {
+            tokens.next();
+            Ok(Expr::StrLit(x.to_string()))
+        }
+
+        Some(&LexerToken::Identifier(ident)) => {
+            tokens.next();
+
+            if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::LParen)) {
+                tokens.next();
+
+                let args = parse_expr_list(tokens, Punctuation::RParen)?;
+                return Ok(Expr::Call(Call {
+                    name: ident.to_string(),
+                    args,
+                    span: tokens.covered(start),
+                }));
+            }
+
+            Ok(Expr::Ident(ident.to_string()))
+        }
+
+        Some(&LexerToken::NullLit) => {
+            tokens.next();
+            Ok(Expr::NullLit)
+        }
+
+        Some(&LexerToken::Keyword(Keyword::New)) => {
+            tokens.next();
+
+            match tokens.next() {
+                // `new(X)` allocates a pointer, `new Name(...)` builds an object
+                Some(LexerToken::Punctuation(Punctuation::LParen)) => {
+                    let args = parse_expr_list(tokens, Punctuation::RParen)?;
+                    Ok(Expr::Call(Call {
+                        name: "NEW".into(),
+                        args,
+                        span: tokens.covered(start),
+                    }))
+                }
+
+                Some(LexerToken::Identifier(class)) => {
+                    let args = if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::LParen)) {
+                        tokens.next();
+                        parse_expr_list(tokens, Punctuation::RParen)?
+                    } else {
+                        Vec::new()
+                    };
+
+                    Ok(Expr::New(New {
+                        class: class.to_string(),
+                        args,
+                        span: tokens.covered(start),
+                    }))
+                }
+
+                _ => Err(ParseError {
+                    msg: "Expected class name after 'new'".into(),
+                    span: None,
+                    code: Some("E012"),
+                }),
+            }
+        }
+
+        // `if C then A else B` used as a value
+        Some(&LexerToken::Keyword(Keyword::If)) => {
+            tokens.next();
+            let cond = parse_expr(tokens)?;
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::Then)) {
+                return Err(ParseError {
+                    msg: "Expected 'then' in if expression".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+            let then = parse_expr(tokens)?;
+
+            if tokens.next() != Some(&LexerToken::Keyword(Keyword::Else)) {
+                return Err(ParseError {
+                    msg: "Expected 'else' in if expression".into(),
+                    span: None,
+                    code: Some("E012"),
+                });
+            }
+            let otherwise = parse_expr(tokens)?;
+
+            Ok(Expr::IfElse(Box::new(IfElse { cond, then, otherwise })))
+        }
+
+        Some(&LexerToken::Keyword(Keyword::Super)) => {
+            tokens.next();
+            expect(tokens, Punctuation::Dot, "Expected '.' after 'super'")?;
+
+            let name = match tokens.next() {
+                Some(LexerToken::Identifier(name)) => name.clone(),
+                _ => {
+                    return Err(ParseError {
+                        msg: "Expected method name after 'super.'".into(),
+                        span: None,
+                        code: Some("E012"),
+                    })
+                }
+            };
+
+            expect(tokens, Punctuation::LParen, "Expected '(' after method name")?;
+
+            let args = parse_expr_list(tokens, Punctuation::RParen)?;
+            Ok(Expr::SuperCall(SuperCall {
+                name,
+                args,
+                span: tokens.covered(start),
+            }))
+        }
+
+        Some(&LexerToken::Punctuation(Punctuation::BNot)) => {
+            tokens.next();
+            Ok(Expr::BNot(Box::new(parse_atom(tokens)?)))
+        }
+
+        Some(&LexerToken::Punctuation(Punctuation::At)) => {
+            tokens.next();
+
+            match tokens.next() {
+                Some(LexerToken::Identifier(ident)) => Ok(Expr::AddressOf(ident.to_string())),
+                _ => Err(ParseError {
+                    msg: "Expected variable name after '@'".into(),
+                    span: None,
+                    code: Some("E012"),
+                }),
+            }
+        }
+
+        Some(&LexerToken::Punctuation(Punctuation::LParen)) => {
+            tokens.next();
+            let expr = parse_expr(tokens)?;
+            expect(tokens, Punctuation::RParen, "Expected ')'")?;
+            Ok(expr)
+        }
+
+        Some(&LexerToken::Punctuation(Punctuation::LBracket)) => {
+            tokens.next();
+            Ok(Expr::Array(parse_expr_list(tokens, Punctuation::RBracket)?))
+        }
+
+        Some(&LexerToken::Punctuation(Punctuation::LBrace)) => {
+            tokens.next();
+
+            let mut entries: Vec<(Expr, Expr)> = Vec::new();
+
+            if tokens.peek() == Some(&&LexerToken::Punctuation(Punctuation::RBrace)) {
+                tokens.next();
+                return Ok(Expr::Map(entries));
+            }
+
+            let first = parse_expr(tokens)?;
+
+            // `{1, 2, 3}` is a set literal, `{K: V, ...}` a map literal
+            if tokens.peek() != Some(&&LexerToken::Punctuation(Punctuation::Colon)) {
+                let mut items = vec![first];
+
+                match tokens.next() {
+                    Some(LexerToken::Punctuation(Punctuation::Comma)) => {
+                        items.append(&mut parse_expr_list(tokens, Punctuation::RBrace)?);
+                    }
+                    Some(LexerToken::Punctuation(Punctuation::RBrace)) => {}
+                    _ => {
+                        return Err(ParseError {
+                            msg: "Failed to parse set literal".into(),
+                            span: None,
+                            code: Some("E012"),
+                        })
+                    }
+                }
+
+                return Ok(Expr::Set(items));
+            }
+
+            let mut key = first;
+
+            loop {
+                expect(tokens, Punctuation::Colon, "Expected ':' in map literal")?;
+                entries.push((key, parse_expr(tokens)?));
+
+                match tokens.next() {
+                    Some(LexerToken::Punctuation(Punctuation::Comma)) => key = parse_expr(tokens)?,
+                    Some(LexerToken::Punctuation(Punctuation::RBrace)) => return Ok(Expr::Map(entries)),
+                    _ => {
+                        return Err(ParseError {
+                            msg: "Failed to parse map literal".into(),
+                            span: None,
+                            code: Some("E012"),
+                        })
+                    }
+                }
+            }
+        }
+
+        _ => Err(ParseError {
+            msg: "Failed to parse atom".into(),
+            span: None,
+            code: Some("E012"),
+        }),
+    }
+}
+
+fn parse_bin_op(tokens: &mut TokenStream, left: Expr, precedence: u32) -> Result<Expr, ParseError> {
+    match tokens.peek() {
+        Some(&LexerToken::Punctuation(tok)) => match BinaryOp::from_token(tok) {
+            Some(op) if op.precedence() >= precedence => {
+                let new_precedence = op.precedence();
+                let span = tokens.spans[tokens.pos];
+                tokens.next();
+                let next_atom = parse_atom(tokens)?;
+
+                // Only tighter operators go on the right, so `A - B - C` is
+                // `(A - B) - C`
+                let ret = Expr::BinOp(Box::new(BinOp {
+                    left,
+                    right: parse_bin_op(tokens, next_atom, new_precedence + 1)?,
+                    op,
+                    span,
+                }));
+
+                parse_bin_op(tokens, ret, precedence)
+            }
+
+            _ => Ok(left),
+        },
+
+        _ => Ok(left),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{Declared, Expr, StmtKind};
+    use crate::dialect::Dialect;
+    use crate::lex::{LexOptions, LexerToken, Naming};
+
+    fn tokens(source: &str, naming: Naming) -> Result<Vec<LexerToken>, String> {
+        let options = LexOptions { naming, ..Default::default() };
+        crate::lex(source, &options).map(|x| x.into_iter().map(|x| x.tok).collect()).map_err(|x| x.msg)
+    }
+
+    /// The variable each statement assigns to
+    fn assigned(source: &str) -> Vec<String> {
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        stmts
+            .iter()
+            .filter_map(|x| match &x.kind {
+                StmtKind::Assign(x) => match &x.target {
+                    Expr::Ident(name) => Some(name.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn digits_after_the_first_character_are_part_of_identifiers() {
+        let ident = |x: &str| LexerToken::Identifier(x.to_string());
+        assert_eq!(tokens("NUM1", Naming::Upper), Ok(vec![ident("NUM1")]));
+        assert_eq!(tokens("TOTAL_2", Naming::Upper), Ok(vec![ident("TOTAL_2")]));
+        assert_eq!(tokens("A1B2", Naming::Upper), Ok(vec![ident("A1B2")]));
+        assert_eq!(tokens("num1", Naming::Camel), Ok(vec![ident("num1")]));
+    }
+
+    #[test]
+    fn identifiers_with_digits_parse() {
+        assert_eq!(assigned("NUM1 = 3\nTOTAL_2 = NUM1 + 1"), ["NUM1", "TOTAL_2"]);
+        assert_eq!(assigned("loop I from 1 to 2\nX2 = I\nend loop\nLOOP2 = 1"), ["LOOP2"]);
+        assert!(crate::parse("output LIST1[0] + NUM2", &Default::default()).is_ok());
+    }
+
+    #[test]
+    fn identifiers_are_unicode_xid() {
+        let ident = |x: &str| LexerToken::Identifier(x.to_string());
+        assert_eq!(tokens("ÉLÈVE", Naming::Upper), Ok(vec![ident("ÉLÈVE")]));
+        // Decomposed, with the accents as combining marks after the letters
+        let nfd = "E\u{301}LE\u{300}VE";
+        assert_eq!(tokens(nfd, Naming::Upper), Ok(vec![ident(nfd)]));
+        assert_eq!(tokens("e\u{301}le\u{300}ve", Naming::Camel), Ok(vec![ident("e\u{301}le\u{300}ve")]));
+        assert_eq!(tokens("Σ_1", Naming::Upper), Ok(vec![ident("Σ_1")]));
+        // Numeric, but not something an identifier can be made of
+        assert!(tokens("X²", Naming::Upper).is_err());
+        assert!(tokens("²X", Naming::Upper).is_err());
+    }
+
+    #[test]
+    fn leading_digits_and_the_wrong_case_are_rejected() {
+        let split = vec![LexerToken::IntLit(1), LexerToken::Identifier("NUM".to_string())];
+        assert_eq!(tokens("1NUM", Naming::Upper), Ok(split));
+        assert!(crate::parse("1NUM = 3", &Default::default()).is_err());
+        assert!(tokens("num1", Naming::Upper).is_err());
+        assert!(tokens("Num1", Naming::Camel).is_err());
+    }
+
+    #[test]
+    fn one_word_terminators_are_only_reserved_in_their_dialects() {
+        assert_eq!(assigned("NEXT = 3\nENDIF = NEXT"), ["NEXT", "ENDIF"]);
+        assert!(crate::parse("class NODE\n  public NEXT\nend class", &Default::default()).is_ok());
+
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let source = "IF 1 < 2 THEN\n  OUTPUT 1\nENDIF\nFOR I <- 1 TO 2\n  OUTPUT I\nNEXT I";
+        assert!(crate::parse(source, &cambridge).is_ok());
+        assert!(crate::parse("NEXT <- 3", &cambridge).is_err());
+
+        let aqa = LexOptions { dialect: Dialect::Aqa, ..Default::default() };
+        assert!(crate::parse("FOR I <- 1 TO 2\n  OUTPUT I\nENDFOR", &aqa).is_ok());
+    }
+
+    #[test]
+    fn declare_parses_array_types() {
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let stmts = crate::parse("DECLARE A : ARRAY[1:3, 0:1] OF INTEGER", &cambridge).unwrap();
+        let StmtKind::Assign(x) = &stmts[0].kind else { panic!("DECLARE should be an assignment") };
+        let ty = Declared { name: "INTEGER".to_string(), bounds: vec![(1, 3), (0, 1)] };
+        assert_eq!(x.declared, Some(ty));
+        assert!(matches!(x.expr, Expr::IntLit(0)));
+        let Expr::Array(rows) = x.value() else { panic!("ARRAY should start as an array") };
+        assert_eq!(rows.len(), 3);
+        let zeros = |x: &Expr| matches!(x, Expr::Array(x) if x.len() == 2 && x.iter().all(|x| matches!(x, Expr::IntLit(0))));
+        assert!(rows.iter().all(zeros));
+
+        let source = "PROCEDURE P(B : ARRAY[1:10] OF INTEGER)\n  OUTPUT B[1]\nENDPROCEDURE";
+        assert!(crate::parse(source, &cambridge).is_ok());
+        assert!(crate::parse("DECLARE A : ARRAY[1:3] INTEGER", &cambridge).is_err());
+        assert!(crate::parse("DECLARE A : ARRAY[3:1] OF INTEGER", &cambridge).is_err());
+        // Nothing is made until the program runs
+        assert!(crate::parse("DECLARE A : ARRAY[1:2000000000] OF INTEGER", &cambridge).is_ok());
+    }
+
+    #[test]
+    fn declare_parses_pointer_types() {
+        use crate::env::{Captured, Env};
+
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let source = "TYPE INTPTR = ^INTEGER\nDECLARE X : INTEGER\nDECLARE P : ^INTEGER\nDECLARE Q : INTPTR\nOUTPUT P = NULL\nX <- 5\nP <- @X\nQ <- P\nQ^ <- 7\nOUTPUT X";
+        let stmts = crate::parse(source, &cambridge).unwrap();
+        let pointer = Declared { name: "^INTEGER".to_string(), bounds: vec![] };
+        assert!(matches!(&stmts[0].kind, StmtKind::Type(x) if x.name == "INTPTR" && x.ty == pointer));
+        assert!(matches!(&stmts[2].kind, StmtKind::Assign(x) if x.declared == Some(pointer.clone())));
+
+        let out = Captured::default();
+        let mut env = Env::new();
+        env.dialect = Dialect::Cambridge;
+        env.out = Box::new(out.clone());
+        env.run(&stmts).unwrap();
+        assert_eq!(out.text(), "true\n7\n");
+
+        assert!(crate::parse("DECLARE P : ^ARRAY[1:2] OF INTEGER", &cambridge).is_err());
     }
 }
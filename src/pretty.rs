@@ -0,0 +1,572 @@
+use crate::dialect::{Dialect, Forms};
+use crate::eval::*;
+use std::fmt;
+
+/// Writes a program back out in the canonical layout: the dialect's own
+/// keywords and statement forms, single spaces around operators and a tab
+/// per level of nesting
+struct Printer {
+    out: String,
+    indent: usize,
+    /// `=` where that assigns, `←` in dialects that use `=` to compare
+    assign: &'static str,
+    forms: &'static Forms,
+}
+
+/// `form` with each `{N}` replaced by `parts[N]`
+fn fill(form: &str, parts: &[&str]) -> String {
+    let mut out = form.to_string();
+    for (i, part) in parts.iter().enumerate() {
+        out = out.replace(&format!("{{{}}}", i), part);
+    }
+    out
+}
+
+pub fn op_symbol(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Plus => "+",
+        BinaryOp::Minus => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::FloorDiv => "div",
+        BinaryOp::Mod => "mod",
+        BinaryOp::Equals => "==",
+        BinaryOp::NotEquals => "!=",
+        BinaryOp::GT => ">",
+        BinaryOp::LT => "<",
+        BinaryOp::GE => ">=",
+        BinaryOp::LE => "<=",
+        BinaryOp::In => "in",
+        BinaryOp::BAnd => "band",
+        BinaryOp::BOr => "bor",
+        BinaryOp::BXor => "bxor",
+        BinaryOp::Shl => "<<",
+        BinaryOp::Shr => ">>",
+    }
+}
+
+/// Functions are the definitions that return a value somewhere, the AST
+/// doesn't remember which keyword was used
+pub fn returns_value(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return(x) => x.is_some(),
+        StmtKind::If(x) => x.branches.iter().any(|(_, stmts)| returns_value(stmts)),
+        StmtKind::While(While { stmts, .. })
+        | StmtKind::Until(Until { stmts, .. })
+        | StmtKind::Repeat(Until { stmts, .. })
+        | StmtKind::For(For { stmts, .. })
+        | StmtKind::ForEach(ForEach { stmts, .. }) => returns_value(stmts),
+        StmtKind::Attempt(x) => returns_value(&x.stmts) || returns_value(&x.handler),
+        _ => false,
+    })
+}
+
+fn is_definition(stmt: &Stmt) -> bool {
+    matches!(stmt.kind, StmtKind::Function(_) | StmtKind::Class(_))
+}
+
+impl Printer {
+    fn new(dialect: Dialect) -> Printer {
+        Printer {
+            out: String::new(),
+            indent: 0,
+            assign: if dialect.table().assign_equals { "=" } else { "←" },
+            forms: dialect.table().forms,
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for text in text.split('\n') {
+            for _ in 0..self.indent {
+                self.out.push('\t');
+            }
+            self.out.push_str(text);
+            self.out.push('\n');
+        }
+    }
+
+    /// A keyword in the dialect's case
+    fn word(&self, word: &str) -> String {
+        match self.forms.upper {
+            true => word.to_uppercase(),
+            false => word.to_string(),
+        }
+    }
+
+    /// A type as `DECLARE` and `TYPE` write it
+    fn type_name(&self, ty: &Declared) -> String {
+        if ty.bounds.is_empty() {
+            return ty.name.clone();
+        }
+        let bounds: Vec<String> = ty.bounds.iter().map(|(lower, upper)| format!("{}:{}", lower, upper)).collect();
+        format!("{}[{}] {} {}", self.word("array"), bounds.join(", "), self.word("of"), ty.name)
+    }
+
+    /// The first line of a block, its body and then its last line
+    fn block_form(&mut self, form: [&str; 2], parts: &[&str], stmts: &[Stmt]) {
+        self.line(&fill(form[0], parts));
+        self.block(stmts);
+        self.line(&fill(form[1], parts));
+    }
+
+    fn blank(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        self.stmts(stmts);
+        self.indent -= 1;
+    }
+
+    /// Definitions get a blank line on either side
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            if i > 0 && (is_definition(stmt) || is_definition(&stmts[i - 1])) {
+                self.blank();
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    fn list(&self, exprs: &[Expr]) -> String {
+        exprs.iter().map(|x| self.expr(x)).collect::<Vec<String>>().join(", ")
+    }
+
+    fn function(&mut self, prefix: &str, func: &Function) {
+        let form = if returns_value(&func.stmts) { self.forms.function } else { self.forms.procedure };
+
+        for line in func.doc.iter().flat_map(|x| x.lines()) {
+            match line.is_empty() {
+                true => self.line("///"),
+                false => self.line(&format!("/// {}", line)),
+            }
+        }
+        let first = format!("{}{}", prefix, form[0]);
+        self.block_form([&first, form[1]], &[&func.name, &func.params.join(", ")], &func.stmts);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(Assign { target, declared: Some(ty), .. }) if self.forms.declare.is_some() => {
+                let text = fill(self.forms.declare.unwrap_or_default(), &[&self.expr(target), &self.type_name(ty)]);
+                self.line(&text);
+            }
+            StmtKind::Assign(x) => {
+                let text = fill(self.forms.assign, &[&self.expr(&x.target), &self.expr(x.value())]);
+                self.line(&text);
+            }
+            StmtKind::MultiAssign(x) => {
+                let text = format!("{} {} {}", self.list(&x.targets), self.assign, self.list(&x.exprs));
+                self.line(&text);
+            }
+            StmtKind::Input(x) => match &x.kind {
+                Some(kind) => {
+                    let kind = match kind {
+                        InputType::Integer => "integer",
+                        InputType::Real => "real",
+                        InputType::String => "string",
+                        InputType::Boolean => "boolean",
+                    };
+                    self.line(&fill(self.forms.input_typed, &[&x.ident, &self.word(kind)]));
+                }
+                None => self.line(&fill(self.forms.input, &[&x.ident])),
+            },
+            StmtKind::Output(x) => {
+                let text = fill(self.forms.output, &[&self.expr(&x.expr)]);
+                self.line(&text);
+            }
+            StmtKind::If(x) => {
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    let text = match (i, cond) {
+                        (0, _) => fill(self.forms.if_then, &[&self.expr(cond)]),
+                        (_, Expr::BoolLit(true)) if i == x.branches.len() - 1 => self.word("else"),
+                        _ => fill(self.forms.else_if, &[&self.expr(cond)]),
+                    };
+                    self.line(&text);
+                    self.block(stmts);
+                }
+                self.line(self.forms.end_if);
+            }
+            StmtKind::While(x) => self.block_form(self.forms.while_loop, &[&self.expr(&x.cond)], &x.stmts),
+            StmtKind::Until(x) => self.block_form(self.forms.until_loop, &[&self.expr(&x.cond)], &x.stmts),
+            StmtKind::Repeat(x) => self.block_form(self.forms.repeat, &[&self.expr(&x.cond)], &x.stmts),
+            StmtKind::For(x) => {
+                let parts = [x.name.as_str(), &self.expr(&x.start), &self.expr(&x.end)];
+                self.block_form(self.forms.for_loop, &parts, &x.stmts);
+            }
+            StmtKind::ForEach(x) => self.block_form(self.forms.for_each, &[&x.name, &self.expr(&x.iter)], &x.stmts),
+            StmtKind::Expr(x @ (Expr::Call(_) | Expr::MethodCall(_))) => {
+                let text = fill(self.forms.call, &[&self.expr(x)]);
+                self.line(&text);
+            }
+            StmtKind::Expr(x) => {
+                let text = self.expr(x);
+                self.line(&text);
+            }
+            StmtKind::Function(x) => self.function("", x),
+            StmtKind::Class(x) => {
+                match &x.parent {
+                    Some(parent) => self.line(&format!("{} {} {} {}", self.word("class"), x.name, self.word("inherits"), parent)),
+                    None => self.line(&format!("{} {}", self.word("class"), x.name)),
+                }
+                self.indent += 1;
+
+                for field in &x.fields {
+                    let prefix = if field.public { String::new() } else { self.word("private ") };
+                    let text = match &field.init {
+                        Some(init) => format!("{}{} {} {}", prefix, field.name, self.assign, self.expr(init)),
+                        None => format!("{}{}", prefix, field.name),
+                    };
+                    self.line(&text);
+                }
+
+                for (i, method) in x.methods.iter().enumerate() {
+                    if i > 0 || !x.fields.is_empty() {
+                        self.blank();
+                    }
+                    let prefix = if method.public { String::new() } else { self.word("private ") };
+                    self.function(&prefix, &method.func);
+                }
+
+                self.indent -= 1;
+                self.line(self.forms.end_class);
+            }
+            StmtKind::Return(x) => match x {
+                Some(x) => {
+                    let text = format!("{} {}", self.word("return"), self.expr(x));
+                    self.line(&text);
+                }
+                None => self.line(&self.word("return")),
+            },
+            StmtKind::Global(names) => self.line(&format!("{} {}", self.word("global"), names.join(", "))),
+            StmtKind::Type(x) => {
+                // Types are only named where they can be used
+                if let Some(form) = self.forms.type_def {
+                    let text = fill(form, &[&x.name, &self.type_name(&x.ty)]);
+                    self.line(&text);
+                }
+            }
+            StmtKind::Attempt(x) => {
+                self.line(&self.word("attempt"));
+                self.block(&x.stmts);
+                match &x.name {
+                    Some(name) => self.line(&format!("{} {}", self.word("on error"), name)),
+                    None => self.line(&self.word("on error")),
+                }
+                self.block(&x.handler);
+                self.line(self.forms.end_attempt);
+            }
+            StmtKind::Assert(x) => {
+                let text = match &x.msg {
+                    Some(msg) => format!("{} {}, {}", self.word("assert"), self.expr(&x.cond), self.expr(msg)),
+                    None => format!("{} {}", self.word("assert"), self.expr(&x.cond)),
+                };
+                self.line(&text);
+            }
+            StmtKind::Halt(x) => match x {
+                Some(x) => {
+                    let text = format!("{} {}", self.word("halt"), self.expr(x));
+                    self.line(&text);
+                }
+                None => self.line(&self.word("halt")),
+            },
+            StmtKind::Include(file) => self.line(&format!("{} \"{}\"", self.word("include"), file)),
+        }
+    }
+
+    /// An operand of a binary operator, bracketed when it binds looser. The
+    /// operators are left associative, so equal ones only go bare on the left
+    fn operand(&self, expr: &Expr, precedence: u32, right: bool) -> String {
+        let bracket = match expr {
+            Expr::BinOp(x) => {
+                let inner = x.op.precedence();
+                inner < precedence || (right && inner == precedence)
+            }
+            Expr::IfElse(_) => true,
+            _ => false,
+        };
+
+        if bracket {
+            format!("({})", self.expr(expr))
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    /// Whatever `[...]`, `.name` or `^` is applied to
+    fn target(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::BinOp(_) | Expr::IfElse(_) | Expr::BNot(_) => format!("({})", self.expr(expr)),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::BinOp(x) => {
+                let precedence = x.op.precedence();
+                let op = match x.op {
+                    BinaryOp::Equals => self.forms.equals.to_string(),
+                    BinaryOp::NotEquals => self.forms.not_equals.to_string(),
+                    _ => self.word(op_symbol(&x.op)),
+                };
+                format!(
+                    "{} {} {}",
+                    self.operand(&x.left, precedence, false),
+                    op,
+                    self.operand(&x.right, precedence, true)
+                )
+            }
+            Expr::IfElse(x) => format!(
+                "{} {} {} {} {} {}",
+                self.word("if"),
+                self.expr(&x.cond),
+                self.word("then"),
+                self.expr(&x.then),
+                self.word("else"),
+                self.expr(&x.otherwise)
+            ),
+            Expr::Index(x) => format!("{}[{}]", self.target(&x.target), self.expr(&x.index)),
+            Expr::MethodCall(x) => format!("{}.{}({})", self.target(&x.target), x.name, self.list(&x.args)),
+            Expr::Field(x) => format!("{}.{}", self.target(&x.target), x.name),
+            // `new(X)` is parsed as a call to `NEW`
+            Expr::Call(x) if x.name == "NEW" => format!("{}({})", self.word("new"), self.list(&x.args)),
+            Expr::Call(x) => format!("{}({})", x.name, self.list(&x.args)),
+            Expr::SuperCall(x) => format!("{}.{}({})", self.word("super"), x.name, self.list(&x.args)),
+            // `new Collection` and `new Collection()` are the same
+            Expr::New(x) if x.args.is_empty() => format!("{} {}", self.word("new"), x.class),
+            Expr::New(x) => format!("{} {}({})", self.word("new"), x.class, self.list(&x.args)),
+            Expr::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", self.expr(k), self.expr(v)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            // `{}` is an empty map
+            Expr::Set(items) if items.is_empty() => "SET()".to_string(),
+            Expr::Set(items) => format!("{{{}}}", self.list(items)),
+            Expr::Array(items) => format!("[{}]", self.list(items)),
+            Expr::AddressOf(name) => format!("@{}", name),
+            Expr::Deref(x) => format!("{}^", self.target(x)),
+            Expr::BNot(x) => format!("{} {}", self.word("bnot"), self.target(x)),
+            // There's no unary minus
+            Expr::IntLit(x) if *x < 0 => format!("(0 - {})", x.unsigned_abs()),
+            Expr::IntLit(x) => x.to_string(),
+            Expr::FloatLit(x) if *x < 0.0 => format!("(0 - {:?})", -x),
+            Expr::FloatLit(x) => format!("{:?}", x),
+            Expr::BoolLit(x) => self.word(&x.to_string()),
+            Expr::StrLit(x) => format!("\"{}\"", x),
+            Expr::NullLit => self.word("null"),
+            Expr::Ident(name) => name.clone(),
+        }
+    }
+}
+
+/// Canonical source for `stmts`, in the keywords and forms of `dialect`
+pub fn program(stmts: &[Stmt], dialect: Dialect) -> String {
+    let mut printer = Printer::new(dialect);
+    printer.stmts(stmts);
+    printer.out
+}
+
+/// One expression, as it would appear in the source
+pub fn expr(expr: &Expr) -> String {
+    Printer::new(Dialect::default()).expr(expr)
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", expr(self))
+    }
+}
+
+/// The statement with any body, one line per statement without the last
+/// line ending
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = program(std::slice::from_ref(self), Dialect::default());
+        write!(f, "{}", text.trim_end_matches('\n'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::program;
+    use crate::dialect::Dialect;
+    use crate::emit;
+    use crate::json::Json;
+    use crate::lex::LexOptions;
+
+    /// The tree as JSON without where each node was, which printing moves
+    fn shape(json: Json) -> Json {
+        match json {
+            Json::Object(fields) => Json::Object(
+                fields
+                    .into_iter()
+                    .filter(|(key, _)| !matches!(key.as_str(), "line" | "col" | "len"))
+                    .map(|(key, value)| (key, shape(value)))
+                    .collect(),
+            ),
+            Json::Array(items) => Json::Array(items.into_iter().map(shape).collect()),
+            x => x,
+        }
+    }
+
+    /// Parses `source`, prints it and checks the printed program parses to
+    /// the same tree
+    fn round_trip(source: &str, dialect: Dialect) {
+        let options = LexOptions { dialect, ..Default::default() };
+        let stmts = crate::parse(source, &options).unwrap();
+        let printed = program(&stmts, dialect);
+        let reparsed = crate::parse(&printed, &options).unwrap_or_else(|e| panic!("{}\n{}", e.msg, printed));
+        assert_eq!(shape(emit::stmts(&reparsed)), shape(emit::stmts(&stmts)), "\n{}", printed);
+    }
+
+    #[test]
+    fn every_statement_round_trips() {
+        let source = r#"
+            include "lib.psc"
+            X = 1
+            A, B = 2, 3
+            input Y
+            input integer Z
+            output X
+            if X == 1 then
+                output 1
+            else if X == 2
+                output 2
+            else
+                output 3
+            end if
+            loop while X < 3
+                X = X + 1
+            end loop
+            loop until X > 5
+                X = X + 1
+            end loop
+            loop I from 1 to 3
+                output I
+            end loop
+            loop I over [1, 2]
+                output I
+            end loop
+            P(1)
+            L.append(2)
+            /// Doubles N
+            ///
+            /// Twice over
+            function F(N)
+                return N * 2
+            end function
+            procedure P(N)
+                global X
+                return
+            end procedure
+            class C inherits B
+                V = 1
+                private W
+                procedure M()
+                    super.M()
+                end procedure
+                private function N()
+                    return O.V
+                end function
+            end class
+            attempt
+                halt 2
+            on error E
+                assert E != null, "no error"
+            end attempt
+            attempt
+                halt
+            on error
+                assert false
+            end attempt
+        "#;
+        round_trip(source, Dialect::Ib);
+    }
+
+    #[test]
+    fn every_expression_round_trips() {
+        let source = r#"
+            X = 1 + 2 - 3 * 4 / 5 div 6 mod 7
+            X = (1 + 2) * (3 - (4 - 5))
+            X = [1 == 2, 1 != 2, 1 < 2, 1 > 2, 1 <= 2, 1 >= 2, 1 in [1]]
+            X = 1 band 2 bor 3 bxor 4 << 5 >> 6
+            X = bnot (1 + 2)
+            X = if true then 1.5 else 2.25
+            X = L[1][2] + O.F.G + O.M(1, 2)
+            X = F(1) + new(3) + new C + new C(1, 2)
+            X = {1: "a", "b": null} + {1, 2} + SET() + [1, [2]]
+            X = @Y
+            X = Y^ + (@Y)^
+            X = false
+        "#;
+        round_trip(source, Dialect::Ib);
+    }
+
+    #[test]
+    fn dialect_forms_round_trip() {
+        let source = r#"
+            TYPE INTPTR = ^INTEGER
+            DECLARE T : INTEGER
+            DECLARE A : ARRAY[1:3, 0:1] OF REAL
+            DECLARE P : ^INTEGER
+            FOR I <- 1 TO 3
+                T <- T + I
+            NEXT I
+            IF T = 6 THEN
+                OUTPUT T <> 2
+            ENDIF
+            REPEAT
+                T <- T - 1
+            UNTIL T < 0
+            WHILE T < 3 DO
+                T <- T + 1
+            ENDWHILE
+            PROCEDURE P(X : INTEGER)
+                OUTPUT X
+            ENDPROCEDURE
+            CALL P(1)
+        "#;
+        round_trip(source, Dialect::Cambridge);
+
+        let source = r#"
+            SET X TO 1
+            RECEIVE Y FROM (INTEGER) KEYBOARD
+            FOR I FROM 1 TO 3 DO
+                SEND I TO DISPLAY
+            END FOR
+            PROCEDURE P(A)
+            BEGIN PROCEDURE
+                SEND A = 1 TO DISPLAY
+            END PROCEDURE
+        "#;
+        round_trip(source, Dialect::Edexcel);
+
+        let source = r#"
+            X <- 3
+            FOR I <- 1 TO 3
+                OUTPUT I
+            ENDFOR
+            WHILE X > 1
+                X <- X - 1
+            ENDWHILE
+            SUBROUTINE S(A)
+                OUTPUT A ≠ 1
+            ENDSUBROUTINE
+        "#;
+        round_trip(source, Dialect::Aqa);
+    }
+
+    #[test]
+    fn declared_arrays_are_written_out_without_declare() {
+        let cambridge = LexOptions { dialect: Dialect::Cambridge, ..Default::default() };
+        let stmts = crate::parse("DECLARE A : ARRAY[1:2, 0:1] OF INTEGER", &cambridge).unwrap();
+        assert_eq!(program(&stmts, Dialect::Ib), "A = [[0, 0], [0, 0]]\n");
+    }
+}
@@ -0,0 +1,106 @@
+use psc::env::{Env, Hook};
+use psc::err::RuntimeError;
+use psc::eval::Stmt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Lines shown in the table, the rest are only counted
+const SHOWN: usize = 10;
+
+#[derive(Default)]
+struct Line {
+    count: u64,
+    time: Duration,
+}
+
+/// Time and runs per line for `--profile`. A line only gets the time spent
+/// on itself, the statements in its body or the calls it makes count for
+/// their own lines, so a slow loop shows up on the line doing the work
+pub struct Profile {
+    source: Vec<String>,
+    lines: HashMap<usize, Line>,
+    /// The statements started but not finished, innermost last. They're
+    /// kept as pointers only to tell them apart
+    running: Vec<(*const Stmt, usize)>,
+    last: Instant,
+}
+
+impl Profile {
+    /// For a program with the text `source`
+    pub fn new(source: &str) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Profile {
+            source: source.lines().map(String::from).collect(),
+            lines: HashMap::new(),
+            running: Vec::new(),
+            last: Instant::now(),
+        }))
+    }
+
+    /// Gives the time since the last statement started or finished to the
+    /// innermost one running
+    fn charge(&mut self) {
+        let now = Instant::now();
+        if let Some((_, line)) = self.running.last() {
+            self.lines.entry(*line).or_default().time += now - self.last;
+        }
+        self.last = now;
+    }
+
+    /// The hotspots, slowest lines first
+    pub fn table(&mut self) -> String {
+        self.charge();
+
+        let total: Duration = self.lines.values().map(|x| x.time).sum();
+        let mut lines: Vec<(&usize, &Line)> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+
+        let mut ret = format!("{:>6} {:>10} {:>12} {:>6}  Source\n", "Line", "Runs", "Time", "%");
+        for (line, stats) in lines.iter().take(SHOWN) {
+            let share = match total.as_nanos() {
+                0 => 0.0,
+                x => stats.time.as_nanos() as f64 * 100.0 / x as f64,
+            };
+            let text = self.source.get(**line - 1).map_or("", |x| x.trim());
+            ret += &format!(
+                "{:>6} {:>10} {:>10.3}ms {:>5.1}%  {}\n",
+                line,
+                stats.count,
+                stats.time.as_secs_f64() * 1000.0,
+                share,
+                text
+            );
+        }
+        if lines.len() > SHOWN {
+            ret += &format!("{} more lines ran\n", lines.len() - SHOWN);
+        }
+        ret += &format!("Total {:.3}ms", total.as_secs_f64() * 1000.0);
+        ret
+    }
+}
+
+/// The hook feeding a `Profile`, which stays with the caller to print
+pub struct Profiler(pub Rc<RefCell<Profile>>);
+
+impl Hook for Profiler {
+    fn before(&mut self, stmt: &Stmt, _env: &mut Env) -> Result<(), RuntimeError> {
+        let mut profile = self.0.borrow_mut();
+        profile.charge();
+        profile.lines.entry(stmt.span.line).or_default().count += 1;
+        profile.running.push((stmt, stmt.span.line));
+        Ok(())
+    }
+
+    fn after(&mut self, stmt: &Stmt, _env: &mut Env) -> Result<(), RuntimeError> {
+        let mut profile = self.0.borrow_mut();
+        profile.charge();
+        // Statements an error cut short never finish, so they go too
+        while let Some((x, _)) = profile.running.pop() {
+            if std::ptr::eq(x, stmt) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
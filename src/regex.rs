@@ -0,0 +1,557 @@
+use crate::err::RuntimeError;
+use std::mem;
+
+/// A small regular expression engine covering what exercises need:
+/// literals, `.`, classes, `\d \w \s`, anchors, groups, alternation and
+/// `* + ? {m,n}` quantifiers (lazy with a trailing `?`). It runs as a Pike
+/// VM, so no pattern takes more than linear time in the text, and matches
+/// anywhere in it unless anchored with `^` and `$`
+pub struct Regex {
+    insts: Vec<Inst>,
+    groups: usize,
+}
+
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    /// Capturing groups have their index, counted from 1
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')];
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> RuntimeError {
+        RuntimeError {
+            msg: format!("Invalid regular expression: {}", msg),
+            span: None,
+            code: Some("E030"),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, RuntimeError> {
+        let mut options = vec![self.parse_seq()?];
+        while self.eat('|') {
+            options.push(self.parse_seq()?);
+        }
+
+        if options.len() == 1 {
+            Ok(options.remove(0))
+        } else {
+            Ok(Node::Alt(options))
+        }
+    }
+
+    fn parse_seq(&mut self) -> Result<Node, RuntimeError> {
+        let mut nodes = Vec::new();
+        while !matches!(self.peek(), None | Some('|') | Some(')')) {
+            nodes.push(self.parse_repeat()?);
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, RuntimeError> {
+        let node = self.parse_atom()?;
+
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.pos += 1;
+                let min = match self.parse_number() {
+                    Some(x) => x,
+                    None => return Err(self.error("expected a number after '{'")),
+                };
+                let max = if self.eat(',') { self.parse_number() } else { Some(min) };
+
+                if self.peek() != Some('}') || max.is_some_and(|x| x < min) {
+                    return Err(self.error("malformed {m,n} quantifier"));
+                }
+                (min, max)
+            }
+            _ => return Ok(node),
+        };
+        self.pos += 1;
+
+        if matches!(node, Node::Start | Node::End) {
+            return Err(self.error("nothing to repeat"));
+        }
+
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+            greedy: !self.eat('?'),
+        })
+    }
+
+    /// `\d`, `\w` and `\s` (and their negations) as a class, or an escaped
+    /// single character
+    fn parse_escape(&mut self) -> Result<Node, RuntimeError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("trailing '\\'")),
+        };
+        self.pos += 1;
+
+        let class = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+
+        Ok(match c {
+            'd' => class(DIGIT, false),
+            'D' => class(DIGIT, true),
+            'w' => class(WORD, false),
+            'W' => class(WORD, true),
+            's' => class(SPACE, false),
+            'S' => class(SPACE, true),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, RuntimeError> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+
+        loop {
+            let c = match self.peek() {
+                Some(']') if !first => break,
+                Some(c) => c,
+                None => return Err(self.error("unterminated '['")),
+            };
+            self.pos += 1;
+            first = false;
+
+            let lo = if c == '\\' {
+                match self.parse_escape()? {
+                    Node::Char(c) => c,
+                    Node::Class { ranges: x, negated: false } => {
+                        ranges.extend(x);
+                        continue;
+                    }
+                    _ => return Err(self.error("negated escapes aren't allowed in '[...]'")),
+                }
+            } else {
+                c
+            };
+
+            let hi = match (self.peek(), self.chars.get(self.pos + 1)) {
+                (Some('-'), Some(&hi)) if hi != ']' => {
+                    self.pos += 2;
+                    hi
+                }
+                _ => lo,
+            };
+
+            if hi < lo {
+                return Err(self.error("range out of order in '[...]'"));
+            }
+            ranges.push((lo, hi));
+        }
+        self.pos += 1;
+
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RuntimeError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("unexpected end of pattern")),
+        };
+        self.pos += 1;
+
+        match c {
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(self.error("only (?:...) groups are supported"));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+
+                let inner = self.parse_alt()?;
+                if !self.eat(')') {
+                    return Err(self.error("unclosed '('"));
+                }
+
+                Ok(Node::Group(Box::new(inner), index))
+            }
+            '[' => self.parse_class(),
+            '\\' => self.parse_escape(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '*' | '+' | '?' | '{' => Err(self.error("nothing to repeat")),
+            c => Ok(Node::Char(c)),
+        }
+    }
+}
+
+/// A step of the compiled program, which runs on every character of the
+/// text at once so matching takes time linear in its length
+enum Inst {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    /// Records the position in a capture slot, two for each group
+    Save(usize),
+    /// Carries on at both, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// More than enough for exercises, while `(a{1000}){1000}` can't take up
+/// all the memory there is
+const MAX_INSTS: usize = 10_000;
+
+struct Compiler {
+    insts: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Result<usize, RuntimeError> {
+        if self.insts.len() >= MAX_INSTS {
+            return Err(RuntimeError {
+                msg: "Invalid regular expression: pattern is too big".into(),
+                span: None,
+                code: Some("E030"),
+            });
+        }
+        self.insts.push(inst);
+        Ok(self.insts.len() - 1)
+    }
+
+    /// Points the second branch of the split at `pc` to `to`
+    fn patch(&mut self, pc: usize, to: usize) {
+        match &mut self.insts[pc] {
+            Inst::Split(_, x) | Inst::Jump(x) => *x = to,
+            _ => unreachable!("only splits and jumps are patched"),
+        }
+    }
+
+    /// A split to `pc + 1` and somewhere patched in later, the other way
+    /// round when `greedy` is false
+    fn split(&mut self, greedy: bool) -> Result<usize, RuntimeError> {
+        let pc = self.push(Inst::Split(0, 0))?;
+        self.insts[pc] = if greedy { Inst::Split(pc + 1, 0) } else { Inst::Split(0, pc + 1) };
+        Ok(pc)
+    }
+
+    fn patch_split(&mut self, pc: usize, to: usize, greedy: bool) {
+        if greedy {
+            self.patch(pc, to);
+        } else if let Inst::Split(x, _) = &mut self.insts[pc] {
+            *x = to;
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        match node {
+            Node::Char(c) => {
+                self.push(Inst::Char(*c))?;
+            }
+            Node::Any => {
+                self.push(Inst::Any)?;
+            }
+            Node::Class { ranges, negated } => {
+                self.push(Inst::Class { ranges: ranges.clone(), negated: *negated })?;
+            }
+            Node::Start => {
+                self.push(Inst::Start)?;
+            }
+            Node::End => {
+                self.push(Inst::End)?;
+            }
+
+            Node::Group(inner, Some(i)) => {
+                self.push(Inst::Save(2 * i))?;
+                self.node(inner)?;
+                self.push(Inst::Save(2 * i + 1))?;
+            }
+            Node::Group(inner, None) => self.node(inner)?,
+            Node::Concat(nodes) => nodes.iter().try_for_each(|x| self.node(x))?,
+
+            Node::Alt(options) => {
+                let mut jumps = Vec::new();
+                for (i, option) in options.iter().enumerate() {
+                    if i + 1 == options.len() {
+                        self.node(option)?;
+                        break;
+                    }
+                    let split = self.split(true)?;
+                    self.node(option)?;
+                    jumps.push(self.push(Inst::Jump(0))?);
+                    let next = self.insts.len();
+                    self.patch(split, next);
+                }
+                let end = self.insts.len();
+                jumps.into_iter().for_each(|x| self.patch(x, end));
+            }
+
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.node(node)?;
+                }
+
+                match max {
+                    None => {
+                        let split = self.split(*greedy)?;
+                        self.node(node)?;
+                        self.push(Inst::Jump(split))?;
+                        let end = self.insts.len();
+                        self.patch_split(split, end, *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.split(*greedy)?);
+                            self.node(node)?;
+                        }
+                        let end = self.insts.len();
+                        splits.into_iter().for_each(|x| self.patch_split(x, end, *greedy));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The threads at one position of the text, highest priority first, each
+/// with where its capture slots were recorded
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    /// The position each instruction was last added at, so a thread that
+    /// loops without reading anything is only added once
+    seen: Vec<Option<usize>>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RuntimeError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+
+        let root = parser.parse_alt()?;
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+
+        let mut compiler = Compiler { insts: Vec::new() };
+        compiler.push(Inst::Save(0))?;
+        compiler.node(&root)?;
+        compiler.push(Inst::Save(1))?;
+        compiler.push(Inst::Match)?;
+
+        Ok(Regex {
+            insts: compiler.insts,
+            groups: parser.groups,
+        })
+    }
+
+    /// Adds the thread at `pc` to `threads`, following the splits, jumps,
+    /// saves and anchors that don't read a character
+    fn add(&self, threads: &mut Threads, pc: usize, caps: Vec<Option<usize>>, pos: usize, len: usize) {
+        let mut stack = vec![(pc, caps)];
+
+        while let Some((pc, mut caps)) = stack.pop() {
+            if threads.seen[pc] == Some(pos) {
+                continue;
+            }
+            threads.seen[pc] = Some(pos);
+
+            match &self.insts[pc] {
+                Inst::Jump(x) => stack.push((*x, caps)),
+                Inst::Split(a, b) => {
+                    stack.push((*b, caps.clone()));
+                    stack.push((*a, caps));
+                }
+                Inst::Save(slot) => {
+                    caps[*slot] = Some(pos);
+                    stack.push((pc + 1, caps));
+                }
+                Inst::Start if pos == 0 => stack.push((pc + 1, caps)),
+                Inst::End if pos == len => stack.push((pc + 1, caps)),
+                Inst::Start | Inst::End => {}
+                _ => threads.list.push((pc, caps)),
+            }
+        }
+    }
+
+    /// The capture slots of the leftmost match, preferring what comes first
+    /// in alternations and as much or as little as the quantifiers ask for
+    fn find(&self, text: &[char]) -> Option<Vec<Option<usize>>> {
+        let slots = 2 * (self.groups + 1);
+        let new = || Threads {
+            list: Vec::new(),
+            seen: vec![None; self.insts.len()],
+        };
+        let (mut current, mut next) = (new(), new());
+        let mut matched = None;
+
+        for pos in 0..=text.len() {
+            // Starting later has lower priority, and no later start can be
+            // leftmost once something has matched
+            if matched.is_none() {
+                self.add(&mut current, 0, vec![None; slots], pos, text.len());
+            }
+
+            for (pc, caps) in mem::take(&mut current.list) {
+                let ok = match (&self.insts[pc], text.get(pos)) {
+                    (Inst::Match, _) => {
+                        matched = Some(caps);
+                        // Every thread after this one is lower priority
+                        break;
+                    }
+                    (Inst::Char(c), Some(x)) => c == x,
+                    (Inst::Any, Some(x)) => *x != '\n',
+                    (Inst::Class { ranges, negated }, Some(x)) => {
+                        ranges.iter().any(|&(lo, hi)| lo <= *x && *x <= hi) != *negated
+                    }
+                    _ => false,
+                };
+                if ok {
+                    self.add(&mut next, pc + 1, caps, pos + 1, text.len());
+                }
+            }
+
+            mem::swap(&mut current, &mut next);
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+        }
+
+        matched
+    }
+
+    /// The capture groups of the first place the pattern matches in
+    /// `text`, `None` for groups that took no part in the match
+    pub fn captures(&self, text: &str) -> Option<Vec<Option<String>>> {
+        let text: Vec<char> = text.chars().collect();
+        let caps = self.find(&text)?;
+
+        Some(
+            caps.chunks(2)
+                .skip(1)
+                .map(|x| match x {
+                    [Some(start), Some(end)] => Some(text[*start..*end].iter().collect()),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(&text.chars().collect::<Vec<char>>()).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(text: &str, pattern: &str) -> Option<Vec<Option<String>>> {
+        Regex::new(pattern).unwrap().captures(text)
+    }
+
+    #[test]
+    fn matches_anywhere_unless_anchored() {
+        assert!(Regex::new("l+").unwrap().is_match("hello"));
+        assert!(!Regex::new("^l+$").unwrap().is_match("hello"));
+        assert!(Regex::new("^h.*o$").unwrap().is_match("hello"));
+        assert!(!Regex::new("x").unwrap().is_match("hello"));
+    }
+
+    #[test]
+    fn groups_come_from_the_leftmost_match() {
+        let some = |x: &[&str]| Some(x.iter().map(|x| Some(x.to_string())).collect::<Vec<_>>());
+        assert_eq!(groups("on 2024-05-06", r"(\d+)-(\d+)-(\d+)"), some(&["2024", "05", "06"]));
+        assert_eq!(groups("ab", "(a|ab)(c|b)?"), some(&["a", "b"]));
+        assert_eq!(groups("aaa", "(a+?)"), some(&["a"]));
+        assert_eq!(groups("aaa", "(a+)"), some(&["aaa"]));
+        assert_eq!(groups("xyz", "(q)?y"), Some(vec![None]));
+        assert_eq!(groups("xyz", "q"), None);
+    }
+
+    #[test]
+    fn counted_repeats() {
+        let re = Regex::new("^a{2,3}$").unwrap();
+        assert!(!re.is_match("a"));
+        assert!(re.is_match("aa"));
+        assert!(re.is_match("aaa"));
+        assert!(!re.is_match("aaaa"));
+        assert!(Regex::new("^a{2,}$").unwrap().is_match("aaaaa"));
+    }
+
+    /// These take exponential time backtracking
+    #[test]
+    fn nested_repeats_are_linear() {
+        let text = format!("{}!", "a".repeat(10_000));
+        assert!(!Regex::new("^(a*)*$").unwrap().is_match(&text));
+        assert!(!Regex::new("^(a|aa)+$").unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["(a", "a)", "*a", "[a", "a{2,1}", "(a{1000}){1000}"] {
+            assert!(Regex::new(pattern).is_err(), "{} should be rejected", pattern);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use psc::env::Env;
+use psc::lex::{self, LexOptions};
+use psc::{load, parse};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+enum Input {
+    Line(String),
+    /// Ctrl-C, dropping whatever was typed so far
+    Interrupt,
+    Eof,
+}
+
+/// Reads lines with arrow key editing and history when stdin is a terminal,
+/// falling back to plain lines otherwise
+struct LineEditor {
+    history: Vec<String>,
+    tty: bool,
+}
+
+/// Puts the terminal in character at a time mode without echo for as long
+/// as it lives, so keys can be handled one by one
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn stty(args: &[&str]) -> Option<String> {
+        let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    }
+
+    fn enable() -> Option<RawMode> {
+        let saved = RawMode::stty(&["-g"])?;
+        RawMode::stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Some(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        RawMode::stty(&[&self.saved]);
+    }
+}
+
+impl LineEditor {
+    fn new() -> LineEditor {
+        LineEditor {
+            history: Vec::new(),
+            tty: io::stdin().is_terminal(),
+        }
+    }
+
+    /// `Input::Eof` at the end of input or on Ctrl-D with an empty line
+    fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+
+        let raw = if self.tty { RawMode::enable() } else { None };
+        let line = match raw {
+            Some(_) => self.edit(prompt)?,
+            None => {
+                let mut buf = String::new();
+                match io::stdin().read_line(&mut buf)? {
+                    0 => Input::Eof,
+                    _ => Input::Line(buf.trim_end_matches(['\n', '\r']).to_string()),
+                }
+            }
+        };
+        drop(raw);
+
+        if let Input::Line(line) = &line {
+            if !line.trim().is_empty() && self.history.last() != Some(line) {
+                self.history.push(line.clone());
+            }
+        }
+
+        Ok(line)
+    }
+
+    fn redraw(prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+        let text: String = line.iter().collect();
+        print!("\r{}{}\x1b[K", prompt, text);
+        if cursor < line.len() {
+            print!("\x1b[{}D", line.len() - cursor);
+        }
+        io::stdout().flush()
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdin = io::stdin().lock();
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Position in the history, `history.len()` is the line being typed
+        let mut pos = self.history.len();
+        let mut typed: Vec<char> = Vec::new();
+        let mut pending: Vec<u8> = Vec::new();
+
+        let mut byte = [0u8; 1];
+        loop {
+            if stdin.read(&mut byte)? == 0 {
+                println!();
+                return Ok(Input::Eof);
+            }
+
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    println!();
+                    return Ok(Input::Line(line.into_iter().collect()));
+                }
+
+                // Ctrl-D
+                4 if line.is_empty() => {
+                    println!();
+                    return Ok(Input::Eof);
+                }
+
+                3 => {
+                    println!("^C");
+                    return Ok(Input::Interrupt);
+                }
+
+                // Backspace
+                8 | 127 if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+
+                // Arrow keys, home/end and delete arrive as `ESC [ ...`
+                27 => {
+                    let mut seq = [0u8; 2];
+                    stdin.read_exact(&mut seq)?;
+
+                    match &seq {
+                        b"[A" | b"[B" => {
+                            if pos == self.history.len() {
+                                typed = line.clone();
+                            }
+
+                            let next = if seq[1] == b'A' {
+                                pos.saturating_sub(1)
+                            } else {
+                                (pos + 1).min(self.history.len())
+                            };
+
+                            if next != pos {
+                                pos = next;
+                                line = match self.history.get(pos) {
+                                    Some(x) => x.chars().collect(),
+                                    None => typed.clone(),
+                                };
+                                cursor = line.len();
+                            }
+                        }
+                        b"[C" if cursor < line.len() => cursor += 1,
+                        b"[D" if cursor > 0 => cursor -= 1,
+                        b"[H" => cursor = 0,
+                        b"[F" => cursor = line.len(),
+                        b"[3" => {
+                            stdin.read_exact(&mut byte)?;
+                            if cursor < line.len() {
+                                line.remove(cursor);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                c if c >= 0x20 && c != 127 => {
+                    pending.push(c);
+
+                    // Wait for the rest of multi-byte characters
+                    if let Ok(s) = std::str::from_utf8(&pending) {
+                        for ch in s.chars() {
+                            line.insert(cursor, ch);
+                            cursor += 1;
+                        }
+                        pending.clear();
+                    } else if pending.len() >= 4 {
+                        pending.clear();
+                    }
+                }
+
+                _ => {}
+            }
+
+            LineEditor::redraw(prompt, &line, cursor)?;
+        }
+    }
+}
+
+fn show_vars(env: &Env) {
+    let vars = env.globals();
+    if vars.is_empty() {
+        println!("(no variables)");
+    }
+
+    for (name, val) in vars {
+        println!("{} = {}", name, val);
+    }
+}
+
+/// Interactive session. Entries are run as soon as they form complete
+/// statements, unfinished blocks keep reading with a `...` prompt
+pub fn run(options: &LexOptions, make_env: &dyn Fn() -> Env) -> io::Result<()> {
+    let mut editor = LineEditor::new();
+    let mut env = make_env();
+    let mut buf = String::new();
+
+    loop {
+        let prompt = if buf.is_empty() { ">>> " } else { "... " };
+        let line = match editor.read_line(prompt)? {
+            Input::Line(x) => x,
+            Input::Interrupt => {
+                buf.clear();
+                continue;
+            }
+            Input::Eof => return Ok(()),
+        };
+
+        if buf.is_empty() {
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some(":vars") => {
+                    show_vars(&env);
+                    continue;
+                }
+                Some(":reset") => {
+                    env = make_env();
+                    println!("Environment reset");
+                    continue;
+                }
+                Some(":load") => {
+                    match words.next() {
+                        Some(file) => match load::load(Path::new(file), options) {
+                            Ok(stmts) => {
+                                if let Err(e) = env.run(&stmts) {
+                                    println!("{}", e);
+                                }
+                            }
+                            Err(e) => println!("{}", e),
+                        },
+                        None => println!(":load expects a file name"),
+                    }
+                    continue;
+                }
+                Some(":quit") | Some(":q") => return Ok(()),
+                Some(cmd) if cmd.starts_with(':') => {
+                    println!("Unknown command {}, try :vars, :load FILE, :reset or :quit", cmd);
+                    continue;
+                }
+                None => continue,
+                _ => {}
+            }
+        }
+
+        buf.push_str(&line);
+        buf.push('\n');
+
+        let tokens = match lex::lex(&buf, options) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("{}", e);
+                buf.clear();
+                continue;
+            }
+        };
+
+        let stmts = match parse::parse_partial(tokens) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("{}", e);
+                buf.clear();
+                continue;
+            }
+        };
+        buf.clear();
+
+        if let Err(e) = env.run(&stmts) {
+            if let Some(code) = env.halted {
+                std::process::exit(code);
+            }
+            println!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use crate::bigint::BigInt;
+use crate::emit;
+use crate::err::ParseError;
+use crate::eval::*;
+use crate::json::Json;
+use crate::lex::Span;
+use crate::pretty::op_symbol;
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+
+/// Written as JSON, for statements and expressions the JSON `--emit-ast`
+/// prints
+pub trait Serialize {
+    fn serialize(&self) -> Json;
+}
+
+/// Read from the JSON `Serialize` writes
+pub trait Deserialize: Sized {
+    fn deserialize(json: &Json) -> Result<Self, ParseError>;
+}
+
+impl Serialize for Stmt {
+    fn serialize(&self) -> Json {
+        emit::stmt(self)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize(&self) -> Json {
+        emit::expr(self)
+    }
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize(&self) -> Json {
+        Json::Array(self.iter().map(T::serialize).collect())
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize(json: &Json) -> Result<Self, ParseError> {
+        match json {
+            Json::Array(items) => items.iter().map(T::deserialize).collect(),
+            _ => Err(error("expected an array")),
+        }
+    }
+}
+
+fn error(msg: &str) -> ParseError {
+    ParseError { msg: format!("Bad JSON for the AST or a value, {}", msg), span: None, code: None }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, ParseError> {
+    json.get(key).ok_or_else(|| error(&format!("missing \"{}\"", key)))
+}
+
+fn string(json: &Json, key: &str) -> Result<String, ParseError> {
+    match field(json, key)? {
+        Json::String(x) => Ok(x.clone()),
+        _ => Err(error(&format!("\"{}\" should be a string", key))),
+    }
+}
+
+fn boolean(json: &Json, key: &str) -> Result<bool, ParseError> {
+    match field(json, key)? {
+        Json::Bool(x) => Ok(*x),
+        _ => Err(error(&format!("\"{}\" should be true or false", key))),
+    }
+}
+
+fn number(json: &Json, key: &str) -> Result<usize, ParseError> {
+    match field(json, key)?.as_i64() {
+        Some(x) if x >= 0 => Ok(x as usize),
+        _ => Err(error(&format!("\"{}\" should be a whole number", key))),
+    }
+}
+
+/// A string or null
+fn name(json: &Json, key: &str) -> Result<Option<String>, ParseError> {
+    match field(json, key)? {
+        Json::Null => Ok(None),
+        _ => string(json, key).map(Some),
+    }
+}
+
+fn names(json: &Json, key: &str) -> Result<Vec<String>, ParseError> {
+    let items = field(json, key)?.as_array().ok_or_else(|| error(&format!("\"{}\" should be an array", key)))?;
+    items.iter().map(|x| x.as_str().map(str::to_string).ok_or_else(|| error("expected a string"))).collect()
+}
+
+fn span(json: &Json) -> Result<Span, ParseError> {
+    Ok(Span {
+        line: number(json, "line")?,
+        col: number(json, "col")?,
+        len: number(json, "len")?,
+    })
+}
+
+fn expr(json: &Json, key: &str) -> Result<Expr, ParseError> {
+    Expr::deserialize(field(json, key)?)
+}
+
+/// An expression or null
+fn opt(json: &Json, key: &str) -> Result<Option<Expr>, ParseError> {
+    match field(json, key)? {
+        Json::Null => Ok(None),
+        x => Expr::deserialize(x).map(Some),
+    }
+}
+
+/// The type of a `DECLARE`, left out of any other assignment
+fn declared(json: &Json) -> Result<Option<Declared>, ParseError> {
+    let ty = match json.get("declared") {
+        None | Some(Json::Null) => return Ok(None),
+        Some(x) => x,
+    };
+    let bounds = field(ty, "bounds")?.as_array().ok_or_else(|| error("\"bounds\" should be an array"))?;
+    let bounds = bounds.iter().map(|x| match x.as_array() {
+        Some([lower, upper]) => lower.as_i64().zip(upper.as_i64()),
+        _ => None,
+    });
+    let bounds = bounds.collect::<Option<_>>().ok_or_else(|| error("each of \"bounds\" should be two integers"))?;
+    Ok(Some(Declared { name: string(ty, "name")?, bounds }))
+}
+
+fn exprs(json: &Json, key: &str) -> Result<Vec<Expr>, ParseError> {
+    Vec::deserialize(field(json, key)?)
+}
+
+fn stmts(json: &Json, key: &str) -> Result<Vec<Stmt>, ParseError> {
+    Vec::deserialize(field(json, key)?)
+}
+
+fn function(json: &Json) -> Result<Rc<Function>, ParseError> {
+    Ok(Rc::new(Function {
+        name: string(json, "name")?,
+        params: names(json, "params")?,
+        stmts: stmts(json, "body")?,
+        doc: name(json, "doc")?,
+    }))
+}
+
+/// The operators `op_symbol` has a symbol for
+const OPERATORS: &[BinaryOp] = &[
+    BinaryOp::Plus,
+    BinaryOp::Minus,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::FloorDiv,
+    BinaryOp::Mod,
+    BinaryOp::Equals,
+    BinaryOp::NotEquals,
+    BinaryOp::GT,
+    BinaryOp::LT,
+    BinaryOp::GE,
+    BinaryOp::LE,
+    BinaryOp::In,
+    BinaryOp::BAnd,
+    BinaryOp::BOr,
+    BinaryOp::BXor,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+];
+
+impl Deserialize for Expr {
+    fn deserialize(json: &Json) -> Result<Self, ParseError> {
+        let ret = match string(json, "type")?.as_str() {
+            "BinOp" => {
+                let op = string(json, "op")?;
+                let op = match OPERATORS.iter().find(|x| op_symbol(x) == op) {
+                    Some(x) => x.clone(),
+                    None => return Err(error(&format!("unknown operator {}", op))),
+                };
+                Expr::BinOp(Box::new(BinOp {
+                    left: expr(json, "left")?,
+                    right: expr(json, "right")?,
+                    op,
+                    span: span(json)?,
+                }))
+            }
+            "IfElse" => Expr::IfElse(Box::new(IfElse {
+                cond: expr(json, "cond")?,
+                then: expr(json, "then")?,
+                otherwise: expr(json, "otherwise")?,
+            })),
+            "Index" => Expr::Index(Box::new(Index {
+                target: expr(json, "target")?,
+                index: expr(json, "index")?,
+            })),
+            "MethodCall" => Expr::MethodCall(Box::new(MethodCall {
+                target: expr(json, "target")?,
+                name: string(json, "name")?,
+                args: exprs(json, "args")?,
+                span: span(json)?,
+            })),
+            "Field" => Expr::Field(Box::new(Field {
+                target: expr(json, "target")?,
+                name: string(json, "name")?,
+            })),
+            "Call" => Expr::Call(Call {
+                name: string(json, "name")?,
+                args: exprs(json, "args")?,
+                span: span(json)?,
+            }),
+            "SuperCall" => Expr::SuperCall(SuperCall {
+                name: string(json, "name")?,
+                args: exprs(json, "args")?,
+                span: span(json)?,
+            }),
+            "New" => Expr::New(New {
+                class: string(json, "class")?,
+                args: exprs(json, "args")?,
+                span: span(json)?,
+            }),
+            "Map" => {
+                let entries = field(json, "entries")?.as_array().ok_or_else(|| error("\"entries\" should be an array"))?;
+                let entries = entries.iter().map(|x| Ok((expr(x, "key")?, expr(x, "value")?)));
+                Expr::Map(entries.collect::<Result<_, ParseError>>()?)
+            }
+            "Set" => Expr::Set(exprs(json, "items")?),
+            "Array" => Expr::Array(exprs(json, "items")?),
+            "AddressOf" => Expr::AddressOf(string(json, "name")?),
+            "Deref" => Expr::Deref(Box::new(expr(json, "expr")?)),
+            "BNot" => Expr::BNot(Box::new(expr(json, "expr")?)),
+            "IntLit" => match field(json, "value")? {
+                Json::Int(x) => Expr::IntLit(*x),
+                _ => return Err(error("\"value\" of an IntLit should be an integer")),
+            },
+            // Other tools can write `2.0` as `2`
+            "FloatLit" => match field(json, "value")? {
+                Json::Float(x) => Expr::FloatLit(*x),
+                Json::Int(x) => Expr::FloatLit(*x as f64),
+                _ => return Err(error("\"value\" of a FloatLit should be a number")),
+            },
+            "BoolLit" => Expr::BoolLit(boolean(json, "value")?),
+            "StrLit" => Expr::StrLit(string(json, "value")?),
+            "NullLit" => Expr::NullLit,
+            "Ident" => Expr::Ident(string(json, "name")?),
+            x => return Err(error(&format!("unknown expression {}", x))),
+        };
+        Ok(ret)
+    }
+}
+
+impl Deserialize for Stmt {
+    fn deserialize(json: &Json) -> Result<Self, ParseError> {
+        let kind = match string(json, "type")?.as_str() {
+            "Assign" => StmtKind::Assign(Assign {
+                target: expr(json, "target")?,
+                expr: expr(json, "expr")?,
+                declared: declared(json)?,
+                literal: OnceCell::new(),
+            }),
+            "MultiAssign" => StmtKind::MultiAssign(MultiAssign {
+                targets: exprs(json, "targets")?,
+                exprs: exprs(json, "exprs")?,
+            }),
+            "Input" => {
+                let kind = match name(json, "kind")?.as_deref() {
+                    Some("integer") => Some(InputType::Integer),
+                    Some("real") => Some(InputType::Real),
+                    Some("string") => Some(InputType::String),
+                    Some("boolean") => Some(InputType::Boolean),
+                    Some(x) => return Err(error(&format!("unknown input type {}", x))),
+                    None => None,
+                };
+                StmtKind::Input(Input { ident: string(json, "ident")?, kind })
+            }
+            "Output" => StmtKind::Output(Output { expr: expr(json, "expr")? }),
+            "If" => {
+                let branches = field(json, "branches")?.as_array().ok_or_else(|| error("\"branches\" should be an array"))?;
+                let branches = branches.iter().map(|x| Ok((expr(x, "cond")?, stmts(x, "body")?)));
+                StmtKind::If(If { branches: branches.collect::<Result<_, ParseError>>()? })
+            }
+            "While" => StmtKind::While(While {
+                cond: expr(json, "cond")?,
+                stmts: stmts(json, "body")?,
+            }),
+            "Until" => StmtKind::Until(Until {
+                cond: expr(json, "cond")?,
+                stmts: stmts(json, "body")?,
+            }),
+            "Repeat" => StmtKind::Repeat(Until {
+                cond: expr(json, "cond")?,
+                stmts: stmts(json, "body")?,
+            }),
+            "For" => StmtKind::For(For {
+                name: string(json, "name")?,
+                start: expr(json, "start")?,
+                end: expr(json, "end")?,
+                stmts: stmts(json, "body")?,
+            }),
+            "ForEach" => StmtKind::ForEach(ForEach {
+                name: string(json, "name")?,
+                iter: expr(json, "iter")?,
+                stmts: stmts(json, "body")?,
+            }),
+            "Expr" => StmtKind::Expr(expr(json, "expr")?),
+            "Function" => StmtKind::Function(function(field(json, "function")?)?),
+            "Class" => {
+                let fields = field(json, "fields")?.as_array().ok_or_else(|| error("\"fields\" should be an array"))?;
+                let fields = fields.iter().map(|x| {
+                    Ok(FieldDecl {
+                        name: string(x, "name")?,
+                        public: boolean(x, "public")?,
+                        init: opt(x, "init")?,
+                    })
+                });
+                let methods = field(json, "methods")?.as_array().ok_or_else(|| error("\"methods\" should be an array"))?;
+                let methods = methods.iter().map(|x| {
+                    Ok(Method {
+                        public: boolean(x, "public")?,
+                        func: function(field(x, "function")?)?,
+                    })
+                });
+                StmtKind::Class(Rc::new(Class {
+                    name: string(json, "name")?,
+                    parent: name(json, "parent")?,
+                    fields: fields.collect::<Result<_, ParseError>>()?,
+                    methods: methods.collect::<Result<_, ParseError>>()?,
+                }))
+            }
+            "Return" => StmtKind::Return(opt(json, "expr")?),
+            "Global" => StmtKind::Global(names(json, "names")?),
+            "Type" => StmtKind::Type(TypeDef {
+                name: string(json, "name")?,
+                ty: declared(json)?.ok_or_else(|| error("missing field \"declared\""))?,
+            }),
+            "Attempt" => StmtKind::Attempt(Attempt {
+                stmts: stmts(json, "body")?,
+                name: name(json, "name")?,
+                handler: stmts(json, "handler")?,
+            }),
+            "Assert" => StmtKind::Assert(Assert {
+                cond: expr(json, "cond")?,
+                msg: opt(json, "msg")?,
+            }),
+            "Halt" => StmtKind::Halt(opt(json, "code")?),
+            "Include" => StmtKind::Include(string(json, "file")?),
+            x => return Err(error(&format!("unknown statement {}", x))),
+        };
+        Ok(Stmt { kind, span: span(json)? })
+    }
+}
+
+/// `{"type": NAME, ...}` for the values JSON has nothing like
+fn tagged(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", Json::str(kind)));
+    Json::object(fields)
+}
+
+/// `seen` are the pointers and objects it's inside of. One inside itself is
+/// left out the second time, a pointer's value is null and an object has no
+/// fields
+fn value(val: &PscObject, seen: &mut Vec<*const ()>) -> Json {
+    let mut items = |items: &[PscObject]| Json::Array(items.iter().map(|x| value(x, seen)).collect());
+
+    match val {
+        PscObject::IntT(x) => Json::Int(*x),
+        PscObject::BigIntT(x) => tagged("bigint", vec![("value", Json::String(x.to_string()))]),
+        // JSON has no infinities or NaN
+        PscObject::FloatT(x) if !x.is_finite() => tagged("real", vec![("value", Json::String(x.to_string()))]),
+        PscObject::FloatT(x) => Json::Float(*x),
+        PscObject::StringT(x) => Json::str(x),
+        PscObject::BoolT(x) => Json::Bool(*x),
+        PscObject::ArrayT(x) => items(x),
+        PscObject::MapT(x) => {
+            let entries = x.iter().map(|(k, v)| Json::Array(vec![value(k, seen), value(v, seen)])).collect();
+            tagged("map", vec![("entries", Json::Array(entries))])
+        }
+        PscObject::SetT(x) => tagged("set", vec![("items", items(x))]),
+        PscObject::CollectionT(x, next) => tagged("collection", vec![("items", items(x)), ("next", Json::Int(*next as i64))]),
+        PscObject::RefT(x) => {
+            let ptr = Rc::as_ptr(x) as *const ();
+            if seen.contains(&ptr) {
+                return tagged("pointer", vec![("value", Json::Null)]);
+            }
+            seen.push(ptr);
+            let ret = tagged("pointer", vec![("value", value(&x.borrow(), seen))]);
+            seen.pop();
+            ret
+        }
+        PscObject::ObjectT(x) => {
+            let ptr = Rc::as_ptr(x) as *const ();
+            let obj = x.borrow();
+            let mut fields = Vec::new();
+            if !seen.contains(&ptr) {
+                seen.push(ptr);
+                let mut names: Vec<&String> = obj.fields.keys().collect();
+                names.sort();
+                fields = names.into_iter().map(|k| (k.clone(), value(&obj.fields[k].borrow(), seen))).collect();
+                seen.pop();
+            }
+            tagged("object", vec![("class", Json::str(&obj.class.name)), ("fields", Json::Object(fields))])
+        }
+        PscObject::FunctionT(x) => tagged("function", vec![("name", Json::str(&x.func.name))]),
+        PscObject::NullT => Json::Null,
+    }
+}
+
+/// Values JSON has are written as themselves, like `[1, "a"]` for an array,
+/// and the rest as `{"type": ..., ...}`. Objects and functions only go one
+/// way, they need the program that made them
+impl Serialize for PscObject {
+    fn serialize(&self) -> Json {
+        value(self, &mut Vec::new())
+    }
+}
+
+fn items(json: &Json, key: &str) -> Result<Vec<PscObject>, ParseError> {
+    Vec::deserialize(field(json, key)?)
+}
+
+impl Deserialize for PscObject {
+    fn deserialize(json: &Json) -> Result<Self, ParseError> {
+        let kind = match json {
+            Json::Null => return Ok(PscObject::NullT),
+            Json::Bool(x) => return Ok(PscObject::BoolT(*x)),
+            Json::Int(x) => return Ok(PscObject::IntT(*x)),
+            Json::Float(x) => return Ok(PscObject::FloatT(*x)),
+            Json::String(x) => return Ok(PscObject::StringT(x.clone())),
+            Json::Array(_) => return Vec::deserialize(json).map(PscObject::ArrayT),
+            Json::Object(_) => string(json, "type")?,
+        };
+
+        match kind.as_str() {
+            "bigint" => match BigInt::parse(&string(json, "value")?) {
+                Some(x) => Ok(PscObject::BigIntT(x)),
+                None => Err(error("\"value\" of a bigint should be its digits")),
+            },
+            "real" => match string(json, "value")?.parse::<f64>() {
+                Ok(x) => Ok(PscObject::FloatT(x)),
+                Err(_) => Err(error("\"value\" of a real should be a number")),
+            },
+            "map" => {
+                let entries = field(json, "entries")?.as_array().ok_or_else(|| error("\"entries\" should be an array"))?;
+                let entries = entries.iter().map(|x| match x.as_array() {
+                    Some([k, v]) => Ok((PscObject::deserialize(k)?, PscObject::deserialize(v)?)),
+                    _ => Err(error("each entry of a map should be a key and a value")),
+                });
+                Ok(PscObject::MapT(entries.collect::<Result<_, ParseError>>()?))
+            }
+            "set" => Ok(PscObject::set_from(items(json, "items")?)),
+            "collection" => Ok(PscObject::CollectionT(items(json, "items")?, number(json, "next")?)),
+            "pointer" => {
+                let val = PscObject::deserialize(field(json, "value")?)?;
+                Ok(PscObject::RefT(Rc::new(RefCell::new(val))))
+            }
+            "object" | "function" => Err(error(&format!("{}s can't be read back", kind))),
+            x => Err(error(&format!("unknown value {}", x))),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use psc::json::Json;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+/// Everything a run depends on from outside, enough to run it again the
+/// same way
+pub struct Session {
+    pub program: String,
+    pub seed: u64,
+    pub args: Vec<String>,
+    /// The lines of input the program read, each with its line ending
+    pub input: Vec<String>,
+}
+
+impl Session {
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let strings = |items: &[String]| Json::Array(items.iter().map(|x| Json::str(x)).collect());
+        let json = Json::object(vec![
+            ("program", Json::str(&self.program)),
+            // Kept as the bits of the seed, which JSON integers can't all hold
+            ("seed", Json::Int(self.seed as i64)),
+            ("args", strings(&self.args)),
+            ("input", strings(&self.input)),
+        ]);
+        fs::write(path, format!("{}\n", json)).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    pub fn load(path: &str) -> Result<Session, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json = Json::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        let bad = || format!("{}: not a session recorded with --record", path);
+
+        let strings = |key: &str| -> Option<Vec<String>> {
+            json.get(key)?.as_array()?.iter().map(|x| x.as_str().map(String::from)).collect()
+        };
+
+        Ok(Session {
+            program: json.get("program").and_then(Json::as_str).ok_or_else(bad)?.to_string(),
+            seed: json.get("seed").and_then(Json::as_i64).ok_or_else(bad)? as u64,
+            args: strings("args").ok_or_else(bad)?,
+            input: strings("input").ok_or_else(bad)?,
+        })
+    }
+}
+
+/// Input that keeps a copy of everything read through it
+pub struct Recorder {
+    pub inner: Box<dyn BufRead>,
+    pub read: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl BufRead for Recorder {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Already buffered, so this doesn't read any more
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.read.borrow_mut().extend_from_slice(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// The lines of `read`, each keeping its line ending
+pub fn lines(read: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(read).split_inclusive('\n').map(String::from).collect()
+}
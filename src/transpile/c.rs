@@ -0,0 +1,1500 @@
+use super::{all_globals, assigned_in_scope, globals, unsupported, walk, walk_scope, Program, Target};
+use psc::eval::*;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+/// Strings, arrays, checked arithmetic and I/O, at the top of the
+/// generated code so it's one file that compiles by itself
+const RUNTIME: &str = include_str!("psc.h");
+
+type Res<T> = Result<T, String>;
+
+/// C has no dynamic values, so every variable, parameter and result gets one
+/// of these, worked out from how the program uses it
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    /// Not worked out yet
+    Unknown,
+    /// What procedures return
+    Void,
+    Int,
+    Real,
+    Bool,
+    Str,
+    Array(Box<Type>),
+}
+
+impl Type {
+    fn c(&self) -> &'static str {
+        match self {
+            Type::Unknown | Type::Int => "long long",
+            Type::Real => "double",
+            Type::Bool => "bool",
+            Type::Str => "const char *",
+            Type::Array(_) => "psc_array",
+            Type::Void => "void",
+        }
+    }
+
+    /// The `psc_kind` of arrays holding this
+    fn kind(&self) -> &'static str {
+        match self {
+            Type::Real => "PSC_REAL",
+            Type::Bool => "PSC_BOOL",
+            Type::Str => "PSC_STR",
+            Type::Array(_) => "PSC_ARRAY",
+            _ => "PSC_INT",
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Type::Unknown => "an unknown value".into(),
+            Type::Void => "nothing".into(),
+            Type::Int => "an integer".into(),
+            Type::Real => "a real".into(),
+            Type::Bool => "a boolean".into(),
+            Type::Str => "a string".into(),
+            Type::Array(x) => format!("an array of {}", x.plural()),
+        }
+    }
+
+    fn plural(&self) -> String {
+        match self {
+            Type::Array(x) => format!("arrays of {}", x.plural()),
+            Type::Unknown | Type::Void => "unknown values".into(),
+            x => format!("{}s", x.name().split_once(' ').map_or("", |x| x.1)),
+        }
+    }
+
+    fn numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Real)
+    }
+
+    /// Settles whatever is still unknown as an integer
+    fn resolve(&self) -> Type {
+        match self {
+            Type::Unknown => Type::Int,
+            Type::Array(x) => Type::Array(Box::new(x.resolve())),
+            x => x.clone(),
+        }
+    }
+
+    fn array(of: Type) -> Type {
+        Type::Array(Box::new(of))
+    }
+}
+
+/// The type that can hold both `a` and `b`, integers widen to reals
+fn unify(a: &Type, b: &Type) -> Option<Type> {
+    match (a, b) {
+        (Type::Unknown, x) | (x, Type::Unknown) => Some(x.clone()),
+        (Type::Int, Type::Real) | (Type::Real, Type::Int) => Some(Type::Real),
+        (Type::Array(a), Type::Array(b)) => unify(a, b).map(Type::array),
+        (a, b) if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+/// What inference knows about a function
+struct Sig<'a> {
+    func: &'a Function,
+    /// Parameters and locals
+    vars: HashMap<String, Type>,
+    ret: Type,
+    /// Whether any `return` gives a value, procedures return `void`
+    returns: bool,
+}
+
+/// Names C or the header already use
+const RESERVED: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern",
+    "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "bool",
+    "true", "false", "main", "argc", "argv", "printf", "puts", "exit", "malloc", "free", "strlen", "strcmp",
+    "strstr", "index", "time", "clock", "rand", "random", "abs", "floor", "fmod", "sqrt", "pow", "getenv", "errno",
+    "NULL", "INFINITY", "NAN",
+];
+
+fn ident(name: &str) -> String {
+    if RESERVED.contains(&name) || name.starts_with("psc_") || name.starts_with("PSC_") {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn literal(text: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '\r' => ret.push_str("\\r"),
+            // Keeps `??` from starting a trigraph
+            '?' if ret.ends_with('?') => ret.push_str("\\?"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => ret.push_str(&format!("\\{:03o}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn real(x: f64) -> String {
+    if x.is_nan() {
+        "NAN".into()
+    } else if x.is_infinite() {
+        if x > 0.0 { "INFINITY" } else { "-INFINITY" }.into()
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+/// The variable an assignment target or `append` starts from
+fn root(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Ident(name) => Some(name),
+        Expr::Index(x) => root(&x.target),
+        _ => None,
+    }
+}
+
+/// Whether `expr` calls a function of the program, or appends to `name`
+fn touches(expr: &Expr, name: &str, prog: &Program) -> bool {
+    let f = |x: &Expr| touches(x, name, prog);
+    match expr {
+        Expr::Call(x) => prog.functions.contains(x.name.as_str()) || x.args.iter().any(f),
+        Expr::MethodCall(x) => root(&x.target) == Some(name) || f(&x.target) || x.args.iter().any(f),
+        Expr::BinOp(x) => f(&x.left) || f(&x.right),
+        Expr::IfElse(x) => f(&x.cond) || f(&x.then) || f(&x.otherwise),
+        Expr::Index(x) => f(&x.target) || f(&x.index),
+        Expr::Array(items) => items.iter().any(f),
+        Expr::BNot(x) => f(x),
+        _ => false,
+    }
+}
+
+/// Whether running `stmts` can change the variable `name`
+fn modifies(stmts: &[Stmt], name: &str, prog: &Program) -> bool {
+    let mut ret = false;
+    walk(stmts, &mut |stmt| {
+        let exprs: Vec<&Expr> = match &stmt.kind {
+            StmtKind::Assign(x) => {
+                ret |= root(&x.target) == Some(name);
+                vec![&x.target, x.value()]
+            }
+            StmtKind::MultiAssign(x) => {
+                ret |= x.targets.iter().any(|t| root(t) == Some(name));
+                x.targets.iter().chain(&x.exprs).collect()
+            }
+            StmtKind::Input(x) => {
+                ret |= x.ident == name;
+                vec![]
+            }
+            StmtKind::Output(x) => vec![&x.expr],
+            StmtKind::If(x) => x.branches.iter().map(|(cond, _)| cond).collect(),
+            StmtKind::While(While { cond, .. }) | StmtKind::Until(Until { cond, .. }) | StmtKind::Repeat(Until { cond, .. }) => {
+                vec![cond]
+            }
+            StmtKind::For(x) => vec![&x.start, &x.end],
+            StmtKind::ForEach(x) => vec![&x.iter],
+            StmtKind::Expr(x) | StmtKind::Return(Some(x)) | StmtKind::Halt(Some(x)) => vec![x],
+            StmtKind::Assert(x) => vec![&x.cond],
+            _ => vec![],
+        };
+        ret |= exprs.into_iter().any(|x| touches(x, name, prog));
+    });
+    ret
+}
+
+/// A C declaration of the C name `name` holding `t`, without the semicolon
+fn declare(t: &Type, name: &str) -> String {
+    let c = t.c();
+    let space = if c.ends_with('*') { "" } else { " " };
+    format!("{}{}{}", c, space, name)
+}
+
+/// Whether `expr` is made of literals only. C gives those the type `int`,
+/// which is too small for psc integers
+fn constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::IntLit(_) | Expr::FloatLit(_) | Expr::BoolLit(_) | Expr::StrLit(_) => true,
+        Expr::BinOp(x) if matches!(x.op, BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod) => false,
+        Expr::BinOp(x) => constant(&x.left) && constant(&x.right),
+        Expr::IfElse(x) => constant(&x.cond) && constant(&x.then) && constant(&x.otherwise),
+        Expr::BNot(x) => constant(x),
+        _ => false,
+    }
+}
+
+/// Names that only exist inside the loops of a body, bound by a loop or
+/// first assigned in its body, where `outer` are the body's own
+fn loop_locals(stmts: &[Stmt], outer: &HashSet<String>, ret: &mut Vec<String>) {
+    walk_scope(stmts, &mut |stmt| {
+        let (stmts, bind) = match &stmt.kind {
+            StmtKind::While(While { stmts, .. })
+            | StmtKind::Until(Until { stmts, .. })
+            | StmtKind::Repeat(Until { stmts, .. }) => (stmts, None),
+            StmtKind::For(For { name, stmts, .. }) | StmtKind::ForEach(ForEach { name, stmts, .. }) => {
+                (stmts, Some(name))
+            }
+            _ => return,
+        };
+
+        let mut outer = outer.clone();
+        for name in bind.cloned().into_iter().chain(assigned_in_scope(stmts)) {
+            if outer.insert(name.clone()) && !ret.contains(&name) {
+                ret.push(name);
+            }
+        }
+        loop_locals(stmts, &outer, ret);
+    });
+}
+
+struct C<'a> {
+    prog: &'a Program<'a>,
+    out: String,
+    indent: usize,
+    sigs: HashMap<&'a str, Sig<'a>>,
+    /// Top level variables, which are C globals
+    globals: HashMap<String, Type>,
+    /// Variables `input` reads without a type, strings unless the program
+    /// uses them some other way. Keyed by function, `None` for the top level
+    untyped: HashSet<(Option<&'a str>, String)>,
+    /// Variables only loop bodies have, keyed by function like `untyped`
+    loop_locals: HashSet<(Option<&'a str>, String)>,
+    /// Those of the loop bodies being looked at, innermost last
+    blocks: Vec<HashSet<String>>,
+    /// The function being looked at
+    func: Option<&'a str>,
+    /// Line of the statement being looked at, for errors
+    line: usize,
+    /// Whether an inference pass learnt anything
+    changed: bool,
+    tmp: Cell<usize>,
+}
+
+impl<'a> C<'a> {
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.out.push_str(&"    ".repeat(self.indent));
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn tmp(&self) -> usize {
+        self.tmp.set(self.tmp.get() + 1);
+        self.tmp.get()
+    }
+
+    fn error(&self, msg: String) -> String {
+        format!("Line {}: {}", self.line, msg)
+    }
+
+    /// The function whose local `name` is, as seen from the current one
+    fn scope(&self, name: &str) -> Option<&'a str> {
+        self.func.filter(|f| self.sigs[f].vars.contains_key(name))
+    }
+
+    /// Whether `name` is seen from the code being looked at, which a
+    /// variable only loop bodies have isn't outside them
+    fn visible(&self, name: &str) -> bool {
+        !self.loop_locals.contains(&(self.scope(name), name.to_string()))
+            || self.blocks.iter().any(|x| x.contains(name))
+    }
+
+    fn var_type(&self, name: &str) -> Option<Type> {
+        if !self.visible(name) {
+            return None;
+        }
+
+        match self.scope(name) {
+            Some(f) => Some(self.sigs[f].vars[name].clone()),
+            None => self.globals.get(name).cloned(),
+        }
+    }
+
+    fn slot(&mut self, func: Option<&str>, name: &str) -> &mut Type {
+        match func {
+            Some(f) => self.sigs.get_mut(f).unwrap().vars.get_mut(name).unwrap(),
+            None => self.globals.entry(name.to_string()).or_insert(Type::Unknown),
+        }
+    }
+
+    /// Widens what `slot` holds to take `ty` too
+    fn widen(&mut self, func: Option<&str>, name: &str, ty: &Type) -> Res<()> {
+        let old = self.slot(func, name).clone();
+        match unify(&old, ty) {
+            Some(new) if new != old => {
+                *self.slot(func, name) = new;
+                self.changed = true;
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None => Err(self.error(format!("{} can't hold both {} and {} in C", name, old.name(), ty.name()))),
+        }
+    }
+
+    /// Learns that `place` holds values of `ty`. Other expressions are left
+    /// alone, their types come from what's in them
+    fn constrain(&mut self, place: &Expr, ty: Type) -> Res<()> {
+        match place {
+            _ if ty == Type::Void => Err(self.error("There's no value here, the call returns nothing".into())),
+            Expr::Ident(name) if self.var_type(name).is_some() => self.widen(self.scope(name), name, &ty),
+            Expr::Index(x) => self.constrain(&x.target, Type::array(ty)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Learns from the operators and calls inside `expr`
+    fn learn(&mut self, expr: &'a Expr) -> Res<()> {
+        match expr {
+            Expr::BinOp(x) => {
+                self.learn(&x.left)?;
+                self.learn(&x.right)?;
+                let (l, r) = (self.type_of(&x.left)?, self.type_of(&x.right)?);
+
+                match x.op {
+                    BinaryOp::BAnd | BinaryOp::BOr | BinaryOp::BXor | BinaryOp::Shl | BinaryOp::Shr => {
+                        self.constrain(&x.left, Type::Int)?;
+                        self.constrain(&x.right, Type::Int)?;
+                    }
+                    BinaryOp::In => match r {
+                        Type::Array(t) => self.constrain(&x.left, *t)?,
+                        Type::Str => self.constrain(&x.left, Type::Str)?,
+                        _ => {}
+                    },
+                    _ => {
+                        if l == Type::Unknown {
+                            self.constrain(&x.left, r.clone())?;
+                        }
+                        if r == Type::Unknown {
+                            self.constrain(&x.right, l)?;
+                        }
+                    }
+                }
+            }
+            Expr::IfElse(x) => {
+                self.learn(&x.cond)?;
+                self.learn(&x.then)?;
+                self.learn(&x.otherwise)?;
+                self.constrain(&x.cond, Type::Bool)?;
+            }
+            Expr::Index(x) => {
+                self.learn(&x.target)?;
+                self.learn(&x.index)?;
+                self.constrain(&x.index, Type::Int)?;
+            }
+            Expr::MethodCall(x) => {
+                self.learn(&x.target)?;
+                for arg in &x.args {
+                    self.learn(arg)?;
+                }
+
+                match (x.name.to_lowercase().as_str(), x.args.as_slice()) {
+                    ("append", [arg]) => {
+                        let t = self.type_of(arg)?;
+                        self.constrain(&x.target, Type::array(t))?;
+                    }
+                    ("length", []) => self.constrain(&x.target, Type::array(Type::Unknown))?,
+                    _ => {}
+                }
+            }
+            Expr::Call(x) => {
+                for arg in &x.args {
+                    self.learn(arg)?;
+                }
+
+                if let Some(sig) = self.sigs.get(x.name.as_str()) {
+                    let func = sig.func;
+                    if func.params.len() == x.args.len() {
+                        for (param, arg) in func.params.iter().zip(&x.args) {
+                            let t = self.type_of(arg)?;
+                            self.widen(Some(&func.name), param, &t)?;
+                            let t = self.sigs[func.name.as_str()].vars[param].clone();
+                            self.constrain(arg, t)?;
+                        }
+                    }
+                } else {
+                    let want = match self.prog.builtin(&x.name) {
+                        Some("TRIM" | "REPLACE" | "SPLIT" | "CONTAINS" | "STARTSWITH" | "ENDSWITH") => Type::Str,
+                        Some("RANDOM" | "SETSEED" | "SLEEP" | "WAIT") => Type::Int,
+                        _ => Type::Unknown,
+                    };
+                    for arg in &x.args {
+                        self.constrain(arg, want.clone())?;
+                    }
+                }
+            }
+            Expr::Array(items) | Expr::Set(items) => {
+                for item in items {
+                    self.learn(item)?;
+                }
+            }
+            Expr::BNot(x) => {
+                self.learn(x)?;
+                self.constrain(x, Type::Int)?;
+            }
+            _ => {}
+        }
+
+        self.type_of(expr).map(|_| ())
+    }
+
+    /// Starts looking at a loop body, which has a scope of its own in psc.
+    /// Returns the variables first assigned in it, for C to declare there
+    fn enter(&mut self, stmts: &[Stmt], bind: Option<&str>) -> Vec<String> {
+        let fresh: Vec<String> = assigned_in_scope(stmts)
+            .into_iter()
+            .filter(|x| Some(x.as_str()) != bind && self.loop_locals.contains(&(self.func, x.clone())))
+            .filter(|x| !self.blocks.iter().any(|b| b.contains(x)))
+            .collect();
+
+        let mut block: HashSet<String> = fresh.iter().cloned().collect();
+        block.extend(bind.map(String::from));
+        self.blocks.push(block);
+        fresh
+    }
+
+    fn infer_loop(&mut self, stmts: &'a [Stmt], bind: Option<&str>) -> Res<()> {
+        self.enter(stmts, bind);
+        let res = self.infer(stmts, false);
+        self.blocks.pop();
+        res
+    }
+
+    fn infer(&mut self, stmts: &'a [Stmt], top: bool) -> Res<()> {
+        for stmt in stmts {
+            self.line = stmt.span.line;
+
+            match &stmt.kind {
+                StmtKind::Assign(x) => {
+                    self.learn(x.value())?;
+                    self.learn(&x.target)?;
+                    let t = self.type_of(x.value())?;
+                    self.constrain(&x.target, t)?;
+                }
+                StmtKind::MultiAssign(x) => {
+                    for (target, expr) in x.targets.iter().zip(&x.exprs) {
+                        self.learn(expr)?;
+                        self.learn(target)?;
+                        let t = self.type_of(expr)?;
+                        self.constrain(target, t)?;
+                    }
+                }
+                StmtKind::Input(x) => {
+                    let t = match x.kind {
+                        Some(InputType::Integer) => Type::Int,
+                        Some(InputType::Real) => Type::Real,
+                        Some(InputType::String) => Type::Str,
+                        Some(InputType::Boolean) => Type::Bool,
+                        None => {
+                            self.untyped.insert((self.scope(&x.ident), x.ident.clone()));
+                            Type::Unknown
+                        }
+                    };
+                    self.widen(self.scope(&x.ident), &x.ident, &t)?;
+                }
+                StmtKind::Output(x) => self.learn(&x.expr)?,
+                StmtKind::If(x) => {
+                    for (cond, stmts) in &x.branches {
+                        self.learn(cond)?;
+                        self.constrain(cond, Type::Bool)?;
+                        self.infer(stmts, false)?;
+                    }
+                }
+                StmtKind::While(While { cond, stmts })
+                | StmtKind::Until(Until { cond, stmts })
+                | StmtKind::Repeat(Until { cond, stmts }) => {
+                    self.learn(cond)?;
+                    self.constrain(cond, Type::Bool)?;
+                    self.infer_loop(stmts, None)?;
+                }
+                StmtKind::For(x) => {
+                    self.learn(&x.start)?;
+                    self.learn(&x.end)?;
+                    self.constrain(&x.start, Type::Int)?;
+                    self.constrain(&x.end, Type::Int)?;
+                    self.widen(self.scope(&x.name), &x.name, &Type::Int)?;
+                    self.infer_loop(&x.stmts, Some(&x.name))?;
+                }
+                StmtKind::ForEach(x) => {
+                    self.learn(&x.iter)?;
+                    let t = match self.type_of(&x.iter)? {
+                        Type::Array(t) => *t,
+                        Type::Str => Type::Str,
+                        Type::Unknown => Type::Unknown,
+                        t => return Err(self.error(format!("Cannot loop over {}", t.name()))),
+                    };
+                    self.widen(self.scope(&x.name), &x.name, &t)?;
+                    self.infer_loop(&x.stmts, Some(&x.name))?;
+                }
+                StmtKind::Expr(x) => self.learn(x)?,
+                StmtKind::Function(_) if top && self.func.is_none() => {}
+                StmtKind::Function(_) => return Err(unsupported("Nested functions", Target::C)),
+                StmtKind::Class(_) => return Err(unsupported("Classes", Target::C)),
+                StmtKind::Return(x) => {
+                    if let Some(x) = x {
+                        self.learn(x)?;
+                    }
+                    let t = match x {
+                        Some(x) => self.type_of(x)?,
+                        None => Type::Void,
+                    };
+
+                    if let Some(f) = self.func {
+                        let sig = &self.sigs[f];
+                        match (&t, sig.returns) {
+                            (Type::Void, _) => {}
+                            (t, false) => {
+                                let sig = self.sigs.get_mut(f).unwrap();
+                                sig.ret = t.clone();
+                                sig.returns = true;
+                                self.changed = true;
+                            }
+                            (t, true) => match unify(&sig.ret, t) {
+                                Some(new) if new != sig.ret => {
+                                    self.sigs.get_mut(f).unwrap().ret = new;
+                                    self.changed = true;
+                                }
+                                Some(_) => {}
+                                None => {
+                                    return Err(self.error(format!(
+                                        "{} returns both {} and {}, C needs one type",
+                                        f,
+                                        sig.ret.name(),
+                                        t.name()
+                                    )))
+                                }
+                            },
+                        }
+                    }
+                }
+                StmtKind::Global(_) | StmtKind::Type(_) => {}
+                StmtKind::Attempt(_) => return Err(unsupported("Attempt blocks", Target::C)),
+                StmtKind::Assert(x) => {
+                    self.learn(&x.cond)?;
+                    self.constrain(&x.cond, Type::Bool)?;
+                    if let Some(msg) = &x.msg {
+                        self.learn(msg)?;
+                    }
+                }
+                StmtKind::Halt(x) => {
+                    if let Some(x) = x {
+                        self.learn(x)?;
+                        self.constrain(x, Type::Int)?;
+                    }
+                }
+                StmtKind::Include(_) => unreachable!(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs inference over the whole program until it stops learning
+    fn settle(&mut self) -> Res<()> {
+        for _ in 0..100 {
+            self.changed = false;
+
+            self.func = None;
+            self.infer(self.prog.stmts, true)?;
+
+            let funcs: Vec<&'a Function> = self.sigs.values().map(|x| x.func).collect();
+            for func in funcs {
+                self.func = Some(&func.name);
+                self.infer(&func.stmts, false)?;
+            }
+            self.func = None;
+
+            if !self.changed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out the type of every variable, parameter and result
+    fn types(&mut self) -> Res<()> {
+        self.settle()?;
+
+        for (func, name) in self.untyped.clone() {
+            let slot = self.slot(func, &name);
+            if *slot == Type::Unknown {
+                *slot = Type::Str;
+            }
+        }
+        self.settle()?;
+
+        for x in self.globals.values_mut() {
+            *x = x.resolve();
+        }
+        for sig in self.sigs.values_mut() {
+            for x in sig.vars.values_mut() {
+                *x = x.resolve();
+            }
+            sig.ret = if sig.returns { sig.ret.resolve() } else { Type::Void };
+        }
+        self.settle()
+    }
+
+    fn call_type(&self, call: &Call) -> Res<Type> {
+        if let Some(sig) = self.sigs.get(call.name.as_str()) {
+            if sig.func.params.len() != call.args.len() {
+                return Err(self.error(format!(
+                    "{} expects {} argument(s) but got {}",
+                    call.name,
+                    sig.func.params.len(),
+                    call.args.len()
+                )));
+            }
+            return Ok(if sig.returns { sig.ret.clone() } else { Type::Unknown });
+        }
+
+        let args: Vec<Type> = call.args.iter().map(|x| self.type_of(x)).collect::<Res<_>>()?;
+        let name = match self.prog.builtin(&call.name) {
+            Some("NEW") => return Err(unsupported("Pointers", Target::C)),
+            Some("SET") => return Err(unsupported("Sets", Target::C)),
+            Some("MATCHES" | "MATCHGROUPS") => return Err(unsupported("Regular expressions", Target::C)),
+            Some("GETENV") => return Err(self.error("GETENV can return null, which C strings don't have".into())),
+            Some(name) => name,
+            None => {
+                return Err(self.error(format!(
+                    "Unknown function {} with {} argument(s)",
+                    call.name,
+                    call.args.len()
+                )))
+            }
+        };
+
+        use Type::*;
+        let s = |t: &Type| matches!(t, Str | Unknown);
+        let n = |t: &Type| t.numeric() || *t == Unknown;
+        let a = |t: &Type| matches!(t, Array(_) | Unknown);
+        let ordered = |t: &Type| matches!(t, Int | Real | Str | Unknown);
+
+        let ret = match (name, args.as_slice()) {
+            ("LENGTH", [x]) if s(x) || a(x) => Int,
+            ("INDEXOF" | "COUNT", [x, y]) if s(x) && s(y) || a(x) => Int,
+            ("MIN" | "MAX", [Array(t)]) if ordered(t) => (**t).clone(),
+            ("MIN" | "MAX", [x, ..]) if !a(x) || *x == Unknown => {
+                let mut ret = Unknown;
+                for t in &args {
+                    match unify(&ret, t) {
+                        Some(x) if ordered(&x) => ret = x,
+                        _ => return Err(self.error(format!("{} can't compare {} and {}", name, ret.name(), t.name()))),
+                    }
+                }
+                ret
+            }
+            ("SUM", [Array(t)]) if n(t) => (**t).clone(),
+            ("SUM", [Unknown]) => Unknown,
+            ("AVERAGE", [Array(t)]) if n(t) => Real,
+            ("AVERAGE", [Unknown]) => Real,
+            ("TRIM", [x]) if s(x) => Str,
+            ("REPLACE", [x, y, z]) if s(x) && s(y) && s(z) => Str,
+            ("SPLIT", [x, y]) if s(x) && s(y) => Type::array(Str),
+            ("JOIN", [x, y]) if a(x) && s(y) => Str,
+            ("CONTAINS" | "STARTSWITH" | "ENDSWITH", [x, y]) if s(x) && s(y) => Bool,
+            ("RANDOM", []) => Real,
+            ("RANDOM", [x, y]) if n(x) && n(y) => Int,
+            ("SETSEED" | "SLEEP" | "WAIT", [x]) if n(x) => Void,
+            ("ARGS", []) => Type::array(Str),
+            ("NOW", []) => Int,
+            ("TODAY", []) => Str,
+            _ => {
+                let args: Vec<String> = args.iter().map(|x| x.name()).collect();
+                return Err(self.error(format!("{} can't be called with {} in C", call.name, args.join(", "))));
+            }
+        };
+        Ok(ret)
+    }
+
+    fn binop_type(&self, x: &BinOp) -> Res<Type> {
+        let (l, r) = (self.type_of(&x.left)?, self.type_of(&x.right)?);
+        let bad = || {
+            Err(format!(
+                "Line {}: Mismatched types, {} and {}",
+                x.span.line,
+                l.name(),
+                r.name()
+            ))
+        };
+        let num = |t: &Type| t.numeric() || *t == Type::Unknown;
+        let both_num = num(&l) && num(&r);
+        let arith = if l == Type::Int && r == Type::Int {
+            Type::Int
+        } else if both_num && l != Type::Unknown && r != Type::Unknown {
+            Type::Real
+        } else {
+            Type::Unknown
+        };
+
+        Ok(match x.op {
+            BinaryOp::Plus => match (&l, &r) {
+                (Type::Str, Type::Str | Type::Unknown) | (Type::Unknown, Type::Str) => Type::Str,
+                _ if both_num => arith,
+                _ => return bad(),
+            },
+            BinaryOp::Minus | BinaryOp::Mul | BinaryOp::Mod if both_num => arith,
+            BinaryOp::Div | BinaryOp::FloorDiv if both_num => Type::Real,
+            BinaryOp::GT | BinaryOp::LT | BinaryOp::GE | BinaryOp::LE
+                if both_num || matches!((&l, &r), (Type::Str | Type::Unknown, Type::Str | Type::Unknown)) =>
+            {
+                Type::Bool
+            }
+            BinaryOp::Equals | BinaryOp::NotEquals if both_num || unify(&l, &r).is_some() => Type::Bool,
+            BinaryOp::In => match (&l, &r) {
+                (x, Type::Array(t)) if unify(x, t).is_some() => Type::Bool,
+                (Type::Str | Type::Unknown, Type::Str) | (_, Type::Unknown) => Type::Bool,
+                _ => return bad(),
+            },
+            BinaryOp::BAnd | BinaryOp::BOr | BinaryOp::BXor | BinaryOp::Shl | BinaryOp::Shr
+                if matches!((&l, &r), (Type::Int | Type::Unknown, Type::Int | Type::Unknown)) =>
+            {
+                Type::Int
+            }
+            _ => return bad(),
+        })
+    }
+
+    fn type_of(&self, expr: &Expr) -> Res<Type> {
+        Ok(match expr {
+            Expr::IntLit(_) => Type::Int,
+            Expr::FloatLit(_) => Type::Real,
+            Expr::BoolLit(_) => Type::Bool,
+            Expr::StrLit(_) => Type::Str,
+            Expr::NullLit => return Err(unsupported("Null values", Target::C)),
+            Expr::Ident(name) => match self.var_type(name) {
+                Some(x) => x,
+                None if self.prog.functions.contains(name.as_str()) => {
+                    return Err(unsupported("Functions as values", Target::C))
+                }
+                None => return Err(self.error(format!("Unknow identifier: {}", name))),
+            },
+            Expr::BinOp(x) => self.binop_type(x)?,
+            Expr::IfElse(x) => {
+                let (a, b) = (self.type_of(&x.then)?, self.type_of(&x.otherwise)?);
+                match unify(&a, &b) {
+                    Some(x) => x,
+                    None => {
+                        return Err(self.error(format!("if-else gives {} or {}, C needs one type", a.name(), b.name())))
+                    }
+                }
+            }
+            Expr::Index(x) => match self.type_of(&x.target)? {
+                Type::Array(t) => *t,
+                Type::Unknown => Type::Unknown,
+                t => return Err(self.error(format!("Cannot index into {}", t.name()))),
+            },
+            Expr::MethodCall(x) => match (self.type_of(&x.target)?, x.name.to_lowercase().as_str(), x.args.len()) {
+                (Type::Array(_) | Type::Unknown, "length", 0) => Type::Int,
+                (Type::Array(_) | Type::Unknown, "append", 1) => Type::Void,
+                (t, _, _) => return Err(self.error(format!("Unknown method {} on {}", x.name, t.name()))),
+            },
+            Expr::Call(x) => self.call_type(x)?,
+            Expr::Field(_) | Expr::SuperCall(_) => return Err(unsupported("Classes", Target::C)),
+            Expr::New(x) if x.class == "Collection" => return Err(unsupported("Collections", Target::C)),
+            Expr::New(_) => return Err(unsupported("Classes", Target::C)),
+            Expr::Map(_) => return Err(unsupported("Maps", Target::C)),
+            Expr::Set(_) => return Err(unsupported("Sets", Target::C)),
+            Expr::Array(items) => {
+                let mut ret = Type::Unknown;
+                for item in items {
+                    let t = self.type_of(item)?;
+                    ret = match unify(&ret, &t) {
+                        Some(x) => x,
+                        None => {
+                            return Err(self.error(format!(
+                                "An array holds {} and {}, C arrays hold one type",
+                                ret.name(),
+                                t.name()
+                            )))
+                        }
+                    };
+                }
+                Type::array(ret)
+            }
+            Expr::AddressOf(_) | Expr::Deref(_) => return Err(unsupported("Pointers", Target::C)),
+            Expr::BNot(x) => match self.type_of(x)? {
+                Type::Int | Type::Unknown => Type::Int,
+                t => return Err(self.error(format!("Mismatched types, ~ of {}", t.name()))),
+            },
+        })
+    }
+
+    /// `expr` where a value of `want` goes, copying arrays so they aren't
+    /// shared
+    fn coerce(&self, expr: &Expr, want: &Type) -> Res<String> {
+        let have = self.type_of(expr)?;
+        match (expr, want) {
+            (Expr::Array(items), Type::Array(of)) => self.array(items, of),
+            _ if have == *want || have == Type::Int && *want == Type::Real => {
+                let text = self.expr(expr)?;
+                match (expr, have) {
+                    (Expr::Ident(_) | Expr::Index(_), Type::Array(_)) => Ok(format!("psc_copy({})", text)),
+                    _ => Ok(text),
+                }
+            }
+            _ if have == Type::Void => Err(self.error("There's no value here, the call returns nothing".into())),
+            _ => Err(self.error(format!("{} can't be used as {} in C", have.name(), want.name()))),
+        }
+    }
+
+    fn value(&self, expr: &Expr) -> Res<String> {
+        let t = self.type_of(expr)?.resolve();
+        self.coerce(expr, &t)
+    }
+
+    fn array(&self, items: &[Expr], of: &Type) -> Res<String> {
+        let of = of.resolve();
+        if items.is_empty() {
+            return Ok(format!("psc_array_new({})", of.kind()));
+        }
+
+        let items: Vec<String> = items.iter().map(|x| self.coerce(x, &of)).collect::<Res<_>>()?;
+        Ok(format!(
+            "psc_array_of({}, {}, ({}[]){{{}}})",
+            of.kind(),
+            items.len(),
+            of.c(),
+            items.join(", ")
+        ))
+    }
+
+    /// A pointer to `expr` as an item of arrays of `of`
+    fn item(&self, expr: &Expr, of: &Type) -> Res<String> {
+        Ok(format!("&({}){{{}}}", of.c(), self.coerce(expr, of)?))
+    }
+
+    /// `expr` as it prints
+    fn string(&self, expr: &Expr) -> Res<String> {
+        let text = self.expr(expr)?;
+        Ok(match self.type_of(expr)? {
+            Type::Str => text,
+            Type::Real => format!("psc_real_str({})", text),
+            Type::Bool => format!("{} ? \"true\" : \"false\"", self.operand(expr, 4)?),
+            Type::Array(_) => format!("psc_array_str({})", text),
+            _ => format!("psc_int_str({})", text),
+        })
+    }
+
+    /// `expr` as an operand of an operator binding as tightly as `prec`
+    fn operand(&self, expr: &Expr, prec: u8) -> Res<String> {
+        match expr {
+            Expr::BinOp(x) => {
+                let (text, p) = self.binop(x)?;
+                // Only arithmetic goes without brackets, mixing anything
+                // else reads badly and C compilers warn about it
+                if p >= prec && p >= 9 {
+                    Ok(text)
+                } else {
+                    Ok(format!("({})", text))
+                }
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    /// An operator expression, with how tightly its C operator binds
+    fn binop(&self, x: &BinOp) -> Res<(String, u8)> {
+        let (lt, rt) = (self.type_of(&x.left)?, self.type_of(&x.right)?);
+        let infix = |op: &str, prec: u8| -> Res<(String, u8)> {
+            let mut left = self.operand(&x.left, prec)?;
+            if lt == Type::Int && constant(&x.left) && constant(&x.right) && matches!(op, "+" | "-" | "*" | "<<") {
+                left = format!("(long long){}", self.operand(&x.left, 100)?);
+            }
+            let text = format!("{} {} {}", left, op, self.operand(&x.right, prec + 1)?);
+            Ok((text, prec))
+        };
+        let checked = |name: &str| -> Res<(String, u8)> {
+            Ok((format!("{}({}, {})", name, self.expr(&x.left)?, self.expr(&x.right)?), 100))
+        };
+        let call = |name: &str| -> Res<(String, u8)> {
+            let text = format!("{}({}, {}, {})", name, self.expr(&x.left)?, self.expr(&x.right)?, x.span.line);
+            Ok((text, 100))
+        };
+        let compare = |op: &str| -> Res<(String, u8)> {
+            match lt {
+                Type::Str => {
+                    let text = format!("strcmp({}, {}) {} 0", self.expr(&x.left)?, self.expr(&x.right)?, op);
+                    Ok((text, 7))
+                }
+                _ => infix(op, 7),
+            }
+        };
+        let equals = |negate: bool| -> Res<(String, u8)> {
+            let not = if negate { "!" } else { "" };
+            let (l, r) = (self.expr(&x.left)?, self.expr(&x.right)?);
+            match lt {
+                Type::Str => Ok((format!("{}psc_str_eq({}, {})", not, l, r), 100)),
+                Type::Array(_) => Ok((format!("{}psc_equal({}, {})", not, l, r), 100)),
+                _ => infix(if negate { "!=" } else { "==" }, 6),
+            }
+        };
+
+        match x.op {
+            BinaryOp::Plus if lt == Type::Str => {
+                Ok((format!("psc_concat({}, {})", self.expr(&x.left)?, self.expr(&x.right)?), 100))
+            }
+            // Signed overflow is undefined in C, so integers are checked
+            BinaryOp::Plus if lt == Type::Int && rt == Type::Int => checked("psc_add"),
+            BinaryOp::Minus if lt == Type::Int && rt == Type::Int => checked("psc_sub"),
+            BinaryOp::Mul if lt == Type::Int && rt == Type::Int => checked("psc_mul"),
+            BinaryOp::Plus => infix("+", 9),
+            BinaryOp::Minus => infix("-", 9),
+            BinaryOp::Mul => infix("*", 10),
+            BinaryOp::Div => call("psc_div"),
+            BinaryOp::FloorDiv => call("psc_floor_div"),
+            BinaryOp::Mod if lt == Type::Int && rt == Type::Int => call("psc_mod"),
+            BinaryOp::Mod => call("psc_fmod"),
+            BinaryOp::GT => compare(">"),
+            BinaryOp::LT => compare("<"),
+            BinaryOp::GE => compare(">="),
+            BinaryOp::LE => compare("<="),
+            BinaryOp::Equals => equals(false),
+            BinaryOp::NotEquals => equals(true),
+            BinaryOp::In => match rt {
+                Type::Array(t) => {
+                    let text = format!("psc_array_has({}, {})", self.expr(&x.right)?, self.item(&x.left, &t)?);
+                    Ok((text, 100))
+                }
+                _ => Ok((format!("psc_contains({}, {})", self.expr(&x.right)?, self.expr(&x.left)?), 100)),
+            },
+            BinaryOp::BAnd => infix("&", 5),
+            BinaryOp::BOr => infix("|", 3),
+            BinaryOp::BXor => infix("^", 4),
+            BinaryOp::Shl => infix("<<", 8),
+            BinaryOp::Shr => infix(">>", 8),
+        }
+    }
+
+    fn call(&self, call: &Call) -> Res<String> {
+        if let Some(sig) = self.sigs.get(call.name.as_str()) {
+            let args: Vec<String> = sig
+                .func
+                .params
+                .iter()
+                .zip(&call.args)
+                .map(|(param, arg)| self.coerce(arg, &sig.vars[param]))
+                .collect::<Res<_>>()?;
+            return Ok(format!("{}({})", ident(&call.name), args.join(", ")));
+        }
+
+        let name = self.prog.builtin(&call.name).unwrap();
+        let args = &call.args;
+        let types: Vec<Type> = args.iter().map(|x| self.type_of(x)).collect::<Res<_>>()?;
+        let list = || -> Res<String> {
+            let args: Vec<String> = args.iter().map(|x| self.expr(x)).collect::<Res<_>>()?;
+            Ok(args.join(", "))
+        };
+        let extreme = |items: String, of: &Type| {
+            let want = if name == "MIN" { -1 } else { 1 };
+            format!("(*({} *)psc_extreme({}, {}, {}))", of.c(), items, want, literal(name))
+        };
+
+        Ok(match (name, types.as_slice()) {
+            ("LENGTH", [Type::Str]) => format!("psc_length({})", list()?),
+            ("LENGTH", _) => format!("{}.len", self.operand(&args[0], 100)?),
+            ("INDEXOF", [Type::Str, _]) => format!("psc_index_of({})", list()?),
+            ("COUNT", [Type::Str, _]) => format!("psc_count({})", list()?),
+            ("INDEXOF" | "COUNT", [Type::Array(t), _]) => format!(
+                "psc_array_{}({}, {})",
+                if name == "COUNT" { "count" } else { "index" },
+                self.expr(&args[0])?,
+                self.item(&args[1], t)?
+            ),
+            ("MIN" | "MAX", [Type::Array(t)]) => extreme(self.expr(&args[0])?, t),
+            ("MIN" | "MAX", _) => {
+                let of = self.call_type(call)?;
+                extreme(self.array(args, &of)?, &of)
+            }
+            ("SUM", [Type::Array(t)]) if **t == Type::Int => format!("psc_sum_int({})", list()?),
+            ("SUM", _) => format!("psc_sum_real({})", list()?),
+            ("AVERAGE", _) => format!("psc_average({})", list()?),
+            ("TRIM", _) => format!("psc_trim({})", list()?),
+            ("REPLACE", _) => format!("psc_replace({})", list()?),
+            ("SPLIT", _) => format!("psc_split({})", list()?),
+            ("JOIN", _) => format!("psc_join({})", list()?),
+            ("CONTAINS", _) => format!("psc_contains({})", list()?),
+            ("STARTSWITH", _) => format!("psc_starts_with({})", list()?),
+            ("ENDSWITH", _) => format!("psc_ends_with({})", list()?),
+            ("RANDOM", []) => "psc_random()".to_string(),
+            ("RANDOM", _) => format!("psc_random_int({})", list()?),
+            ("SETSEED", _) => format!("psc_setseed({})", list()?),
+            ("SLEEP" | "WAIT", _) => format!("psc_sleep({})", list()?),
+            ("ARGS", _) => "psc_args()".to_string(),
+            ("NOW", _) => "psc_now()".to_string(),
+            ("TODAY", _) => "psc_today()".to_string(),
+            _ => unreachable!(),
+        })
+    }
+
+    fn expr(&self, expr: &Expr) -> Res<String> {
+        Ok(match expr {
+            Expr::IntLit(x) => x.to_string(),
+            Expr::FloatLit(x) => real(*x),
+            Expr::BoolLit(x) => x.to_string(),
+            Expr::StrLit(x) => literal(x),
+            Expr::Ident(name) => ident(name),
+            Expr::BinOp(x) => self.binop(x)?.0,
+            Expr::IfElse(x) => {
+                let t = self.type_of(expr)?;
+                format!(
+                    "({} ? {} : {})",
+                    self.operand(&x.cond, 4)?,
+                    self.coerce(&x.then, &t)?,
+                    self.coerce(&x.otherwise, &t)?
+                )
+            }
+            Expr::Index(x) => {
+                let t = self.type_of(expr)?.resolve();
+                format!("PSC_AT({}, {}, {})", t.c(), self.expr(&x.target)?, self.expr(&x.index)?)
+            }
+            Expr::MethodCall(x) => match x.name.to_lowercase().as_str() {
+                "length" => format!("{}.len", self.operand(&x.target, 100)?),
+                _ => {
+                    let of = match self.type_of(&x.target)? {
+                        Type::Array(t) => t.resolve(),
+                        _ => unreachable!(),
+                    };
+                    if root(&x.target).is_none() {
+                        return Err(self.error("Only variables and their items can be appended to in C".into()));
+                    }
+                    format!("psc_append(&{}, {})", self.expr(&x.target)?, self.item(&x.args[0], &of)?)
+                }
+            },
+            Expr::Call(x) => self.call(x)?,
+            Expr::Array(items) => {
+                let of = match self.type_of(expr)? {
+                    Type::Array(t) => *t,
+                    _ => unreachable!(),
+                };
+                self.array(items, &of)?
+            }
+            Expr::BNot(x) => format!("~{}", self.operand(x, 100)?),
+            _ => {
+                self.type_of(expr)?;
+                unreachable!()
+            }
+        })
+    }
+
+    fn cond(&self, expr: &Expr, what: &str) -> Res<String> {
+        match self.type_of(expr)? {
+            Type::Bool => self.expr(expr),
+            _ => Err(self.error(format!("{} expression not bool type", what))),
+        }
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) -> Res<()> {
+        self.indent += 1;
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        self.indent -= 1;
+        Ok(())
+    }
+
+    /// A loop body, declaring the variables first assigned in it
+    fn scoped(&mut self, stmts: &'a [Stmt], bind: Option<&str>) -> Res<()> {
+        for name in self.enter(stmts, bind) {
+            let t = self.var_type(&name).unwrap_or(Type::Unknown);
+            let text = format!("    {}", self.decl(&name, &t));
+            self.line(&text);
+        }
+
+        let res = self.block(stmts);
+        self.blocks.pop();
+        res
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> Res<()> {
+        self.line = stmt.span.line;
+
+        match &stmt.kind {
+            StmtKind::Assign(x) => {
+                let t = self.type_of(&x.target)?;
+                let text = format!("{} = {};", self.expr(&x.target)?, self.coerce(x.value(), &t)?);
+                self.line(&text);
+            }
+            StmtKind::MultiAssign(x) => {
+                // Every value is worked out before any is assigned
+                self.line("{");
+                let mut tmps = Vec::new();
+                for (target, expr) in x.targets.iter().zip(&x.exprs) {
+                    let t = self.type_of(target)?;
+                    let tmp = format!("psc_t{}", self.tmp());
+                    let text = format!("    {} = {};", declare(&t, &tmp), self.coerce(expr, &t)?);
+                    self.line(&text);
+                    tmps.push(tmp);
+                }
+                for (target, tmp) in x.targets.iter().zip(tmps) {
+                    let text = format!("    {} = {};", self.expr(target)?, tmp);
+                    self.line(&text);
+                }
+                self.line("}");
+            }
+            StmtKind::Input(x) => {
+                let name = literal(&x.ident);
+                let read = match self.var_type(&x.ident) {
+                    Some(Type::Int) => format!("psc_input_int({})", name),
+                    Some(Type::Real) => format!("psc_input_real({})", name),
+                    Some(Type::Bool) => format!("psc_input_bool({})", name),
+                    Some(Type::Str) if x.kind.is_none() => format!("psc_trim(psc_input_str({}))", name),
+                    Some(Type::Str) => format!("psc_input_str({})", name),
+                    _ => return Err(self.error(format!("{} is an array, input can't read one", x.ident))),
+                };
+                let text = format!("{} = {};", ident(&x.ident), read);
+                self.line(&text);
+            }
+            StmtKind::Output(x) => {
+                let text = match self.type_of(&x.expr)? {
+                    Type::Int if constant(&x.expr) => format!("printf(\"%lld\\n\", (long long)({}));", self.expr(&x.expr)?),
+                    Type::Int => format!("printf(\"%lld\\n\", {});", self.expr(&x.expr)?),
+                    Type::Void => return Err(self.error("There's no value here, the call returns nothing".into())),
+                    _ => format!("puts({});", self.string(&x.expr)?),
+                };
+                self.line(&text);
+            }
+            StmtKind::If(x) => {
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    let text = match (i, cond) {
+                        (0, _) => format!("if ({}) {{", self.cond(cond, "If")?),
+                        (_, Expr::BoolLit(true)) if i == x.branches.len() - 1 => "} else {".to_string(),
+                        _ => format!("}} else if ({}) {{", self.cond(cond, "If")?),
+                    };
+                    self.line(&text);
+                    self.block(stmts)?;
+                }
+                self.line("}");
+            }
+            StmtKind::While(x) => {
+                let text = format!("while ({}) {{", self.cond(&x.cond, "While")?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Until(x) => {
+                let text = format!("while (!({})) {{", self.cond(&x.cond, "Until")?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Repeat(x) => {
+                self.line("do {");
+                self.scoped(&x.stmts, None)?;
+                let text = format!("}} while (!({}));", self.cond(&x.cond, "Until")?);
+                self.line(&text);
+            }
+            StmtKind::For(x) => self.for_loop(x)?,
+            StmtKind::ForEach(x) => self.for_each(x)?,
+            StmtKind::Expr(x) => {
+                let text = format!("{};", self.expr(x)?);
+                self.line(&text);
+            }
+            StmtKind::Function(_) | StmtKind::Global(_) | StmtKind::Type(_) => {}
+            StmtKind::Return(x) => {
+                let text = match (self.func, x) {
+                    (None, _) => "return 0;".to_string(),
+                    (Some(f), Some(x)) if self.sigs[f].returns => {
+                        format!("return {};", self.coerce(x, &self.sigs[f].ret)?)
+                    }
+                    (Some(_), Some(x)) => format!("return {};", self.expr(x)?),
+                    (Some(f), None) if self.sigs[f].returns => {
+                        return Err(self.error(format!("{} returns a value elsewhere, here it returns nothing", f)))
+                    }
+                    (Some(_), None) => "return;".to_string(),
+                };
+                self.line(&text);
+            }
+            StmtKind::Assert(x) => {
+                let text = format!("if (!({})) {{", self.cond(&x.cond, &format!("Assert at line {}:", stmt.span.line))?);
+                self.line(&text);
+                let text = match &x.msg {
+                    Some(msg) => format!(
+                        "    psc_fail(\"Assertion failed at line {}: %s\", {});",
+                        stmt.span.line,
+                        self.string(msg)?
+                    ),
+                    None => format!("    psc_fail(\"Assertion failed at line {}\");", stmt.span.line),
+                };
+                self.line(&text);
+                self.line("}");
+            }
+            StmtKind::Halt(x) => {
+                let text = match x {
+                    Some(x) => format!("exit({});", self.expr(x)?),
+                    None => "exit(0);".to_string(),
+                };
+                self.line(&text);
+            }
+            StmtKind::Class(_) | StmtKind::Attempt(_) | StmtKind::Include(_) => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// A count loop. The end is worked out once and assigning the loop
+    /// variable doesn't change the next one, like in psc, which only needs
+    /// extra variables when the body could tell the difference
+    fn for_loop(&mut self, x: &'a For) -> Res<()> {
+        for bound in [&x.start, &x.end] {
+            if self.type_of(bound)? != Type::Int {
+                return Err(self.error("Loop bounds must be integers in C".into()));
+            }
+        }
+
+        let name = ident(&x.name);
+        let count = if modifies(&x.stmts, &x.name, self.prog) {
+            format!("psc_i{}", self.tmp())
+        } else {
+            name.clone()
+        };
+
+        let stable = match &x.end {
+            Expr::IntLit(_) => true,
+            Expr::Ident(end) => !modifies(&x.stmts, end, self.prog),
+            _ => false,
+        };
+        let (init, end) = if stable {
+            (String::new(), self.expr(&x.end)?)
+        } else {
+            let end = format!("psc_end{}", self.tmp());
+            (format!(", {} = {}", end, self.expr(&x.end)?), end)
+        };
+
+        let text = format!(
+            "for (long long {} = {}{}; {} <= {}; {}++) {{",
+            count,
+            self.expr(&x.start)?,
+            init,
+            count,
+            end,
+            count
+        );
+        self.line(&text);
+        if count != name {
+            self.line(&format!("    long long {} = {};", name, count));
+        }
+        self.scoped(&x.stmts, Some(&x.name))?;
+        self.line("}");
+        Ok(())
+    }
+
+    /// Loops over the items of an array, or a copy of them when the body
+    /// could change the array
+    fn for_each(&mut self, x: &'a ForEach) -> Res<()> {
+        let t = self.type_of(&x.iter)?;
+        let items = match (&x.iter, &t) {
+            (Expr::Ident(name), Type::Array(_)) if !modifies(&x.stmts, name, self.prog) => ident(name),
+            _ => {
+                let items = format!("psc_items{}", self.tmp());
+                let value = match t {
+                    Type::Str => format!("psc_chars({})", self.expr(&x.iter)?),
+                    _ => self.value(&x.iter)?,
+                };
+                self.line(&format!("psc_array {} = {};", items, value));
+                items
+            }
+        };
+
+        let of = match t {
+            Type::Array(t) => t.resolve(),
+            _ => Type::Str,
+        };
+        let i = format!("psc_i{}", self.tmp());
+        self.line(&format!("for (long long {} = 0; {} < {}.len; {}++) {{", i, i, items, i));
+        self.line(&format!("    {} = PSC_AT({}, {}, {});", declare(&of, &ident(&x.name)), of.c(), items, i));
+        self.scoped(&x.stmts, Some(&x.name))?;
+        self.line("}");
+        Ok(())
+    }
+
+    fn decl(&self, name: &str, t: &Type) -> String {
+        let init = match t {
+            Type::Real => "0.0".to_string(),
+            Type::Bool => "false".to_string(),
+            Type::Str => "\"\"".to_string(),
+            Type::Array(x) => format!("PSC_EMPTY({})", x.kind()),
+            _ => "0".to_string(),
+        };
+        format!("{} = {};", declare(t, &ident(name)), init)
+    }
+
+    fn signature(&self, func: &Function) -> String {
+        let sig = &self.sigs[func.name.as_str()];
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .map(|x| declare(&sig.vars[x], &ident(x)))
+            .collect();
+        let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+        format!("{} {}({})", sig.ret.c(), ident(&func.name), params)
+    }
+
+    fn function(&mut self, func: &'a Function) -> Res<()> {
+        self.func = Some(&func.name);
+
+        let text = format!("{} {{", self.signature(func));
+        self.line(&text);
+
+        let declared = globals(&func.stmts);
+        for name in assigned_in_scope(&func.stmts) {
+            if !func.params.contains(&name) && !declared.contains(&name) {
+                let text = format!("    {}", self.decl(&name, &self.sigs[func.name.as_str()].vars[&name]));
+                self.line(&text);
+            }
+        }
+
+        self.block(&func.stmts)?;
+        self.line("}");
+        self.line("");
+
+        self.func = None;
+        Ok(())
+    }
+}
+
+/// A C99 file starting with the runtime from `psc.h`. Integers are `long
+/// long`, and overflowing one fails like it does in psc
+pub fn transpile(prog: &Program) -> Res<String> {
+    let mut c = C {
+        prog,
+        out: String::new(),
+        indent: 0,
+        sigs: HashMap::new(),
+        globals: HashMap::new(),
+        untyped: HashSet::new(),
+        loop_locals: HashSet::new(),
+        blocks: Vec::new(),
+        func: None,
+        line: 0,
+        changed: false,
+        tmp: Cell::new(0),
+    };
+
+    let mut top = assigned_in_scope(prog.stmts);
+    for name in all_globals(prog.stmts) {
+        if !top.contains(&name) {
+            top.push(name);
+        }
+    }
+    let mut locals = Vec::new();
+    loop_locals(prog.stmts, &top.iter().cloned().collect(), &mut locals);
+    for name in top.iter().chain(&locals) {
+        c.globals.insert(name.clone(), Type::Unknown);
+    }
+    c.loop_locals.extend(locals.into_iter().map(|x| (None, x)));
+
+    let mut funcs = Vec::new();
+    for stmt in prog.stmts {
+        if let StmtKind::Function(func) = &stmt.kind {
+            let declared = globals(&func.stmts);
+            let mut own: HashSet<String> = func.params.iter().cloned().chain(assigned_in_scope(&func.stmts)).collect();
+            own.extend(declared.iter().cloned());
+            let mut locals = Vec::new();
+            loop_locals(&func.stmts, &own, &mut locals);
+
+            let mut vars = HashMap::new();
+            for name in own.into_iter().chain(locals.iter().cloned()) {
+                if !declared.contains(&name) {
+                    vars.insert(name, Type::Unknown);
+                }
+            }
+            c.loop_locals.extend(locals.into_iter().map(|x| (Some(func.name.as_str()), x)));
+
+            c.sigs.insert(
+                &func.name,
+                Sig {
+                    func,
+                    vars,
+                    ret: Type::Unknown,
+                    returns: false,
+                },
+            );
+            funcs.push(&**func);
+        }
+    }
+
+    c.types()?;
+
+    c.out.push_str(RUNTIME);
+    c.line("");
+
+    if !top.is_empty() {
+        for name in &top {
+            let text = c.decl(name, &c.globals[name]);
+            c.line(&text);
+        }
+        c.line("");
+    }
+
+    if !funcs.is_empty() {
+        for func in &funcs {
+            let text = format!("{};", c.signature(func));
+            c.line(&text);
+        }
+        c.line("");
+
+        for func in funcs {
+            c.function(func)?;
+        }
+    }
+
+    c.line("int main(int argc, char **argv) {");
+    c.line("    psc_init(argc, argv);");
+    c.block(prog.stmts)?;
+    c.line("    return 0;");
+    c.line("}");
+
+    Ok(c.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{transpile as to, Target};
+
+    fn c(source: &str) -> String {
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        to(&stmts, Target::C, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn integer_arithmetic_is_checked() {
+        let code = c("A = 2\nB = 3\nC = A * B + A - B\noutput C");
+        assert!(code.contains("C = psc_sub(psc_add(psc_mul(A, B), A), B);"), "{}", code);
+    }
+
+    #[test]
+    fn real_arithmetic_is_plain() {
+        let code = c("A = 2.5\nB = A * 2.0 + 1.5\noutput B");
+        assert!(code.contains("B = A * 2.0 + 1.5;"), "{}", code);
+    }
+
+    #[test]
+    fn the_runtime_is_inlined() {
+        let code = c("output 1");
+        assert!(code.starts_with("/* Runtime for programs"));
+        assert!(!code.contains("#include \"psc.h\""));
+    }
+
+    #[test]
+    fn loop_variables_stay_in_the_loop() {
+        let code = c("X = 1\nloop while X < 3\n\tY = X * 2\n\tX = X + 1\n\toutput Y\nend loop");
+        assert!(code.contains("while (X < 3) {\n        long long Y = 0;\n"), "{}", code);
+
+        let source = "X = 1\nloop while X < 3\n\tinput Y\n\tX = X + 1\nend loop\noutput Y";
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        let mut interpreter = psc::Interpreter::with_io(std::io::Cursor::new("5\n6\n"), std::io::sink());
+        let msg = interpreter.run(&stmts).unwrap_err().msg;
+        assert_eq!(msg, "Unknow identifier: Y");
+
+        let err = to(&stmts, Target::C, Default::default()).unwrap_err();
+        assert_eq!(err.to_string(), format!("Line 6: {}", msg));
+    }
+}
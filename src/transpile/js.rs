@@ -0,0 +1,1092 @@
+use super::{all_globals, assigned_in_scope, functions, globals, unsupported, Program, Target};
+use psc::eval::*;
+use psc::json::Json;
+use std::collections::HashSet;
+
+/// Helpers the generated code relies on, keeping psc's semantics where
+/// JavaScript's differ: collections are values, `==` compares contents,
+/// division by zero is an error and integer `+`, `-` and `*` are exact over
+/// 64 bits and fail on overflow. Integers a double can't hold exactly are
+/// BigInts, which arithmetic, comparisons and printing handle but builtins
+/// and indexes don't
+const RUNTIME: &str = r#""use strict";
+
+class $Error extends Error {}
+class $Halt {
+    constructor(code) {
+        this.code = code;
+    }
+}
+class $Object {
+    $fields() {}
+}
+class $Map {
+    constructor(entries) {
+        this.entries = entries;
+    }
+}
+class $Set {
+    constructor(items) {
+        this.items = items;
+    }
+}
+class $Collection {
+    constructor(items) {
+        this.items = items;
+        this.next = 0;
+    }
+}
+
+function $fail(msg) {
+    throw new $Error(msg);
+}
+
+function $typeName(x) {
+    if (x === null || x === undefined) return "null";
+    if (typeof x === "bigint") return "integer";
+    if (typeof x === "number") return Number.isInteger(x) ? "integer" : "real";
+    if (typeof x === "string") return "string";
+    if (typeof x === "boolean") return "boolean";
+    if (typeof x === "function") return "function";
+    if (Array.isArray(x)) return "array";
+    if (x instanceof $Map) return "map";
+    if (x instanceof $Set) return "set";
+    if (x instanceof $Collection) return "collection";
+    return "object";
+}
+
+function $str(x) {
+    if (x === null || x === undefined) return "null";
+    if (x === Infinity) return "inf";
+    if (x === -Infinity) return "-inf";
+    if (typeof x === "function") return `<function ${x.name}>`;
+    if (typeof x !== "object") return String(x);
+    if (Array.isArray(x)) return "[" + x.map($repr).join(", ") + "]";
+    if (x instanceof $Map) return "{" + x.entries.map(([k, v]) => $repr(k) + ": " + $repr(v)).join(", ") + "}";
+    if (x instanceof $Set) return "{" + x.items.map($repr).join(", ") + "}";
+    if (x instanceof $Collection) return "Collection[" + x.items.map($repr).join(", ") + "]";
+    return `<${x.constructor.name} object>`;
+}
+
+function $repr(x) {
+    return typeof x === "string" ? `"${x}"` : $str(x);
+}
+
+function $eq(a, b) {
+    if (a === b) return true;
+    if (a === null || b === null || a === undefined || b === undefined) return false;
+    if (Array.isArray(a) && Array.isArray(b)) return a.length === b.length && a.every((x, i) => $eq(x, b[i]));
+    if (a instanceof $Map && b instanceof $Map) {
+        return a.entries.length === b.entries.length &&
+            a.entries.every(([k, v], i) => $eq(k, b.entries[i][0]) && $eq(v, b.entries[i][1]));
+    }
+    if (a instanceof $Set && b instanceof $Set) return a.items.length === b.items.length && a.items.every((x) => $has(b.items, x));
+    if (a instanceof $Collection && b instanceof $Collection) return $eq(a.items, b.items);
+    return false;
+}
+
+function $has(items, x) {
+    return items.some((y) => $eq(x, y));
+}
+
+function $clone(x) {
+    if (Array.isArray(x)) return x.map($clone);
+    if (x instanceof $Map) return new $Map(x.entries.map(([k, v]) => [$clone(k), $clone(v)]));
+    if (x instanceof $Set) return new $Set(x.items.map($clone));
+    if (x instanceof $Collection) {
+        const ret = new $Collection(x.items.map($clone));
+        ret.next = x.next;
+        return ret;
+    }
+    return x;
+}
+
+function $map(entries) {
+    const ret = new $Map([]);
+    for (const [k, v] of entries) $setIndex(ret, k, v);
+    return ret;
+}
+
+function $set(items) {
+    const ret = new $Set([]);
+    for (const x of items) if (!$has(ret.items, x)) ret.items.push(x);
+    return ret;
+}
+
+function $pos(items, k) {
+    if (!$isInt(k)) $fail(`Array index must be an integer, not ${$typeName(k)}`);
+    if (typeof k === "bigint" || k < 0 || k >= items.length) $fail(`Index ${k} out of bounds for array of length ${items.length}`);
+    return k;
+}
+
+function $index(c, k) {
+    if (Array.isArray(c)) return c[$pos(c, k)];
+    if (c instanceof $Map) {
+        const entry = c.entries.find(([x]) => $eq(x, k));
+        if (!entry) $fail(`Key not found: ${$repr(k)}`);
+        return entry[1];
+    }
+    $fail(`Cannot index into ${$typeName(c)}`);
+}
+
+function $setIndex(c, k, v) {
+    if (Array.isArray(c)) {
+        c[$pos(c, k)] = v;
+    } else if (c instanceof $Map) {
+        const entry = c.entries.find(([x]) => $eq(x, k));
+        if (entry) entry[1] = v;
+        else c.entries.push([k, v]);
+    } else {
+        $fail(`Cannot index into ${$typeName(c)}`);
+    }
+}
+
+function $isInt(x) {
+    return typeof x === "bigint" || Number.isInteger(x);
+}
+
+// BigInts go back to numbers whenever a double holds them exactly
+function $fromBig(x) {
+    return x >= BigInt(Number.MIN_SAFE_INTEGER) && x <= BigInt(Number.MAX_SAFE_INTEGER) ? Number(x) : x;
+}
+
+function $toNumber(x) {
+    return typeof x === "bigint" ? Number(x) : x;
+}
+
+// Integer arithmetic the fast way didn't do exactly, or anything with a
+// BigInt in it
+function $arith(op, a, b) {
+    if (!$isInt(a) || !$isInt(b)) {
+        [a, b] = [$toNumber(a), $toNumber(b)];
+        return op === "+" ? a + b : op === "-" ? a - b : a * b;
+    }
+    const [l, r] = [BigInt(a), BigInt(b)];
+    const x = op === "+" ? l + r : op === "-" ? l - r : l * r;
+    if (x < -(2n ** 63n) || x >= 2n ** 63n) $fail(`Integer overflow in ${a} ${op} ${b}`);
+    return $fromBig(x);
+}
+
+// Whether JavaScript's own `x` from numbers `a` and `b` is what psc gives,
+// always so for reals
+function $exact(a, b, x) {
+    return Number.isSafeInteger(x) || !Number.isInteger(a) || !Number.isInteger(b);
+}
+
+function $add(a, b) {
+    const fast = typeof a === "number" && typeof b === "number";
+    return fast && $exact(a, b, a + b) ? a + b : $arith("+", a, b);
+}
+
+function $sub(a, b) {
+    const fast = typeof a === "number" && typeof b === "number";
+    return fast && $exact(a, b, a - b) ? a - b : $arith("-", a, b);
+}
+
+function $mul(a, b) {
+    const fast = typeof a === "number" && typeof b === "number";
+    return fast && $exact(a, b, a * b) ? a * b : $arith("*", a, b);
+}
+
+function $div(a, b, line) {
+    if (b === 0) $fail(`Division by zero at line ${line}`);
+    return $toNumber(a) / $toNumber(b);
+}
+
+function $floorDiv(a, b, line) {
+    if (typeof a === "bigint" || typeof b === "bigint") {
+        if (b === 0) $fail(`Division by zero at line ${line}`);
+        if (!$isInt(a) || !$isInt(b)) return Math.floor($div(a, b, line));
+        const [l, r] = [BigInt(a), BigInt(b)];
+        return $fromBig(l / r - (l % r !== 0n && l < 0n !== r < 0n ? 1n : 0n));
+    }
+    return Math.floor($div(a, b, line));
+}
+
+function $mod(a, b, line) {
+    if (b === 0) $fail(`Division by zero at line ${line}`);
+    if ($isInt(a) && $isInt(b) && (typeof a === "bigint" || typeof b === "bigint")) return $fromBig(BigInt(a) % BigInt(b));
+    return Math.floor($toNumber(a) % $toNumber(b));
+}
+
+function $in(x, c) {
+    if (typeof c === "string") return c.includes(x);
+    if (Array.isArray(c)) return $has(c, x);
+    if (c instanceof $Set) return $has(c.items, x);
+    if (c instanceof $Map) return c.entries.some(([k]) => $eq(k, x));
+    $fail(`Cannot look for ${$typeName(x)} in ${$typeName(c)}`);
+}
+
+// Bitwise operators work on 64 bit integers like psc, not on 32 bits
+function $bits(op, a, b) {
+    const l = BigInt.asIntN(64, BigInt(a));
+    const r = BigInt(b);
+    if ((op === "<<" || op === ">>") && (r < 0n || r >= 64n)) $fail(`Cannot shift by ${b} bits`);
+    switch (op) {
+        case "&": return Number(l & r);
+        case "|": return Number(l | r);
+        case "^": return Number(l ^ r);
+        case "<<": return Number(BigInt.asIntN(64, l << r));
+        case ">>": return Number(l >> r);
+    }
+}
+
+function $bnot(x) {
+    return Number(~BigInt(x));
+}
+
+function $iter(x) {
+    if (Array.isArray(x)) return x;
+    if (typeof x === "string") return [...x];
+    if (x instanceof $Map) return x.entries.map(([k]) => k);
+    if (x instanceof $Set) return x.items;
+    $fail(`Cannot loop over ${$typeName(x)}`);
+}
+
+function $method(t, name, args) {
+    if (t instanceof $Object) {
+        if (typeof t[name] !== "function") $fail(`Class ${t.constructor.name} has no method ${name}`);
+        return t[name](...args);
+    }
+
+    const m = name.toLowerCase();
+    const [x] = args;
+    if (Array.isArray(t)) {
+        if (m === "length" && args.length === 0) return t.length;
+        if (m === "append" && args.length === 1) {
+            t.push(x);
+            return null;
+        }
+    } else if (t instanceof $Map) {
+        if (m === "containskey" && args.length === 1) return t.entries.some(([k]) => $eq(k, x));
+    } else if (t instanceof $Set) {
+        if (m === "add" && args.length === 1) {
+            if ($has(t.items, x)) return false;
+            t.items.push(x);
+            return true;
+        }
+        if (m === "contains" && args.length === 1) return $has(t.items, x);
+        if (m === "union" && x instanceof $Set) return $set([...t.items, ...x.items]);
+        if (m === "intersection" && x instanceof $Set) return new $Set(t.items.filter((y) => $has(x.items, y)));
+        if (m === "difference" && x instanceof $Set) return new $Set(t.items.filter((y) => !$has(x.items, y)));
+    } else if (t instanceof $Collection) {
+        if (m === "additem" && args.length === 1) {
+            t.items.push(x);
+            return null;
+        }
+        if (m === "hasnext" && args.length === 0) return t.next < t.items.length;
+        if (m === "getnext" && args.length === 0) {
+            if (t.next >= t.items.length) $fail("getNext called with no items left in the collection");
+            return t.items[t.next++];
+        }
+        if (m === "resetnext" && args.length === 0) {
+            t.next = 0;
+            return null;
+        }
+        if (m === "isempty" && args.length === 0) return t.items.length === 0;
+    }
+    $fail(`Unknown method ${name} with ${args.length} argument(s) on ${$typeName(t)}`);
+}
+
+// The constructor is whichever method is called `new`, in any case
+function $new(cls, args) {
+    const obj = new cls();
+    obj.$fields();
+
+    for (let p = cls.prototype; p !== $Object.prototype; p = Object.getPrototypeOf(p)) {
+        const ctor = Object.getOwnPropertyNames(p).find((k) => k.toLowerCase() === "new");
+        if (ctor) {
+            obj[ctor](...args);
+            return obj;
+        }
+    }
+
+    if (args.length > 0) $fail(`Class ${cls.name} has no constructor`);
+    return obj;
+}
+
+let $stdin = "";
+let $stdinDone = false;
+
+function $stdinLine() {
+    const fs = require("fs");
+    const chunk = Buffer.alloc(65536);
+
+    for (;;) {
+        const end = $stdin.indexOf("\n");
+        if (end >= 0) {
+            const line = $stdin.slice(0, end);
+            $stdin = $stdin.slice(end + 1);
+            return line;
+        }
+        if ($stdinDone) {
+            const line = $stdin;
+            $stdin = "";
+            return line === "" ? null : line;
+        }
+
+        let n = 0;
+        try {
+            n = fs.readSync(0, chunk, 0, chunk.length, null);
+        } catch (e) {
+            if (e.code === "EAGAIN") continue;
+            if (e.code !== "EOF") throw e;
+        }
+        if (n === 0) $stdinDone = true;
+        $stdin += chunk.toString("utf8", 0, n);
+    }
+}
+
+// Reads the line for `input NAME`, null once there is no more input. Set
+// `globalThis.pscInput` to a function taking the variable name to read
+// input some other way, otherwise browsers use prompt() and Node.js stdin
+function $readLine(name) {
+    if (typeof globalThis.pscInput === "function") return globalThis.pscInput(name);
+    if (typeof prompt === "function") return prompt(name);
+    return $stdinLine();
+}
+
+function $parseInput(kind, line) {
+    const text = line.trim();
+    switch (kind) {
+        case "integer": return /^[+-]?[0-9]+$/.test(text) ? Number(text) : undefined;
+        case "real": return text !== "" && !isNaN(Number(text)) ? Number(text) : undefined;
+        case "boolean": return text === "true" ? true : text === "false" ? false : undefined;
+        case "string": return line.replace(/\r?\n$/, "");
+    }
+
+    // Untyped input guesses what it was given
+    for (const guess of ["boolean", "integer", "real"]) {
+        const val = $parseInput(guess, text);
+        if (val !== undefined) return val;
+    }
+    return text;
+}
+
+function $input(name, kind) {
+    const names = { integer: "an integer", real: "a real", string: "a string", boolean: "a boolean" };
+
+    for (;;) {
+        const line = $readLine(name);
+        if (line === null || line === undefined) $fail(`Unexpected end of input while reading ${name}`);
+
+        const val = $parseInput(kind, line);
+        if (val !== undefined) return val;
+        console.error(`Expected ${names[kind]} for ${name}, got "${line.trim()}", try again`);
+    }
+}
+
+let $seed = BigInt(Date.now()) * 1000000n;
+
+// The same splitmix64 generator as psc, so seeded runs match
+function $random() {
+    $seed = BigInt.asUintN(64, $seed + 0x9e3779b97f4a7c15n);
+    let x = $seed;
+    x = BigInt.asUintN(64, (x ^ (x >> 30n)) * 0xbf58476d1ce4e5b9n);
+    x = BigInt.asUintN(64, (x ^ (x >> 27n)) * 0x94d049bb133111ebn);
+    return x ^ (x >> 31n);
+}
+
+function $extreme(name, items) {
+    if (items.length === 0) $fail(`${name} of an empty array`);
+    return items.reduce((best, x) => ((name === "MIN" ? x < best : x > best) ? x : best));
+}
+
+function $sum(items) {
+    return items.reduce((total, x) => {
+        if (typeof x !== "number") $fail(`Cannot add up ${$typeName(x)}`);
+        return total + x;
+    }, 0);
+}
+
+function $regex(pattern) {
+    return new RegExp(pattern, "u");
+}
+
+const $b = {
+    SET: (...items) => $set(items),
+    LENGTH: (x) => {
+        if (typeof x === "string") return [...x].length;
+        if (Array.isArray(x)) return x.length;
+        if (x instanceof $Set) return x.items.length;
+        if (x instanceof $Map) return x.entries.length;
+        $fail(`Unknown function LENGTH for ${$typeName(x)}`);
+    },
+    INDEXOF: (s, x) => {
+        if (Array.isArray(s)) return s.findIndex((y) => $eq(x, y));
+        const i = s.indexOf(x);
+        return i < 0 ? -1 : [...s.slice(0, i)].length;
+    },
+    COUNT: (s, x) => (Array.isArray(s) ? s.filter((y) => $eq(x, y)).length : s.split(x).length - 1),
+    MIN: (...args) => $extreme("MIN", args.length === 1 && Array.isArray(args[0]) ? args[0] : args),
+    MAX: (...args) => $extreme("MAX", args.length === 1 && Array.isArray(args[0]) ? args[0] : args),
+    SUM: (items) => $sum(items),
+    AVERAGE: (items) => {
+        if (items.length === 0) $fail("AVERAGE of an empty array");
+        return $sum(items) / items.length;
+    },
+    TRIM: (s) => s.trim(),
+    REPLACE: (s, from, to) => s.split(from).join(to),
+    SPLIT: (s, sep) => (sep === "" ? [...s] : s.split(sep)),
+    JOIN: (items, sep) => items.map($str).join(sep),
+    CONTAINS: (s, x) => s.includes(x),
+    STARTSWITH: (s, x) => s.startsWith(x),
+    ENDSWITH: (s, x) => s.endsWith(x),
+    MATCHES: (s, pattern) => $regex(pattern).test(s),
+    MATCHGROUPS: (s, pattern) => {
+        const m = s.match($regex(pattern));
+        return m ? Array.from(m.slice(1), (x) => (x === undefined ? null : x)) : null;
+    },
+    RANDOM: (...args) => {
+        if (args.length === 0) return Number($random() >> 11n) / 2 ** 53;
+        const [lo, hi] = args;
+        return lo + Number($random() % BigInt(hi - lo + 1));
+    },
+    SETSEED: (seed) => {
+        $seed = BigInt.asUintN(64, BigInt(seed));
+        return null;
+    },
+    SLEEP: (ms) => {
+        try {
+            Atomics.wait(new Int32Array(new SharedArrayBuffer(4)), 0, 0, ms);
+        } catch (e) {
+            const end = Date.now() + ms;
+            while (Date.now() < end);
+        }
+        return null;
+    },
+    WAIT: (ms) => $b.SLEEP(ms),
+    ARGS: () => (typeof process !== "undefined" ? process.argv.slice(2) : []),
+    GETENV: (name) => (typeof process !== "undefined" && name in process.env ? process.env[name] : null),
+    NOW: () => Date.now(),
+    TODAY: () => new Date().toISOString().slice(0, 10),
+};
+
+function $run(main) {
+    try {
+        main();
+    } catch (e) {
+        if (!(e instanceof $Halt)) console.error(`Error: ${e.message}`);
+        if (typeof process !== "undefined") process.exitCode = e instanceof $Halt ? e.code : 1;
+    }
+}
+"#;
+
+/// JavaScript words psc allows as names of variables, procedures or
+/// classes. Properties can be called anything, so fields and methods keep
+/// their names
+const RESERVED: &[&str] = &[
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do", "else",
+    "enum", "export", "extends", "false", "finally", "for", "function", "if", "import", "in", "instanceof", "let",
+    "new", "null", "return", "static", "super", "switch", "this", "throw", "true", "try", "typeof", "var", "void",
+    "while", "with", "yield", "arguments", "eval", "undefined", "NaN", "Infinity",
+];
+
+fn ident(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// The names visible inside one function, loop body or error handler
+/// being generated
+struct Scope {
+    /// Parameters, declared variables and loop variables
+    locals: HashSet<String>,
+    /// Nested functions defined in the body
+    functions: HashSet<String>,
+    /// Names opted into with `global`
+    globals: HashSet<String>,
+    /// A loop body or error handler rather than a function
+    block: bool,
+}
+
+struct Js<'a> {
+    prog: &'a Program<'a>,
+    out: String,
+    indent: usize,
+    /// Innermost last, empty at the top level
+    scopes: Vec<Scope>,
+    /// Variables of the top level, declared at the start of the program
+    top: HashSet<String>,
+    /// The class whose method is being generated
+    class: Option<&'a Class>,
+    /// Counter for temporaries, so nested loops don't share them
+    tmp: usize,
+}
+
+type Res<T> = Result<T, String>;
+
+/// Bare operands are places, whose values have to be copied so the copy
+/// can change without the original changing
+fn is_place(expr: &Expr) -> bool {
+    matches!(expr, Expr::Ident(_) | Expr::Index(_) | Expr::Field(_))
+}
+
+fn binary_op(op: &BinaryOp) -> Option<&'static str> {
+    match op {
+        BinaryOp::GT => Some(">"),
+        BinaryOp::LT => Some("<"),
+        BinaryOp::GE => Some(">="),
+        BinaryOp::LE => Some("<="),
+        _ => None,
+    }
+}
+
+impl<'a> Js<'a> {
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.out.push_str(&"    ".repeat(self.indent));
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) -> Res<()> {
+        self.indent += 1;
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        self.indent -= 1;
+        Ok(())
+    }
+
+    fn tmp(&mut self) -> usize {
+        self.tmp += 1;
+        self.tmp
+    }
+
+    /// Whether the top level's variables can be assigned from the code
+    /// being generated, which they can't from inside a function
+    fn at_top(&self) -> bool {
+        self.scopes.iter().all(|x| x.block)
+    }
+
+    /// Whether assigning `name` would change an existing variable rather
+    /// than create one in the innermost scope
+    fn visible(&self, name: &str) -> bool {
+        self.scopes.iter().any(|x| x.locals.contains(name) || x.globals.contains(name))
+            || self.class.is_some_and(|class| self.prog.has_field(class, name))
+            || (self.at_top() && self.top.contains(name))
+    }
+
+    /// A variable as seen from the code being generated, fields of the
+    /// current object come after the function's own variables. Reading a
+    /// variable no scope has fails like it does in psc
+    fn var(&self, name: &str) -> String {
+        for scope in self.scopes.iter().rev() {
+            if scope.globals.contains(name) {
+                return ident(name);
+            }
+            if scope.locals.contains(name) || scope.functions.contains(name) {
+                return ident(name);
+            }
+        }
+
+        match self.class {
+            Some(class) if self.prog.has_field(class, name) => format!("this.{}", name),
+            _ if self.top.contains(name) || self.prog.functions.contains(name) => ident(name),
+            _ => format!("$fail({})", Json::str(&format!("Unknow identifier: {}", name))),
+        }
+    }
+
+    fn list(&self, exprs: &[Expr]) -> Res<String> {
+        let items: Res<Vec<String>> = exprs.iter().map(|x| self.value(x)).collect();
+        Ok(items?.join(", "))
+    }
+
+    /// A function's parameters and body, declaring the variables it assigns
+    /// outside its loops
+    fn function_body(&mut self, func: &'a Function) -> Res<()> {
+        let globals = globals(&func.stmts);
+        let params: HashSet<String> = func.params.iter().cloned().collect();
+
+        let mut declared: Vec<String> = Vec::new();
+        for name in assigned_in_scope(&func.stmts) {
+            let outer = self.scopes.iter().any(|x| x.locals.contains(&name));
+            let field = self.class.is_some_and(|class| self.prog.has_field(class, &name));
+
+            if !params.contains(&name) && !globals.contains(&name) && !outer && !field {
+                declared.push(name);
+            }
+        }
+
+        let mut locals = params;
+        locals.extend(declared.iter().cloned());
+        self.scopes.push(Scope {
+            locals,
+            functions: functions(&func.stmts),
+            globals,
+            block: false,
+        });
+
+        self.indent += 1;
+        if !declared.is_empty() {
+            let names: Vec<String> = declared.iter().map(|x| ident(x)).collect();
+            self.line(&format!("let {};", names.join(", ")));
+        }
+        self.indent -= 1;
+
+        let res = self.block(&func.stmts);
+        self.scopes.pop();
+        res
+    }
+
+    /// A loop body or error handler, with `bind` (the loop variable or
+    /// error name) and the variables first assigned in it local to it
+    fn scoped(&mut self, stmts: &'a [Stmt], bind: Option<&str>) -> Res<()> {
+        let declared: Vec<String> = assigned_in_scope(stmts)
+            .into_iter()
+            .filter(|x| Some(x.as_str()) != bind && !self.visible(x))
+            .collect();
+
+        let mut locals: HashSet<String> = declared.iter().cloned().collect();
+        locals.extend(bind.map(String::from));
+        self.scopes.push(Scope {
+            locals,
+            functions: HashSet::new(),
+            globals: HashSet::new(),
+            block: true,
+        });
+
+        self.indent += 1;
+        if !declared.is_empty() {
+            let names: Vec<String> = declared.iter().map(|x| ident(x)).collect();
+            self.line(&format!("let {};", names.join(", ")));
+        }
+        self.indent -= 1;
+
+        let res = self.block(stmts);
+        self.scopes.pop();
+        res
+    }
+
+    fn params(func: &Function) -> String {
+        func.params.iter().map(|x| ident(x)).collect::<Vec<String>>().join(", ")
+    }
+
+    fn function(&mut self, func: &'a Function) -> Res<()> {
+        // Functions outside other functions are global, so they don't see
+        // the variables of a loop they're in
+        if self.at_top() {
+            self.line(&format!("function {}({}) {{", ident(&func.name), Js::params(func)));
+            let scopes = std::mem::take(&mut self.scopes);
+            let res = self.function_body(func);
+            self.scopes = scopes;
+            res?;
+            self.line("}");
+            return Ok(());
+        }
+
+        // Nested functions are arrows so methods' `this` carries into them
+        self.line(&format!("const {} = ({}) => {{", ident(&func.name), Js::params(func)));
+        self.function_body(func)?;
+        self.line("};");
+        Ok(())
+    }
+
+    fn class(&mut self, class: &'a Class) -> Res<()> {
+        let parent = class.parent.as_deref().map_or("$Object".to_string(), ident);
+        self.line(&format!("class {} extends {} {{", ident(&class.name), parent));
+        self.indent += 1;
+
+        self.line("$fields() {");
+        self.indent += 1;
+        self.line("super.$fields();");
+        for field in &class.fields {
+            let init = match &field.init {
+                Some(init) => self.value(init)?,
+                None => "null".to_string(),
+            };
+            self.line(&format!("this.{} = {};", field.name, init));
+        }
+        self.indent -= 1;
+        self.line("}");
+
+        let outer = self.class.replace(class);
+        let scopes = std::mem::take(&mut self.scopes);
+        let mut res = Ok(());
+
+        for method in &class.methods {
+            self.line("");
+            self.line(&format!("{}({}) {{", method.func.name, Js::params(&method.func)));
+            res = self.function_body(&method.func);
+            if res.is_err() {
+                break;
+            }
+            self.line("}");
+        }
+
+        self.scopes = scopes;
+        self.class = outer;
+        res?;
+
+        self.indent -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    /// Where an assignment stores its value
+    fn assign(&mut self, target: &Expr, val: &str) -> Res<()> {
+        match target {
+            Expr::Ident(name) => {
+                let text = format!("{} = {};", self.var(name), val);
+                self.line(&text);
+            }
+            Expr::Index(x) => {
+                let text = format!("$setIndex({}, {}, {});", self.expr(&x.target)?, self.value(&x.index)?, val);
+                self.line(&text);
+            }
+            Expr::Field(x) => {
+                let text = format!("{}.{} = {};", self.expr(&x.target)?, x.name, val);
+                self.line(&text);
+            }
+            _ => return Err(unsupported("Pointers", Target::Js)),
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> Res<()> {
+        match &stmt.kind {
+            StmtKind::Assign(x) => {
+                let val = self.value(x.value())?;
+                self.assign(&x.target, &val)?;
+            }
+            StmtKind::MultiAssign(x) => {
+                // Every value is worked out before anything is assigned
+                let tmp = self.tmp();
+                let vals: Res<Vec<String>> = x
+                    .exprs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, expr)| Ok(format!("${}_{} = {}", tmp, i, self.value(expr)?)))
+                    .collect();
+
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("const {};", vals?.join(", ")));
+                for (i, target) in x.targets.iter().enumerate() {
+                    self.assign(target, &format!("${}_{}", tmp, i))?;
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            StmtKind::Input(x) => {
+                let kind = match &x.kind {
+                    Some(InputType::Integer) => "\"integer\"",
+                    Some(InputType::Real) => "\"real\"",
+                    Some(InputType::String) => "\"string\"",
+                    Some(InputType::Boolean) => "\"boolean\"",
+                    None => "null",
+                };
+                let text = format!("{} = $input({}, {});", self.var(&x.ident), Json::str(&x.ident), kind);
+                self.line(&text);
+            }
+            StmtKind::Output(x) => {
+                let text = format!("console.log($str({}));", self.expr(&x.expr)?);
+                self.line(&text);
+            }
+            StmtKind::If(x) => {
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    let text = match (i, cond) {
+                        (0, _) => format!("if ({}) {{", self.expr(cond)?),
+                        (_, Expr::BoolLit(true)) if i == x.branches.len() - 1 => "} else {".to_string(),
+                        _ => format!("}} else if ({}) {{", self.expr(cond)?),
+                    };
+                    self.line(&text);
+                    self.block(stmts)?;
+                }
+                self.line("}");
+            }
+            StmtKind::While(x) => {
+                let text = format!("while ({}) {{", self.expr(&x.cond)?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Until(x) => {
+                let text = format!("while (!({})) {{", self.expr(&x.cond)?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Repeat(x) => {
+                self.line("do {");
+                self.scoped(&x.stmts, None)?;
+                let text = format!("}} while (!({}));", self.expr(&x.cond)?);
+                self.line(&text);
+            }
+            StmtKind::For(x) => {
+                // Assigning to the loop variable doesn't change the iteration
+                let tmp = self.tmp();
+                let text = format!(
+                    "for (let $i{0} = {1}, $end{0} = {2}; $i{0} <= $end{0}; $i{0}++) {{",
+                    tmp,
+                    self.expr(&x.start)?,
+                    self.expr(&x.end)?
+                );
+                self.line(&text);
+                self.line(&format!("    let {} = $i{};", ident(&x.name), tmp));
+                self.scoped(&x.stmts, Some(&x.name))?;
+                self.line("}");
+            }
+            StmtKind::ForEach(x) => {
+                let text = format!("for (let {} of $iter({})) {{", ident(&x.name), self.value(&x.iter)?);
+                self.line(&text);
+                self.scoped(&x.stmts, Some(&x.name))?;
+                self.line("}");
+            }
+            StmtKind::Expr(x) => {
+                let text = format!("{};", self.expr(x)?);
+                self.line(&text);
+            }
+            StmtKind::Function(x) => self.function(x)?,
+            StmtKind::Class(x) => self.class(x)?,
+            StmtKind::Return(x) => match x {
+                Some(x) => {
+                    let text = format!("return {};", self.value(x)?);
+                    self.line(&text);
+                }
+                None => self.line("return null;"),
+            },
+            StmtKind::Global(_) | StmtKind::Type(_) => {}
+            StmtKind::Attempt(x) => {
+                self.line("try {");
+                self.block(&x.stmts)?;
+                self.line("} catch ($err) {");
+                self.indent += 1;
+                self.line("if ($err instanceof $Halt) throw $err;");
+                if let Some(name) = &x.name {
+                    self.line(&format!("let {} = $err.message;", ident(name)));
+                }
+                self.indent -= 1;
+                self.scoped(&x.handler, x.name.as_deref())?;
+                self.line("}");
+            }
+            StmtKind::Assert(x) => {
+                let msg = match &x.msg {
+                    Some(msg) => format!("\"Assertion failed at line {}: \" + $str({})", stmt.span.line, self.expr(msg)?),
+                    None => format!("\"Assertion failed at line {}\"", stmt.span.line),
+                };
+                let text = format!("if (!({})) $fail({});", self.expr(&x.cond)?, msg);
+                self.line(&text);
+            }
+            StmtKind::Halt(x) => {
+                let code = match x {
+                    Some(x) => self.expr(x)?,
+                    None => "0".to_string(),
+                };
+                self.line(&format!("throw new $Halt({});", code));
+            }
+            StmtKind::Include(_) => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// An expression whose value is stored somewhere, copied if it's a place
+    fn value(&self, expr: &Expr) -> Res<String> {
+        if is_place(expr) {
+            Ok(format!("$clone({})", self.expr(expr)?))
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    /// Operands written with JavaScript's own operators go in brackets, so
+    /// its precedence can't change the meaning
+    fn operand(&self, expr: &Expr) -> Res<String> {
+        match expr {
+            Expr::BinOp(x) if binary_op(&x.op).is_some() => Ok(format!("({})", self.expr(expr)?)),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn call(&self, call: &Call) -> Res<String> {
+        let args = self.list(&call.args)?;
+        let visible = self
+            .scopes
+            .iter()
+            .any(|x| x.functions.contains(&call.name) || x.locals.contains(&call.name));
+
+        if visible || self.prog.functions.contains(call.name.as_str()) {
+            return Ok(format!("{}({})", ident(&call.name), args));
+        }
+
+        if let Some(class) = self.class {
+            if self.prog.has_method(class, &call.name) {
+                return Ok(format!("this.{}({})", call.name, args));
+            }
+        }
+
+        match self.prog.builtin(&call.name) {
+            Some("NEW") => Err(unsupported("Pointers", Target::Js)),
+            Some(name) => Ok(format!("$b.{}({})", name, args)),
+            None => Ok(format!("{}({})", ident(&call.name), args)),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Res<String> {
+        let ret = match expr {
+            Expr::BinOp(x) => {
+                let (l, r) = (self.operand(&x.left)?, self.operand(&x.right)?);
+                match (&x.op, binary_op(&x.op)) {
+                    (_, Some(op)) => format!("{} {} {}", l, op, r),
+                    (BinaryOp::Plus, _) => format!("$add({}, {})", l, r),
+                    (BinaryOp::Minus, _) => format!("$sub({}, {})", l, r),
+                    (BinaryOp::Mul, _) => format!("$mul({}, {})", l, r),
+                    (BinaryOp::Div, _) => format!("$div({}, {}, {})", l, r, x.span.line),
+                    (BinaryOp::FloorDiv, _) => format!("$floorDiv({}, {}, {})", l, r, x.span.line),
+                    (BinaryOp::Mod, _) => format!("$mod({}, {}, {})", l, r, x.span.line),
+                    (BinaryOp::Equals, _) => format!("$eq({}, {})", l, r),
+                    (BinaryOp::NotEquals, _) => format!("!$eq({}, {})", l, r),
+                    (BinaryOp::In, _) => format!("$in({}, {})", l, r),
+                    (BinaryOp::BAnd, _) => format!("$bits(\"&\", {}, {})", l, r),
+                    (BinaryOp::BOr, _) => format!("$bits(\"|\", {}, {})", l, r),
+                    (BinaryOp::BXor, _) => format!("$bits(\"^\", {}, {})", l, r),
+                    (BinaryOp::Shl, _) => format!("$bits(\"<<\", {}, {})", l, r),
+                    (BinaryOp::Shr, _) => format!("$bits(\">>\", {}, {})", l, r),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::IfElse(x) => format!(
+                "({} ? {} : {})",
+                self.expr(&x.cond)?,
+                self.expr(&x.then)?,
+                self.expr(&x.otherwise)?
+            ),
+            Expr::Index(x) => format!("$index({}, {})", self.expr(&x.target)?, self.expr(&x.index)?),
+            Expr::MethodCall(x) => format!(
+                "$method({}, {}, [{}])",
+                self.expr(&x.target)?,
+                Json::str(&x.name),
+                self.list(&x.args)?
+            ),
+            Expr::Field(x) => format!("{}.{}", self.expr(&x.target)?, x.name),
+            Expr::Call(x) => self.call(x)?,
+            Expr::SuperCall(x) => format!("super.{}({})", x.name, self.list(&x.args)?),
+            Expr::New(x) if x.class == "Collection" && !self.prog.classes.contains_key("Collection") => {
+                "new $Collection([])".to_string()
+            }
+            Expr::New(x) => format!("$new({}, [{}])", ident(&x.class), self.list(&x.args)?),
+            Expr::Map(entries) => {
+                let entries: Res<Vec<String>> = entries
+                    .iter()
+                    .map(|(k, v)| Ok(format!("[{}, {}]", self.value(k)?, self.value(v)?)))
+                    .collect();
+                format!("$map([{}])", entries?.join(", "))
+            }
+            Expr::Set(items) => format!("$set([{}])", self.list(items)?),
+            Expr::Array(items) => format!("[{}]", self.list(items)?),
+            Expr::AddressOf(_) | Expr::Deref(_) => return Err(unsupported("Pointers", Target::Js)),
+            Expr::BNot(x) => format!("$bnot({})", self.expr(x)?),
+            // Past 2^53 a double would round it
+            Expr::IntLit(x) if x.unsigned_abs() > 1 << 53 => format!("{}n", x),
+            Expr::IntLit(x) => x.to_string(),
+            Expr::FloatLit(x) if x.is_infinite() => (if *x > 0.0 { "Infinity" } else { "-Infinity" }).to_string(),
+            Expr::FloatLit(x) => format!("{:?}", x),
+            Expr::BoolLit(x) => x.to_string(),
+            Expr::StrLit(x) => Json::str(x).to_string(),
+            Expr::NullLit => "null".to_string(),
+            Expr::Ident(name) => self.var(name),
+        };
+
+        Ok(ret)
+    }
+}
+
+/// A script for Node.js or browsers. `output` goes to `console.log` and
+/// `input` to the function described in the runtime
+pub fn transpile(prog: &Program) -> Res<String> {
+    let mut js = Js {
+        prog,
+        out: String::new(),
+        indent: 0,
+        scopes: Vec::new(),
+        top: HashSet::new(),
+        class: None,
+        tmp: 0,
+    };
+
+    js.out.push_str(RUNTIME);
+    js.line("");
+    js.line("$run(() => {");
+    js.indent += 1;
+
+    // Functions can create globals too, with `global`
+    let mut names: Vec<String> = Vec::new();
+    for name in assigned_in_scope(prog.stmts).into_iter().chain(all_globals(prog.stmts)) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    js.top = names.iter().cloned().collect();
+    let names: Vec<String> = names.iter().map(|x| ident(x)).collect();
+    if !names.is_empty() {
+        js.line(&format!("let {};", names.join(", ")));
+    }
+
+    js.indent -= 1;
+    js.block(prog.stmts)?;
+    js.line("});");
+
+    Ok(js.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{transpile as to, Target};
+    use std::io::{self, Write};
+    use std::process::{Command, Stdio};
+
+    fn js(source: &str) -> String {
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        to(&stmts, Target::Js, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn integer_arithmetic_is_checked() {
+        let code = js("A = 2\nB = 3\nC = A * B + A - B < A\noutput C");
+        assert!(code.contains("C = $sub($add($mul(A, B), A), B) < A;"), "{}", code);
+    }
+
+    /// Runs the program too where node is installed
+    #[test]
+    fn integer_overflow_is_an_error() {
+        let code = js("X = 9223372036854775807\nattempt\n\tX = X + 1\non error E\n\toutput E\nend attempt");
+        assert!(code.contains("X = 9223372036854775807n;"), "{}", code);
+
+        let Ok(out) = Command::new("node").arg("-e").arg(&code).output() else {
+            return;
+        };
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "Integer overflow in 9223372036854775807 + 1\n");
+    }
+
+    /// Runs the program too where node is installed
+    #[test]
+    fn loop_variables_stay_in_the_loop() {
+        let source = "X = 1\nloop while X < 3\n\tinput Y\n\tX = X + 1\nend loop\noutput Y";
+        let code = js(source);
+        assert!(code.contains("    while (X < 3) {\n        let Y;\n"), "{}", code);
+
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        let mut interpreter = psc::Interpreter::with_io(io::Cursor::new("5\n6\n"), io::sink());
+        let msg = interpreter.run(&stmts).unwrap_err().msg;
+        assert_eq!(msg, "Unknow identifier: Y");
+
+        let Ok(mut node) = Command::new("node")
+            .arg("-e")
+            .arg(&code)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        else {
+            return;
+        };
+        node.stdin.take().unwrap().write_all(b"5\n6\n").unwrap();
+        let out = node.wait_with_output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stderr), format!("Error: {}\n", msg));
+    }
+}
@@ -0,0 +1,344 @@
+mod c;
+mod js;
+mod rust;
+
+use psc::dialect::Dialect;
+use psc::eval::*;
+use psc::lex::LexOptions;
+use psc::load;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Languages programs can be transpiled into
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    Js,
+    Rust,
+    C,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "js" | "javascript" => Some(Target::Js),
+            "rs" | "rust" => Some(Target::Rust),
+            "c" => Some(Target::C),
+            _ => None,
+        }
+    }
+
+    /// The name used in errors about things the target can't do
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Js => "JavaScript",
+            Target::Rust => "Rust",
+            Target::C => "C",
+        }
+    }
+}
+
+/// Builtins the generated runtimes provide, by their psc names
+pub const BUILTINS: &[&str] = &[
+    "SET", "NEW", "LENGTH", "INDEXOF", "COUNT", "MIN", "MAX", "SUM", "AVERAGE", "TRIM", "REPLACE", "SPLIT", "JOIN",
+    "CONTAINS", "STARTSWITH", "ENDSWITH", "MATCHES", "MATCHGROUPS", "RANDOM", "SETSEED", "SLEEP", "WAIT", "ARGS",
+    "GETENV", "NOW", "TODAY",
+];
+
+/// What the backends need to know about the program as a whole
+pub struct Program<'a> {
+    pub stmts: &'a [Stmt],
+    pub classes: HashMap<&'a str, &'a Class>,
+    /// Functions defined at the top level, callable from anywhere
+    pub functions: HashSet<&'a str>,
+    pub dialect: Dialect,
+}
+
+impl<'a> Program<'a> {
+    pub fn new(stmts: &'a [Stmt], dialect: Dialect) -> Program<'a> {
+        let mut classes = HashMap::new();
+        let mut functions = HashSet::new();
+
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Class(x) => {
+                    classes.insert(x.name.as_str(), &**x);
+                }
+                StmtKind::Function(x) => {
+                    functions.insert(x.name.as_str());
+                }
+                _ => {}
+            }
+        }
+
+        Program {
+            stmts,
+            classes,
+            functions,
+            dialect,
+        }
+    }
+
+    /// `class` followed by its parent, grandparent and so on
+    pub fn ancestors(&self, class: &'a Class) -> Vec<&'a Class> {
+        let mut ret = vec![class];
+
+        while let Some(parent) = ret.last().and_then(|x| x.parent.as_deref()) {
+            match self.classes.get(parent) {
+                Some(x) if !ret.iter().any(|y| std::ptr::eq(*x, *y)) => ret.push(x),
+                _ => break,
+            }
+        }
+
+        ret
+    }
+
+    /// Whether objects of `class` have a field called `name`, inherited or not
+    pub fn has_field(&self, class: &'a Class, name: &str) -> bool {
+        self.ancestors(class).iter().any(|x| x.fields.iter().any(|f| f.name == name))
+    }
+
+    pub fn has_method(&self, class: &'a Class, name: &str) -> bool {
+        self.ancestors(class).iter().any(|x| x.method(name).is_some())
+    }
+
+    /// The psc name of a builtin called `name` in the program's dialect
+    pub fn builtin<'n>(&self, name: &'n str) -> Option<&'n str> {
+        let name = self.dialect.table().builtin(name);
+        if BUILTINS.contains(&name) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+/// The error for a construct `target` has no equivalent for
+pub fn unsupported(what: &str, target: Target) -> String {
+    format!("{} are not supported when transpiling to {}", what, target.name())
+}
+
+/// Runs `f` on every statement of a body, looking into nested blocks but
+/// not nested definitions
+pub fn walk<'a>(stmts: &'a [Stmt], f: &mut dyn FnMut(&'a Stmt)) {
+    for stmt in stmts {
+        f(stmt);
+
+        match &stmt.kind {
+            StmtKind::If(x) => {
+                for (_, stmts) in &x.branches {
+                    walk(stmts, f);
+                }
+            }
+            StmtKind::While(While { stmts, .. })
+            | StmtKind::Until(Until { stmts, .. })
+            | StmtKind::Repeat(Until { stmts, .. })
+            | StmtKind::For(For { stmts, .. })
+            | StmtKind::ForEach(ForEach { stmts, .. }) => walk(stmts, f),
+            StmtKind::Attempt(x) => {
+                walk(&x.stmts, f);
+                walk(&x.handler, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Runs `f` on the statements that run in the same scope as the body,
+/// looking into `if` branches and `attempt` bodies but not loop bodies or
+/// error handlers, which have scopes of their own
+pub fn walk_scope<'a>(stmts: &'a [Stmt], f: &mut dyn FnMut(&'a Stmt)) {
+    for stmt in stmts {
+        f(stmt);
+
+        match &stmt.kind {
+            StmtKind::If(x) => {
+                for (_, stmts) in &x.branches {
+                    walk_scope(stmts, f);
+                }
+            }
+            StmtKind::Attempt(x) => walk_scope(&x.stmts, f),
+            _ => {}
+        }
+    }
+}
+
+/// Variables a body assigns to in its own scope, in order of first
+/// assignment. Target languages declare these up front, where psc creates
+/// them on first use. Ones first assigned in a loop body belong to the loop
+pub fn assigned_in_scope(stmts: &[Stmt]) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    let mut add = |name: &String| {
+        if !ret.contains(name) {
+            ret.push(name.clone());
+        }
+    };
+
+    walk_scope(stmts, &mut |stmt| match &stmt.kind {
+        StmtKind::Assign(Assign {
+            target: Expr::Ident(name),
+            ..
+        }) => add(name),
+        StmtKind::MultiAssign(x) => {
+            for target in &x.targets {
+                if let Expr::Ident(name) = target {
+                    add(name);
+                }
+            }
+        }
+        StmtKind::Input(x) => add(&x.ident),
+        _ => {}
+    });
+
+    ret
+}
+
+/// Names a body opts into with `global`
+pub fn globals(stmts: &[Stmt]) -> HashSet<String> {
+    let mut ret = HashSet::new();
+    walk(stmts, &mut |stmt| {
+        if let StmtKind::Global(names) = &stmt.kind {
+            ret.extend(names.iter().cloned());
+        }
+    });
+    ret
+}
+
+/// Names declared `global` anywhere in the program, in nested functions and
+/// methods too, sorted
+pub fn all_globals(stmts: &[Stmt]) -> Vec<String> {
+    fn visit(stmts: &[Stmt], ret: &mut HashSet<String>) {
+        ret.extend(globals(stmts));
+        walk(stmts, &mut |stmt| match &stmt.kind {
+            StmtKind::Function(x) => visit(&x.stmts, ret),
+            StmtKind::Class(x) => {
+                for method in &x.methods {
+                    visit(&method.func.stmts, ret);
+                }
+            }
+            _ => {}
+        });
+    }
+
+    let mut ret = HashSet::new();
+    visit(stmts, &mut ret);
+
+    let mut ret: Vec<String> = ret.into_iter().collect();
+    ret.sort();
+    ret
+}
+
+/// Names of the functions a body defines
+pub fn functions(stmts: &[Stmt]) -> HashSet<String> {
+    let mut ret = HashSet::new();
+    walk(stmts, &mut |stmt| {
+        if let StmtKind::Function(x) = &stmt.kind {
+            ret.insert(x.name.clone());
+        }
+    });
+    ret
+}
+
+/// Source code for `stmts` in the `target` language
+pub fn transpile(stmts: &[Stmt], target: Target, dialect: Dialect) -> Result<String, Box<dyn Error>> {
+    let prog = Program::new(stmts, dialect);
+
+    let res = match target {
+        Target::Js => js::transpile(&prog),
+        Target::Rust => rust::transpile(&prog),
+        Target::C => c::transpile(&prog),
+    };
+    Ok(res?)
+}
+
+/// Writes `code` from the Rust target as a cargo project in `dir` and builds
+/// it, returning the path of the binary
+fn cargo_build(code: &str, file: &str, dir: &Path) -> Result<String, Box<dyn Error>> {
+    let stem = Path::new(file).file_stem().map_or("program".into(), |x| x.to_string_lossy());
+    let mut name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name = format!("psc_{}", name);
+    }
+
+    let manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n",
+        name
+    );
+
+    fs::create_dir_all(dir.join("src")).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    fs::write(dir.join("Cargo.toml"), manifest)?;
+    fs::write(dir.join("src").join("main.rs"), code)?;
+
+    let status = Command::new("cargo")
+        .arg("build")
+        .arg("--release")
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        .status()
+        .map_err(|e| format!("Couldn't run cargo: {}", e))?;
+
+    if !status.success() {
+        return Err("cargo build failed".into());
+    }
+
+    Ok(dir.join("target").join("release").join(name).display().to_string())
+}
+
+/// `psc transpile [--target js|rust|c] FILE [-o OUT] [--build DIR]` writes
+/// the program in another language, to `OUT` or stdout. `--build` makes a
+/// cargo project of the Rust output and compiles it
+pub fn main(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut target = Target::Js;
+    let mut file: Option<String> = None;
+    let mut out: Option<String> = None;
+    let mut build: Option<String> = None;
+    let mut options = LexOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => match args.next().as_deref().and_then(Target::from_name) {
+                Some(x) => target = x,
+                None => return Err("--target expects js, rust or c".into()),
+            },
+            "-o" => match args.next() {
+                Some(x) => out = Some(x),
+                None => return Err("-o expects a file name".into()),
+            },
+            "--build" => match args.next() {
+                Some(x) => build = Some(x),
+                None => return Err("--build expects a directory".into()),
+            },
+            _ if options.parse_flag(&arg, &mut args)? => {}
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unexpected transpile argument {}", arg).into()),
+        }
+    }
+
+    let file = match file {
+        Some(x) => x,
+        None => return Err("transpile expects a file".into()),
+    };
+
+    if build.is_some() && target != Target::Rust {
+        return Err("--build only works with --target rust".into());
+    }
+
+    let stmts = load::load(Path::new(&file), &options)?;
+    let res = transpile(&stmts, target, options.dialect).map_err(|e| format!("{}: {}", file, e))?;
+
+    match (out, build) {
+        (Some(_), Some(_)) => return Err("-o and --build can't be used together".into()),
+        (Some(out), None) => {
+            fs::write(&out, res).map_err(|e| format!("{}: {}", out, e))?;
+        }
+        (None, Some(dir)) => println!("{}", cargo_build(&res, &file, Path::new(&dir))?),
+        (None, None) => print!("{}", res),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,552 @@
+/* Runtime for programs from `psc transpile --target c`, copied to the top of
+ * each one: strings, arrays, checked arithmetic, input and output. Memory is
+ * never freed, the programs are short lived. */
+
+#ifndef PSC_H
+#define PSC_H
+
+#ifndef _POSIX_C_SOURCE
+#define _POSIX_C_SOURCE 200809L
+#endif
+
+#include <limits.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/* What an array holds */
+typedef enum { PSC_INT, PSC_REAL, PSC_BOOL, PSC_STR, PSC_ARRAY } psc_kind;
+
+/* Arrays grow as items are appended. Assigning one copies the values, the
+ * generated code calls psc_copy for that */
+typedef struct {
+    psc_kind kind;
+    long long len, cap;
+    void *items;
+} psc_array;
+
+#define PSC_EMPTY(kind) {kind, 0, 0, NULL}
+
+/* An item of an array, usable on either side of `=` */
+#define PSC_AT(type, a, i) (*(type *)psc_at((a), (i)))
+
+static int psc_argc;
+static char **psc_argv;
+static uint64_t psc_rng;
+
+static inline void psc_fail(const char *fmt, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("Error: ", stderr);
+    va_start(args, fmt);
+    vfprintf(stderr, fmt, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static inline void *psc_alloc(size_t n) {
+    void *ret = malloc(n ? n : 1);
+    if (!ret) psc_fail("Out of memory");
+    return ret;
+}
+
+static inline void psc_init(int argc, char **argv) {
+    psc_argc = argc;
+    psc_argv = argv;
+    psc_rng = (uint64_t)time(NULL) * 1000003u ^ (uint64_t)clock();
+}
+
+/* Strings */
+
+static inline const char *psc_concat(const char *a, const char *b) {
+    size_t la = strlen(a), lb = strlen(b);
+    char *ret = psc_alloc(la + lb + 1);
+    memcpy(ret, a, la);
+    memcpy(ret + la, b, lb + 1);
+    return ret;
+}
+
+static inline const char *psc_substr(const char *s, size_t len) {
+    char *ret = psc_alloc(len + 1);
+    memcpy(ret, s, len);
+    ret[len] = '\0';
+    return ret;
+}
+
+static inline bool psc_str_eq(const char *a, const char *b) {
+    return strcmp(a, b) == 0;
+}
+
+/* Lengths and positions count characters rather than UTF-8 bytes */
+static inline long long psc_chars_in(const char *s, size_t bytes) {
+    long long ret = 0;
+    for (size_t i = 0; i < bytes; i++)
+        if (((unsigned char)s[i] & 0xc0) != 0x80) ret++;
+    return ret;
+}
+
+static inline long long psc_length(const char *s) {
+    return psc_chars_in(s, strlen(s));
+}
+
+static inline bool psc_contains(const char *s, const char *x) {
+    return strstr(s, x) != NULL;
+}
+
+static inline bool psc_starts_with(const char *s, const char *x) {
+    return strncmp(s, x, strlen(x)) == 0;
+}
+
+static inline bool psc_ends_with(const char *s, const char *x) {
+    size_t ls = strlen(s), lx = strlen(x);
+    return lx <= ls && strcmp(s + ls - lx, x) == 0;
+}
+
+static inline long long psc_index_of(const char *s, const char *x) {
+    const char *at = strstr(s, x);
+    return at ? psc_chars_in(s, at - s) : -1;
+}
+
+static inline long long psc_count(const char *s, const char *x) {
+    long long ret = 0;
+    size_t lx = strlen(x);
+    if (lx == 0) psc_fail("Unknown function COUNT with 2 argument(s)");
+    for (const char *at = strstr(s, x); at; at = strstr(at + lx, x)) ret++;
+    return ret;
+}
+
+static inline bool psc_space(char c) {
+    return c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\v' || c == '\f';
+}
+
+static inline const char *psc_trim(const char *s) {
+    size_t len = strlen(s);
+    while (len > 0 && psc_space(s[len - 1])) len--;
+    while (len > 0 && psc_space(*s)) s++, len--;
+    return psc_substr(s, len);
+}
+
+static inline const char *psc_replace(const char *s, const char *from, const char *to) {
+    size_t lf = strlen(from), lt = strlen(to);
+    if (lf == 0) psc_fail("Unknown function REPLACE with 3 argument(s)");
+
+    size_t len = strlen(s) + (size_t)psc_count(s, from) * lt + 1;
+    char *ret = psc_alloc(len), *out = ret;
+    for (const char *at; (at = strstr(s, from)); s = at + lf) {
+        memcpy(out, s, at - s);
+        out += at - s;
+        memcpy(out, to, lt);
+        out += lt;
+    }
+    strcpy(out, s);
+    return ret;
+}
+
+static inline const char *psc_int_str(long long x) {
+    char buf[32];
+    snprintf(buf, sizeof buf, "%lld", x);
+    return psc_substr(buf, strlen(buf));
+}
+
+/* Reals print like psc does: the shortest digits that read back as the same
+ * number, never in exponent form */
+static inline const char *psc_real_str(double x) {
+    char buf[64], digits[32];
+    int exp, n = 0, p;
+
+    if (isnan(x)) return "NaN";
+    if (isinf(x)) return x > 0 ? "inf" : "-inf";
+
+    for (p = 1; p <= 17; p++) {
+        snprintf(buf, sizeof buf, "%.*e", p - 1, x);
+        if (strtod(buf, NULL) == x) break;
+    }
+
+    for (char *c = buf; *c != 'e'; c++)
+        if (*c >= '0' && *c <= '9') digits[n++] = *c;
+    while (n > 1 && digits[n - 1] == '0') n--;
+    exp = atoi(strchr(buf, 'e') + 1);
+
+    char *out = psc_alloc(n + (exp < 0 ? -exp : exp) + 4), *o = out;
+    if (buf[0] == '-') *o++ = '-';
+    if (exp < 0) {
+        *o++ = '0';
+        *o++ = '.';
+        for (int i = 1; i < -exp; i++) *o++ = '0';
+        for (int i = 0; i < n; i++) *o++ = digits[i];
+    } else {
+        for (int i = 0; i <= exp || i < n; i++) {
+            if (i == exp + 1) *o++ = '.';
+            *o++ = i < n ? digits[i] : '0';
+        }
+    }
+    *o = '\0';
+    return out;
+}
+
+/* Arrays */
+
+static inline size_t psc_size(psc_kind kind) {
+    switch (kind) {
+    case PSC_INT: return sizeof(long long);
+    case PSC_REAL: return sizeof(double);
+    case PSC_BOOL: return sizeof(bool);
+    case PSC_STR: return sizeof(const char *);
+    default: return sizeof(psc_array);
+    }
+}
+
+static inline psc_array psc_array_new(psc_kind kind) {
+    psc_array ret = PSC_EMPTY(kind);
+    return ret;
+}
+
+static inline psc_array psc_array_of(psc_kind kind, long long len, const void *items) {
+    psc_array ret = {kind, len, len, psc_alloc(len * psc_size(kind))};
+    memcpy(ret.items, items, len * psc_size(kind));
+    return ret;
+}
+
+static inline psc_array psc_copy(psc_array a) {
+    psc_array ret = psc_array_of(a.kind, a.len, a.items);
+    if (a.kind == PSC_ARRAY)
+        for (long long i = 0; i < a.len; i++)
+            ((psc_array *)ret.items)[i] = psc_copy(((psc_array *)a.items)[i]);
+    return ret;
+}
+
+static inline void *psc_at(psc_array a, long long i) {
+    if (i < 0 || i >= a.len) psc_fail("Index %lld out of bounds for array of length %lld", i, a.len);
+    return (char *)a.items + i * psc_size(a.kind);
+}
+
+static inline void psc_append(psc_array *a, const void *item) {
+    if (a->len == a->cap) {
+        void *old = a->items;
+        a->cap = a->cap ? a->cap * 2 : 4;
+        a->items = psc_alloc(a->cap * psc_size(a->kind));
+        if (old) memcpy(a->items, old, a->len * psc_size(a->kind));
+    }
+    memcpy((char *)a->items + a->len * psc_size(a->kind), item, psc_size(a->kind));
+    a->len++;
+}
+
+static inline bool psc_equal(psc_array a, psc_array b);
+
+static inline bool psc_item_eq(psc_kind kind, const void *x, const void *y) {
+    switch (kind) {
+    case PSC_INT: return *(const long long *)x == *(const long long *)y;
+    case PSC_REAL: return *(const double *)x == *(const double *)y;
+    case PSC_BOOL: return *(const bool *)x == *(const bool *)y;
+    case PSC_STR: return strcmp(*(const char *const *)x, *(const char *const *)y) == 0;
+    default: return psc_equal(*(const psc_array *)x, *(const psc_array *)y);
+    }
+}
+
+static inline bool psc_equal(psc_array a, psc_array b) {
+    if (a.len != b.len) return false;
+    for (long long i = 0; i < a.len; i++)
+        if (!psc_item_eq(a.kind, psc_at(a, i), psc_at(b, i))) return false;
+    return true;
+}
+
+static inline long long psc_array_index(psc_array a, const void *item) {
+    for (long long i = 0; i < a.len; i++)
+        if (psc_item_eq(a.kind, psc_at(a, i), item)) return i;
+    return -1;
+}
+
+static inline bool psc_array_has(psc_array a, const void *item) {
+    return psc_array_index(a, item) >= 0;
+}
+
+static inline long long psc_array_count(psc_array a, const void *item) {
+    long long ret = 0;
+    for (long long i = 0; i < a.len; i++)
+        if (psc_item_eq(a.kind, psc_at(a, i), item)) ret++;
+    return ret;
+}
+
+static inline const char *psc_array_str(psc_array a);
+
+/* `item` as output would print it */
+static inline const char *psc_item_str(psc_kind kind, const void *item) {
+    switch (kind) {
+    case PSC_INT: return psc_int_str(*(const long long *)item);
+    case PSC_REAL: return psc_real_str(*(const double *)item);
+    case PSC_BOOL: return *(const bool *)item ? "true" : "false";
+    case PSC_STR: return *(const char *const *)item;
+    default: return psc_array_str(*(const psc_array *)item);
+    }
+}
+
+static inline const char *psc_join(psc_array a, const char *sep) {
+    const char *ret = "";
+    for (long long i = 0; i < a.len; i++)
+        ret = psc_concat(i ? psc_concat(ret, sep) : ret, psc_item_str(a.kind, psc_at(a, i)));
+    return ret;
+}
+
+/* Strings inside arrays are quoted, like psc prints them */
+static inline const char *psc_array_str(psc_array a) {
+    const char *ret = "[";
+    for (long long i = 0; i < a.len; i++) {
+        const char *item = psc_item_str(a.kind, psc_at(a, i));
+        if (a.kind == PSC_STR) item = psc_concat(psc_concat("\"", item), "\"");
+        ret = psc_concat(i ? psc_concat(ret, ", ") : ret, item);
+    }
+    return psc_concat(ret, "]");
+}
+
+static inline psc_array psc_split(const char *s, const char *sep) {
+    psc_array ret = psc_array_new(PSC_STR);
+    size_t ls = strlen(sep);
+
+    if (ls == 0) {
+        while (*s) {
+            size_t len = 1;
+            while (((unsigned char)s[len] & 0xc0) == 0x80) len++;
+            const char *item = psc_substr(s, len);
+            psc_append(&ret, &item);
+            s += len;
+        }
+        return ret;
+    }
+
+    for (const char *at; (at = strstr(s, sep)); s = at + ls) {
+        const char *item = psc_substr(s, at - s);
+        psc_append(&ret, &item);
+    }
+    const char *last = psc_substr(s, strlen(s));
+    psc_append(&ret, &last);
+    return ret;
+}
+
+/* The characters of a string, for looping over it */
+static inline psc_array psc_chars(const char *s) {
+    return psc_split(s, "");
+}
+
+/* Integer arithmetic fails on overflow like psc does, where plain C would
+ * be undefined */
+#if defined(__GNUC__) || defined(__clang__)
+#define psc_add_overflow(a, b, ret) __builtin_add_overflow(a, b, ret)
+#define psc_sub_overflow(a, b, ret) __builtin_sub_overflow(a, b, ret)
+#define psc_mul_overflow(a, b, ret) __builtin_mul_overflow(a, b, ret)
+#else
+static inline bool psc_add_overflow(long long a, long long b, long long *ret) {
+    if (b > 0 ? a > LLONG_MAX - b : a < LLONG_MIN - b) return true;
+    *ret = a + b;
+    return false;
+}
+
+static inline bool psc_sub_overflow(long long a, long long b, long long *ret) {
+    if (b < 0 ? a > LLONG_MAX + b : a < LLONG_MIN + b) return true;
+    *ret = a - b;
+    return false;
+}
+
+static inline bool psc_mul_overflow(long long a, long long b, long long *ret) {
+    bool over;
+    if (a == 0 || b == 0) over = false;
+    else if (a == -1) over = b == LLONG_MIN;
+    else if (b == -1) over = a == LLONG_MIN;
+    else if ((a > 0) == (b > 0)) over = b > 0 ? a > LLONG_MAX / b : a < LLONG_MAX / b;
+    else over = a > 0 ? b < LLONG_MIN / a : a < LLONG_MIN / b;
+
+    if (!over) *ret = a * b;
+    return over;
+}
+#endif
+
+static inline long long psc_add(long long a, long long b) {
+    long long ret;
+    if (psc_add_overflow(a, b, &ret)) psc_fail("Integer overflow in %lld + %lld", a, b);
+    return ret;
+}
+
+static inline long long psc_sub(long long a, long long b) {
+    long long ret;
+    if (psc_sub_overflow(a, b, &ret)) psc_fail("Integer overflow in %lld - %lld", a, b);
+    return ret;
+}
+
+static inline long long psc_mul(long long a, long long b) {
+    long long ret;
+    if (psc_mul_overflow(a, b, &ret)) psc_fail("Integer overflow in %lld * %lld", a, b);
+    return ret;
+}
+
+static inline long long psc_sum_int(psc_array a) {
+    long long ret = 0;
+    for (long long i = 0; i < a.len; i++)
+        if (psc_add_overflow(ret, PSC_AT(long long, a, i), &ret)) psc_fail("Integer overflow in SUM");
+    return ret;
+}
+
+static inline double psc_sum_real(psc_array a) {
+    double ret = 0;
+    for (long long i = 0; i < a.len; i++) ret += PSC_AT(double, a, i);
+    return ret;
+}
+
+static inline double psc_average(psc_array a) {
+    if (a.len == 0) psc_fail("AVERAGE of an empty array");
+    return (a.kind == PSC_INT ? (double)psc_sum_int(a) : psc_sum_real(a)) / a.len;
+}
+
+static inline int psc_item_cmp(psc_kind kind, const void *x, const void *y) {
+    switch (kind) {
+    case PSC_INT: return (*(const long long *)x > *(const long long *)y) - (*(const long long *)x < *(const long long *)y);
+    case PSC_REAL: return (*(const double *)x > *(const double *)y) - (*(const double *)x < *(const double *)y);
+    default: return strcmp(*(const char *const *)x, *(const char *const *)y);
+    }
+}
+
+/* The smallest item for `want` -1, the largest for 1, the first on ties */
+static inline void *psc_extreme(psc_array a, int want, const char *name) {
+    if (a.len == 0) psc_fail("%s of an empty array", name);
+
+    void *best = psc_at(a, 0);
+    for (long long i = 1; i < a.len; i++)
+        if (psc_item_cmp(a.kind, psc_at(a, i), best) == want) best = psc_at(a, i);
+    return best;
+}
+
+static inline psc_array psc_args(void) {
+    psc_array ret = psc_array_new(PSC_STR);
+    for (int i = 1; i < psc_argc; i++) psc_append(&ret, &psc_argv[i]);
+    return ret;
+}
+
+/* Numbers */
+
+static inline double psc_div(double a, double b, int line) {
+    if (b == 0) psc_fail("Division by zero at line %d", line);
+    return a / b;
+}
+
+static inline double psc_floor_div(double a, double b, int line) {
+    return floor(psc_div(a, b, line));
+}
+
+static inline long long psc_mod(long long a, long long b, int line) {
+    if (b == 0) psc_fail("Division by zero at line %d", line);
+    return b == -1 ? 0 : a % b;
+}
+
+static inline double psc_fmod(double a, double b, int line) {
+    if (b == 0) psc_fail("Division by zero at line %d", line);
+    return floor(fmod(a, b));
+}
+
+/* The same splitmix64 generator as psc, so seeded runs match */
+static inline uint64_t psc_next(void) {
+    uint64_t x = psc_rng += 0x9e3779b97f4a7c15u;
+    x = (x ^ (x >> 30)) * 0xbf58476d1ce4e5b9u;
+    x = (x ^ (x >> 27)) * 0x94d049bb133111ebu;
+    return x ^ (x >> 31);
+}
+
+static inline double psc_random(void) {
+    return (psc_next() >> 11) / 9007199254740992.0;
+}
+
+static inline long long psc_random_int(long long lo, long long hi) {
+    if (lo > hi) psc_fail("Unknown function RANDOM with 2 argument(s)");
+    return lo + (long long)(psc_next() % ((uint64_t)(hi - lo) + 1));
+}
+
+static inline void psc_setseed(long long seed) {
+    psc_rng = (uint64_t)seed;
+}
+
+static inline void psc_sleep(long long ms) {
+    struct timespec t = {ms / 1000, ms % 1000 * 1000000};
+    nanosleep(&t, NULL);
+}
+
+/* Milliseconds since 1970 began */
+static inline long long psc_now(void) {
+    struct timespec t;
+    clock_gettime(CLOCK_REALTIME, &t);
+    return (long long)t.tv_sec * 1000 + t.tv_nsec / 1000000;
+}
+
+/* Today's date in UTC, as YYYY-MM-DD */
+static inline const char *psc_today(void) {
+    time_t now = time(NULL);
+    struct tm tm;
+    char *ret = psc_alloc(32);
+    gmtime_r(&now, &tm);
+    strftime(ret, 32, "%Y-%m-%d", &tm);
+    return ret;
+}
+
+/* Input */
+
+/* The next line without its line ending */
+static inline const char *psc_read_line(const char *name) {
+    size_t len = 0, cap = 64;
+    char *ret = psc_alloc(cap);
+    int c;
+
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (len + 1 == cap) {
+            char *old = ret;
+            ret = psc_alloc(cap *= 2);
+            memcpy(ret, old, len);
+        }
+        ret[len++] = (char)c;
+    }
+    if (c == EOF && len == 0) psc_fail("Unexpected end of input while reading %s", name);
+
+    if (len > 0 && ret[len - 1] == '\r') len--;
+    ret[len] = '\0';
+    return ret;
+}
+
+static inline void psc_retry(const char *what, const char *name, const char *line) {
+    fprintf(stderr, "Expected %s for %s, got \"%s\", try again\n", what, name, line);
+}
+
+static inline long long psc_input_int(const char *name) {
+    for (;;) {
+        const char *line = psc_trim(psc_read_line(name));
+        char *end;
+        long long ret = strtoll(line, &end, 10);
+        if (*line && !*end) return ret;
+        psc_retry("an integer", name, line);
+    }
+}
+
+static inline double psc_input_real(const char *name) {
+    for (;;) {
+        const char *line = psc_trim(psc_read_line(name));
+        char *end;
+        double ret = strtod(line, &end);
+        if (*line && !*end) return ret;
+        psc_retry("a real", name, line);
+    }
+}
+
+static inline bool psc_input_bool(const char *name) {
+    for (;;) {
+        const char *line = psc_trim(psc_read_line(name));
+        if (psc_str_eq(line, "true")) return true;
+        if (psc_str_eq(line, "false")) return false;
+        psc_retry("a boolean", name, line);
+    }
+}
+
+static inline const char *psc_input_str(const char *name) {
+    return psc_read_line(name);
+}
+
+#endif
@@ -0,0 +1,873 @@
+use super::{all_globals, assigned_in_scope, globals, unsupported, Program, Target};
+use psc::eval::*;
+use std::cell::Cell;
+use std::collections::HashSet;
+
+/// Value type, operators and builtins the generated code is written against,
+/// ending with `main`
+const RUNTIME: &str = include_str!("rust_runtime.rs");
+
+type Res<T> = Result<T, String>;
+
+/// Where a variable lives in the generated code
+enum Var {
+    /// A Rust local, `v_NAME`
+    Local(String),
+    /// A member of `G`, the struct holding the top level variables
+    Global(String),
+    /// A field of the object the current method runs on
+    Field,
+    Unknown,
+}
+
+/// A step from a value into part of it
+enum Step {
+    /// Indexing with the key stored in this temporary
+    Key(String),
+    Field(String),
+}
+
+/// Whether evaluating `expr` can run program code, which needs `g` and so
+/// can't happen while part of `g` is borrowed
+fn calls(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) | Expr::SuperCall(_) | Expr::New(_) | Expr::MethodCall(_) => true,
+        Expr::BinOp(x) => calls(&x.left) || calls(&x.right),
+        Expr::IfElse(x) => calls(&x.cond) || calls(&x.then) || calls(&x.otherwise),
+        Expr::Index(x) => calls(&x.target) || calls(&x.index),
+        Expr::Field(x) => calls(&x.target),
+        Expr::Map(entries) => entries.iter().any(|(k, v)| calls(k) || calls(v)),
+        Expr::Set(items) | Expr::Array(items) => items.iter().any(calls),
+        Expr::Deref(x) | Expr::BNot(x) => calls(x),
+        _ => false,
+    }
+}
+
+fn literal(text: &str) -> String {
+    format!("{:?}", text)
+}
+
+struct Rust<'a> {
+    prog: &'a Program<'a>,
+    out: String,
+    indent: usize,
+    /// Top level variables, the members of `G`
+    globals: Vec<String>,
+    /// Rust locals in scope, innermost block last
+    scopes: Vec<HashSet<String>>,
+    /// Names the function being generated opts into with `global`, `None`
+    /// at the top level, where every global can be assigned
+    opted: Option<HashSet<String>>,
+    /// The class whose method is being generated
+    class: Option<&'a Class>,
+    /// How many `attempt` bodies deep the code is, those run in closures
+    attempts: usize,
+    tmp: Cell<usize>,
+    /// Whether the statement being generated runs program code, see `calls`
+    calling: Cell<bool>,
+}
+
+impl<'a> Rust<'a> {
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.out.push_str(&"    ".repeat(self.indent));
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn tmp(&self) -> String {
+        self.tmp.set(self.tmp.get() + 1);
+        format!("t{}", self.tmp.get())
+    }
+
+    fn var(&self, name: &str) -> Var {
+        if self.scopes.iter().any(|x| x.contains(name)) {
+            return Var::Local(format!("v_{}", name));
+        }
+
+        if let Some(class) = self.class {
+            if self.prog.has_field(class, name) {
+                return Var::Field;
+            }
+        }
+
+        if self.globals.iter().any(|x| x == name) {
+            Var::Global(format!("g.v_{}", name))
+        } else {
+            Var::Unknown
+        }
+    }
+
+    /// Whether assigning `name` would change an existing variable rather
+    /// than create one in the innermost psc scope
+    fn visible(&self, name: &str) -> bool {
+        match self.var(name) {
+            Var::Local(_) | Var::Field => true,
+            Var::Global(_) => self.opted.as_ref().is_none_or(|x| x.contains(name)),
+            Var::Unknown => false,
+        }
+    }
+
+    fn unknown(&self, name: &str) -> Res<String> {
+        if self.prog.functions.contains(name) {
+            return Err(unsupported("Functions as values", Target::Rust));
+        }
+        Ok(format!("fail::<V>({})?", literal(&format!("Unknow identifier: {}", name))))
+    }
+
+    /// Generates a whole expression of a statement
+    fn root(&self, expr: &Expr) -> Res<String> {
+        let outer = self.calling.replace(calls(expr));
+        let res = self.expr(expr);
+        self.calling.set(outer);
+        res
+    }
+
+    /// Whether the variable `expr` starts from can be borrowed for as long
+    /// as the expression takes
+    fn borrowable(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Ident(name) => match self.var(name) {
+                Var::Local(_) => true,
+                Var::Global(_) => !self.calling.get(),
+                _ => false,
+            },
+            Expr::Index(x) => self.borrowable(&x.target),
+            _ => false,
+        }
+    }
+
+    /// A `&V` for `expr`, borrowing the variable it's in where possible
+    /// rather than copying the value
+    fn refer(&self, expr: &Expr) -> Res<String> {
+        match expr {
+            Expr::Ident(name) if self.borrowable(expr) => match self.var(name) {
+                Var::Local(x) | Var::Global(x) => Ok(format!("&{}", x)),
+                _ => unreachable!(),
+            },
+            Expr::Index(x) if self.borrowable(expr) => {
+                Ok(format!("index({}, {})?", self.refer(&x.target)?, self.refer(&x.index)?))
+            }
+            _ => Ok(format!("&{}", self.expr(expr)?)),
+        }
+    }
+
+    fn list(&self, exprs: &[Expr]) -> Res<String> {
+        let items: Res<Vec<String>> = exprs.iter().map(|x| self.expr(x)).collect();
+        Ok(items?.join(", "))
+    }
+
+    /// Splits a place into the variable it starts from and the steps into
+    /// it, adding statements that evaluate keys, in order, to `pre`
+    fn path(&self, expr: &Expr, pre: &mut Vec<String>) -> Res<(String, Vec<Step>)> {
+        match expr {
+            Expr::Ident(name) => match self.var(name) {
+                Var::Local(x) | Var::Global(x) => return Ok((x, Vec::new())),
+                Var::Field => return Ok(("(*this)".to_string(), vec![Step::Field(name.clone())])),
+                Var::Unknown => {}
+            },
+            Expr::Index(x) => {
+                let (root, mut steps) = self.path(&x.target, pre)?;
+                let key = self.tmp();
+                pre.push(format!("let {} = {};", key, self.root(&x.index)?));
+                steps.push(Step::Key(key));
+                return Ok((root, steps));
+            }
+            Expr::Field(x) => {
+                let (root, mut steps) = self.path(&x.target, pre)?;
+                steps.push(Step::Field(x.name.clone()));
+                return Ok((root, steps));
+            }
+            _ => {}
+        }
+
+        let tmp = self.tmp();
+        pre.push(format!("let mut {} = {};", tmp, self.root(expr)?));
+        Ok((tmp, Vec::new()))
+    }
+
+    /// A reference to a place, which is already one for places that
+    /// dereference it
+    fn by_ref(place: &str, mutable: bool) -> String {
+        match place.strip_prefix("(*").and_then(|x| x.strip_suffix(')')) {
+            Some(x) => x.to_string(),
+            None if mutable => format!("&mut {}", place),
+            None => format!("&{}", place),
+        }
+    }
+
+    /// Code reaching the place `root` then `steps` lead to, with `leaf`
+    /// making the final expression from it. Fields are only reachable from
+    /// inside a closure while their object is borrowed
+    fn access(place: &str, steps: &[Step], mutable: bool, leaf: &dyn Fn(&str) -> String) -> String {
+        match steps.split_first() {
+            None => leaf(place),
+            Some((Step::Key(key), rest)) if mutable => {
+                Rust::access(&format!("(*at({}, &{})?)", Rust::by_ref(place, true), key), rest, mutable, leaf)
+            }
+            Some((Step::Key(key), rest)) => {
+                Rust::access(&format!("(*index({}, &{})?)", Rust::by_ref(place, false), key), rest, mutable, leaf)
+            }
+            Some((Step::Field(name), rest)) => format!(
+                "with_field({}, {}, |p| Ok({}))?",
+                Rust::by_ref(place, false),
+                literal(name),
+                Rust::access("(*p)", rest, mutable, leaf)
+            ),
+        }
+    }
+
+    /// Statements storing the value in the variable `val` into `target`
+    fn assign(&mut self, target: &Expr, val: &str) -> Res<()> {
+        let mut pre = Vec::new();
+        let (root, mut steps) = match target {
+            Expr::Ident(_) | Expr::Index(_) | Expr::Field(_) => self.path(target, &mut pre)?,
+            _ => return Err(unsupported("Pointers", Target::Rust)),
+        };
+
+        for line in pre {
+            self.line(&line);
+        }
+
+        let text = match steps.pop() {
+            None => format!("{} = {};", root, val),
+            Some(Step::Key(key)) => {
+                let leaf = |place: &str| format!("set_index({}, {}, {})?", Rust::by_ref(place, true), key, val);
+                format!("{};", Rust::access(&root, &steps, true, &leaf))
+            }
+            Some(Step::Field(name)) => {
+                let leaf = |place: &str| format!("set_field({}, {}, {})?", Rust::by_ref(place, false), literal(&name), val);
+                format!("{};", Rust::access(&root, &steps, true, &leaf))
+            }
+        };
+        self.line(&text);
+        Ok(())
+    }
+
+    fn method_call(&self, call: &MethodCall) -> Res<String> {
+        let mut pre = Vec::new();
+        let (root, steps) = self.path(&call.target, &mut pre)?;
+
+        let args = self.tmp();
+        pre.push(format!("let {} = vec![{}];", args, self.list(&call.args)?));
+
+        let name = literal(&call.name);
+        let peek = Rust::access(&root, &steps, false, &|place| format!("as_object({})", Rust::by_ref(place, false)));
+        let builtin = Rust::access(&root, &steps, true, &|place| format!("method({}, {}, {})?", Rust::by_ref(place, true), name, args));
+
+        Ok(format!(
+            "{{ {} match {} {{ Some(o) => dispatch(&o, {}, {}, g)?, None => {} }} }}",
+            pre.join(" "),
+            peek,
+            name,
+            args,
+            builtin
+        ))
+    }
+
+    fn call(&self, call: &Call) -> Res<String> {
+        let args = self.list(&call.args)?;
+
+        let func = self.prog.stmts.iter().find_map(|x| match &x.kind {
+            StmtKind::Function(f) if f.name == call.name => Some(f),
+            _ => None,
+        });
+
+        if let Some(func) = func {
+            return match func {
+                func if func.params.len() != call.args.len() => Ok(format!(
+                    "fail::<V>({})?",
+                    literal(&format!(
+                        "{} expects {} argument(s) but got {}",
+                        call.name,
+                        func.params.len(),
+                        call.args.len()
+                    ))
+                )),
+                _ if args.is_empty() => Ok(format!("f_{}(g)?", call.name)),
+                _ => Ok(format!("f_{}({}, g)?", call.name, args)),
+            };
+        }
+
+        if let Some(class) = self.class {
+            if self.prog.has_method(class, &call.name) {
+                return Ok(format!("dispatch(this, {}, vec![{}], g)?", literal(&call.name), args));
+            }
+        }
+
+        match self.prog.builtin(&call.name) {
+            Some("NEW") => Err(unsupported("Pointers", Target::Rust)),
+            Some("MATCHES" | "MATCHGROUPS") => Err(unsupported("Regular expressions", Target::Rust)),
+            Some(name) => {
+                let args: Res<Vec<String>> = call.args.iter().map(|x| self.refer(x)).collect();
+                Ok(format!("builtin({}, &[{}])?", literal(name), args?.join(", ")))
+            }
+            None => Ok(format!(
+                "fail::<V>({})?",
+                literal(&format!("Unknown function {} with {} argument(s)", call.name, call.args.len()))
+            )),
+        }
+    }
+
+    fn super_call(&self, call: &SuperCall) -> Res<String> {
+        let args = self.list(&call.args)?;
+        let parent = self
+            .class
+            .and_then(|x| x.parent.as_deref())
+            .and_then(|x| self.prog.classes.get(x));
+
+        let owner = parent.and_then(|parent| {
+            self.prog
+                .ancestors(parent)
+                .into_iter()
+                .find(|x| x.method(&call.name).is_some())
+        });
+
+        match owner {
+            Some(owner) => Ok(format!("m_{}_{}(this, vec![{}], g)?", owner.name, call.name, args)),
+            None => Ok(format!(
+                "fail::<V>({})?",
+                literal(&format!("No parent class has a method {}", call.name))
+            )),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Res<String> {
+        let ret = match expr {
+            Expr::BinOp(x) => {
+                let (l, r) = (self.refer(&x.left)?, self.refer(&x.right)?);
+                match &x.op {
+                    BinaryOp::Plus => format!("add({}, {})?", l, r),
+                    BinaryOp::Minus => format!("sub({}, {})?", l, r),
+                    BinaryOp::Mul => format!("mul({}, {})?", l, r),
+                    BinaryOp::Div => format!("div({}, {}, {})?", l, r, x.span.line),
+                    BinaryOp::FloorDiv => format!("floor_div({}, {}, {})?", l, r, x.span.line),
+                    BinaryOp::Mod => format!("modulo({}, {}, {})?", l, r, x.span.line),
+                    BinaryOp::Equals => format!("eq({}, {})?", l, r),
+                    BinaryOp::NotEquals => format!("ne({}, {})?", l, r),
+                    BinaryOp::GT => format!("compare({}, {}, \">\")?", l, r),
+                    BinaryOp::LT => format!("compare({}, {}, \"<\")?", l, r),
+                    BinaryOp::GE => format!("compare({}, {}, \">=\")?", l, r),
+                    BinaryOp::LE => format!("compare({}, {}, \"<=\")?", l, r),
+                    BinaryOp::In => format!("is_in({}, {})?", l, r),
+                    BinaryOp::BAnd => format!("bits({}, {}, \"&\")?", l, r),
+                    BinaryOp::BOr => format!("bits({}, {}, \"|\")?", l, r),
+                    BinaryOp::BXor => format!("bits({}, {}, \"^\")?", l, r),
+                    BinaryOp::Shl => format!("bits({}, {}, \"<<\")?", l, r),
+                    BinaryOp::Shr => format!("bits({}, {}, \">>\")?", l, r),
+                }
+            }
+            Expr::IfElse(x) => format!(
+                "if cond({}, \"If expression not bool type\")? {{ {} }} else {{ {} }}",
+                self.expr(&x.cond)?,
+                self.expr(&x.then)?,
+                self.expr(&x.otherwise)?
+            ),
+            Expr::Index(x) if self.borrowable(expr) => {
+                format!("index({}, {})?.clone()", self.refer(&x.target)?, self.refer(&x.index)?)
+            }
+            Expr::Index(x) if matches!(&x.target, Expr::Ident(_) | Expr::Index(_)) => {
+                // Keys go first so the variable isn't borrowed while they're
+                // worked out
+                let mut pre = Vec::new();
+                let (root, steps) = self.path(expr, &mut pre)?;
+                let value = Rust::access(&root, &steps, false, &|place| format!("{}.clone()", place));
+                format!("{{ {} {} }}", pre.join(" "), value)
+            }
+            Expr::Index(x) => format!("index(&{}, {})?.clone()", self.expr(&x.target)?, self.refer(&x.index)?),
+            Expr::MethodCall(x) => self.method_call(x)?,
+            Expr::Field(x) => format!("field({}, {})?", self.refer(&x.target)?, literal(&x.name)),
+            Expr::Call(x) => self.call(x)?,
+            Expr::SuperCall(x) => self.super_call(x)?,
+            Expr::New(x) if self.prog.classes.contains_key(x.class.as_str()) => {
+                format!("new_{}(vec![{}], g)?", x.class, self.list(&x.args)?)
+            }
+            Expr::New(x) if x.class == "Collection" && x.args.is_empty() => "V::Collection(Vec::new(), 0)".to_string(),
+            Expr::New(x) => format!("fail::<V>({})?", literal(&format!("Unknown class: {}", x.class))),
+            Expr::Map(entries) => {
+                let entries: Res<Vec<String>> = entries
+                    .iter()
+                    .map(|(k, v)| Ok(format!("({}, {})", self.expr(k)?, self.expr(v)?)))
+                    .collect();
+                format!("map(vec![{}])?", entries?.join(", "))
+            }
+            Expr::Set(items) => format!("set(vec![{}])", self.list(items)?),
+            Expr::Array(items) => format!("V::Array(vec![{}])", self.list(items)?),
+            Expr::AddressOf(_) | Expr::Deref(_) => return Err(unsupported("Pointers", Target::Rust)),
+            Expr::BNot(x) => format!("bnot({})?", self.refer(x)?),
+            Expr::IntLit(x) => format!("V::Int({})", x),
+            Expr::FloatLit(x) if x.is_infinite() && *x > 0.0 => "V::Float(f64::INFINITY)".to_string(),
+            Expr::FloatLit(x) if x.is_infinite() => "V::Float(f64::NEG_INFINITY)".to_string(),
+            Expr::FloatLit(x) => format!("V::Float({:?})", x),
+            Expr::BoolLit(x) => format!("V::Bool({})", x),
+            Expr::StrLit(x) => format!("V::Str({}.to_string())", literal(x)),
+            Expr::NullLit => "V::Null".to_string(),
+            Expr::Ident(name) => match self.var(name) {
+                Var::Local(x) | Var::Global(x) => format!("{}.clone()", x),
+                Var::Field => format!("field(this, {})?", literal(name)),
+                Var::Unknown => self.unknown(name)?,
+            },
+        };
+
+        Ok(ret)
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt], bind: Option<&str>) -> Res<()> {
+        self.block_declaring(stmts, bind, Vec::new())
+    }
+
+    /// A loop body or error handler, which has a scope of its own in psc,
+    /// so the variables first assigned in it are declared at its start
+    fn scoped(&mut self, stmts: &'a [Stmt], bind: Option<&str>) -> Res<()> {
+        let declared: Vec<String> = assigned_in_scope(stmts)
+            .into_iter()
+            .filter(|x| Some(x.as_str()) != bind && !self.visible(x))
+            .collect();
+        self.block_declaring(stmts, bind, declared)
+    }
+
+    fn block_declaring(&mut self, stmts: &'a [Stmt], bind: Option<&str>, declared: Vec<String>) -> Res<()> {
+        let mut scope = HashSet::new();
+        scope.extend(bind.map(|x| x.to_string()));
+        self.indent += 1;
+        for name in declared {
+            self.line(&format!("let mut v_{} = V::Null;", name));
+            scope.insert(name);
+        }
+        self.scopes.push(scope);
+
+        let mut res = Ok(());
+        for stmt in stmts {
+            res = self.stmt(stmt);
+            if res.is_err() {
+                break;
+            }
+        }
+
+        self.indent -= 1;
+        self.scopes.pop();
+        res
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> Res<()> {
+        match &stmt.kind {
+            StmtKind::Assign(x) => {
+                let val = self.root(x.value())?;
+                match &x.target {
+                    Expr::Ident(_) => self.assign(&x.target, &val)?,
+                    _ => {
+                        let tmp = self.tmp();
+                        self.line(&format!("let {} = {};", tmp, val));
+                        self.assign(&x.target, &tmp)?;
+                    }
+                }
+            }
+            StmtKind::MultiAssign(x) => {
+                // Every value is worked out before anything is assigned
+                let mut vals = Vec::new();
+                for expr in &x.exprs {
+                    let tmp = self.tmp();
+                    self.line(&format!("let {} = {};", tmp, self.root(expr)?));
+                    vals.push(tmp);
+                }
+                for (target, val) in x.targets.iter().zip(vals) {
+                    self.assign(target, &val)?;
+                }
+            }
+            StmtKind::Input(x) => {
+                let kind = match &x.kind {
+                    Some(InputType::Integer) => "integer",
+                    Some(InputType::Real) => "real",
+                    Some(InputType::String) => "string",
+                    Some(InputType::Boolean) => "boolean",
+                    None => "",
+                };
+                let val = format!("input({}, {:?})?", literal(&x.ident), kind);
+                self.assign(&Expr::Ident(x.ident.clone()), &val)?;
+            }
+            StmtKind::Output(x) => {
+                let text = format!("println!(\"{{}}\", {});", self.root(&x.expr)?);
+                self.line(&text);
+            }
+            StmtKind::If(x) => {
+                for (i, (cond, stmts)) in x.branches.iter().enumerate() {
+                    let test = || Ok::<String, String>(format!("cond({}, \"If expression not bool type\")?", self.root(cond)?));
+                    let text = match (i, cond) {
+                        (0, _) => format!("if {} {{", test()?),
+                        (_, Expr::BoolLit(true)) if i == x.branches.len() - 1 => "} else {".to_string(),
+                        _ => format!("}} else if {} {{", test()?),
+                    };
+                    self.line(&text);
+                    self.block(stmts, None)?;
+                }
+                self.line("}");
+            }
+            StmtKind::While(x) => {
+                let text = format!("while cond({}, \"While expression not bool type\")? {{", self.root(&x.cond)?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Until(x) => {
+                let text = format!("while !cond({}, \"Until expression not bool type\")? {{", self.root(&x.cond)?);
+                self.line(&text);
+                self.scoped(&x.stmts, None)?;
+                self.line("}");
+            }
+            StmtKind::Repeat(x) => {
+                self.line("loop {");
+                self.scoped(&x.stmts, None)?;
+                let text = format!("    if cond({}, \"Until expression not bool type\")? {{", self.root(&x.cond)?);
+                self.line(&text);
+                self.line("        break;");
+                self.line("    }");
+                self.line("}");
+            }
+            StmtKind::For(x) => {
+                // The loop only runs between integers, like in psc
+                let (start, end, i) = (self.tmp(), self.tmp(), self.tmp());
+                let text = format!(
+                    "if let (V::Int({}), V::Int({})) = ({}, {}) {{",
+                    start,
+                    end,
+                    self.root(&x.start)?,
+                    self.root(&x.end)?
+                );
+                self.line(&text);
+                self.line(&format!("    for {} in {}..={} {{", i, start, end));
+                self.line(&format!("        let mut v_{} = V::Int({});", x.name, i));
+                self.indent += 1;
+                self.scoped(&x.stmts, Some(&x.name))?;
+                self.indent -= 1;
+                self.line("    }");
+                self.line("}");
+            }
+            StmtKind::ForEach(x) => {
+                let item = self.tmp();
+                let text = format!("for {} in iter({})? {{", item, self.root(&x.iter)?);
+                self.line(&text);
+                self.line(&format!("    let mut v_{} = {};", x.name, item));
+                self.scoped(&x.stmts, Some(&x.name))?;
+                self.line("}");
+            }
+            StmtKind::Expr(x) => {
+                let text = format!("{};", self.root(x)?);
+                self.line(&text);
+            }
+            StmtKind::Function(_) => return Err(unsupported("Nested functions", Target::Rust)),
+            StmtKind::Class(_) => return Err(unsupported("Classes inside blocks", Target::Rust)),
+            StmtKind::Return(x) => {
+                let val = match x {
+                    Some(x) => self.root(x)?,
+                    None => "V::Null".to_string(),
+                };
+                let text = match self.attempts {
+                    0 => format!("return Ok({});", val),
+                    _ => format!("return Ok(Some({}));", val),
+                };
+                self.line(&text);
+            }
+            StmtKind::Global(_) | StmtKind::Type(_) => {}
+            StmtKind::Attempt(x) => {
+                // The body runs in a closure so errors from `?` end up here,
+                // a `return` inside comes back as `Some`
+                let res = self.tmp();
+                self.line(&format!("let {} = (|| -> Result<Option<V>, Stop> {{", res));
+                self.attempts += 1;
+                self.block(&x.stmts, None)?;
+                self.attempts -= 1;
+                self.line("    Ok(None)");
+                self.line("})();");
+
+                self.line(&format!("match {} {{", res));
+                self.indent += 1;
+                self.line("Ok(None) => {}");
+                match self.attempts {
+                    0 => self.line("Ok(Some(x)) => return Ok(x),"),
+                    _ => self.line("Ok(Some(x)) => return Ok(Some(x)),"),
+                }
+                self.line("Err(Stop::Error(msg)) => {");
+                if let Some(name) = &x.name {
+                    self.line(&format!("    let mut v_{} = V::Str(msg);", name));
+                }
+                self.scoped(&x.handler, x.name.as_deref())?;
+                self.line("}");
+                self.line("Err(halt) => return Err(halt),");
+                self.indent -= 1;
+                self.line("}");
+            }
+            StmtKind::Assert(x) => {
+                let text = format!(
+                    "if !cond({}, {})? {{",
+                    self.root(&x.cond)?,
+                    literal(&format!("Assert expression not bool type at line {}", stmt.span.line))
+                );
+                self.line(&text);
+                let text = match &x.msg {
+                    Some(msg) => format!(
+                        "    return fail(format!(\"Assertion failed at line {}: {{}}\", {}));",
+                        stmt.span.line,
+                        self.root(msg)?
+                    ),
+                    None => format!("    return fail(\"Assertion failed at line {}\");", stmt.span.line),
+                };
+                self.line(&text);
+                self.line("}");
+            }
+            StmtKind::Halt(x) => {
+                let text = match x {
+                    Some(x) => format!("return Err(exit_code({}));", self.root(x)?),
+                    None => "return Err(Stop::Halt(0));".to_string(),
+                };
+                self.line(&text);
+            }
+            StmtKind::Include(_) => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Declares the variables a function body assigns outside its loops,
+    /// other than its parameters, globals and fields, then the body itself
+    fn body(&mut self, func: &'a Function, params: HashSet<String>) -> Res<()> {
+        let declared = globals(&func.stmts);
+        let mut locals = params;
+
+        self.indent += 1;
+        for name in assigned_in_scope(&func.stmts) {
+            let field = self.class.is_some_and(|class| self.prog.has_field(class, &name));
+            if !locals.contains(&name) && !declared.contains(&name) && !field {
+                self.line(&format!("let mut v_{} = V::Null;", name));
+                locals.insert(name);
+            }
+        }
+        self.indent -= 1;
+
+        // `global` names resolve to `G` even where there's a local, so the
+        // top level's own scope is left out
+        let outer = std::mem::replace(&mut self.scopes, vec![locals]);
+        let opted = self.opted.replace(declared);
+        let res = self.block(&func.stmts, None);
+        self.scopes = outer;
+        self.opted = opted;
+        res?;
+
+        self.line("    Ok(V::Null)");
+        self.line("}");
+        Ok(())
+    }
+
+    fn function(&mut self, func: &'a Function) -> Res<()> {
+        let mut params: Vec<String> = func.params.iter().map(|x| format!("mut v_{}: V", x)).collect();
+        params.push("g: &mut G".to_string());
+
+        self.line(&format!("fn f_{}({}) -> R {{", func.name, params.join(", ")));
+        self.body(func, func.params.iter().cloned().collect())?;
+        self.line("");
+        Ok(())
+    }
+
+    fn class(&mut self, class: &'a Class) -> Res<()> {
+        let ancestors = self.prog.ancestors(class);
+
+        // Fields start from their declared values, worked out like top level
+        // expressions
+        self.line(&format!("fn new_{}(args: Vec<V>, g: &mut G) -> R {{", class.name));
+        let mut fields = Vec::new();
+        for ancestor in ancestors.iter().rev() {
+            for field in &ancestor.fields {
+                let init = match &field.init {
+                    Some(init) => self.root(init)?,
+                    None => "V::Null".to_string(),
+                };
+                fields.push(format!("({}, {})", literal(&field.name), init));
+            }
+        }
+        self.line(&format!(
+            "    let this = alloc({}, vec![{}]);",
+            literal(&class.name),
+            fields.join(", ")
+        ));
+
+        let constructor = ancestors.iter().find_map(|x| {
+            x.methods
+                .iter()
+                .find(|m| m.func.name.eq_ignore_ascii_case("new"))
+                .map(|m| (x, m))
+        });
+        match constructor {
+            Some((owner, method)) => self.line(&format!("    m_{}_{}(&this, args, g)?;", owner.name, method.func.name)),
+            None => {
+                self.line("    if !args.is_empty() {");
+                self.line(&format!(
+                    "        return fail({});",
+                    literal(&format!("Class {} has no constructor", class.name))
+                ));
+                self.line("    }");
+            }
+        }
+        self.line("    Ok(this)");
+        self.line("}");
+        self.line("");
+
+        let outer = self.class.replace(class);
+        let mut res = Ok(());
+
+        for method in &class.methods {
+            let func = &method.func;
+            self.line(&format!(
+                "fn m_{}_{}(this: &V, args: Vec<V>, g: &mut G) -> R {{",
+                class.name, func.name
+            ));
+            self.line(&format!("    arity({}, &args, {})?;", literal(&func.name), func.params.len()));
+            if !func.params.is_empty() {
+                self.line("    let mut args = args.into_iter();");
+            }
+            for param in &func.params {
+                self.line(&format!("    let mut v_{} = args.next().unwrap_or_default();", param));
+            }
+
+            res = self.body(func, func.params.iter().cloned().collect());
+            if res.is_err() {
+                break;
+            }
+            self.line("");
+        }
+
+        self.class = outer;
+        res
+    }
+
+    /// Calls a method by name on an object, looking up its class and then
+    /// the class's ancestors
+    fn dispatch(&mut self, classes: &[&'a Class]) {
+        self.line("fn dispatch(this: &V, name: &str, args: Vec<V>, g: &mut G) -> R {");
+        self.line("    match (class_of(this)?, name) {");
+
+        for class in classes {
+            let mut seen: HashSet<&str> = HashSet::new();
+            for owner in self.prog.ancestors(class) {
+                for method in &owner.methods {
+                    if seen.insert(&method.func.name) {
+                        self.line(&format!(
+                            "        ({}, {}) => m_{}_{}(this, args, g),",
+                            literal(&class.name),
+                            literal(&method.func.name),
+                            owner.name,
+                            method.func.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        self.line("        (class, _) => fail(format!(\"Class {} has no method {}\", class, name)),");
+        self.line("    }");
+        self.line("}");
+        self.line("");
+    }
+}
+
+/// A standalone `main.rs` with no dependencies
+pub fn transpile(prog: &Program) -> Res<String> {
+    let mut globals = assigned_in_scope(prog.stmts);
+    for name in all_globals(prog.stmts) {
+        if !globals.contains(&name) {
+            globals.push(name);
+        }
+    }
+
+    let mut rust = Rust {
+        prog,
+        out: String::new(),
+        indent: 0,
+        globals,
+        scopes: Vec::new(),
+        opted: None,
+        class: None,
+        attempts: 0,
+        tmp: Cell::new(0),
+        calling: Cell::new(false),
+    };
+
+    rust.out.push_str(RUNTIME);
+    rust.line("");
+
+    rust.line("/// Variables of the top level");
+    rust.line("#[derive(Default)]");
+    rust.line("struct G {");
+    for name in rust.globals.clone() {
+        rust.line(&format!("    v_{}: V,", name));
+    }
+    rust.line("}");
+    rust.line("");
+
+    let mut classes = Vec::new();
+    for stmt in prog.stmts {
+        match &stmt.kind {
+            StmtKind::Function(x) => rust.function(x)?,
+            StmtKind::Class(x) => {
+                rust.class(x)?;
+                classes.push(&**x);
+            }
+            _ => {}
+        }
+    }
+    rust.dispatch(&classes);
+
+    rust.line("fn run(g: &mut G) -> R {");
+    rust.scopes.push(HashSet::new());
+    rust.indent += 1;
+    for stmt in prog.stmts {
+        if !matches!(&stmt.kind, StmtKind::Function(_) | StmtKind::Class(_)) {
+            rust.stmt(stmt)?;
+        }
+    }
+    rust.line("Ok(V::Null)");
+    rust.indent -= 1;
+    rust.line("}");
+
+    Ok(rust.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{transpile as to, Target};
+    use std::fs;
+    use std::process::{self, Command};
+
+    fn rust(source: &str) -> String {
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        to(&stmts, Target::Rust, Default::default()).unwrap()
+    }
+
+    /// Compiles and runs the program too where rustc is installed
+    #[test]
+    fn loop_variables_stay_in_the_loop() {
+        let source = "X = 1\nloop while X < 3\n\tY = if X > 1 then X else 0\n\tX = X + 1\nend loop\noutput Y";
+        let code = rust(source);
+        assert!(code.contains("type\")? {\n        let mut v_Y = V::Null;\n        v_Y = if cond("), "{}", code);
+
+        let stmts = psc::parse(source, &Default::default()).unwrap();
+        let msg = psc::Interpreter::with_io(std::io::empty(), std::io::sink()).run(&stmts).unwrap_err().msg;
+        assert_eq!(msg, "Unknow identifier: Y");
+
+        let dir = std::env::temp_dir().join(format!("psc-rust-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.rs"), &code).unwrap();
+        let built = Command::new("rustc")
+            .args(["--edition", "2021", "-D", "warnings", "-o"])
+            .arg(dir.join("main"))
+            .arg(dir.join("main.rs"))
+            .output();
+        let Ok(built) = built else {
+            return;
+        };
+        assert!(built.status.success(), "{}", String::from_utf8_lossy(&built.stderr));
+
+        let out = Command::new(dir.join("main")).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stderr), format!("Error: {}\n", msg));
+    }
+}
@@ -0,0 +1,690 @@
+// Runtime for programs from `psc transpile --target rust`, followed by the
+// program itself. Values carry their type at run time like in psc: cloning
+// copies arrays, maps and sets, while objects stay shared.
+
+#![allow(dead_code, non_snake_case, unreachable_code, unused_assignments, unused_mut, unused_variables)]
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+// In the 2021 prelude, but plain `rustc` compiles as 2015
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Default)]
+pub enum V {
+    #[default]
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Array(Vec<V>),
+    Map(Vec<(V, V)>),
+    Set(Vec<V>),
+    Collection(Vec<V>, usize),
+    Object(Rc<RefCell<Obj>>),
+}
+
+pub struct Obj {
+    pub class: &'static str,
+    pub fields: Vec<(&'static str, V)>,
+}
+
+/// Why the program stopped early
+pub enum Stop {
+    Error(String),
+    Halt(i32),
+}
+
+pub type R = Result<V, Stop>;
+
+pub fn fail<T>(msg: impl Into<String>) -> Result<T, Stop> {
+    Err(Stop::Error(msg.into()))
+}
+
+impl PartialEq for V {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (V::Int(l), V::Int(r)) => l == r,
+            (V::Float(l), V::Float(r)) => l == r,
+            (V::Bool(l), V::Bool(r)) => l == r,
+            (V::Str(l), V::Str(r)) => l == r,
+            (V::Array(l), V::Array(r)) => l == r,
+            (V::Map(l), V::Map(r)) => l == r,
+            (V::Set(l), V::Set(r)) => l.len() == r.len() && l.iter().all(|x| r.contains(x)),
+            (V::Collection(l, _), V::Collection(r, _)) => l == r,
+            (V::Object(l), V::Object(r)) => Rc::ptr_eq(l, r),
+            (V::Null, V::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl V {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            V::Null => "null",
+            V::Int(_) => "integer",
+            V::Float(_) => "real",
+            V::Bool(_) => "boolean",
+            V::Str(_) => "string",
+            V::Array(_) => "array",
+            V::Map(_) => "map",
+            V::Set(_) => "set",
+            V::Collection(..) => "collection",
+            V::Object(_) => "object",
+        }
+    }
+
+    fn repr(&self) -> String {
+        match self {
+            V::Str(x) => format!("\"{}\"", x),
+            _ => self.to_string(),
+        }
+    }
+}
+
+fn join(f: &mut fmt::Formatter, items: &[V]) -> fmt::Result {
+    for (i, x) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", x.repr())?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for V {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            V::Null => write!(f, "null"),
+            V::Int(x) => write!(f, "{}", x),
+            V::Float(x) => write!(f, "{}", x),
+            V::Bool(x) => write!(f, "{}", x),
+            V::Str(x) => write!(f, "{}", x),
+            V::Array(items) => {
+                write!(f, "[")?;
+                join(f, items)?;
+                write!(f, "]")
+            }
+            V::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", k.repr(), v.repr())?;
+                }
+                write!(f, "}}")
+            }
+            V::Set(items) => {
+                write!(f, "{{")?;
+                join(f, items)?;
+                write!(f, "}}")
+            }
+            V::Collection(items, _) => {
+                write!(f, "Collection[")?;
+                join(f, items)?;
+                write!(f, "]")
+            }
+            V::Object(obj) => write!(f, "<{} object>", obj.borrow().class),
+        }
+    }
+}
+
+/// A condition, `what` is the error for anything but a boolean
+pub fn cond(x: V, what: &str) -> Result<bool, Stop> {
+    match x {
+        V::Bool(b) => Ok(b),
+        _ => fail(what),
+    }
+}
+
+fn floats(l: &V, r: &V) -> Option<(f64, f64)> {
+    match (l, r) {
+        (V::Float(l), V::Float(r)) => Some((*l, *r)),
+        (V::Int(l), V::Float(r)) => Some((*l as f64, *r)),
+        (V::Float(l), V::Int(r)) => Some((*l, *r as f64)),
+        _ => None,
+    }
+}
+
+fn arith(l: &V, r: &V, op: &str) -> R {
+    if let (V::Int(a), V::Int(b)) = (l, r) {
+        let res = match op {
+            "+" => a.checked_add(*b),
+            "-" => a.checked_sub(*b),
+            _ => a.checked_mul(*b),
+        };
+        return match res {
+            Some(x) => Ok(V::Int(x)),
+            None => fail(format!("Integer overflow in {} {} {}", a, op, b)),
+        };
+    }
+
+    match floats(l, r) {
+        Some((a, b)) if op == "+" => Ok(V::Float(a + b)),
+        Some((a, b)) if op == "-" => Ok(V::Float(a - b)),
+        Some((a, b)) => Ok(V::Float(a * b)),
+        None => fail("Mismatched types"),
+    }
+}
+
+pub fn add(l: &V, r: &V) -> R {
+    match (l, r) {
+        (V::Str(l), V::Str(r)) => Ok(V::Str(format!("{}{}", l, r))),
+        _ => arith(l, r, "+"),
+    }
+}
+
+pub fn sub(l: &V, r: &V) -> R {
+    arith(l, r, "-")
+}
+
+pub fn mul(l: &V, r: &V) -> R {
+    arith(l, r, "*")
+}
+
+fn divisor(l: &V, r: &V, line: usize) -> Result<(f64, f64), Stop> {
+    if matches!(r, V::Int(0)) || matches!(r, V::Float(x) if *x == 0.0) {
+        return fail(format!("Division by zero at line {}", line));
+    }
+
+    match (l, r) {
+        (V::Int(l), V::Int(r)) => Ok((*l as f64, *r as f64)),
+        _ => floats(l, r).map_or_else(|| fail("Mismatched types"), Ok),
+    }
+}
+
+pub fn div(l: &V, r: &V, line: usize) -> R {
+    let (a, b) = divisor(l, r, line)?;
+    Ok(V::Float(a / b))
+}
+
+pub fn floor_div(l: &V, r: &V, line: usize) -> R {
+    let (a, b) = divisor(l, r, line)?;
+    Ok(V::Float((a / b).floor()))
+}
+
+pub fn modulo(l: &V, r: &V, line: usize) -> R {
+    let (a, b) = divisor(l, r, line)?;
+    match (l, r) {
+        (V::Int(l), V::Int(r)) => Ok(V::Int(l.wrapping_rem(*r))),
+        _ => Ok(V::Float((a % b).floor())),
+    }
+}
+
+fn order(l: &V, r: &V) -> Result<Option<Ordering>, Stop> {
+    match (l, r) {
+        (V::Int(l), V::Int(r)) => Ok(Some(l.cmp(r))),
+        (V::Str(l), V::Str(r)) => Ok(Some(l.cmp(r))),
+        _ => match floats(l, r) {
+            Some((a, b)) => Ok(a.partial_cmp(&b)),
+            None => fail(format!("Cannot compare {} with {}", l.type_name(), r.type_name())),
+        },
+    }
+}
+
+pub fn compare(l: &V, r: &V, op: &str) -> R {
+    let ord = order(l, r)?;
+    Ok(V::Bool(match op {
+        "<" => ord == Some(Ordering::Less),
+        ">" => ord == Some(Ordering::Greater),
+        "<=" => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+        _ => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+    }))
+}
+
+pub fn equals(l: &V, r: &V) -> Result<bool, Stop> {
+    match (l, r) {
+        (V::Int(a), V::Float(b)) => Ok(*a as f64 == *b),
+        (V::Float(a), V::Int(b)) => Ok(*a == *b as f64),
+        _ if l.type_name() == r.type_name() || matches!(l, V::Null) || matches!(r, V::Null) => Ok(l == r),
+        _ => fail("Mismatched types"),
+    }
+}
+
+pub fn eq(l: &V, r: &V) -> R {
+    Ok(V::Bool(equals(l, r)?))
+}
+
+pub fn ne(l: &V, r: &V) -> R {
+    Ok(V::Bool(!equals(l, r)?))
+}
+
+pub fn is_in(l: &V, r: &V) -> R {
+    match (l, r) {
+        (V::Str(l), V::Str(r)) => Ok(V::Bool(r.contains(l.as_str()))),
+        (l, V::Array(items) | V::Set(items)) => Ok(V::Bool(items.contains(l))),
+        (l, V::Map(entries)) => Ok(V::Bool(entries.iter().any(|(k, _)| k == l))),
+        (l, r) => fail(format!("Cannot look for {} in {}", l.type_name(), r.type_name())),
+    }
+}
+
+pub fn bits(l: &V, r: &V, op: &str) -> R {
+    let (l, r) = match (l, r) {
+        (V::Int(l), V::Int(r)) => (*l, *r),
+        _ => {
+            return fail(format!(
+                "Bitwise operators expect integers, not {} and {}",
+                l.type_name(),
+                r.type_name()
+            ))
+        }
+    };
+
+    let shifted = match (op, u32::try_from(r)) {
+        ("&", _) => return Ok(V::Int(l & r)),
+        ("|", _) => return Ok(V::Int(l | r)),
+        ("^", _) => return Ok(V::Int(l ^ r)),
+        ("<<", Ok(r)) => l.checked_shl(r),
+        (_, Ok(r)) => l.checked_shr(r),
+        _ => None,
+    };
+
+    match shifted {
+        Some(x) => Ok(V::Int(x)),
+        None => fail(format!("Cannot shift by {} bits", r)),
+    }
+}
+
+pub fn bnot(x: &V) -> R {
+    match x {
+        V::Int(x) => Ok(V::Int(!x)),
+        x => fail(format!("bnot expects an integer, not {}", x.type_name())),
+    }
+}
+
+fn pos(items: &[V], key: &V) -> Result<usize, Stop> {
+    match key {
+        V::Int(i) if *i >= 0 && (*i as usize) < items.len() => Ok(*i as usize),
+        V::Int(i) => fail(format!("Index {} out of bounds for array of length {}", i, items.len())),
+        _ => fail(format!("Array index must be an integer, not {}", key.type_name())),
+    }
+}
+
+pub fn index<'a>(c: &'a V, key: &V) -> Result<&'a V, Stop> {
+    match c {
+        V::Array(items) => Ok(&items[pos(items, key)?]),
+        V::Map(entries) => match entries.iter().find(|(k, _)| k == key) {
+            Some((_, v)) => Ok(v),
+            None => fail(format!("Key not found: {}", key.repr())),
+        },
+        _ => fail(format!("Cannot index into {}", c.type_name())),
+    }
+}
+
+pub fn at<'a>(c: &'a mut V, key: &V) -> Result<&'a mut V, Stop> {
+    match c {
+        V::Array(items) => {
+            let i = pos(items, key)?;
+            Ok(&mut items[i])
+        }
+        V::Map(entries) => match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => Ok(v),
+            None => fail(format!("Key not found: {}", key.repr())),
+        },
+        _ => fail(format!("Cannot index into {}", c.type_name())),
+    }
+}
+
+pub fn set_index(c: &mut V, key: V, val: V) -> Result<(), Stop> {
+    match c {
+        V::Map(entries) => {
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = val,
+                None => entries.push((key, val)),
+            }
+            Ok(())
+        }
+        _ => {
+            *at(c, &key)? = val;
+            Ok(())
+        }
+    }
+}
+
+pub fn map(entries: Vec<(V, V)>) -> R {
+    let mut ret = V::Map(Vec::new());
+    for (k, v) in entries {
+        set_index(&mut ret, k, v)?;
+    }
+    Ok(ret)
+}
+
+pub fn set(items: Vec<V>) -> V {
+    let mut ret: Vec<V> = Vec::new();
+    for x in items {
+        if !ret.contains(&x) {
+            ret.push(x);
+        }
+    }
+    V::Set(ret)
+}
+
+pub fn as_object(x: &V) -> Option<V> {
+    match x {
+        V::Object(_) => Some(x.clone()),
+        _ => None,
+    }
+}
+
+fn object(x: &V) -> Result<&Rc<RefCell<Obj>>, Stop> {
+    match x {
+        V::Object(obj) => Ok(obj),
+        x => fail(format!("Expected object but got {}", x.type_name())),
+    }
+}
+
+pub fn class_of(x: &V) -> Result<&'static str, Stop> {
+    Ok(object(x)?.borrow().class)
+}
+
+/// Runs `f` on a field of an object, which stays borrowed meanwhile
+pub fn with_field<T>(x: &V, name: &str, f: impl FnOnce(&mut V) -> Result<T, Stop>) -> Result<T, Stop> {
+    let obj = object(x)?;
+    let mut obj = obj.borrow_mut();
+    let class = obj.class;
+
+    match obj.fields.iter_mut().find(|(k, _)| *k == name) {
+        Some((_, v)) => f(v),
+        None => fail(format!("Class {} has no field {}", class, name)),
+    }
+}
+
+pub fn field(x: &V, name: &str) -> R {
+    with_field(x, name, |v| Ok(v.clone()))
+}
+
+pub fn set_field(x: &V, name: &str, val: V) -> Result<(), Stop> {
+    with_field(x, name, |v| {
+        *v = val;
+        Ok(())
+    })
+}
+
+pub fn alloc(class: &'static str, fields: Vec<(&'static str, V)>) -> V {
+    let mut obj = Obj { class, fields: Vec::new() };
+
+    // Later declarations, from subclasses, replace inherited ones
+    for (k, v) in fields {
+        match obj.fields.iter_mut().find(|(x, _)| *x == k) {
+            Some((_, old)) => *old = v,
+            None => obj.fields.push((k, v)),
+        }
+    }
+
+    V::Object(Rc::new(RefCell::new(obj)))
+}
+
+pub fn arity(name: &str, args: &[V], want: usize) -> Result<(), Stop> {
+    if args.len() != want {
+        return fail(format!("{} expects {} argument(s) but got {}", name, want, args.len()));
+    }
+    Ok(())
+}
+
+/// Methods of arrays, maps, sets and collections
+pub fn method(t: &mut V, name: &str, args: Vec<V>) -> R {
+    match (t, name.to_lowercase().as_str(), args.as_slice()) {
+        (V::Array(items), "length", []) => Ok(V::Int(items.len() as i64)),
+        (V::Array(items), "append", [x]) => {
+            items.push(x.clone());
+            Ok(V::Null)
+        }
+        (V::Map(entries), "containskey", [key]) => Ok(V::Bool(entries.iter().any(|(k, _)| k == key))),
+        (V::Set(items), "add", [x]) => {
+            if items.contains(x) {
+                return Ok(V::Bool(false));
+            }
+            items.push(x.clone());
+            Ok(V::Bool(true))
+        }
+        (V::Set(items), "contains", [x]) => Ok(V::Bool(items.contains(x))),
+        (V::Set(items), "union", [V::Set(other)]) => Ok(set(items.iter().chain(other).cloned().collect())),
+        (V::Set(items), "intersection", [V::Set(other)]) => {
+            Ok(V::Set(items.iter().filter(|x| other.contains(x)).cloned().collect()))
+        }
+        (V::Set(items), "difference", [V::Set(other)]) => {
+            Ok(V::Set(items.iter().filter(|x| !other.contains(x)).cloned().collect()))
+        }
+        (V::Collection(items, _), "additem", [x]) => {
+            items.push(x.clone());
+            Ok(V::Null)
+        }
+        (V::Collection(items, next), "hasnext", []) => Ok(V::Bool(*next < items.len())),
+        (V::Collection(items, next), "getnext", []) => match items.get(*next) {
+            Some(x) => {
+                *next += 1;
+                Ok(x.clone())
+            }
+            None => fail("getNext called with no items left in the collection"),
+        },
+        (V::Collection(_, next), "resetnext", []) => {
+            *next = 0;
+            Ok(V::Null)
+        }
+        (V::Collection(items, _), "isempty", []) => Ok(V::Bool(items.is_empty())),
+        (t, _, _) => fail(format!(
+            "Unknown method {} with {} argument(s) on {}",
+            name,
+            args.len(),
+            t.type_name()
+        )),
+    }
+}
+
+pub fn iter(x: V) -> Result<Vec<V>, Stop> {
+    match x {
+        V::Array(items) | V::Set(items) => Ok(items),
+        V::Str(x) => Ok(x.chars().map(|c| V::Str(c.to_string())).collect()),
+        V::Map(entries) => Ok(entries.into_iter().map(|(k, _)| k).collect()),
+        x => fail(format!("Cannot loop over {}", x.type_name())),
+    }
+}
+
+pub fn exit_code(x: V) -> Stop {
+    match x {
+        V::Int(x) => match i32::try_from(x) {
+            Ok(x) => Stop::Halt(x),
+            Err(_) => Stop::Error(format!("Exit code {} out of range", x)),
+        },
+        x => Stop::Error(format!("Exit code must be an integer, not {}", x.type_name())),
+    }
+}
+
+fn parse_input(kind: &str, line: &str) -> Option<V> {
+    match kind {
+        "integer" => line.trim().parse::<i64>().ok().map(V::Int),
+        "real" => line.trim().parse::<f64>().ok().map(V::Float),
+        "boolean" => line.trim().parse::<bool>().ok().map(V::Bool),
+        _ => Some(V::Str(line.trim_end_matches(['\r', '\n']).to_string())),
+    }
+}
+
+/// Reads the value for `input NAME`, `kind` is empty when it wasn't given
+pub fn input(name: &str, kind: &str) -> R {
+    loop {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => return fail(format!("Unexpected end of input while reading {}", name)),
+            Ok(_) => {}
+            Err(e) => return fail(e.to_string()),
+        }
+
+        if kind.is_empty() {
+            let text = line.trim();
+            let guess = ["boolean", "integer", "real"].iter().find_map(|x| parse_input(x, text));
+            return Ok(guess.unwrap_or_else(|| V::Str(text.to_string())));
+        }
+
+        if let Some(x) = parse_input(kind, &line) {
+            return Ok(x);
+        }
+
+        let article = if kind == "integer" { "an" } else { "a" };
+        eprintln!("Expected {} {} for {}, got \"{}\", try again", article, kind, name, line.trim());
+    }
+}
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_nanos() as u64));
+}
+
+/// The next number from a splitmix64 generator, the same one psc uses
+fn random() -> u64 {
+    RNG.with(|rng| {
+        rng.set(rng.get().wrapping_add(0x9e37_79b9_7f4a_7c15));
+
+        let mut x = rng.get();
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    })
+}
+
+/// Milliseconds since 1970 began
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis() as i64)
+}
+
+/// The day `ms` milliseconds after 1970 began is in, as `YYYY-MM-DD`, the
+/// same way psc works it out
+fn date(ms: i64) -> String {
+    let z = ms.div_euclid(86_400_000) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn sum(items: &[V]) -> R {
+    let mut ret = V::Int(0);
+    for x in items {
+        ret = match (&ret, x) {
+            (V::Int(l), V::Int(r)) => match l.checked_add(*r) {
+                Some(x) => V::Int(x),
+                None => return fail("Integer overflow in SUM"),
+            },
+            (l, r) => match floats(l, r) {
+                Some((a, b)) => V::Float(a + b),
+                None => return fail(format!("Cannot add up {}", x.type_name())),
+            },
+        };
+    }
+    Ok(ret)
+}
+
+fn extreme(name: &str, items: &[V]) -> R {
+    let want = if name == "MIN" { Ordering::Less } else { Ordering::Greater };
+
+    let mut it = items.iter();
+    let mut best = match it.next() {
+        Some(x) => x,
+        None => return fail(format!("{} of an empty array", name)),
+    };
+
+    for x in it {
+        match order(x, best)? {
+            Some(ord) if ord == want => best = x,
+            Some(_) => {}
+            None => return fail(format!("Cannot compare {} with {}", x.type_name(), best.type_name())),
+        }
+    }
+
+    Ok(best.clone())
+}
+
+pub fn builtin(name: &str, args: &[&V]) -> R {
+    match (name, args) {
+        ("SET", _) => Ok(set(args.iter().map(|x| (*x).clone()).collect())),
+
+        ("LENGTH", [V::Str(s)]) => Ok(V::Int(s.chars().count() as i64)),
+        ("LENGTH", [V::Array(items) | V::Set(items)]) => Ok(V::Int(items.len() as i64)),
+        ("LENGTH", [V::Map(entries)]) => Ok(V::Int(entries.len() as i64)),
+
+        ("INDEXOF", [V::Array(items), x]) => Ok(V::Int(items.iter().position(|y| y == *x).map_or(-1, |i| i as i64))),
+        ("INDEXOF", [V::Str(s), V::Str(x)]) => {
+            let pos = s.find(x.as_str()).map(|i| s[..i].chars().count());
+            Ok(V::Int(pos.map_or(-1, |i| i as i64)))
+        }
+
+        ("COUNT", [V::Array(items), x]) => Ok(V::Int(items.iter().filter(|y| *y == *x).count() as i64)),
+        ("COUNT", [V::Str(s), V::Str(x)]) if !x.is_empty() => Ok(V::Int(s.matches(x.as_str()).count() as i64)),
+
+        ("MIN" | "MAX", [V::Array(items)]) => extreme(name, items),
+        ("MIN" | "MAX", [_, ..]) => extreme(name, &args.iter().map(|x| (*x).clone()).collect::<Vec<V>>()),
+
+        ("SUM", [V::Array(items)]) => sum(items),
+        ("AVERAGE", [V::Array(items)]) if !items.is_empty() => match sum(items)? {
+            V::Int(x) => Ok(V::Float(x as f64 / items.len() as f64)),
+            V::Float(x) => Ok(V::Float(x / items.len() as f64)),
+            _ => unreachable!(),
+        },
+        ("AVERAGE", [V::Array(_)]) => fail("AVERAGE of an empty array"),
+
+        ("TRIM", [V::Str(s)]) => Ok(V::Str(s.trim().to_string())),
+        ("REPLACE", [V::Str(s), V::Str(from), V::Str(to)]) if !from.is_empty() => {
+            Ok(V::Str(s.replace(from.as_str(), to)))
+        }
+        ("SPLIT", [V::Str(s), V::Str(sep)]) => Ok(V::Array(if sep.is_empty() {
+            s.chars().map(|c| V::Str(c.to_string())).collect()
+        } else {
+            s.split(sep.as_str()).map(|x| V::Str(x.to_string())).collect()
+        })),
+        ("JOIN", [V::Array(items), V::Str(sep)]) => {
+            Ok(V::Str(items.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(sep)))
+        }
+
+        ("CONTAINS", [V::Str(s), V::Str(x)]) => Ok(V::Bool(s.contains(x.as_str()))),
+        ("STARTSWITH", [V::Str(s), V::Str(x)]) => Ok(V::Bool(s.starts_with(x.as_str()))),
+        ("ENDSWITH", [V::Str(s), V::Str(x)]) => Ok(V::Bool(s.ends_with(x.as_str()))),
+
+        ("RANDOM", []) => Ok(V::Float((random() >> 11) as f64 / (1u64 << 53) as f64)),
+        ("RANDOM", [V::Int(lo), V::Int(hi)]) if lo <= hi => {
+            let span = hi.abs_diff(*lo) as u128 + 1;
+            Ok(V::Int((*lo as i128 + (random() as u128 % span) as i128) as i64))
+        }
+        ("SETSEED", [V::Int(seed)]) => {
+            RNG.with(|rng| rng.set(*seed as u64));
+            Ok(V::Null)
+        }
+
+        ("SLEEP" | "WAIT", [V::Int(ms)]) if *ms >= 0 => {
+            thread::sleep(Duration::from_millis(*ms as u64));
+            Ok(V::Null)
+        }
+
+        ("ARGS", []) => Ok(V::Array(std::env::args().skip(1).map(V::Str).collect())),
+        ("GETENV", [V::Str(name)]) => Ok(std::env::var(name).map_or(V::Null, V::Str)),
+        ("NOW", []) => Ok(V::Int(now())),
+        ("TODAY", []) => Ok(V::Str(date(now()))),
+
+        _ => fail(format!("Unknown function {} with {} argument(s)", name, args.len())),
+    }
+}
+
+fn main() {
+    let mut g = G::default();
+
+    let code = match run(&mut g) {
+        Ok(_) => 0,
+        Err(Stop::Halt(code)) => code,
+        Err(Stop::Error(msg)) => {
+            eprintln!("Error: {}", msg);
+            1
+        }
+    };
+
+    let _ = io::stdout().flush();
+    std::process::exit(code);
+}
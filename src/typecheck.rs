@@ -0,0 +1,515 @@
+use crate::dialect::Dialect;
+use crate::err::ParseError;
+use crate::eval::*;
+use crate::lex::Span;
+use crate::pretty;
+use std::collections::{HashMap, HashSet};
+
+/// What a value is known to be before the program runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Real,
+    /// Either an integer or a real, from a variable assigned both
+    Number,
+    Bool,
+    Str,
+    Null,
+    Array,
+    Map,
+    Set,
+    Object,
+    Pointer,
+    /// Could be anything, so nothing is reported about it
+    Unknown,
+    /// Nothing is known yet, a variable that's assigned further on
+    Unset,
+}
+
+impl Type {
+    /// As `PscObject::type_name` has it
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Int => "integer",
+            Type::Real => "real",
+            Type::Number => "number",
+            Type::Bool => "boolean",
+            Type::Str => "string",
+            Type::Null => "null",
+            Type::Array => "array",
+            Type::Map => "map",
+            Type::Set => "set",
+            Type::Object => "object",
+            Type::Pointer => "pointer",
+            Type::Unknown | Type::Unset => "unknown",
+        }
+    }
+
+    fn numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Real | Type::Number)
+    }
+
+    /// What a value that's sometimes `self` and sometimes `other` is
+    fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Type::Unset, x) | (x, Type::Unset) => x,
+            (a, b) if a.numeric() && b.numeric() => Type::Number,
+            _ => Type::Unknown,
+        }
+    }
+}
+
+/// The variables of the top level or of one procedure, each with every
+/// type it's ever assigned joined together
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Type>,
+    /// Names opted into with `global`
+    globals: HashSet<String>,
+}
+
+/// The top level's scope, the others are keyed by the procedure they're for
+const TOP: usize = 0;
+
+struct Checker {
+    dialect: Dialect,
+    scopes: HashMap<usize, Scope>,
+    /// The scopes around the code being checked, innermost last
+    stack: Vec<usize>,
+    /// What each procedure returns, by name
+    returns: HashMap<String, Type>,
+    /// The first time through, when every variable and procedure is found
+    /// before anything about them is
+    declare: bool,
+    /// Something was learnt this time through, so it needs going over again
+    changed: bool,
+    /// Only on the last time through, once every type is known
+    report: bool,
+    errors: Vec<ParseError>,
+    /// The statement being checked, for errors without a span of their own
+    span: Span,
+}
+
+impl Checker {
+    /// Everything the checker finds is some kind of mismatched types
+    fn error(&mut self, msg: String, span: Span) {
+        if self.report {
+            self.errors.push(ParseError { msg, span: Some(span), code: Some("E014") });
+        }
+    }
+
+    fn scope(&mut self, key: usize) -> &mut Scope {
+        self.scopes.entry(key).or_default()
+    }
+
+    /// Anything that's never assigned, like the fields of a class, could be
+    /// anything
+    fn lookup(&self, name: &str) -> Type {
+        let found = self.stack.iter().rev().find_map(|key| self.scopes.get(key)?.vars.get(name));
+        found.copied().unwrap_or(Type::Unknown)
+    }
+
+    fn assign(&mut self, name: &str, kind: Type) {
+        let top = *self.stack.last().unwrap();
+        let key = if self.scope(top).globals.contains(name) { TOP } else { top };
+
+        let declare = self.declare;
+        let vars = &mut self.scope(key).vars;
+        if declare {
+            vars.entry(name.to_string()).or_insert(Type::Unset);
+            return;
+        }
+        let joined = vars.get(name).map_or(kind, |x| x.join(kind));
+        if vars.insert(name.to_string(), joined) != Some(joined) {
+            self.changed = true;
+        }
+    }
+
+    fn returns(&mut self, name: &str, kind: Type) {
+        if self.declare {
+            self.returns.entry(name.to_string()).or_insert(Type::Unset);
+            return;
+        }
+        let joined = self.returns.get(name).map_or(kind, |x| x.join(kind));
+        if self.returns.insert(name.to_string(), joined) != Some(joined) {
+            self.changed = true;
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt], func: Option<&str>) {
+        for stmt in stmts {
+            self.span = stmt.span;
+            self.stmt(stmt, func);
+        }
+    }
+
+    /// A condition that has to be true or false, `what` being the statement
+    /// it's for
+    fn cond(&mut self, expr: &Expr, what: &str) {
+        let span = self.span;
+        match self.expr(expr) {
+            Type::Bool | Type::Unknown | Type::Unset => {}
+            _ => self.error(format!("{} expression not bool type", what), span),
+        }
+    }
+
+    fn function(&mut self, func: &Function, name: Option<&str>) {
+        let key = func as *const Function as usize;
+        self.stack.push(key);
+        for param in &func.params {
+            self.assign(param, Type::Unknown);
+        }
+        self.stmts(&func.stmts, name);
+        self.stack.pop();
+
+        // Falling off the end returns null
+        if let Some(name) = name {
+            if !matches!(func.stmts.last().map(|x| &x.kind), Some(StmtKind::Return(_))) {
+                self.returns(name, Type::Null);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, func: Option<&str>) {
+        match &stmt.kind {
+            StmtKind::Assign(x) => {
+                let mut kind = self.expr(&x.expr);
+                if x.declared.as_ref().is_some_and(|x| !x.bounds.is_empty()) {
+                    kind = Type::Array;
+                }
+                match &x.target {
+                    Expr::Ident(name) => self.assign(name, kind),
+                    target => {
+                        self.expr(target);
+                    }
+                }
+            }
+            StmtKind::MultiAssign(x) => {
+                let kinds: Vec<Type> = x.exprs.iter().map(|e| self.expr(e)).collect();
+                for (i, target) in x.targets.iter().enumerate() {
+                    match (target, kinds.len() == x.targets.len()) {
+                        (Expr::Ident(name), true) => self.assign(name, kinds[i]),
+                        (Expr::Ident(name), false) => self.assign(name, Type::Unknown),
+                        (target, _) => {
+                            self.expr(target);
+                        }
+                    }
+                }
+            }
+            StmtKind::Input(x) => {
+                let kind = match x.kind {
+                    Some(InputType::Integer) => Type::Int,
+                    Some(InputType::Real) => Type::Real,
+                    Some(InputType::String) => Type::Str,
+                    Some(InputType::Boolean) => Type::Bool,
+                    None => Type::Unknown,
+                };
+                self.assign(&x.ident, kind);
+            }
+            StmtKind::Output(x) => {
+                self.expr(&x.expr);
+            }
+            StmtKind::If(x) => {
+                for (cond, stmts) in &x.branches {
+                    self.cond(cond, "If");
+                    self.stmts(stmts, func);
+                    self.span = stmt.span;
+                }
+            }
+            StmtKind::While(x) => {
+                self.cond(&x.cond, "While");
+                self.stmts(&x.stmts, func);
+            }
+            StmtKind::Until(x) => {
+                self.cond(&x.cond, "Until");
+                self.stmts(&x.stmts, func);
+            }
+            StmtKind::Repeat(x) => {
+                self.stmts(&x.stmts, func);
+                self.span = stmt.span;
+                self.cond(&x.cond, "Until");
+            }
+            StmtKind::For(x) => {
+                self.expr(&x.start);
+                self.expr(&x.end);
+                self.assign(&x.name, Type::Int);
+                self.stmts(&x.stmts, func);
+            }
+            StmtKind::ForEach(x) => {
+                let kind = match self.expr(&x.iter) {
+                    Type::Str => Type::Str,
+                    kind @ (Type::Int | Type::Real | Type::Number | Type::Bool | Type::Null) => {
+                        self.error(format!("Cannot loop over {}", kind.name()), stmt.span);
+                        Type::Unknown
+                    }
+                    _ => Type::Unknown,
+                };
+                self.assign(&x.name, kind);
+                self.stmts(&x.stmts, func);
+            }
+            StmtKind::Expr(x) => {
+                self.expr(x);
+            }
+            StmtKind::Function(x) => self.function(x, Some(&x.name)),
+            StmtKind::Class(x) => {
+                for method in &x.methods {
+                    self.function(&method.func, None);
+                }
+            }
+            StmtKind::Return(x) => {
+                let kind = x.as_ref().map_or(Type::Null, |x| self.expr(x));
+                if let Some(name) = func {
+                    self.returns(name, kind);
+                }
+            }
+            StmtKind::Type(_) => {}
+            StmtKind::Global(names) => {
+                let top = *self.stack.last().unwrap();
+                for name in names {
+                    self.scope(top).globals.insert(name.clone());
+                }
+            }
+            StmtKind::Attempt(x) => {
+                self.stmts(&x.stmts, func);
+                if let Some(name) = &x.name {
+                    self.assign(name, Type::Str);
+                }
+                self.stmts(&x.handler, func);
+            }
+            StmtKind::Assert(x) => {
+                self.cond(&x.cond, "Assert");
+                if let Some(msg) = &x.msg {
+                    self.expr(msg);
+                }
+            }
+            StmtKind::Halt(x) => {
+                if let Some(x) = x {
+                    self.expr(x);
+                }
+            }
+            StmtKind::Include(_) => {}
+        }
+    }
+
+    /// What the builtins give back, where it's always the same
+    /// What the builtin `name` gives when called with `args` arguments
+    fn builtin(&self, name: &str, args: usize) -> Type {
+        match self.dialect.table().builtin(name) {
+            "LENGTH" | "COUNT" | "INDEXOF" | "NOW" => Type::Int,
+            "TRIM" | "REPLACE" | "JOIN" | "TODAY" => Type::Str,
+            "CONTAINS" | "STARTSWITH" | "ENDSWITH" | "MATCHES" => Type::Bool,
+            "SPLIT" | "ARGS" => Type::Array,
+            "SET" => Type::Set,
+            "NEW" => Type::Pointer,
+            // A whole number between two bounds, or a real from 0 to 1
+            "RANDOM" if args == 2 => Type::Int,
+            "RANDOM" => Type::Real,
+            _ => Type::Unknown,
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::IntLit(_) => Type::Int,
+            Expr::FloatLit(_) => Type::Real,
+            Expr::BoolLit(_) => Type::Bool,
+            Expr::StrLit(_) => Type::Str,
+            Expr::NullLit => Type::Null,
+            Expr::Ident(name) => self.lookup(name),
+            Expr::Array(items) => {
+                items.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                Type::Array
+            }
+            Expr::Set(items) => {
+                items.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                Type::Set
+            }
+            Expr::Map(entries) => {
+                entries.iter().for_each(|(k, v)| {
+                    self.expr(k);
+                    self.expr(v);
+                });
+                Type::Map
+            }
+            Expr::AddressOf(_) => Type::Pointer,
+            Expr::Deref(x) => {
+                self.expr(x);
+                Type::Unknown
+            }
+            Expr::BNot(x) => match self.expr(x) {
+                Type::Int => Type::Int,
+                kind @ (Type::Unknown | Type::Unset) => kind,
+                Type::Number => Type::Unknown,
+                kind => {
+                    self.error(format!("bnot expects an integer, not {}", kind.name()), self.span);
+                    Type::Unknown
+                }
+            },
+            Expr::IfElse(x) => {
+                self.cond(&x.cond, "If");
+                let then = self.expr(&x.then);
+                then.join(self.expr(&x.otherwise))
+            }
+            Expr::Index(x) => {
+                self.expr(&x.target);
+                self.expr(&x.index);
+                Type::Unknown
+            }
+            Expr::Field(x) => {
+                self.expr(&x.target);
+                Type::Unknown
+            }
+            Expr::MethodCall(x) => {
+                self.expr(&x.target);
+                x.args.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                Type::Unknown
+            }
+            Expr::SuperCall(x) => {
+                x.args.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                Type::Unknown
+            }
+            Expr::New(x) => {
+                x.args.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                Type::Object
+            }
+            Expr::Call(x) => {
+                x.args.iter().for_each(|x| {
+                    self.expr(x);
+                });
+                // A variable holding a function comes first, then procedures
+                if self.stack.iter().any(|key| self.scopes.get(key).is_some_and(|s| s.vars.contains_key(&x.name))) {
+                    return Type::Unknown;
+                }
+                match self.returns.get(&x.name) {
+                    Some(kind) => *kind,
+                    None => self.builtin(&x.name, x.args.len()),
+                }
+            }
+            Expr::BinOp(x) => {
+                let left = self.expr(&x.left);
+                let right = self.expr(&x.right);
+                match bin_op(&x.op, left, right) {
+                    Ok(kind) => kind,
+                    Err(msg) => {
+                        self.error(msg, x.span);
+                        Type::Unknown
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The type `l op r` gives, or the error it's sure to fail with, following
+/// what `Expr::eval` does with the values
+fn bin_op(op: &BinaryOp, l: Type, r: Type) -> Result<Type, String> {
+    use Type::*;
+
+    if l == Unset || r == Unset {
+        return Ok(Unset);
+    }
+    let unknown = l == Unknown || r == Unknown;
+    let numbers = l.numeric() && r.numeric();
+    let mismatched = || Err(format!("Mismatched types, {} {} {}", l.name(), pretty::op_symbol(op), r.name()));
+
+    match op {
+        BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Mul => match (l, r) {
+            (Int, Int) => Ok(Int),
+            (Real, _) | (_, Real) if numbers => Ok(Real),
+            _ if numbers => Ok(Number),
+            (Str, Str) if *op == BinaryOp::Plus => Ok(Str),
+            _ if unknown => Ok(Unknown),
+            _ => mismatched(),
+        },
+        BinaryOp::Div | BinaryOp::FloorDiv => match () {
+            _ if numbers || unknown => Ok(Real),
+            _ => mismatched(),
+        },
+        BinaryOp::Mod => match (l, r) {
+            (Int, Int) => Ok(Int),
+            (Real, _) | (_, Real) if numbers => Ok(Real),
+            _ if numbers || unknown => Ok(Unknown),
+            _ => mismatched(),
+        },
+        BinaryOp::GE | BinaryOp::LE | BinaryOp::GT | BinaryOp::LT => match (l, r) {
+            _ if numbers || unknown => Ok(Bool),
+            (Str, Str) => Ok(Bool),
+            _ => Err(format!("Cannot compare {} with {}", l.name(), r.name())),
+        },
+        BinaryOp::In => match (l, r) {
+            (_, Array | Set | Map | Unknown) | (Str | Unknown, Str) => Ok(Bool),
+            _ => Err(format!("Cannot look for {} in {}", l.name(), r.name())),
+        },
+        BinaryOp::Equals | BinaryOp::NotEquals => match (l, r) {
+            _ if numbers || unknown || l == r || l == Null || r == Null => Ok(Bool),
+            _ => mismatched(),
+        },
+        BinaryOp::BAnd | BinaryOp::BOr | BinaryOp::BXor | BinaryOp::Shl | BinaryOp::Shr => match (l, r) {
+            (Int, Int) => Ok(Int),
+            (Int | Number | Unknown, Int | Number | Unknown) => Ok(Unknown),
+            _ => Err(format!("Bitwise operators expect integers, not {} and {}", l.name(), r.name())),
+        },
+    }
+}
+
+/// Every operation in `stmts` that's sure to fail on the types it's given,
+/// wherever it is and whether or not it would ever run. Each variable has
+/// one type for the whole of its scope, the join of everything it's
+/// assigned, and nothing is said about one that could be more than one
+/// kind of thing
+pub fn check(stmts: &[Stmt], dialect: Dialect) -> Vec<ParseError> {
+    let mut checker = Checker {
+        dialect,
+        scopes: HashMap::new(),
+        stack: vec![TOP],
+        returns: HashMap::new(),
+        declare: true,
+        changed: true,
+        report: false,
+        errors: Vec::new(),
+        span: Span { line: 1, col: 1, len: 1 },
+    };
+    checker.stmts(stmts, None);
+    checker.declare = false;
+
+    // Types only ever get less precise, so this settles in a few goes
+    for _ in 0..32 {
+        if !checker.changed {
+            break;
+        }
+        checker.changed = false;
+        checker.stmts(stmts, None);
+    }
+
+    checker.report = true;
+    checker.stmts(stmts, None);
+    checker.errors.sort_by_key(|x| x.span.map(|x| (x.line, x.col)));
+    checker.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<String> {
+        let stmts = crate::parse(source, &Default::default()).unwrap();
+        check(&stmts, Dialect::default()).into_iter().map(|x| x.msg).collect()
+    }
+
+    #[test]
+    fn random_between_bounds_is_an_integer() {
+        assert!(errors("output RANDOM(1, 6) band 1").is_empty());
+        assert_eq!(errors("output RANDOM() band 1"), ["Bitwise operators expect integers, not real and integer"]);
+    }
+}
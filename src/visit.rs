@@ -0,0 +1,243 @@
+use crate::eval::*;
+use std::rc::Rc;
+
+/// Goes over every statement and expression of a program in order. Each
+/// `visit_` method walks into what's inside with the `walk_` function of the
+/// same name unless it's overridden, and an override calls that function
+/// itself to keep going
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    /// A procedure, function or method, nested ones included
+    fn visit_function(&mut self, func: &Function) {
+        walk_function(self, func)
+    }
+}
+
+/// `Visitor` for changing the program. Definitions are only walked into
+/// while nothing else holds them, which is until the program starts running
+pub trait VisitorMut {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_function(&mut self, func: &mut Function) {
+        walk_function_mut(self, func)
+    }
+}
+
+pub fn walk_stmts<V: Visitor + ?Sized>(v: &mut V, stmts: &[Stmt]) {
+    stmts.iter().for_each(|x| v.visit_stmt(x));
+}
+
+/// The expressions of `stmt` in the order they run, then its body
+pub fn walk_stmt<V: Visitor + ?Sized>(v: &mut V, stmt: &Stmt) {
+    match &stmt.kind {
+        StmtKind::Assign(x) => {
+            v.visit_expr(&x.target);
+            v.visit_expr(&x.expr);
+        }
+        StmtKind::MultiAssign(x) => x.targets.iter().chain(&x.exprs).for_each(|x| v.visit_expr(x)),
+        StmtKind::Input(_) | StmtKind::Global(_) | StmtKind::Type(_) | StmtKind::Include(_) => {}
+        StmtKind::Output(x) => v.visit_expr(&x.expr),
+        StmtKind::If(x) => {
+            for (cond, stmts) in &x.branches {
+                v.visit_expr(cond);
+                walk_stmts(v, stmts);
+            }
+        }
+        StmtKind::While(x) => {
+            v.visit_expr(&x.cond);
+            walk_stmts(v, &x.stmts);
+        }
+        StmtKind::Until(x) | StmtKind::Repeat(x) => {
+            v.visit_expr(&x.cond);
+            walk_stmts(v, &x.stmts);
+        }
+        StmtKind::For(x) => {
+            v.visit_expr(&x.start);
+            v.visit_expr(&x.end);
+            walk_stmts(v, &x.stmts);
+        }
+        StmtKind::ForEach(x) => {
+            v.visit_expr(&x.iter);
+            walk_stmts(v, &x.stmts);
+        }
+        StmtKind::Expr(x) => v.visit_expr(x),
+        StmtKind::Function(x) => v.visit_function(x),
+        StmtKind::Class(x) => {
+            x.fields.iter().filter_map(|x| x.init.as_ref()).for_each(|x| v.visit_expr(x));
+            x.methods.iter().for_each(|x| v.visit_function(&x.func));
+        }
+        StmtKind::Return(x) | StmtKind::Halt(x) => x.iter().for_each(|x| v.visit_expr(x)),
+        StmtKind::Attempt(x) => {
+            walk_stmts(v, &x.stmts);
+            walk_stmts(v, &x.handler);
+        }
+        StmtKind::Assert(x) => {
+            v.visit_expr(&x.cond);
+            x.msg.iter().for_each(|x| v.visit_expr(x));
+        }
+    }
+}
+
+/// The expressions directly inside `expr`
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::BinOp(x) => {
+            v.visit_expr(&x.left);
+            v.visit_expr(&x.right);
+        }
+        Expr::IfElse(x) => {
+            v.visit_expr(&x.cond);
+            v.visit_expr(&x.then);
+            v.visit_expr(&x.otherwise);
+        }
+        Expr::Index(x) => {
+            v.visit_expr(&x.target);
+            v.visit_expr(&x.index);
+        }
+        Expr::MethodCall(x) => {
+            v.visit_expr(&x.target);
+            x.args.iter().for_each(|x| v.visit_expr(x));
+        }
+        Expr::Field(x) => v.visit_expr(&x.target),
+        Expr::Call(x) => x.args.iter().for_each(|x| v.visit_expr(x)),
+        Expr::SuperCall(x) => x.args.iter().for_each(|x| v.visit_expr(x)),
+        Expr::New(x) => x.args.iter().for_each(|x| v.visit_expr(x)),
+        Expr::Map(entries) => {
+            for (k, val) in entries {
+                v.visit_expr(k);
+                v.visit_expr(val);
+            }
+        }
+        Expr::Set(items) | Expr::Array(items) => items.iter().for_each(|x| v.visit_expr(x)),
+        Expr::Deref(x) | Expr::BNot(x) => v.visit_expr(x),
+        Expr::AddressOf(_)
+        | Expr::IntLit(_)
+        | Expr::FloatLit(_)
+        | Expr::BoolLit(_)
+        | Expr::StrLit(_)
+        | Expr::NullLit
+        | Expr::Ident(_) => {}
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(v: &mut V, func: &Function) {
+    walk_stmts(v, &func.stmts);
+}
+
+pub fn walk_stmts_mut<V: VisitorMut + ?Sized>(v: &mut V, stmts: &mut [Stmt]) {
+    stmts.iter_mut().for_each(|x| v.visit_stmt(x));
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(v: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Assign(x) => {
+            v.visit_expr(&mut x.target);
+            v.visit_expr(&mut x.expr);
+        }
+        StmtKind::MultiAssign(x) => x.targets.iter_mut().chain(&mut x.exprs).for_each(|x| v.visit_expr(x)),
+        StmtKind::Input(_) | StmtKind::Global(_) | StmtKind::Type(_) | StmtKind::Include(_) => {}
+        StmtKind::Output(x) => v.visit_expr(&mut x.expr),
+        StmtKind::If(x) => {
+            for (cond, stmts) in &mut x.branches {
+                v.visit_expr(cond);
+                walk_stmts_mut(v, stmts);
+            }
+        }
+        StmtKind::While(x) => {
+            v.visit_expr(&mut x.cond);
+            walk_stmts_mut(v, &mut x.stmts);
+        }
+        StmtKind::Until(x) | StmtKind::Repeat(x) => {
+            v.visit_expr(&mut x.cond);
+            walk_stmts_mut(v, &mut x.stmts);
+        }
+        StmtKind::For(x) => {
+            v.visit_expr(&mut x.start);
+            v.visit_expr(&mut x.end);
+            walk_stmts_mut(v, &mut x.stmts);
+        }
+        StmtKind::ForEach(x) => {
+            v.visit_expr(&mut x.iter);
+            walk_stmts_mut(v, &mut x.stmts);
+        }
+        StmtKind::Expr(x) => v.visit_expr(x),
+        StmtKind::Function(x) => {
+            if let Some(func) = Rc::get_mut(x) {
+                v.visit_function(func);
+            }
+        }
+        StmtKind::Class(x) => {
+            if let Some(class) = Rc::get_mut(x) {
+                class.fields.iter_mut().filter_map(|x| x.init.as_mut()).for_each(|x| v.visit_expr(x));
+                class.methods.iter_mut().filter_map(|x| Rc::get_mut(&mut x.func)).for_each(|x| v.visit_function(x));
+            }
+        }
+        StmtKind::Return(x) | StmtKind::Halt(x) => x.iter_mut().for_each(|x| v.visit_expr(x)),
+        StmtKind::Attempt(x) => {
+            walk_stmts_mut(v, &mut x.stmts);
+            walk_stmts_mut(v, &mut x.handler);
+        }
+        StmtKind::Assert(x) => {
+            v.visit_expr(&mut x.cond);
+            x.msg.iter_mut().for_each(|x| v.visit_expr(x));
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::BinOp(x) => {
+            v.visit_expr(&mut x.left);
+            v.visit_expr(&mut x.right);
+        }
+        Expr::IfElse(x) => {
+            v.visit_expr(&mut x.cond);
+            v.visit_expr(&mut x.then);
+            v.visit_expr(&mut x.otherwise);
+        }
+        Expr::Index(x) => {
+            v.visit_expr(&mut x.target);
+            v.visit_expr(&mut x.index);
+        }
+        Expr::MethodCall(x) => {
+            v.visit_expr(&mut x.target);
+            x.args.iter_mut().for_each(|x| v.visit_expr(x));
+        }
+        Expr::Field(x) => v.visit_expr(&mut x.target),
+        Expr::Call(x) => x.args.iter_mut().for_each(|x| v.visit_expr(x)),
+        Expr::SuperCall(x) => x.args.iter_mut().for_each(|x| v.visit_expr(x)),
+        Expr::New(x) => x.args.iter_mut().for_each(|x| v.visit_expr(x)),
+        Expr::Map(entries) => {
+            for (k, val) in entries {
+                v.visit_expr(k);
+                v.visit_expr(val);
+            }
+        }
+        Expr::Set(items) | Expr::Array(items) => items.iter_mut().for_each(|x| v.visit_expr(x)),
+        Expr::Deref(x) | Expr::BNot(x) => v.visit_expr(x),
+        Expr::AddressOf(_)
+        | Expr::IntLit(_)
+        | Expr::FloatLit(_)
+        | Expr::BoolLit(_)
+        | Expr::StrLit(_)
+        | Expr::NullLit
+        | Expr::Ident(_) => {}
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(v: &mut V, func: &mut Function) {
+    walk_stmts_mut(v, &mut func.stmts);
+}
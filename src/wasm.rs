@@ -0,0 +1,138 @@
+use crate::env::Env;
+use crate::eval::StmtKind;
+use crate::lex::{self, LexOptions};
+use crate::parse;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+use std::slice;
+
+/// Hands what the program writes to a callback
+struct Callback(Box<dyn FnMut(&str)>);
+
+impl Write for Callback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source` reading `stdin` as its input, and returns everything it
+/// output. An error ends the output with an `Error:` line
+pub fn run(source: &str, stdin: &str) -> String {
+    let ret = Rc::new(RefCell::new(String::new()));
+    let out = ret.clone();
+    run_streaming(source, stdin, move |x| out.borrow_mut().push_str(x));
+    let ret = ret.borrow().clone();
+    ret
+}
+
+/// Like `run`, but passes output to `on_output` as the program writes it, so
+/// a playground can show it before the program ends
+pub fn run_streaming(source: &str, stdin: &str, on_output: impl FnMut(&str) + 'static) {
+    let mut env = Env::new();
+    env.out = Box::new(Callback(Box::new(on_output)));
+    env.input = Some(Box::new(Cursor::new(stdin.as_bytes().to_vec())));
+    // All the input is there up front, so there's no asking again
+    env.strict_input = true;
+
+    let stmts = match lex::lex(source, &LexOptions::default()).and_then(parse::parse) {
+        Ok(x) => x,
+        Err(e) => {
+            let _ = writeln!(env.out, "Error: {}", e);
+            return;
+        }
+    };
+
+    if stmts.iter().any(|x| matches!(x.kind, StmtKind::Include(_))) {
+        let _ = writeln!(env.out, "Error: include needs files, which programs in the browser don't have");
+        return;
+    }
+
+    // A halt just stops the program, there's no process to exit
+    if let Err(e) = env.run(&stmts) {
+        if env.halted.is_none() {
+            let _ = writeln!(env.out, "Error: {}", e);
+        }
+    }
+}
+
+thread_local! {
+    static RESULT_LEN: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Leaks `text` into memory JavaScript can read, see `psc_result_len`
+fn give(text: String) -> *mut u8 {
+    let mut bytes = text.into_bytes().into_boxed_slice();
+    RESULT_LEN.with(|x| x.set(bytes.len()));
+    let ret = bytes.as_mut_ptr();
+    std::mem::forget(bytes);
+    ret
+}
+
+/// # Safety
+/// `ptr` and `len` must come from `psc_alloc` or a result, and the bytes
+/// must be UTF-8
+unsafe fn take<'a>(ptr: *const u8, len: usize) -> &'a str {
+    match len {
+        0 => "",
+        _ => std::str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or(""),
+    }
+}
+
+/// Space for `len` bytes
+#[no_mangle]
+pub extern "C" fn psc_alloc(len: usize) -> *mut u8 {
+    let mut bytes = vec![0u8; len].into_boxed_slice();
+    let ret = bytes.as_mut_ptr();
+    std::mem::forget(bytes);
+    ret
+}
+
+/// # Safety
+/// `ptr` and `len` must come from `psc_alloc` or a result, freed once
+#[no_mangle]
+pub unsafe extern "C" fn psc_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Length of the string the last call returned
+#[no_mangle]
+pub extern "C" fn psc_result_len() -> usize {
+    RESULT_LEN.with(|x| x.get())
+}
+
+/// `run` for JavaScript, which copies both strings into space from
+/// `psc_alloc` as UTF-8. The output's length comes from `psc_result_len`,
+/// and everything is released with `psc_free`
+///
+/// # Safety
+/// Both strings must be UTF-8 from `psc_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn psc_run(source: *const u8, source_len: usize, stdin: *const u8, stdin_len: usize) -> *mut u8 {
+    give(run(take(source, source_len), take(stdin, stdin_len)))
+}
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    /// Provided by JavaScript, gets each piece of output as it's written.
+    /// The string is only valid during the call
+    fn psc_output(ptr: *const u8, len: usize);
+}
+
+/// `run_streaming` for JavaScript, which passes output to its `psc_output`
+/// import rather than returning it
+///
+/// # Safety
+/// Both strings must be UTF-8 from `psc_alloc`
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub unsafe extern "C" fn psc_run_streaming(source: *const u8, source_len: usize, stdin: *const u8, stdin_len: usize) {
+    run_streaming(take(source, source_len), take(stdin, stdin_len), |x| unsafe {
+        psc_output(x.as_ptr(), x.len())
+    });
+}